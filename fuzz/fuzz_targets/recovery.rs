@@ -0,0 +1,256 @@
+//! Drives the manager through random sends, cancellations, resyncs and reconnects against a
+//! scripted node which fails, loses responses, delays them or sees other transactions from
+//! the same key, and checks that no nonce is ever broadcast twice or out of order.
+//!
+//! ```sh
+//! cargo +nightly fuzz run recovery
+//! ```
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use async_trait::async_trait;
+use ethers::prelude::*;
+use ethers::providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use ethers_nonce::{LockedNonceManagerMiddleware, NonceEvent, NonceObserver};
+use futures_util::task::noop_waker_ref;
+use libfuzzer_sys::fuzz_target;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// The key the manager sends from
+const SENDER: Address = H160([0x11; 20]);
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+/// How the scripted node handles a single request
+enum Fault {
+    /// Answers truthfully
+    None,
+    /// Fails without processing the request
+    Transport,
+    /// Processes the request, but the response never arrives
+    LostResponse,
+    /// Another transaction from the key lands right before the request is processed
+    ExternalSend,
+    /// Answers only after the caller was polled this many more times
+    Delay(u8),
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Op {
+    /// Sends a transaction, dropping the future after `polls` polls if set
+    Send {
+        polls: Option<u8>,
+    },
+    Resync,
+    Reconnect,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    faults: Vec<Fault>,
+    ops: Vec<Op>,
+}
+
+#[derive(Debug, Default)]
+struct Chain {
+    /// Transaction count of the key, pending transactions included
+    count: u64,
+    faults: VecDeque<Fault>,
+    /// Nonces of the transactions the manager got accepted
+    accepted: Vec<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ScriptError {
+    #[error("connection reset")]
+    Transport,
+    #[error("{0}")]
+    Rpc(JsonRpcError),
+    #[error("{0}")]
+    Serde(serde_json::Error),
+    #[error("unexpected request {0}")]
+    Unsupported(String),
+}
+
+impl RpcError for ScriptError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            ScriptError::Rpc(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            ScriptError::Serde(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScriptError> for ProviderError {
+    fn from(err: ScriptError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(err))
+    }
+}
+
+fn rejected(message: &str) -> ScriptError {
+    ScriptError::Rpc(JsonRpcError {
+        code: -32000,
+        message: message.to_owned(),
+        data: None,
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+/// A node with a single account, answering transaction counts and accepting transactions
+/// sent at the account's next nonce
+struct Scripted(Arc<Mutex<Chain>>);
+
+/// Stays pending for the given number of polls
+struct Yield(u8);
+
+impl Future for Yield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 == 0 {
+            return Poll::Ready(());
+        }
+        self.0 -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Scripted {
+    type Error = ScriptError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let fault = self.0.lock().unwrap().faults.pop_front();
+        let fault = fault.unwrap_or(Fault::None);
+        if let Fault::Delay(polls) = fault {
+            Yield(polls).await;
+        }
+
+        let result = {
+            let mut chain = self.0.lock().unwrap();
+            match fault {
+                Fault::Transport => return Err(ScriptError::Transport),
+                Fault::ExternalSend => chain.count += 1,
+                _ => {}
+            }
+            match method {
+                "eth_getTransactionCount" => json!(U256::from(chain.count)),
+                "eth_sendTransaction" => {
+                    let params = serde_json::to_value(&params).map_err(ScriptError::Serde)?;
+                    let nonce: U256 = serde_json::from_value(params[0]["nonce"].clone())
+                        .map_err(ScriptError::Serde)?;
+                    let nonce = nonce.as_u64();
+                    if nonce < chain.count {
+                        return Err(rejected("nonce too low"));
+                    }
+                    if nonce > chain.count {
+                        return Err(rejected("nonce too high"));
+                    }
+                    chain.count += 1;
+                    chain.accepted.push(nonce);
+                    json!(H256::from_low_u64_be(nonce + 1))
+                }
+                method => return Err(ScriptError::Unsupported(method.to_owned())),
+            }
+        };
+        if let Fault::LostResponse = fault {
+            return Err(ScriptError::Transport);
+        }
+        serde_json::from_value(result).map_err(ScriptError::Serde)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Broadcasts(Arc<Mutex<Vec<u64>>>);
+
+impl NonceObserver for Broadcasts {
+    fn on_event(&self, event: &NonceEvent) {
+        if let NonceEvent::Broadcast { nonce, .. } = event {
+            self.0.lock().unwrap().push(nonce.as_u64());
+        }
+    }
+}
+
+/// Polls `future` to completion, or drops it after `polls` polls
+fn drive<F: Future>(future: F, polls: Option<u8>) -> Option<F::Output> {
+    let mut future = Box::pin(future);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut remaining = polls.map(usize::from);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+        match &mut remaining {
+            Some(0) => return None,
+            Some(remaining) => *remaining -= 1,
+            None => {}
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let node = Scripted::default();
+    node.0.lock().unwrap().faults = input.faults.into();
+    let broadcasts = Broadcasts::default();
+    let client = LockedNonceManagerMiddleware::new(Provider::new(node.clone()), SENDER)
+        .with_observer(broadcasts.clone());
+    let tx = TransactionRequest::pay(Address::repeat_byte(1), 1u64)
+        .from(SENDER)
+        .gas(21_000u64)
+        .gas_price(1u64);
+
+    for op in input.ops {
+        match op {
+            Op::Send { polls } => {
+                let _ = drive(client.send_transaction(tx.clone(), None), polls);
+            }
+            Op::Resync => {
+                let _ = drive(client.resync(), None);
+            }
+            Op::Reconnect => {
+                let _ = drive(client.notify_reconnected(), None);
+            }
+        }
+    }
+
+    let broadcasts = broadcasts.0.lock().unwrap();
+    let chain = node.0.lock().unwrap();
+    // sends are sequential, so every broadcast reported has to be above the previous one
+    assert!(
+        broadcasts.windows(2).all(|pair| pair[0] < pair[1]),
+        "broadcasts out of order or repeated: {broadcasts:?}"
+    );
+    // a broadcast is only reported once the node accepted it
+    assert!(
+        broadcasts
+            .iter()
+            .all(|nonce| chain.accepted.contains(nonce)),
+        "broadcasts {broadcasts:?} not accepted by the node: {:?}",
+        chain.accepted
+    );
+    if let Some(last) = broadcasts.last() {
+        let next = drive(client.next(), None).unwrap().as_u64();
+        assert!(next > *last, "counter {next} behind broadcast nonce {last}");
+    }
+});
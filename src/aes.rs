@@ -0,0 +1,56 @@
+use crate::{CipherError, StateCipher};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use std::fmt::{self, Debug};
+
+/// Length of the random nonce prefixed to every ciphertext
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+/// AES-256-GCM with a caller-provided key. Every ciphertext carries its own random nonce, so
+/// the same key can be reused for every write.
+pub struct AesGcmCipher {
+    cipher: Aes256Gcm,
+}
+
+impl AesGcmCipher {
+    /// Instantiates the cipher with a 256-bit `key`
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        }
+    }
+}
+
+impl Debug for AesGcmCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keep the key out of logs
+        f.debug_struct("AesGcmCipher").finish_non_exhaustive()
+    }
+}
+
+impl StateCipher for AesGcmCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CipherError::Encrypt)?;
+
+        let mut out = nonce.to_vec();
+        out.extend(ciphertext);
+        Ok(out)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if ciphertext.len() < NONCE_LEN {
+            return Err(CipherError::Decrypt);
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CipherError::Decrypt)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::TxHash;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Starts the counter at `nonce` instead of reading it from the chain. It is applied
+    /// when the first nonce is assigned, unless the counter was initialized by then, e.g.
+    /// through a [`crate::SharedNonceState`] already in use.
+    pub fn with_initial_nonce(mut self, nonce: Nonce) -> Self {
+        self.config_mut().initial_nonce = Some(nonce);
+        self
+    }
+
+    /// Never touches the RPC, e.g. for offline signing ceremonies. Nonces are handed out
+    /// sequentially with [`Self::reserve_nonce`] from the counter seeded by
+    /// [`Self::with_initial_nonce`], and the transactions signed at them are reported with
+    /// [`Self::mark_broadcast`] once submitted elsewhere. Anything which would need the RPC,
+    /// including sends and initializing an unseeded counter, fails with
+    /// [`NonceManagerError::AirGapped`].
+    pub fn with_air_gap(mut self) -> Self {
+        self.config_mut().air_gapped = true;
+        self
+    }
+
+    /// Records that the transaction signed at the reserved `nonce` was submitted outside of
+    /// the manager, releasing the reservation and tracking it as in flight
+    pub async fn mark_broadcast(
+        &self,
+        nonce: Nonce,
+        tx_hash: TxHash,
+    ) -> Result<(), NonceManagerError<M>> {
+        if !self.state.schedule().lock().await.release(nonce) {
+            return Err(NonceManagerError::NotReserved(nonce));
+        }
+        self.record_broadcast(nonce, tx_hash, None, None).await;
+        Ok(())
+    }
+
+    /// Fails if the manager is air-gapped
+    pub(crate) fn ensure_connected(&self) -> Result<(), NonceManagerError<M>> {
+        if self.config().air_gapped {
+            Err(NonceManagerError::AirGapped)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, transfer, SENDER};
+    use crate::{Nonce, NonceManagerError, SimulatedChain};
+    use ethers::providers::Middleware;
+    use ethers::types::{transaction::eip2718::TypedTransaction, TxHash};
+
+    fn nonce(n: u64) -> Nonce {
+        Nonce::try_from(n).unwrap()
+    }
+
+    #[tokio::test]
+    async fn broadcasts_elsewhere_are_tracked() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain).with_initial_nonce(nonce(5)).with_air_gap();
+        assert_eq!(client.reserve_nonce(None).await.unwrap(), nonce(5));
+        assert_eq!(client.reserve_nonce(None).await.unwrap(), nonce(6));
+
+        let tx_hash = TxHash::repeat_byte(5);
+        client.mark_broadcast(nonce(5), tx_hash).await.unwrap();
+        assert_eq!(client.in_flight(), [(nonce(5), tx_hash)]);
+        for unreserved in [nonce(5), nonce(7)] {
+            let err = client
+                .mark_broadcast(unreserved, tx_hash)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, NonceManagerError::NotReserved(n) if n == unreserved));
+        }
+        assert_eq!(client.snapshot().await.reservations.len(), 1);
+        assert!(chain.pending_nonces(SENDER).is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_rpc_is_never_used() {
+        let chain = SimulatedChain::new(2);
+        let unseeded = client(&chain).with_air_gap();
+        let client = client(&chain).with_initial_nonce(nonce(5)).with_air_gap();
+        let err = client.send_transaction(transfer(), None).await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::AirGapped));
+        let mut tx: TypedTransaction = transfer().into();
+        let err = client.fill_transaction(&mut tx, None).await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::AirGapped));
+        let err = client.broadcast_scheduled().await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::AirGapped));
+        assert!(chain.pending_nonces(SENDER).is_empty());
+
+        // without a seed, the counter could only be initialized from the chain
+        let err = unseeded.reserve_nonce(None).await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::AirGapped));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::BroadcastEndpoint;
+use async_trait::async_trait;
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Bytes, TransactionRequest};
+use ethers::utils::id;
+use std::error::Error;
+
+/// Address of the delayed Inbox of Arbitrum One on Ethereum mainnet,
+/// 0x4Dbd4fc535Ac27206064B68FfCf827b0A60BAB3f
+pub const ARBITRUM_ONE_INBOX: Address = ethers::types::H160([
+    0x4d, 0xbd, 0x4f, 0xc5, 0x35, 0xac, 0x27, 0x20, 0x60, 0x64, 0xb6, 0x8f, 0xfc, 0xf8, 0x27, 0xb0,
+    0xa6, 0x0b, 0xab, 0x3f,
+]);
+
+/// Kind of an L2 message carrying a signed transaction, see `L2_MSG_signedTx` in Nitro
+const L2_MESSAGE_SIGNED_TX: u8 = 4;
+
+#[derive(Debug, Clone)]
+/// [`BroadcastEndpoint`] submitting the signed L2 transaction to an Arbitrum chain's delayed
+/// Inbox on L1 through `sendL2Message`, bypassing the sequencer. The sequencer picks delayed
+/// messages up after a few minutes, and once the force-inclusion delay has passed anyone can
+/// include them if it is down.
+///
+/// Posting to L1 costs L1 gas for every transaction, so the endpoint is usually added with
+/// [`crate::LockedNonceManagerMiddleware::with_fallback_endpoint`], to only be used when the
+/// sequencer can't be reached:
+///
+/// ```ignore
+/// let client = LockedNonceManagerMiddleware::new(arbitrum, address)
+///     .with_fallback_endpoint(ArbitrumDelayedInbox::new(l1_client, ARBITRUM_ONE_INBOX));
+/// ```
+///
+/// Operators who'd rather pay L1 gas on every transaction than depend on the sequencer
+/// sequencing what it accepted add it with
+/// [`crate::LockedNonceManagerMiddleware::with_dual_submission`] instead, to submit through
+/// both paths at once.
+///
+/// `l1` sends the Inbox transaction, so it needs a signer funded on L1. The L2 transaction
+/// it carries keeps the nonce the manager assigned, so sequencer and Inbox submissions of it
+/// can't both be executed.
+pub struct ArbitrumDelayedInbox<L1> {
+    l1: L1,
+    inbox: Address,
+}
+
+impl<L1: Middleware> ArbitrumDelayedInbox<L1> {
+    /// Submits through the delayed Inbox deployed at `inbox`, using `l1` to send to it
+    pub fn new(l1: L1, inbox: Address) -> Self {
+        Self { l1, inbox }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<L1: Middleware + 'static> BroadcastEndpoint for ArbitrumDelayedInbox<L1> {
+    async fn send_raw(&self, raw_tx: Bytes) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut message = vec![L2_MESSAGE_SIGNED_TX];
+        message.extend_from_slice(&raw_tx);
+        let mut data = id("sendL2Message(bytes)").to_vec();
+        data.extend(abi::encode(&[Token::Bytes(message)]));
+
+        let tx = TransactionRequest::new().to(self.inbox).data(data);
+        self.l1
+            .send_transaction(tx, None)
+            .await
+            .map_err(|err| Box::new(err) as Box<dyn Error + Send + Sync>)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{Address, BlockId, H256, U256};
+use ethers::utils::{keccak256, rlp::RlpStream};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the EIP-7702 authorization signing payload
+const AUTHORIZATION_MAGIC: u8 = 0x05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// An unsigned EIP-7702 authorization tuple, delegating the managed account's code to
+/// `address`. Including it in a transaction consumes `nonce` of the managed account.
+pub struct Authorization {
+    /// Chain the authorization is valid on
+    pub chain_id: U256,
+    /// Contract the managed account delegates to
+    pub address: Address,
+    /// Nonce of the managed account consumed by the authorization
+    pub nonce: Nonce,
+}
+
+impl Authorization {
+    /// The hash to be signed by the managed account,
+    /// `keccak256(0x05 || rlp([chain_id, address, nonce]))`
+    pub fn signing_hash(&self) -> H256 {
+        let mut rlp = RlpStream::new_list(3);
+        rlp.append(&self.chain_id);
+        rlp.append(&self.address);
+        rlp.append(&self.nonce.as_u256());
+
+        let mut payload = vec![AUTHORIZATION_MAGIC];
+        payload.extend_from_slice(&rlp.out());
+        H256::from(keccak256(payload))
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Reserves the next nonce for an authorization delegating to `delegate`, for a delegation
+    /// relayed by another account. The counter moves past the nonce right away, so the
+    /// sponsor's transaction must be included before transactions at later nonces can be.
+    pub async fn reserve_authorization(
+        &self,
+        delegate: Address,
+        block: Option<BlockId>,
+    ) -> Result<Authorization, NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        let chain_id = self.inner.get_chainid().await.map_err(FromErr::from)?;
+        self.get_or_init_nonce(block).await?;
+
+        let mut write_guard = self.lock_for_assignment(None, arrival).await?;
+        let nonce = *write_guard;
+        *write_guard = Self::increment(nonce)?;
+        self.journal(JournalEntry::Assigned { nonce });
+        self.emit(NonceEvent::Assigned {
+            address: self.address,
+            nonce,
+            label: None,
+        });
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+
+        Ok(Authorization {
+            chain_id,
+            address: delegate,
+            nonce,
+        })
+    }
+
+    /// Reserves the nonces of a self-sponsored delegation, where the managed account sends the
+    /// transaction carrying its own authorization. The transaction nonce is incremented before
+    /// the authorization list is processed, so the authorization has to use the nonce after it
+    /// and the counter advances by two.
+    ///
+    /// The transaction nonce is reserved like [`Self::reserve_nonce`]: once the signed
+    /// transaction is handed to [`Self::schedule_signed`] it is sent by
+    /// [`Self::broadcast_scheduled`]. The authorization nonce needs no transaction of its own
+    /// and never holds back the schedule.
+    pub async fn reserve_self_authorization(
+        &self,
+        delegate: Address,
+        block: Option<BlockId>,
+    ) -> Result<(Nonce, Authorization), NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        let chain_id = self.inner.get_chainid().await.map_err(FromErr::from)?;
+        self.get_or_init_nonce(block).await?;
+
+        let mut write_guard = self.lock_for_assignment(None, arrival).await?;
+        let tx_nonce = *write_guard;
+        let authorization_nonce = Self::increment(tx_nonce)?;
+        *write_guard = Self::increment(authorization_nonce)?;
+        self.state.schedule().lock().await.reserve(tx_nonce);
+        self.journal(JournalEntry::Reserved { nonce: tx_nonce });
+        self.journal(JournalEntry::Assigned {
+            nonce: authorization_nonce,
+        });
+        for nonce in [tx_nonce, authorization_nonce] {
+            self.emit(NonceEvent::Assigned {
+                address: self.address,
+                nonce,
+                label: None,
+            });
+        }
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+
+        Ok((
+            tx_nonce,
+            Authorization {
+                chain_id,
+                address: delegate,
+                nonce: authorization_nonce,
+            },
+        ))
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::U64;
+use std::{num::NonZeroUsize, sync::Mutex};
+
+#[derive(Debug, Default)]
+/// Counts the managed transactions broadcast within the latest block seen
+pub(crate) struct BlockWindow {
+    /// The latest block seen and how many transactions were broadcast in it
+    window: Mutex<(U64, usize)>,
+}
+
+impl BlockWindow {
+    /// Takes a slot in `block`, returning `false` if it already holds `limit` transactions
+    fn try_take(&self, block: U64, limit: NonZeroUsize) -> bool {
+        let mut window = self.window.lock().expect("block cap lock poisoned");
+        if block > window.0 {
+            *window = (block, 0);
+        }
+        if window.1 < limit.get() {
+            window.1 += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Gives back a slot taken in `block`, unless the window moved on to a later block
+    fn release(&self, block: U64) {
+        let mut window = self.window.lock().expect("block cap lock poisoned");
+        if window.0 == block {
+            window.1 = window.1.saturating_sub(1);
+        }
+    }
+}
+
+#[must_use]
+/// A slot taken under the block cap, given back when dropped unless the transaction it was
+/// taken for was broadcast
+pub(crate) struct BlockSlot<'a> {
+    window: &'a BlockWindow,
+    block: Option<U64>,
+}
+
+impl BlockSlot<'_> {
+    /// Called once the transaction was broadcast, keeping the slot taken
+    pub(crate) fn used(mut self) {
+        self.block = None;
+    }
+}
+
+impl Drop for BlockSlot<'_> {
+    fn drop(&mut self) {
+        if let Some(block) = self.block {
+            self.window.release(block);
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Broadcasts at most `max_txs_per_block` managed transactions per block. Sends beyond
+    /// the cap wait for the next block before they are assigned a nonce, which smooths out
+    /// bursts that would otherwise trip the node's per-sender pending limits.
+    pub fn with_block_cap(mut self, max_txs_per_block: NonZeroUsize) -> Self {
+        self.config_mut().max_txs_per_block = Some(max_txs_per_block);
+        self
+    }
+
+    /// Waits until the current block has room for another transaction under the block cap,
+    /// polling for new heads at the provider's interval. The cap is re-read on every poll so
+    /// that waiting sends pick up a [`Self::reconfigure`]. The slot taken is given back
+    /// unless [`BlockSlot::used`] is called once the transaction was broadcast, so failed
+    /// sends don't count against the cap.
+    pub(crate) async fn wait_for_block_slot(&self) -> Result<BlockSlot<'_>, NonceManagerError<M>> {
+        let interval = self.provider().get_interval();
+        loop {
+            let limit = match self.config().max_txs_per_block {
+                Some(limit) => limit,
+                None => {
+                    return Ok(BlockSlot {
+                        window: &self.block_window,
+                        block: None,
+                    })
+                }
+            };
+            let block = self.inner.get_block_number().await.map_err(FromErr::from)?;
+            if self.block_window.try_take(block, limit) {
+                return Ok(BlockSlot {
+                    window: &self.block_window,
+                    block: Some(block),
+                });
+            }
+            self.sleep(interval).await;
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, U256};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What a [`GasBudget`] is measured in
+pub enum BudgetUnit {
+    /// Gas limit of the transactions
+    Gas,
+    /// Maximum fee of the transactions in wei, i.e. gas limit times (max) gas price
+    Fee,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Cap on what managed transactions may consume within a window of time. Transactions are
+/// charged their gas limit, or their maximum fee, when broadcast, so the budget is an upper
+/// bound on what is actually spent.
+pub struct GasBudget {
+    /// Amount available per window, in `unit`
+    pub limit: U256,
+    /// Length of a window, a new one starts as soon as the current one elapsed
+    #[serde(rename = "window_secs", with = "crate::config::duration_secs")]
+    pub window: Duration,
+    /// What `limit` is measured in
+    pub unit: BudgetUnit,
+}
+
+impl GasBudget {
+    /// Budget of `limit` gas per `window`
+    pub fn gas(limit: U256, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            unit: BudgetUnit::Gas,
+        }
+    }
+
+    /// Budget of `limit` wei of fees per `window`
+    pub fn fee(limit: U256, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            unit: BudgetUnit::Fee,
+        }
+    }
+
+    /// What `tx` is charged against the budget, or `None` if it is missing the gas fields
+    /// needed to tell
+    fn cost(&self, tx: &TypedTransaction) -> Option<U256> {
+        let gas = *tx.gas()?;
+        match self.unit {
+            BudgetUnit::Gas => Some(gas),
+            BudgetUnit::Fee => gas.checked_mul(tx.gas_price()?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Cap on the total `value` managed transactions may transfer within a window of time
+pub struct SpendLimit {
+    /// Amount of wei which may be transferred per window
+    pub limit: U256,
+    /// Length of a window, a new one starts as soon as the current one elapsed
+    #[serde(rename = "window_secs", with = "crate::config::duration_secs")]
+    pub window: Duration,
+}
+
+#[derive(Debug, Default)]
+/// Amount spent within the current window of a rolling limit. The limit, the window length
+/// and the time by the manager's clock are passed in on every call, so that they can be
+/// reconfigured at runtime. The first window starts on first use.
+pub(crate) struct Window {
+    spent: Mutex<(Option<Instant>, U256)>,
+}
+
+impl Window {
+    /// Checks that `amount` fits in the current window, returning what is left otherwise
+    pub(crate) fn check(
+        &self,
+        limit: U256,
+        length: Duration,
+        amount: U256,
+        now: Instant,
+    ) -> Result<(), U256> {
+        let remaining = self.remaining(limit, length, now);
+        if amount > remaining {
+            Err(remaining)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// What is left to spend in the current window
+    pub(crate) fn remaining(&self, limit: U256, length: Duration, now: Instant) -> U256 {
+        limit.saturating_sub(self.spent(length, now).1)
+    }
+
+    pub(crate) fn charge(&self, length: Duration, amount: U256, now: Instant) {
+        let mut spent = self.spent(length, now);
+        spent.1 = spent.1.saturating_add(amount);
+    }
+
+    /// Starts a new window right away
+    pub(crate) fn reset(&self, now: Instant) {
+        *self.spent.lock().expect("window lock poisoned") = (Some(now), U256::zero());
+    }
+
+    /// The spent amount, after rolling over to a new window if the current one elapsed
+    fn spent(
+        &self,
+        length: Duration,
+        now: Instant,
+    ) -> std::sync::MutexGuard<'_, (Option<Instant>, U256)> {
+        let mut spent = self.spent.lock().expect("window lock poisoned");
+        let elapsed = spent
+            .0
+            .is_none_or(|start| now.saturating_duration_since(start) >= length);
+        if elapsed {
+            *spent = (Some(now), U256::zero());
+        }
+        spent
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// What a transaction is charged against the configured limits once broadcast
+pub(crate) struct Charges {
+    gas: Option<U256>,
+    value: Option<U256>,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Refuses to assign nonces to transactions which would exceed `budget` within its
+    /// window, failing them with [`NonceManagerError::BudgetExceeded`]. Transactions are
+    /// filled through the inner middleware before they are assigned, so that their gas
+    /// limit is known. Replacements, see [`Self::speed_up`] and [`Self::cancel`], are charged
+    /// what they cost on top of the transaction they replace.
+    pub fn with_gas_budget(mut self, budget: GasBudget) -> Self {
+        self.config_mut().gas_budget = Some(budget);
+        self
+    }
+
+    /// What is left of the gas budget in the current window, if one is configured
+    pub fn remaining_gas_budget(&self) -> Option<U256> {
+        self.config().gas_budget.map(|budget| {
+            self.gas_window
+                .remaining(budget.limit, budget.window, self.now())
+        })
+    }
+
+    /// Caps the total `value` managed transactions may transfer within `window`. Transactions
+    /// which would exceed it are not assigned a nonce and fail with
+    /// [`NonceManagerError::SpendLimitExceeded`] until the window rolls over or an operator
+    /// calls [`Self::override_spend_limit`].
+    pub fn with_spend_limit(mut self, limit: U256, window: Duration) -> Self {
+        self.config_mut().spend_limit = Some(SpendLimit { limit, window });
+        self
+    }
+
+    /// What is left of the spend limit in the current window, if one is configured
+    pub fn remaining_spend_limit(&self) -> Option<U256> {
+        self.config().spend_limit.map(|limit| {
+            self.spend_window
+                .remaining(limit.limit, limit.window, self.now())
+        })
+    }
+
+    /// Starts a new spend limit window right away, unblocking transactions held back by the
+    /// limit
+    pub fn override_spend_limit(&self) {
+        self.spend_window.reset(self.now());
+    }
+
+    /// Checks that `tx` fits in the spend limit and the gas budget, filling it first if a gas
+    /// budget is configured. Needs to be called with the write lock held so that concurrent
+    /// sends cannot overrun the limits together.
+    pub(crate) async fn check_limits(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Charges, NonceManagerError<M>> {
+        self.check_limits_over(tx, block, None).await
+    }
+
+    /// Checks like [`Self::check_limits`] that `tx`, replacing `original` which was charged
+    /// already, fits in the limits, counting only what it costs on top of the original
+    pub(crate) async fn check_replacement_limits(
+        &self,
+        tx: &mut TypedTransaction,
+        original: &TypedTransaction,
+    ) -> Result<Charges, NonceManagerError<M>> {
+        self.check_limits_over(tx, None, Some(original)).await
+    }
+
+    async fn check_limits_over(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+        original: Option<&TypedTransaction>,
+    ) -> Result<Charges, NonceManagerError<M>> {
+        let config = self.config();
+        let value = match config.spend_limit {
+            Some(limit) => {
+                let value_of = |tx: &TypedTransaction| tx.value().copied().unwrap_or_default();
+                let value = value_of(tx).saturating_sub(original.map(value_of).unwrap_or_default());
+                self.spend_window
+                    .check(limit.limit, limit.window, value, self.now())
+                    .map_err(|remaining| NonceManagerError::SpendLimitExceeded {
+                        required: value,
+                        remaining,
+                    })?;
+                Some(value)
+            }
+            None => None,
+        };
+
+        let gas = match config.gas_budget {
+            Some(budget) => {
+                self.inner
+                    .fill_transaction(tx, block)
+                    .await
+                    .map_err(FromErr::from)?;
+                // a transaction whose cost can't be told can't be allowed either
+                let charged = original.and_then(|original| budget.cost(original));
+                let cost = budget
+                    .cost(tx)
+                    .unwrap_or(U256::MAX)
+                    .saturating_sub(charged.unwrap_or_default());
+                self.gas_window
+                    .check(budget.limit, budget.window, cost, self.now())
+                    .map_err(|remaining| NonceManagerError::BudgetExceeded {
+                        required: cost,
+                        remaining,
+                    })?;
+                Some(cost)
+            }
+            None => None,
+        };
+
+        Ok(Charges { gas, value })
+    }
+
+    /// Charges a broadcast transaction against the limits it was checked against, as far as
+    /// they are still configured
+    pub(crate) fn charge(&self, charges: Charges) {
+        let config = self.config();
+        if let (Some(budget), Some(cost)) = (config.gas_budget, charges.gas) {
+            self.gas_window.charge(budget.window, cost, self.now());
+        }
+        if let (Some(limit), Some(value)) = (config.spend_limit, charges.value) {
+            self.spend_window.charge(limit.window, value, self.now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_roll_over_once_elapsed() {
+        let window = Window::default();
+        let (limit, length) = (U256::from(100), Duration::from_secs(60));
+        let start = Instant::now();
+        window.charge(length, 80.into(), start);
+        let late = start + Duration::from_secs(59);
+        assert_eq!(window.check(limit, length, 30.into(), late), Err(20.into()));
+
+        let next = start + length;
+        assert_eq!(window.remaining(limit, length, next), limit);
+        assert_eq!(window.check(limit, length, 30.into(), next), Ok(()));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn sends_over_the_budget_wait_for_the_next_window() {
+        use crate::testkit::fixtures::{client, transfer, SENDER};
+        use crate::{MockClock, SimulatedChain};
+
+        let chain = SimulatedChain::new(1);
+        let clock = MockClock::new();
+        let window = Duration::from_secs(60);
+        let client = client(&chain)
+            .with_clock(clock.clone())
+            .with_gas_budget(GasBudget::gas(50_000.into(), window));
+        for _ in 0..2 {
+            client.send_transaction(transfer(), None).await.unwrap();
+        }
+
+        let err = client.send_transaction(transfer(), None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::BudgetExceeded { required, remaining }
+                if required == U256::from(21_000) && remaining == U256::from(8_000)
+        ));
+        assert_eq!(client.next().await.as_u64(), 2);
+
+        clock.advance(window);
+        client.send_transaction(transfer(), None).await.unwrap();
+        assert_eq!(chain.pending_nonces(SENDER), [0, 1, 2]);
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn replacements_are_charged_what_they_pay_on_top() {
+        use crate::testkit::fixtures::{client, send};
+        use crate::{Nonce, SimulatedChain};
+
+        let chain = SimulatedChain::new(2);
+        let gwei = U256::exp10(9);
+        // a transfer at the simulator's gas price of 1 gwei, and a third of a 10% bump
+        let budget = GasBudget::fee(gwei * 21_000 + gwei * 700, Duration::from_secs(60));
+        let client = client(&chain).with_gas_budget(budget);
+        let original = send(&client).await;
+
+        let err = client.speed_up(Nonce::zero()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::BudgetExceeded { required, remaining }
+                if required == gwei * 2_100 && remaining == gwei * 700
+        ));
+        assert_eq!(client.in_flight(), [(Nonce::zero(), original)]);
+
+        // what was spent in the window carries over
+        let mut config = client.config();
+        config.gas_budget = Some(GasBudget::fee(gwei * 30_000, Duration::from_secs(60)));
+        client.reconfigure(config);
+        client.speed_up(Nonce::zero()).await.unwrap();
+        assert_eq!(client.remaining_gas_budget(), Some(gwei * 6_900));
+    }
+}
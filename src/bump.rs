@@ -0,0 +1,423 @@
+use ethers::types::{transaction::eip2718::TypedTransaction, U256};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The fees a transaction pays, by transaction type
+pub enum TxFees {
+    /// Gas price of a legacy or EIP-2930 transaction
+    Legacy(U256),
+    /// Fees of an EIP-1559 transaction
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+impl TxFees {
+    /// The fees set on `tx`, if it has any
+    pub fn of(tx: &TypedTransaction) -> Option<Self> {
+        match tx {
+            TypedTransaction::Eip1559(inner) => Some(Self::Eip1559 {
+                max_fee_per_gas: inner.max_fee_per_gas?,
+                max_priority_fee_per_gas: inner.max_priority_fee_per_gas?,
+            }),
+            _ => tx.gas_price().map(Self::Legacy),
+        }
+    }
+
+    /// The lowest fees a replacement of a transaction paying `self` gets accepted with: each
+    /// fee raised by `price_bump_percent`, as node pools require, and enough to cover
+    /// `base_fee` so that the replacement can be included right away
+    pub fn min_replacement(self, price_bump_percent: u64, base_fee: Option<U256>) -> Self {
+        let base_fee = base_fee.unwrap_or_default();
+        match self {
+            Self::Legacy(gas_price) => {
+                Self::Legacy(bump(gas_price, price_bump_percent).max(base_fee))
+            }
+            Self::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let max_priority_fee_per_gas = bump(max_priority_fee_per_gas, price_bump_percent);
+                Self::Eip1559 {
+                    max_fee_per_gas: bump(max_fee_per_gas, price_bump_percent)
+                        .max(base_fee.saturating_add(max_priority_fee_per_gas)),
+                    max_priority_fee_per_gas,
+                }
+            }
+        }
+    }
+
+    /// The most paid per gas, the gas price or the max fee
+    pub fn max_fee(self) -> U256 {
+        match self {
+            Self::Legacy(gas_price) => gas_price,
+            Self::Eip1559 {
+                max_fee_per_gas, ..
+            } => max_fee_per_gas,
+        }
+    }
+
+    /// Raises each fee to at least the one of `minimum`. If the types differ, `minimum` is
+    /// returned.
+    pub fn at_least(self, minimum: Self) -> Self {
+        match (self, minimum) {
+            (Self::Legacy(gas_price), Self::Legacy(minimum)) => {
+                Self::Legacy(gas_price.max(minimum))
+            }
+            (
+                Self::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+                Self::Eip1559 {
+                    max_fee_per_gas: min_max_fee,
+                    max_priority_fee_per_gas: min_priority_fee,
+                },
+            ) => Self::Eip1559 {
+                max_fee_per_gas: max_fee_per_gas.max(min_max_fee),
+                max_priority_fee_per_gas: max_priority_fee_per_gas.max(min_priority_fee),
+            },
+            (_, minimum) => minimum,
+        }
+    }
+
+    /// Sets the fees on `tx`. A gas price applied to an EIP-1559 transaction is used as both
+    /// of its fees, while an EIP-1559 max fee applied to another type becomes its gas price.
+    pub fn apply(self, tx: &mut TypedTransaction) {
+        match (self, tx) {
+            (
+                Self::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+                TypedTransaction::Eip1559(inner),
+            ) => {
+                inner.max_fee_per_gas = Some(max_fee_per_gas);
+                inner.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+            (
+                Self::Eip1559 {
+                    max_fee_per_gas, ..
+                },
+                tx,
+            ) => tx.set_gas_price(max_fee_per_gas),
+            (Self::Legacy(gas_price), tx) => tx.set_gas_price(gas_price),
+        }
+    }
+}
+
+/// Decides the fees of a replacement transaction, e.g. when resubmitting a stuck transaction
+/// with [`crate::LockedNonceManagerMiddleware::resubmit_all`]. Implement it to encode your
+/// own escalation curve. Fees below [`TxFees::min_replacement`] are raised to it, so a
+/// policy can't produce a replacement the node would reject as underpriced.
+pub trait BumpPolicy: Send + Sync + Debug {
+    /// The fees to replace `original`, which carries the fees it was sent with. `attempt` is
+    /// 1 for the first replacement of the nonce, `base_fee` the base fee of the latest block
+    /// if the chain has one, and `market` the current fee recommendation for the
+    /// transaction's type.
+    fn replacement_fees(
+        &self,
+        original: &TypedTransaction,
+        attempt: u32,
+        base_fee: Option<U256>,
+        market: TxFees,
+    ) -> TxFees;
+
+    /// The most a replacement may pay per gas. A replacement which would have to pay more to
+    /// be accepted by the node is refused with
+    /// [`crate::NonceManagerError::FeeCapReached`] instead of being sent. Unlimited by
+    /// default.
+    fn max_fee_per_gas(&self) -> Option<U256> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The default [`BumpPolicy`], paying the market fees. Like with any policy, they are raised
+/// to the minimum replacement fees where needed.
+pub struct MarketBump;
+
+impl BumpPolicy for MarketBump {
+    fn replacement_fees(
+        &self,
+        _original: &TypedTransaction,
+        _attempt: u32,
+        _base_fee: Option<U256>,
+        market: TxFees,
+    ) -> TxFees {
+        market
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// A [`BumpPolicy`] raising the fees by a fixed step on every replacement, never below the
+/// market fees, up to an optional cap. Combined with a block-based [`crate::Sla`] with
+/// auto-bump, as [`crate::LockedNonceManagerMiddleware::with_bump_schedule`] sets up, a
+/// stuck transaction is replaced every `every_blocks` blocks.
+pub struct EscalationSchedule {
+    /// How much each replacement raises the fees, in basis points, e.g. 1250 for 12.5%
+    pub step_bps: u64,
+    /// Blocks a transaction stays pending for before it is replaced
+    pub every_blocks: u64,
+    /// The most a replacement may pay per gas
+    #[serde(default)]
+    pub max_fee_per_gas: Option<U256>,
+}
+
+impl EscalationSchedule {
+    /// Raises the fees by `step_bps` basis points every `every_blocks` blocks, without a cap
+    pub fn new(step_bps: u64, every_blocks: u64) -> Self {
+        Self {
+            step_bps,
+            every_blocks,
+            max_fee_per_gas: None,
+        }
+    }
+
+    /// Stops escalating at `max_fee_per_gas`
+    pub fn capped_at(mut self, max_fee_per_gas: U256) -> Self {
+        self.max_fee_per_gas = Some(max_fee_per_gas);
+        self
+    }
+}
+
+impl BumpPolicy for EscalationSchedule {
+    fn replacement_fees(
+        &self,
+        original: &TypedTransaction,
+        _attempt: u32,
+        _base_fee: Option<U256>,
+        market: TxFees,
+    ) -> TxFees {
+        // the original carries the fees of the previous replacement, so the steps compound
+        let stepped = match TxFees::of(original) {
+            Some(TxFees::Legacy(gas_price)) => TxFees::Legacy(bump_bps(gas_price, self.step_bps)),
+            Some(TxFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            }) => TxFees::Eip1559 {
+                max_fee_per_gas: bump_bps(max_fee_per_gas, self.step_bps),
+                max_priority_fee_per_gas: bump_bps(max_priority_fee_per_gas, self.step_bps),
+            },
+            None => return market,
+        };
+        let fees = stepped.at_least(market);
+        match (fees, self.max_fee_per_gas) {
+            (TxFees::Legacy(gas_price), Some(cap)) => TxFees::Legacy(gas_price.min(cap)),
+            (
+                TxFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+                Some(cap),
+            ) => TxFees::Eip1559 {
+                max_fee_per_gas: max_fee_per_gas.min(cap),
+                max_priority_fee_per_gas: max_priority_fee_per_gas.min(cap),
+            },
+            (fees, None) => fees,
+        }
+    }
+
+    fn max_fee_per_gas(&self) -> Option<U256> {
+        self.max_fee_per_gas
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Ready-made [`EscalationSchedule`]s, selectable by name, e.g. `"standard"` in a
+/// configuration file. Each step clears geth's 10% replacement minimum. Caps depend on the
+/// chain, so none is set; add one with [`EscalationSchedule::capped_at`].
+pub enum BumpPreset {
+    /// +12.5% every 5 blocks, for transactions which can wait
+    Conservative,
+    /// +12.5% every 3 blocks
+    Standard,
+    /// +25% every block, for transactions which have to land quickly
+    Aggressive,
+}
+
+impl From<BumpPreset> for EscalationSchedule {
+    fn from(preset: BumpPreset) -> Self {
+        match preset {
+            BumpPreset::Conservative => EscalationSchedule::new(1250, 5),
+            BumpPreset::Standard => EscalationSchedule::new(1250, 3),
+            BumpPreset::Aggressive => EscalationSchedule::new(2500, 1),
+        }
+    }
+}
+
+impl FromStr for BumpPreset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "conservative" => Ok(BumpPreset::Conservative),
+            "standard" => Ok(BumpPreset::Standard),
+            "aggressive" => Ok(BumpPreset::Aggressive),
+            name => Err(format!("unknown bump preset {name:?}")),
+        }
+    }
+}
+
+/// `fee` raised by `percent`, rounded up
+fn bump(fee: U256, percent: u64) -> U256 {
+    bump_bps(fee, percent * 100)
+}
+
+/// `fee` raised by `bps` basis points, rounded up
+fn bump_bps(fee: U256, bps: u64) -> U256 {
+    let raised = fee.saturating_mul(U256::from(10_000 + bps));
+    raised / 10_000 + if (raised % 10_000).is_zero() { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Eip1559TransactionRequest, TransactionRequest};
+
+    fn legacy(gas_price: u64) -> TypedTransaction {
+        TransactionRequest::new().gas_price(gas_price).into()
+    }
+
+    fn eip1559(max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> TypedTransaction {
+        Eip1559TransactionRequest::new()
+            .max_fee_per_gas(max_fee_per_gas)
+            .max_priority_fee_per_gas(max_priority_fee_per_gas)
+            .into()
+    }
+
+    #[test]
+    fn bumps_round_up() {
+        assert_eq!(bump(100.into(), 10), 110.into());
+        assert_eq!(bump(1.into(), 10), 2.into());
+        assert_eq!(bump_bps(8.into(), 1250), 9.into());
+        assert_eq!(bump_bps(U256::zero(), 1250), U256::zero());
+        // saturates rather than overflowing
+        assert!(bump_bps(U256::MAX, 1250) >= U256::MAX / 10_000);
+    }
+
+    #[test]
+    fn legacy_replacements_clear_the_bump_and_the_base_fee() {
+        let paid = TxFees::Legacy(100.into());
+        assert_eq!(paid.min_replacement(10, None), TxFees::Legacy(110.into()));
+        assert_eq!(
+            paid.min_replacement(10, Some(500.into())),
+            TxFees::Legacy(500.into())
+        );
+    }
+
+    #[test]
+    fn eip1559_replacements_cover_the_base_fee_and_the_raised_tip() {
+        let paid = TxFees::Eip1559 {
+            max_fee_per_gas: 100.into(),
+            max_priority_fee_per_gas: 10.into(),
+        };
+        assert_eq!(
+            paid.min_replacement(10, None),
+            TxFees::Eip1559 {
+                max_fee_per_gas: 110.into(),
+                max_priority_fee_per_gas: 11.into(),
+            }
+        );
+        assert_eq!(
+            paid.min_replacement(10, Some(200.into())),
+            TxFees::Eip1559 {
+                max_fee_per_gas: 211.into(),
+                max_priority_fee_per_gas: 11.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn fees_are_read_and_applied_by_type() {
+        assert_eq!(TxFees::of(&legacy(7)), Some(TxFees::Legacy(7.into())));
+        assert_eq!(
+            TxFees::of(&eip1559(7, 2)),
+            Some(TxFees::Eip1559 {
+                max_fee_per_gas: 7.into(),
+                max_priority_fee_per_gas: 2.into(),
+            })
+        );
+        assert_eq!(TxFees::of(&TransactionRequest::new().into()), None);
+
+        let mut tx = legacy(1);
+        TxFees::of(&eip1559(7, 2)).unwrap().apply(&mut tx);
+        assert_eq!(tx.gas_price(), Some(7.into()));
+    }
+
+    #[test]
+    fn at_least_raises_each_fee_and_prefers_the_minimum_type() {
+        let fees = TxFees::Eip1559 {
+            max_fee_per_gas: 100.into(),
+            max_priority_fee_per_gas: 1.into(),
+        };
+        let minimum = TxFees::Eip1559 {
+            max_fee_per_gas: 50.into(),
+            max_priority_fee_per_gas: 2.into(),
+        };
+        assert_eq!(
+            fees.at_least(minimum),
+            TxFees::Eip1559 {
+                max_fee_per_gas: 100.into(),
+                max_priority_fee_per_gas: 2.into(),
+            }
+        );
+        assert_eq!(
+            fees.at_least(TxFees::Legacy(3.into())),
+            TxFees::Legacy(3.into())
+        );
+    }
+
+    #[test]
+    fn escalation_compounds_up_to_the_cap() {
+        let schedule = EscalationSchedule::new(1250, 3).capped_at(120.into());
+        let market = TxFees::Legacy(50.into());
+        let first = schedule.replacement_fees(&legacy(100), 1, None, market);
+        assert_eq!(first, TxFees::Legacy(113.into()));
+        let second = schedule.replacement_fees(&legacy(113), 2, None, market);
+        assert_eq!(second, TxFees::Legacy(120.into()));
+        assert_eq!(schedule.max_fee_per_gas(), Some(120.into()));
+    }
+
+    #[test]
+    fn escalation_never_pays_less_than_the_market() {
+        let schedule = EscalationSchedule::new(1250, 3);
+        let market = TxFees::Eip1559 {
+            max_fee_per_gas: 300.into(),
+            max_priority_fee_per_gas: 1.into(),
+        };
+        assert_eq!(
+            schedule.replacement_fees(&eip1559(100, 8), 1, None, market),
+            TxFees::Eip1559 {
+                max_fee_per_gas: 300.into(),
+                max_priority_fee_per_gas: 9.into(),
+            }
+        );
+        // nothing to step from
+        let unpriced = TransactionRequest::new().into();
+        assert_eq!(
+            schedule.replacement_fees(&unpriced, 1, None, market),
+            market
+        );
+    }
+
+    #[test]
+    fn presets_clear_the_replacement_minimum() {
+        for (name, preset, every_blocks) in [
+            ("conservative", BumpPreset::Conservative, 5),
+            ("standard", BumpPreset::Standard, 3),
+            ("aggressive", BumpPreset::Aggressive, 1),
+        ] {
+            assert_eq!(name.parse::<BumpPreset>(), Ok(preset));
+            assert_eq!(serde_json::to_value(preset).unwrap(), name);
+            let schedule = EscalationSchedule::from(preset);
+            assert_eq!(schedule.every_blocks, every_blocks);
+            assert!(schedule.step_bps >= 1000);
+            assert_eq!(schedule.max_fee_per_gas, None);
+        }
+        assert!("fast".parse::<BumpPreset>().is_err());
+    }
+}
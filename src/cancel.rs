@@ -0,0 +1,125 @@
+use crate::{resubmit::Market, LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::{Middleware, PendingTransaction};
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, transaction::eip2930::AccessList,
+    Eip1559TransactionRequest, Eip2930TransactionRequest, TransactionRequest,
+};
+
+/// Gas used by a plain transfer, which is all a cancellation does
+const TRANSFER_GAS: u64 = 21_000;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Cancels the in-flight transaction at `nonce` by replacing it with an empty transfer to
+    /// the managed address. The replacement has the same transaction type and chain id as
+    /// the original, since some clients reject mixing types at a nonce, and is priced like
+    /// any other replacement, see [`Self::resubmit_all`]. Fails with
+    /// [`NonceManagerError::NotReplaceable`] if there is no transaction at `nonce` which can
+    /// be replaced, and with [`NonceManagerError::BlobNotReplaceable`] if it carries blobs.
+    /// Transactions depending on the cancelled one, see
+    /// [`Self::schedule_dependent`], are given up as well.
+    pub async fn cancel(
+        &self,
+        nonce: Nonce,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let pending = self.cancel_in_flight(nonce).await?;
+        self.drop_dependents(nonce).await;
+        Ok(pending)
+    }
+
+    /// Cancels the in-flight transaction at `nonce`, leaving the ones depending on it alone
+    pub(crate) async fn cancel_in_flight(
+        &self,
+        nonce: Nonce,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let (original, attempt) = self
+            .replaceable(nonce)
+            .await?
+            .ok_or(NonceManagerError::NotReplaceable(nonce))?;
+        let cancellation = self.cancellation(&original);
+        self.replace(
+            nonce,
+            &original,
+            cancellation,
+            attempt,
+            &mut Market::default(),
+        )
+        .await
+    }
+
+    /// An empty transfer to the managed address, of the same type and chain as `original`
+    pub(crate) fn cancellation(&self, original: &TypedTransaction) -> TypedTransaction {
+        let mut cancellation: TypedTransaction = match original {
+            TypedTransaction::Eip1559(_) => Eip1559TransactionRequest::new()
+                .from(self.address)
+                .to(self.address)
+                .value(0)
+                .gas(TRANSFER_GAS)
+                .into(),
+            TypedTransaction::Eip2930(_) => Eip2930TransactionRequest::new(
+                TransactionRequest::new()
+                    .from(self.address)
+                    .to(self.address)
+                    .value(0)
+                    .gas(TRANSFER_GAS),
+                AccessList::default(),
+            )
+            .into(),
+            _ => TransactionRequest::new()
+                .from(self.address)
+                .to(self.address)
+                .value(0)
+                .gas(TRANSFER_GAS)
+                .into(),
+        };
+        if let Some(chain_id) = original.chain_id() {
+            cancellation.set_chain_id(chain_id);
+        }
+        cancellation
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, SENDER};
+    use crate::{Nonce, SimulatedChain};
+    use ethers::providers::Middleware;
+    use ethers::types::{
+        transaction::eip2718::TypedTransaction, Address, Eip1559TransactionRequest, NameOrAddress,
+        U256,
+    };
+
+    #[test]
+    fn cancellations_keep_the_type_and_chain_of_the_original() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain);
+        let original = Eip1559TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .value(5)
+            .chain_id(5)
+            .into();
+
+        let cancellation = client.cancellation(&original);
+        assert!(matches!(cancellation, TypedTransaction::Eip1559(_)));
+        assert_eq!(cancellation.to(), Some(&NameOrAddress::Address(SENDER)));
+        assert_eq!(cancellation.value(), Some(&U256::zero()));
+        assert_eq!(cancellation.chain_id(), Some(5.into()));
+    }
+
+    #[tokio::test]
+    async fn cancelled_transactions_are_replaced_by_an_empty_transfer_to_self() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain);
+        let original = send(&client).await;
+
+        let tx_hash = client.cancel(Nonce::zero()).await.unwrap().tx_hash();
+        assert_ne!(tx_hash, original);
+        chain.mine();
+        let mined = client.get_transaction(tx_hash).await.unwrap().unwrap();
+        assert_eq!(mined.to, Some(SENDER));
+        assert_eq!(mined.value, U256::zero());
+        assert!(client.get_transaction(original).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use std::{sync::Mutex, time::Instant};
+
+#[derive(Debug, Default)]
+/// When the chain id of the provider was last found to match the expected one
+pub(crate) struct ChainIdCheck {
+    verified_at: Mutex<Option<Instant>>,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Refuses to assign nonces unless the provider reports `chain_id`, failing with
+    /// [`NonceManagerError::ChainIdMismatch`] otherwise, e.g. when a misconfigured URL points
+    /// at a testnet. The chain id is checked before the counter is initialized, and again
+    /// every [`crate::NonceManagerConfig::chain_id_recheck_secs`] as nonces are assigned.
+    pub fn with_expected_chain_id(mut self, chain_id: u64) -> Self {
+        self.config_mut().expected_chain_id = Some(chain_id);
+        self
+    }
+
+    /// Asks the provider for its chain id and checks it against the expected one, if
+    /// configured
+    pub async fn verify_chain_id(&self) -> Result<(), NonceManagerError<M>> {
+        let expected = match self.config().expected_chain_id {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let actual = self.inner.get_chainid().await.map_err(FromErr::from)?;
+        if actual != expected.into() {
+            // stays unverified, so every assignment checks again until the provider is fixed
+            *self.chain_id_check.verified_at() = None;
+            return Err(NonceManagerError::ChainIdMismatch { expected, actual });
+        }
+        *self.chain_id_check.verified_at() = Some(self.now());
+        Ok(())
+    }
+
+    /// Verifies the chain id if it never was, or the last check is older than the recheck
+    /// interval
+    pub(crate) async fn check_chain_id(&self) -> Result<(), NonceManagerError<M>> {
+        let config = self.config();
+        if config.expected_chain_id.is_none() || config.air_gapped {
+            return Ok(());
+        }
+        let recheck = std::time::Duration::from_secs(config.chain_id_recheck_secs);
+        let due = self
+            .chain_id_check
+            .verified_at()
+            .is_none_or(|verified_at| self.elapsed(verified_at) >= recheck);
+        if due {
+            self.verify_chain_id().await?;
+        }
+        Ok(())
+    }
+}
+
+impl ChainIdCheck {
+    fn verified_at(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.verified_at.lock().expect("chain id lock poisoned")
+    }
+}
@@ -0,0 +1,23 @@
+use std::fmt::Debug;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+/// Thrown when persisted state can't be encrypted or decrypted
+pub enum CipherError {
+    /// The data was tampered with, truncated or encrypted under another key
+    #[error("failed to decrypt persisted state")]
+    Decrypt,
+    /// The cipher refused to encrypt the data
+    #[error("failed to encrypt persisted state")]
+    Encrypt,
+}
+
+/// Encrypts the state a persistent store writes, so that nonce and journal files don't leak
+/// operational details on shared hosts. Implement it to plug in another scheme, e.g. age.
+pub trait StateCipher: Send + Sync + Debug {
+    /// Encrypts `plaintext` into a self-contained ciphertext
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError>;
+
+    /// Decrypts a ciphertext produced by [`StateCipher::encrypt`]
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError>;
+}
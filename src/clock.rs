@@ -0,0 +1,166 @@
+use crate::LockedNonceManagerMiddleware;
+use ethers::providers::Middleware;
+use futures_util::future::{self, BoxFuture};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Source of the time the manager measures ages and windows against: how long transactions
+/// have been in flight (stuck detection, SLAs, mine latency), the gas and spend budget
+/// windows and the receipt cache TTL. The manager's waits, e.g. retry backoffs, throttles,
+/// deadlines and polling intervals, sleep on it as well.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time, which ages and windows are measured from
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time, which block timestamps are compared against
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    /// Waits until `duration` elapsed by this clock, on the runtime's timer by default
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(crate::rt::sleep(duration))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The monotonic clock of the OS, used by default
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Debug)]
+struct MockTime {
+    now: Instant,
+    system_time: SystemTime,
+    /// Tasks sleeping until the clock is advanced
+    sleepers: Vec<Waker>,
+}
+
+#[derive(Debug, Clone)]
+/// A clock which only moves when told to, for exercising timeouts in tests without sleeping.
+/// Sleeps on it return once it was advanced past their end. Clones share the same time.
+pub struct MockClock {
+    time: Arc<Mutex<MockTime>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// Instantiates the clock at the current time
+    pub fn new() -> Self {
+        Self {
+            time: Arc::new(Mutex::new(MockTime {
+                now: Instant::now(),
+                system_time: SystemTime::now(),
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the clock forward by `duration`, waking the sleeps which ended
+    pub fn advance(&self, duration: Duration) {
+        let sleepers = {
+            let mut time = self.time.lock().expect("clock lock poisoned");
+            time.now += duration;
+            time.system_time += duration;
+            std::mem::take(&mut time.sleepers)
+        };
+        // the sleeps which haven't ended yet register again
+        for sleeper in sleepers {
+            sleeper.wake();
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.time.lock().expect("clock lock poisoned").now
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time.lock().expect("clock lock poisoned").system_time
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let time = self.time.clone();
+        let end = self.now() + duration;
+        Box::pin(future::poll_fn(move |cx| {
+            let mut time = time.lock().expect("clock lock poisoned");
+            if time.now >= end {
+                return Poll::Ready(());
+            }
+            if !time
+                .sleepers
+                .iter()
+                .any(|sleeper| sleeper.will_wake(cx.waker()))
+            {
+                time.sleepers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }))
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Measures time with `clock` instead of the [`SystemClock`], e.g. a [`MockClock`] in tests
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Seconds since the epoch by the manager's clock, comparable to block timestamps
+    pub(crate) fn unix_secs(&self) -> u64 {
+        self.clock
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    }
+
+    /// Time elapsed since `earlier` by the manager's clock
+    pub(crate) fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+
+    /// Waits for `duration` to elapse by the manager's clock
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        self.clock.sleep(duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn mock_sleeps_end_once_the_clock_is_advanced() {
+        let clock = MockClock::new();
+        let mut sleep = clock.sleep(Duration::from_secs(10));
+        assert!((&mut sleep).now_or_never().is_none());
+        clock.advance(Duration::from_secs(9));
+        assert!((&mut sleep).now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(sleep.now_or_never().is_some());
+        assert!(clock.sleep(Duration::ZERO).now_or_never().is_some());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::LockedNonceManagerMiddleware;
+use ethers::providers::Middleware;
+use serde::{Deserialize, Serialize};
+use std::{sync::Mutex, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Bounds of the window of transactions pending ahead of the confirmed chain nonce which
+/// [`LockedNonceManagerMiddleware::with_adaptive_concurrency`] adjusts. The window grows by
+/// one with every send the inner middleware answered within `slow_send_ms`, and halves with
+/// every one which took longer or failed to reach the provider or was rate limited by it,
+/// never leaving `min..=max`. Sends the node rejected, e.g. for a low fee, don't shrink it.
+pub struct AdaptiveConcurrency {
+    /// Smallest window, which the window starts at, at least 1
+    pub min: u64,
+    /// Largest window, at least `min`
+    pub max: u64,
+    /// Latency of a send request above which the window shrinks, in milliseconds
+    pub slow_send_ms: u64,
+}
+
+impl AdaptiveConcurrency {
+    /// A window between `min` and `max`, shrinking on sends slower than 2 seconds
+    pub fn new(min: u64, max: u64) -> Self {
+        Self {
+            min,
+            max,
+            slow_send_ms: 2_000,
+        }
+    }
+
+    /// These bounds with `min` raised to 1 and `max` to `min`, as a window of 0 would never
+    /// let a send through
+    fn validated(self) -> Self {
+        let min = self.min.max(1);
+        Self {
+            min,
+            max: self.max.max(min),
+            ..self
+        }
+    }
+
+    /// The window after a send which took `latency` and `overloaded` the provider or not
+    fn next(&self, window: u64, latency: Duration, overloaded: bool) -> u64 {
+        let window = if overloaded || latency > Duration::from_millis(self.slow_send_ms) {
+            window / 2
+        } else {
+            window.saturating_add(1)
+        };
+        window.clamp(self.min, self.max)
+    }
+}
+
+#[derive(Debug, Default)]
+/// The current window, `None` until the first send was seen
+pub(crate) struct ConcurrencyWindow {
+    window: Mutex<Option<u64>>,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Adjusts how many managed transactions may be pending ahead of the confirmed chain
+    /// nonce at once, additive increase and multiplicative decrease style, so that the
+    /// manager backs off a flaky or overloaded provider and speeds back up once it recovers.
+    /// Sends over the window wait for the chain to catch up, as with
+    /// [`crate::PendingAheadLimit::wait`], which still applies on top of it. A `min` of 0 is
+    /// raised to 1 and a `max` below `min` to `min`.
+    pub fn with_adaptive_concurrency(mut self, bounds: AdaptiveConcurrency) -> Self {
+        self.config_mut().adaptive_concurrency = Some(bounds.validated());
+        self
+    }
+
+    /// The current window of [`Self::with_adaptive_concurrency`], `None` if it isn't enabled
+    pub fn concurrency_window(&self) -> Option<u64> {
+        let bounds = self.config().adaptive_concurrency?.validated();
+        let window = *self
+            .concurrency
+            .window
+            .lock()
+            .expect("concurrency window lock poisoned");
+        Some(window.unwrap_or(bounds.min))
+    }
+
+    /// Adjusts the window after a send to the inner middleware which took `latency` and
+    /// found the provider `overloaded` or not
+    pub(crate) fn adjust_concurrency(&self, latency: Duration, overloaded: bool) {
+        let bounds = match self.config().adaptive_concurrency {
+            Some(bounds) => bounds.validated(),
+            None => return,
+        };
+        let mut window = self
+            .concurrency
+            .window
+            .lock()
+            .expect("concurrency window lock poisoned");
+        let current = window.unwrap_or(bounds.min);
+        *window = Some(bounds.next(current, latency, overloaded));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn grows_by_one_and_halves() {
+        let bounds = AdaptiveConcurrency::new(2, 16);
+        assert_eq!(bounds.next(8, FAST, false), 9);
+        assert_eq!(bounds.next(8, FAST, true), 4);
+        assert_eq!(bounds.next(8, Duration::from_secs(3), false), 4);
+        assert_eq!(bounds.next(16, FAST, false), 16);
+        assert_eq!(bounds.next(3, FAST, true), 2);
+    }
+
+    #[test]
+    fn degenerate_bounds_are_raised() {
+        let bounds = AdaptiveConcurrency::new(0, 0).validated();
+        assert_eq!((bounds.min, bounds.max), (1, 1));
+        assert_eq!(bounds.next(1, FAST, true), 1);
+        let bounds = AdaptiveConcurrency::new(8, 4).validated();
+        assert_eq!((bounds.min, bounds.max), (8, 8));
+    }
+}
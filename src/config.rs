@@ -0,0 +1,447 @@
+use crate::{
+    AdaptiveConcurrency, DriftThrottle, EscalationSchedule, GasBudget, LeftoverPolicy,
+    LockedNonceManagerMiddleware, MulticallBatching, Nonce, PendingAheadLimit, ReceiptCacheConfig,
+    Sla, SpendLimit,
+};
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockNumber};
+use serde::{Deserialize, Serialize};
+use std::{num::NonZeroUsize, time::Duration};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Declarative configuration of a [`LockedNonceManagerMiddleware`], deserializable from any
+/// serde format (TOML, JSON, environment variables via `envy`, ...). Everything but the
+/// address can be changed at runtime through
+/// [`LockedNonceManagerMiddleware::reconfigure`].
+///
+/// ```toml
+/// address = "0x..."
+/// block_tag = "pending"
+/// max_txs_per_block = 16
+/// queue_when_offline = true
+/// expected_chain_id = 1
+/// preset_nonce = "record"
+/// backward_nonce = { ignore_within = 4 }
+/// leftovers = "cancel"
+/// checkpoint_every = 64
+/// write_ahead = true
+/// private_max_blocks = 25
+///
+/// [retry]
+/// max_conflict_retries = 3
+/// backoff_ms = 250
+///
+/// [gas_budget]
+/// limit = "0x1dcd6500"
+/// window_secs = 3600
+/// unit = "fee"
+///
+/// [spend_limit]
+/// limit = "0xde0b6b3a7640000"
+/// window_secs = 86400
+///
+/// [[chain_profiles]]
+/// chain_id = 10
+/// price_bump_percent = 25
+///
+/// [max_pending_ahead]
+/// max = 64
+/// wait = true
+///
+/// [drift_throttle]
+/// free = 8
+/// step_ms = 50
+/// max_delay_ms = 2000
+///
+/// [adaptive_concurrency]
+/// min = 4
+/// max = 64
+/// slow_send_ms = 2000
+///
+/// [sla]
+/// max_blocks = 5
+/// auto_bump = true
+///
+/// [bump_schedule]
+/// step_bps = 1250
+/// every_blocks = 3
+/// max_fee_per_gas = "0x174876e800"
+/// ```
+pub struct NonceManagerConfig {
+    /// The address transactions are sent from
+    pub address: Address,
+    /// Block used for nonce lookups and sends when the caller passes `None`
+    #[serde(default)]
+    pub block_tag: Option<BlockNumber>,
+    /// How sends recover when the chain nonce raced ahead of the local counter
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Maximum number of managed transactions broadcast per block
+    #[serde(default)]
+    pub max_txs_per_block: Option<NonZeroUsize>,
+    /// Cap on the gas or fees managed transactions may consume per window
+    #[serde(default)]
+    pub gas_budget: Option<GasBudget>,
+    /// Cap on the value managed transactions may transfer per window
+    #[serde(default)]
+    pub spend_limit: Option<SpendLimit>,
+    /// Whether sends are signed and queued locally while the RPC is down
+    #[serde(default)]
+    pub queue_when_offline: bool,
+    /// Whether the manager refuses to touch the RPC, only handing out nonces from a counter
+    /// seeded with [`LockedNonceManagerMiddleware::with_initial_nonce`]
+    #[serde(default)]
+    pub air_gapped: bool,
+    /// Nonce the counter starts at instead of the chain's, if it isn't initialized yet
+    #[serde(default)]
+    pub initial_nonce: Option<Nonce>,
+    /// Whether transactions are signed one at a time, e.g. on a hardware wallet
+    #[serde(default)]
+    pub sequential_signing: bool,
+    /// Whether gas and fees are filled with `eth_fillTransaction` on nodes supporting it
+    #[serde(default)]
+    pub use_fill_transaction: bool,
+    /// Whether transactions are simulated with `eth_call` before they are broadcast
+    #[serde(default)]
+    pub simulate: bool,
+    /// Whether the balance is checked against a send's value and gas before it is assigned
+    /// a nonce
+    #[serde(default)]
+    pub check_balance: bool,
+    /// How far assigned nonces may run ahead of the confirmed chain nonce
+    #[serde(default)]
+    pub max_pending_ahead: Option<PendingAheadLimit>,
+    /// How nonce assignment slows down as it runs ahead of the confirmed chain nonce
+    #[serde(default)]
+    pub drift_throttle: Option<DriftThrottle>,
+    /// Bounds of the adaptive window of transactions pending ahead of the chain
+    #[serde(default)]
+    pub adaptive_concurrency: Option<AdaptiveConcurrency>,
+    /// How replacements of stuck transactions escalate their fees, overriding the bump
+    /// policy, see [`LockedNonceManagerMiddleware::with_bump_schedule`]
+    #[serde(default)]
+    pub bump_schedule: Option<EscalationSchedule>,
+    /// Percentage by which node pools require a replacement to raise each fee
+    #[serde(default = "default_price_bump_percent")]
+    pub price_bump_percent: u64,
+    /// Settings of specific chains, taking precedence over the ones above on them
+    #[serde(default)]
+    pub chain_profiles: Vec<ChainProfile>,
+    /// Number of blocks a transaction has to be buried under before its nonce is final
+    #[serde(default = "default_confirmations")]
+    pub confirmations: usize,
+    /// Bounds of the cache of fetched receipts, none are cached if unset
+    #[serde(default)]
+    pub receipt_cache: Option<ReceiptCacheConfig>,
+    /// How quickly pending transactions are expected to be mined
+    #[serde(default)]
+    pub sla: Option<Sla>,
+    /// Batching of calls into Multicall3 transactions, calls are sent on their own if unset
+    #[serde(default)]
+    pub multicall_batching: Option<MulticallBatching>,
+    /// Whether concurrent sends are assigned nonces by their fees rather than their arrival
+    #[serde(default)]
+    pub fee_ordering: bool,
+    /// Whether concurrent sends and fills are assigned nonces in the order they were called
+    #[serde(default)]
+    pub fifo_ordering: bool,
+    /// What a shutdown does with transactions unconfirmed at its deadline
+    #[serde(default)]
+    pub leftovers: LeftoverPolicy,
+    /// Number of broadcasts between two snapshots saved to the state store, with the
+    /// broadcasts in between journaled. Only explicit persists save anything if unset.
+    #[serde(default)]
+    pub checkpoint_every: Option<NonZeroUsize>,
+    /// Whether every managed transaction is journaled before it is broadcast
+    #[serde(default)]
+    pub write_ahead: bool,
+    /// Whether nonces are left to the inner middleware, only compared with the manager's
+    #[serde(default)]
+    pub shadow: bool,
+    /// Number of blocks a private relay tries to include a transaction in before dropping it
+    #[serde(default = "default_private_max_blocks")]
+    pub private_max_blocks: u64,
+    /// What happens when the chain nonce is found below the counter
+    #[serde(default)]
+    pub backward_nonce: BackwardNoncePolicy,
+    /// What happens to transactions whose nonce the caller already set
+    #[serde(default)]
+    pub preset_nonce: PresetNoncePolicy,
+    /// Chain id the provider has to report before nonces are assigned, unchecked if unset
+    #[serde(default)]
+    pub expected_chain_id: Option<u64>,
+    /// How often the expected chain id is checked again, in seconds
+    #[serde(default = "default_chain_id_recheck_secs")]
+    pub chain_id_recheck_secs: u64,
+}
+
+fn default_price_bump_percent() -> u64 {
+    10
+}
+
+fn default_confirmations() -> usize {
+    1
+}
+
+fn default_private_max_blocks() -> u64 {
+    25
+}
+
+fn default_chain_id_recheck_secs() -> u64 {
+    300
+}
+
+impl NonceManagerConfig {
+    /// Default configuration for `address`
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            block_tag: None,
+            retry: RetryPolicy::default(),
+            max_txs_per_block: None,
+            gas_budget: None,
+            spend_limit: None,
+            queue_when_offline: false,
+            air_gapped: false,
+            initial_nonce: None,
+            sequential_signing: false,
+            use_fill_transaction: false,
+            simulate: false,
+            check_balance: false,
+            max_pending_ahead: None,
+            drift_throttle: None,
+            adaptive_concurrency: None,
+            bump_schedule: None,
+            price_bump_percent: default_price_bump_percent(),
+            chain_profiles: Vec::new(),
+            confirmations: default_confirmations(),
+            receipt_cache: None,
+            sla: None,
+            multicall_batching: None,
+            fee_ordering: false,
+            fifo_ordering: false,
+            backward_nonce: BackwardNoncePolicy::default(),
+            leftovers: LeftoverPolicy::default(),
+            checkpoint_every: None,
+            write_ahead: false,
+            shadow: false,
+            private_max_blocks: default_private_max_blocks(),
+            preset_nonce: PresetNoncePolicy::default(),
+            expected_chain_id: None,
+            chain_id_recheck_secs: default_chain_id_recheck_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Settings which differ between chains, for a manager whose transactions aren't all on the
+/// chain the defaults were picked for. A transaction's chain is the chain id it is signed
+/// for, or else the expected chain id.
+pub struct ChainProfile {
+    /// The chain the profile applies to
+    pub chain_id: u64,
+    /// Percentage by which the chain's node pools require a replacement to raise each fee,
+    /// e.g. more than geth's 10% on some L2 sequencers
+    pub price_bump_percent: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What the manager does with transactions whose nonce the caller already set, when they are
+/// sent or filled through it
+pub enum PresetNoncePolicy {
+    /// Passes them through, leaving the counter untouched
+    #[default]
+    Skip,
+    /// Refuses nonces which are reserved or already have a managed transaction in flight,
+    /// and moves the counter past the nonce once the transaction was broadcast, like
+    /// [`LockedNonceManagerMiddleware::send_transaction_with_nonce`]. Filling a transaction
+    /// only checks its nonce, since it may never be broadcast.
+    Record,
+    /// Fails them with [`crate::NonceManagerError::PresetNonceRejected`]
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What the manager does when a rejected send or a [`resync`](LockedNonceManagerMiddleware::resync)
+/// finds the chain's pending transaction count below the counter. After a reorg or a dev node
+/// snapshot revert the chain really went back, while an RPC behind a stale replica only looks
+/// like it did, so the right answer depends on the deployment.
+pub enum BackwardNoncePolicy {
+    /// Keeps the counter, waiting for the chain to catch up
+    #[default]
+    Keep,
+    /// Moves the counter back to the chain nonce as long as the node doesn't know any of the
+    /// transactions in flight at or above it, forgetting them, and retries a rejected send
+    /// there
+    Adopt,
+    /// Keeps the counter while the chain is at most this many nonces behind, as a stale
+    /// replica would be, and fails with [`crate::NonceManagerError::ChainNonceBehind`] beyond
+    /// that
+    IgnoreWithin(u64),
+    /// Fails with [`crate::NonceManagerError::ChainNonceBehind`]
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+/// Controls how often a rejected send is retried after the chain reported a higher nonce
+/// than the local counter
+pub struct RetryPolicy {
+    /// Maximum number of resends at the chain's nonce, 0 disables recovery
+    pub max_conflict_retries: usize,
+    /// Delay before each resend, in milliseconds
+    pub backoff_ms: u64,
+}
+
+impl RetryPolicy {
+    /// The delay before each resend
+    pub fn backoff(&self) -> Duration {
+        Duration::from_millis(self.backoff_ms)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_conflict_retries: 1,
+            backoff_ms: 0,
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Instantiates the nonce manager from a [`NonceManagerConfig`]
+    pub fn from_config(inner: M, config: NonceManagerConfig) -> Self {
+        let mut manager = Self::new(inner, config.address);
+        *manager.config_mut() = config;
+        manager
+    }
+
+    /// Sets the block used for nonce lookups and sends when the caller passes `None`
+    pub fn with_block_tag(mut self, block_tag: Option<BlockNumber>) -> Self {
+        self.config_mut().block_tag = block_tag;
+        self
+    }
+
+    /// Sets how sends recover from nonce conflicts
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.config_mut().retry = retry;
+        self
+    }
+
+    /// Sets what happens to transactions whose nonce the caller already set, see
+    /// [`PresetNoncePolicy`]
+    pub fn with_preset_nonce_policy(mut self, policy: PresetNoncePolicy) -> Self {
+        self.config_mut().preset_nonce = policy;
+        self
+    }
+
+    /// Applies `profile` to transactions on its chain, replacing an earlier profile of the
+    /// same chain
+    pub fn with_chain_profile(mut self, profile: ChainProfile) -> Self {
+        let profiles = &mut self.config_mut().chain_profiles;
+        profiles.retain(|existing| existing.chain_id != profile.chain_id);
+        profiles.push(profile);
+        self
+    }
+
+    /// The configuration currently in effect
+    pub fn config(&self) -> NonceManagerConfig {
+        self.config.read().expect("config lock poisoned").clone()
+    }
+
+    /// Replaces the configuration without restarting, e.g. to loosen a gas budget during
+    /// congestion. Sends already past a check keep the settings they started with, later ones
+    /// use the new configuration. The amounts spent in the current budget and spend limit
+    /// windows are carried over. The managed address can't be changed, so
+    /// `config.address` is ignored.
+    pub fn reconfigure(&self, config: NonceManagerConfig) {
+        let mut current = self.config.write().expect("config lock poisoned");
+        *current = NonceManagerConfig {
+            address: current.address,
+            ..config
+        };
+    }
+
+    pub(crate) fn config_mut(&mut self) -> &mut NonceManagerConfig {
+        self.config.get_mut().expect("config lock poisoned")
+    }
+}
+
+/// (De)serializes a [`Duration`] as a whole number of seconds
+pub(crate) mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        let config: NonceManagerConfig =
+            serde_json::from_value(json!({ "address": Address::zero() })).unwrap();
+        assert_eq!(config, NonceManagerConfig::new(Address::zero()));
+    }
+
+    #[test]
+    fn round_trips() {
+        let config: NonceManagerConfig = serde_json::from_value(json!({
+            "address": Address::repeat_byte(1),
+            "block_tag": "pending",
+            "max_txs_per_block": 16,
+            "queue_when_offline": true,
+            "expected_chain_id": 1,
+            "preset_nonce": "record",
+            "backward_nonce": { "ignore_within": 4 },
+            "leftovers": "cancel",
+            "checkpoint_every": 64,
+            "write_ahead": true,
+            "private_max_blocks": 25,
+            "retry": { "max_conflict_retries": 3, "backoff_ms": 250 },
+            "gas_budget": { "limit": "0x1dcd6500", "window_secs": 3600, "unit": "fee" },
+            "spend_limit": { "limit": "0xde0b6b3a7640000", "window_secs": 86400 },
+            "chain_profiles": [{ "chain_id": 10, "price_bump_percent": 25 }],
+            "max_pending_ahead": { "max": 64, "wait": true },
+            "drift_throttle": { "free": 8, "step_ms": 50, "max_delay_ms": 2000 },
+            "adaptive_concurrency": { "min": 4, "max": 64, "slow_send_ms": 2000 },
+            "sla": { "max_blocks": 5, "auto_bump": true },
+            "bump_schedule": {
+                "step_bps": 1250,
+                "every_blocks": 3,
+                "max_fee_per_gas": "0x174876e800"
+            },
+        }))
+        .unwrap();
+        assert_eq!(config.block_tag, Some(BlockNumber::Pending));
+        assert_eq!(config.backward_nonce, BackwardNoncePolicy::IgnoreWithin(4));
+        assert_eq!(config.preset_nonce, PresetNoncePolicy::Record);
+        assert_eq!(config.retry.backoff(), Duration::from_millis(250));
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<NonceManagerConfig>(&json).unwrap(),
+            config
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceEvent};
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, Bytes};
+use ethers::utils::rlp::Rlp;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Whether the chain's `chain_nonce` raced ahead of the local `nonce`, which a failed fill
+    /// or broadcast at `nonce` is then put down to. Conflicts are reported to the observers.
+    pub(crate) fn is_conflict(&self, nonce: Nonce, chain_nonce: Nonce) -> bool {
+        if chain_nonce <= nonce {
+            return false;
+        }
+        self.emit(NonceEvent::Conflict {
+            address: self.address,
+            local_nonce: nonce,
+            chain_nonce,
+        });
+        true
+    }
+
+    /// Decodes a raw transaction signed by the managed address, with its nonce.
+    pub(crate) fn decode_own(&self, raw_tx: &Bytes) -> Option<(Nonce, TypedTransaction)> {
+        let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(raw_tx)).ok()?;
+        let nonce = Nonce::new(*tx.nonce()?).ok()?;
+        if signature.recover(tx.sighash()).ok() != Some(self.address) {
+            return None;
+        }
+        Some((nonce, tx))
+    }
+
+    /// After the signed `raw_tx` was rejected, moves the counter up to the chain's nonce if
+    /// the transaction is from the managed address and its nonce was already used. A signed
+    /// transaction can't be refilled, but the caller's next fill then gets a fresh nonce.
+    pub(crate) async fn recover_raw_conflict(&self, raw_tx: &Bytes) {
+        let nonce = match self.decode_own(raw_tx) {
+            Some((nonce, _)) => nonce,
+            None => return,
+        };
+        let chain_nonce = match self.pending_chain_nonce().await {
+            Ok(chain_nonce) => chain_nonce,
+            Err(_) => return,
+        };
+        if !self.is_conflict(nonce, chain_nonce) {
+            return;
+        }
+        match self.state.nonce().try_write() {
+            Ok(mut counter) => {
+                if *counter < chain_nonce {
+                    *counter = chain_nonce;
+                }
+            }
+            // the counter is held, e.g. by `with_nonce` around this very send, so it catches
+            // up before the next assignment instead
+            Err(_) => self.state.mark_needs_resync(),
+        }
+    }
+}
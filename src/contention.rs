@@ -0,0 +1,147 @@
+use crate::sync::{AtomicU64, Ordering, RwLockWriteGuard};
+use crate::{Histogram, LockedNonceManagerMiddleware, Nonce};
+use ethers::providers::Middleware;
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+
+/// Upper bounds of the histogram buckets, from an uncontended lock up to waiting behind a
+/// send stuck on a slow RPC
+const BUCKET_BOUNDS: [Duration; 11] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// How contended the nonce lock is, see [`LockedNonceManagerMiddleware::lock_contention`]
+pub struct LockContention {
+    /// Assignments currently waiting for the nonce lock
+    pub waiting: u64,
+    /// How long assignments waited for the nonce lock
+    pub wait: Histogram,
+}
+
+#[derive(Debug)]
+pub(crate) struct ContentionRecorder {
+    waiting: AtomicU64,
+    histogram: Mutex<Histogram>,
+}
+
+impl Default for ContentionRecorder {
+    fn default() -> Self {
+        Self {
+            waiting: AtomicU64::new(0),
+            histogram: Mutex::new(Histogram::new(&BUCKET_BOUNDS)),
+        }
+    }
+}
+
+/// Counts an assignment as waiting for the lock until dropped, so that cancelled waits are
+/// let go of as well
+struct Waiting<'a>(&'a AtomicU64);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// How many calls through this middleware are waiting for the nonce lock, e.g. to assign
+    /// a nonce, release a reservation or take a snapshot, and how long they have been waiting
+    /// for it. Sends hold the lock until the node
+    /// accepted their transaction, so long waits mean throughput is bound by the RPC's
+    /// round-trip rather than the load.
+    pub fn lock_contention(&self) -> LockContention {
+        LockContention {
+            waiting: self.contention.waiting.load(Ordering::SeqCst),
+            wait: self
+                .contention
+                .histogram
+                .lock()
+                .expect("lock wait histogram lock poisoned")
+                .clone(),
+        }
+    }
+
+    /// Takes the nonce lock, recording how long that took. Everything which takes it goes
+    /// through here, so the wait shows in [`Self::lock_contention`].
+    pub(crate) async fn lock_nonce(&self) -> RwLockWriteGuard<'_, Nonce> {
+        self.contention.waiting.fetch_add(1, Ordering::SeqCst);
+        let waiting = Waiting(&self.contention.waiting);
+        let started = self.now();
+        let guard = self.state.nonce().write().await;
+        let wait = self.elapsed(started);
+        drop(waiting);
+        self.contention
+            .histogram
+            .lock()
+            .expect("lock wait histogram lock poisoned")
+            .record(wait);
+        guard
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send};
+    use crate::{Nonce, SimulatedChain};
+    use futures_util::FutureExt;
+
+    #[tokio::test]
+    async fn waits_are_counted_until_they_end() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain);
+        send(&client).await;
+        let recorded = client.lock_contention().wait.count;
+        assert!(recorded > 0);
+
+        let held = client.lock_nonce().await;
+        let mut waiting = Box::pin(client.lock_nonce());
+        assert!((&mut waiting).now_or_never().is_none());
+        assert_eq!(client.lock_contention().waiting, 1);
+        // a cancelled wait stops counting without being recorded
+        drop(waiting);
+        assert_eq!(client.lock_contention().waiting, 0);
+        drop(held);
+
+        let contention = client.lock_contention();
+        assert_eq!(contention.wait.count, recorded + 1);
+        assert_eq!(
+            contention.wait.counts.iter().sum::<u64>(),
+            contention.wait.count
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_outside_of_sends_are_counted() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain);
+        send(&client).await;
+        let recorded = client.lock_contention().wait.count;
+
+        let held = client.lock_nonce().await;
+        let mut snapshot = Box::pin(client.snapshot());
+        assert!((&mut snapshot).now_or_never().is_none());
+        let mut release = Box::pin(client.release_reservation(Nonce::zero()));
+        assert!((&mut release).now_or_never().is_none());
+        assert_eq!(client.lock_contention().waiting, 2);
+        drop(held);
+
+        let snapshot = snapshot.await;
+        release.await;
+        client.restore(snapshot).await.unwrap();
+        assert_eq!(client.lock_contention().wait.count, recorded + 3);
+    }
+}
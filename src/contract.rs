@@ -0,0 +1,22 @@
+use crate::NonceManagerError;
+use ethers::abi::{self, ParamType};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, Address, TransactionRequest, U256};
+
+/// Calls `to` with `data` at the latest block and decodes the single `uint256` it returns
+pub(crate) async fn call_uint<M: Middleware>(
+    inner: &M,
+    to: Address,
+    data: Vec<u8>,
+) -> Result<U256, NonceManagerError<M>> {
+    let tx: TypedTransaction = TransactionRequest::new().to(to).data(data).into();
+
+    let output = inner.call(&tx, None).await.map_err(FromErr::from)?;
+    let value = abi::decode(&[ParamType::Uint(256)], &output)
+        .map_err(NonceManagerError::AbiError)?
+        .remove(0)
+        .into_uint()
+        .expect("decoded as uint256");
+
+    Ok(value)
+}
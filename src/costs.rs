@@ -0,0 +1,130 @@
+use crate::{LockedNonceManagerMiddleware, Nonce};
+use ethers::providers::Middleware;
+use ethers::types::{TransactionReceipt, U256};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// On-chain costs of the confirmed transactions sent with one label
+pub struct LabelCost {
+    /// The label the transactions were sent with, `None` for unlabeled ones
+    pub label: Option<Value>,
+    /// Number of confirmed transactions
+    pub transactions: u64,
+    /// Gas used by the transactions, according to their receipts
+    pub gas_used: U256,
+    /// Fees paid for the gas used, in wei
+    pub fees_paid: U256,
+}
+
+#[derive(Debug, Default)]
+/// Costs of confirmed transactions per label. Labels are keyed by their JSON encoding, which
+/// orders object keys, so equal labels always land on the same entry.
+pub(crate) struct CostLedger {
+    entries: Mutex<BTreeMap<Option<String>, LabelCost>>,
+}
+
+impl CostLedger {
+    fn record(&self, label: Option<Value>, gas_used: U256, fee: U256) {
+        let key = label.as_ref().map(Value::to_string);
+        let mut entries = self.entries.lock().expect("cost ledger lock poisoned");
+        let cost = entries.entry(key).or_insert_with(|| LabelCost {
+            label,
+            transactions: 0,
+            gas_used: U256::zero(),
+            fees_paid: U256::zero(),
+        });
+        cost.transactions += 1;
+        cost.gas_used = cost.gas_used.saturating_add(gas_used);
+        cost.fees_paid = cost.fees_paid.saturating_add(gas_used.saturating_mul(fee));
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Gas used and fees paid by the confirmed transactions, per label they were sent with,
+    /// see [`Self::send_labeled_transaction`]. Unlabeled transactions are summed up under a
+    /// `None` label. Only transactions seen confirmed by [`Self::poll_in_flight`] or
+    /// [`Self::reconcile`] are accounted for.
+    pub fn cost_report(&self) -> Vec<LabelCost> {
+        self.costs
+            .entries
+            .lock()
+            .expect("cost ledger lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// The costs accounted to `label` so far
+    pub fn cost_of(&self, label: &Value) -> Option<LabelCost> {
+        self.costs
+            .entries
+            .lock()
+            .expect("cost ledger lock poisoned")
+            .get(&Some(label.to_string()))
+            .cloned()
+    }
+
+    /// Adds the confirmed transaction at `nonce` to the costs of its label. Must be called
+    /// before the transaction stops being tracked.
+    pub(crate) fn account_confirmed(&self, nonce: Nonce, receipt: &TransactionReceipt) {
+        let gas_used = match receipt.gas_used {
+            Some(gas_used) => gas_used,
+            None => return,
+        };
+        let (label, gas_price) = match self.state.in_flight().get(nonce) {
+            Some(tracked) => (
+                tracked.label.clone(),
+                tracked.tx.as_ref().and_then(|tx| tx.gas_price()),
+            ),
+            None => (None, None),
+        };
+        // nodes predating the london fork don't report the effective price
+        let fee = receipt
+            .effective_gas_price
+            .or(gas_price)
+            .unwrap_or_default();
+        self.costs.record(label, gas_used, fee);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn costs(ledger: &CostLedger) -> Vec<LabelCost> {
+        ledger.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    #[test]
+    fn costs_add_up_per_label() {
+        let ledger = CostLedger::default();
+        ledger.record(Some(json!("swap")), 21_000.into(), 2.into());
+        ledger.record(Some(json!("swap")), 50_000.into(), 3.into());
+        ledger.record(None, 21_000.into(), 1.into());
+
+        let costs = costs(&ledger);
+        assert_eq!(costs.len(), 2);
+        let unlabeled = costs.iter().find(|cost| cost.label.is_none()).unwrap();
+        assert_eq!(unlabeled.transactions, 1);
+        assert_eq!(unlabeled.fees_paid, 21_000.into());
+        let swap = costs.iter().find(|cost| cost.label.is_some()).unwrap();
+        assert_eq!(swap.transactions, 2);
+        assert_eq!(swap.gas_used, 71_000.into());
+        assert_eq!(swap.fees_paid, 192_000.into());
+    }
+
+    #[test]
+    fn equal_labels_share_an_entry_whatever_their_key_order() {
+        let ledger = CostLedger::default();
+        ledger.record(Some(json!({ "a": 1, "b": 2 })), 1.into(), 1.into());
+        ledger.record(Some(json!({ "b": 2, "a": 1 })), 1.into(), 1.into());
+        assert_eq!(costs(&ledger).len(), 1);
+        assert_eq!(costs(&ledger)[0].transactions, 2);
+    }
+}
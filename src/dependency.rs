@@ -0,0 +1,82 @@
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId};
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Schedules `tx` like [`Self::schedule_transaction`], declaring that it depends on the
+    /// transaction at `dependency`, e.g. a swap spending what an approval allowed. It is
+    /// assigned a later nonce, and its signed transaction stays scheduled until the one at
+    /// `dependency` was broadcast, though not necessarily mined, never going out in the same
+    /// batch as it. If the transaction at `dependency` never makes it, because its
+    /// reservation was released, its hold or private submission expired, or it was cancelled
+    /// or replaced, `tx` is given up rather than broadcast into a certain failure, along with
+    /// whatever depends on it in turn, which means cancelling it if it was broadcast already.
+    /// Fails with [`NonceManagerError::NotReserved`] if nothing is reserved or in flight at
+    /// `dependency`.
+    pub async fn schedule_dependent<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        dependency: Nonce,
+        block: Option<BlockId>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        let reserved = self.state.schedule().lock().await.is_reserved(dependency);
+        if !reserved && self.state.in_flight().get(dependency).is_none() {
+            return Err(NonceManagerError::NotReserved(dependency));
+        }
+        let nonce = self.schedule_transaction(tx, block).await?;
+        self.state.schedule().lock().await.depend(nonce, dependency);
+        Ok(nonce)
+    }
+
+    /// Gives up the transactions depending on the one at `nonce`, which won't make it, and
+    /// emits a [`NonceEvent::DependencyFailed`] for each. Scheduled ones are dropped: the
+    /// counter is rolled back over them if no later nonce was handed out, otherwise an empty
+    /// transfer takes their place as for an expired hold. Those already in flight are
+    /// cancelled.
+    pub(crate) async fn drop_dependents(&self, nonce: Nonce) {
+        let mut gaps = Vec::new();
+        let mut in_flight = Vec::new();
+        {
+            let mut counter = self.lock_nonce().await;
+            let mut schedule = self.state.schedule().lock().await;
+            // highest first, so a run of dependents at the top is rolled back as a whole
+            for dependent in schedule.take_dependents(nonce) {
+                let rolled_back = if schedule.unschedule(dependent) {
+                    let rolled_back = dependent.checked_increment() == Some(*counter);
+                    if rolled_back {
+                        schedule.release(dependent);
+                        *counter = dependent;
+                        self.journal(JournalEntry::Released {
+                            nonce: dependent,
+                            rolled_back,
+                        });
+                    } else {
+                        gaps.push(dependent);
+                    }
+                    rolled_back
+                } else {
+                    in_flight.push(dependent);
+                    false
+                };
+                self.emit(NonceEvent::DependencyFailed {
+                    address: self.address,
+                    nonce: dependent,
+                    dependency: nonce,
+                    rolled_back,
+                });
+            }
+        }
+
+        // a gap which can't be filled is released, the failure surfaces on the hold or
+        // private submission which took the dependency down
+        let _ = self.fill_gaps(gaps).await;
+        for dependent in in_flight {
+            // fails if it was mined in the meantime, in which case there is nothing to save
+            let _ = self.cancel_in_flight(dependent).await;
+        }
+        self.checkpoint_if_due().await;
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::BlockNumber;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Limit on how far the assigned nonces may run ahead of the confirmed chain nonce. Most
+/// execution clients silently drop transactions queued too far ahead of the account's
+/// mined transaction count.
+pub struct PendingAheadLimit {
+    /// Maximum distance between an assigned nonce and the mined transaction count
+    pub max: u64,
+    /// Whether sends over the limit wait for the chain to catch up instead of failing
+    #[serde(default)]
+    pub wait: bool,
+}
+
+impl PendingAheadLimit {
+    /// Fails sends over `max` with [`NonceManagerError::PendingTooFarAhead`]
+    pub fn fail(max: u64) -> Self {
+        Self { max, wait: false }
+    }
+
+    /// Holds sends over `max` until enough transactions were mined
+    pub fn wait(max: u64) -> Self {
+        Self { max, wait: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Slows down nonce assignment as the assigned nonces run ahead of the confirmed chain nonce,
+/// giving the mempool time to catch up while still making progress. Each nonce beyond
+/// `free` adds `step_ms` to the delay before assignment, up to `max_delay_ms`.
+pub struct DriftThrottle {
+    /// Distance from the mined transaction count which is assigned without delay
+    pub free: u64,
+    /// Delay added per nonce beyond `free`, in milliseconds
+    pub step_ms: u64,
+    /// Longest delay before a single assignment, in milliseconds
+    pub max_delay_ms: u64,
+}
+
+impl DriftThrottle {
+    /// The delay before assigning a nonce `drift` ahead of the mined transaction count
+    pub fn delay(&self, drift: u64) -> Duration {
+        let delay = drift
+            .saturating_sub(self.free)
+            .saturating_mul(self.step_ms)
+            .min(self.max_delay_ms);
+        Duration::from_millis(delay)
+    }
+}
+
+/// What the drift limits make of assigning a nonce
+pub(crate) enum Drift {
+    /// The nonce can be assigned right away
+    Within,
+    /// The nonce can be assigned once the throttle's delay passed
+    Throttled(Duration),
+    /// The nonce has to wait for the chain to catch up
+    Ahead,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Refuses to assign nonces more than `limit.max` ahead of the confirmed chain nonce,
+    /// either failing or waiting for the chain to catch up
+    pub fn with_max_pending_ahead(mut self, limit: PendingAheadLimit) -> Self {
+        self.config_mut().max_pending_ahead = Some(limit);
+        self
+    }
+
+    /// Delays nonce assignment more the further the assigned nonces run ahead of the
+    /// confirmed chain nonce, as a softer alternative or complement to
+    /// [`Self::with_max_pending_ahead`]
+    pub fn with_drift_throttle(mut self, throttle: DriftThrottle) -> Self {
+        self.config_mut().drift_throttle = Some(throttle);
+        self
+    }
+
+    /// The confirmed chain nonce the drift limits and the adaptive concurrency window are
+    /// checked against, read before the nonce lock is taken so that the request doesn't hold
+    /// up every other sender. `None` if there is nothing to check, or the chain can't be
+    /// asked, i.e. when air-gapped or queueing offline.
+    pub(crate) async fn confirmed_for_drift(&self) -> Result<Option<Nonce>, NonceManagerError<M>> {
+        let config = self.config();
+        if config.air_gapped
+            || self.state.is_offline()
+            || (config.max_pending_ahead.is_none()
+                && config.drift_throttle.is_none()
+                && config.adaptive_concurrency.is_none())
+        {
+            return Ok(None);
+        }
+        self.chain_nonce(Some(BlockNumber::Latest.into()))
+            .await
+            .map(Some)
+    }
+
+    /// Checks that `nonce` is within the configured distance of the `confirmed` chain nonce
+    /// and the adaptive concurrency window, and how long the throttle delays it. Only
+    /// compares, so that it can be called with the nonce lock held, while the caller waits
+    /// without it.
+    pub(crate) fn check_drift(
+        &self,
+        nonce: Nonce,
+        confirmed: Nonce,
+    ) -> Result<Drift, NonceManagerError<M>> {
+        let config = self.config();
+        let drift = nonce.as_u64().saturating_sub(confirmed.as_u64());
+        if self
+            .concurrency_window()
+            .is_some_and(|window| drift > window)
+        {
+            // the adaptive window only ever holds sends back
+            return Ok(Drift::Ahead);
+        }
+        match config.max_pending_ahead {
+            Some(limit) if drift > limit.max && limit.wait => Ok(Drift::Ahead),
+            Some(limit) if drift > limit.max => Err(NonceManagerError::PendingTooFarAhead {
+                nonce,
+                confirmed,
+                max: limit.max,
+            }),
+            _ => Ok(config
+                .drift_throttle
+                .map(|throttle| throttle.delay(drift))
+                .filter(|delay| !delay.is_zero())
+                .map_or(Drift::Within, Drift::Throttled)),
+        }
+    }
+
+    /// Checks like [`Self::check_drift`] that the caller-chosen `nonce` is within the
+    /// configured distance of the `confirmed` chain nonce, along with the nonces it skips.
+    /// Those are left to the caller to fill, so rather than waiting for the chain to catch up
+    /// it fails with [`NonceManagerError::PendingTooFarAhead`], and isn't throttled.
+    pub(crate) fn check_chosen_drift(
+        &self,
+        nonce: Nonce,
+        confirmed: Nonce,
+    ) -> Result<(), NonceManagerError<M>> {
+        match self.check_drift(nonce, confirmed)? {
+            Drift::Within | Drift::Throttled(_) => Ok(()),
+            Drift::Ahead => {
+                let limit = self.config().max_pending_ahead.map(|limit| limit.max);
+                let max = limit.into_iter().chain(self.concurrency_window()).min();
+                Err(NonceManagerError::PendingTooFarAhead {
+                    nonce,
+                    confirmed,
+                    max: max.unwrap_or_default(),
+                })
+            }
+        }
+    }
+}
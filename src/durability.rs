@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How hard the file backed stores, [`crate::FileStateStore`] and
+/// [`crate::FileIdempotencyStore`], push their writes to disk, trading throughput against
+/// what survives a crash. A crash of the process alone never loses writes; these only differ
+/// when the machine goes down.
+pub enum Durability {
+    /// Every write is synced before it returns, so nothing written is lost
+    #[default]
+    Always,
+    /// A write is synced if at least the given time passed since the last sync, and the
+    /// writes in between are synced along with it. Nothing syncs them in the background, so
+    /// a crash loses the writes since the last sync, which may go back further than that
+    /// time if no write came after them.
+    Periodic(Duration),
+    /// Writes are never synced and left for the OS to flush, so a crash loses whatever it
+    /// didn't get to
+    OsDefault,
+}
+
+#[derive(Debug, Default)]
+/// Syncs the writes of a store as its [`Durability`] requires
+pub(crate) struct Syncer {
+    durability: Durability,
+    last_sync: Mutex<Option<Instant>>,
+}
+
+impl Syncer {
+    pub(crate) fn new(durability: Durability) -> Self {
+        Self {
+            durability,
+            last_sync: Mutex::new(None),
+        }
+    }
+
+    /// Syncs the data written to `file`, if it is due
+    pub(crate) fn sync_data(&self, file: &File) -> io::Result<()> {
+        if self.due() {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn due(&self) -> bool {
+        let period = match self.durability {
+            Durability::Always => return true,
+            Durability::OsDefault => return false,
+            Durability::Periodic(period) => period,
+        };
+        let mut last_sync = self.last_sync.lock().expect("sync lock poisoned");
+        let now = Instant::now();
+        match *last_sync {
+            Some(last) if now.duration_since(last) < period => false,
+            _ => {
+                *last_sync = Some(now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodic_syncs_wait_out_their_period() {
+        let periodic = Syncer::new(Durability::Periodic(Duration::from_secs(60)));
+        assert!(periodic.due());
+        assert!(!periodic.due());
+        let always = Syncer::new(Durability::Always);
+        assert!(always.due() && always.due());
+        assert!(!Syncer::new(Durability::OsDefault).due());
+    }
+}
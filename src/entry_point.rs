@@ -0,0 +1,121 @@
+use crate::{contract::call_uint, NonceManagerError};
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use ethers::utils::id;
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+
+/// Width of the sequence in the lower bits of a 2D nonce, the key occupies the upper 192 bits
+const SEQUENCE_BITS: usize = 64;
+
+#[derive(Debug)]
+/// Nonce manager for ERC-4337 smart accounts. The EntryPoint keeps an independent sequence
+/// for every 192-bit key of a sender and exposes it as the 2D nonce `key << 64 | sequence`
+/// through `getNonce(sender, key)`. Each key's sequence is read from the EntryPoint on first
+/// use and handed out locally afterwards, so that bundler and wallet backends can build
+/// consecutive user operations without waiting for them to be included.
+pub struct EntryPointNonceManager<M> {
+    inner: M,
+    entry_point: Address,
+    sender: Address,
+    sequences: Mutex<BTreeMap<U256, u64>>,
+}
+
+impl<M> EntryPointNonceManager<M>
+where
+    M: Middleware,
+{
+    /// Instantiates the manager for the smart account `sender` of the EntryPoint deployed at
+    /// `entry_point`
+    pub fn new(inner: M, entry_point: Address, sender: Address) -> Self {
+        Self {
+            inner,
+            entry_point,
+            sender,
+            sequences: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The smart account whose nonces are managed
+    pub fn sender(&self) -> Address {
+        self.sender
+    }
+
+    /// The EntryPoint contract the nonces are read from
+    pub fn entry_point(&self) -> Address {
+        self.entry_point
+    }
+
+    /// Returns the 2D nonce for the next user operation under `key` and advances the key's
+    /// local sequence
+    pub async fn next_nonce(&self, key: U256) -> Result<U256, NonceManagerError<M>> {
+        let mut sequences = self.sequences.lock().await;
+        let sequence = match sequences.get(&key) {
+            Some(sequence) => *sequence,
+            None => self.chain_sequence(key).await?,
+        };
+        let next = sequence
+            .checked_add(1)
+            .ok_or(NonceManagerError::SequenceExhausted(key))?;
+        sequences.insert(key, next);
+
+        Ok(Self::encode(key, sequence))
+    }
+
+    /// Returns the 2D nonce the next user operation under `key` would get, without
+    /// advancing the sequence
+    pub async fn peek(&self, key: U256) -> Result<U256, NonceManagerError<M>> {
+        let mut sequences = self.sequences.lock().await;
+        let sequence = match sequences.get(&key) {
+            Some(sequence) => *sequence,
+            None => {
+                let sequence = self.chain_sequence(key).await?;
+                sequences.insert(key, sequence);
+                sequence
+            }
+        };
+
+        Ok(Self::encode(key, sequence))
+    }
+
+    /// Brings the local sequence of `key` up to date with the EntryPoint and returns the next
+    /// 2D nonce. The sequence never moves backwards, so user operations handed to a bundler
+    /// but not yet included are not reused.
+    pub async fn resync(&self, key: U256) -> Result<U256, NonceManagerError<M>> {
+        let chain_sequence = self.chain_sequence(key).await?;
+
+        let mut sequences = self.sequences.lock().await;
+        let sequence = sequences.entry(key).or_insert(chain_sequence);
+        if chain_sequence > *sequence {
+            *sequence = chain_sequence;
+        }
+
+        Ok(Self::encode(key, *sequence))
+    }
+
+    /// The keys whose sequences are tracked locally
+    pub async fn keys(&self) -> Vec<U256> {
+        self.sequences.lock().await.keys().copied().collect()
+    }
+
+    /// Reads the sequence of `key` from the EntryPoint's `getNonce(sender, key)`
+    async fn chain_sequence(&self, key: U256) -> Result<u64, NonceManagerError<M>> {
+        if key.bits() > 256 - SEQUENCE_BITS {
+            return Err(NonceManagerError::InvalidNonceKey(key));
+        }
+
+        let mut data = id("getNonce(address,uint192)").to_vec();
+        data.extend(abi::encode(&[
+            Token::Address(self.sender),
+            Token::Uint(key),
+        ]));
+        let nonce = call_uint(&self.inner, self.entry_point, data).await?;
+
+        Ok(nonce.low_u64())
+    }
+
+    fn encode(key: U256, sequence: u64) -> U256 {
+        (key << SEQUENCE_BITS) + U256::from(sequence)
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, H256, U256, U64,
+};
+use ethers::utils::rlp::Rlp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+/// The `eth_estimateGas` state override set, built up from the state changes of the
+/// transactions it is applied on top of
+struct StateOverride(BTreeMap<Address, AccountOverride>);
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<Bytes>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    state_diff: BTreeMap<H256, H256>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Output of geth's `prestateTracer` in diff mode
+struct PrestateDiff {
+    pre: BTreeMap<Address, TracedAccount>,
+    post: BTreeMap<Address, TracedAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TracedAccount {
+    balance: Option<U256>,
+    nonce: Option<u64>,
+    code: Option<Bytes>,
+    #[serde(default)]
+    storage: BTreeMap<H256, H256>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceConfig<'a> {
+    tracer: &'static str,
+    tracer_config: DiffMode,
+    state_overrides: &'a StateOverride,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiffMode {
+    diff_mode: bool,
+}
+
+impl StateOverride {
+    /// Layers the state changes of a traced transaction on top of the overrides
+    fn apply(&mut self, diff: PrestateDiff) {
+        // slots which were cleared only show up in the pre-state
+        for (address, pre) in &diff.pre {
+            let post = diff.post.get(address);
+            for slot in pre.storage.keys() {
+                if !post.is_some_and(|post| post.storage.contains_key(slot)) {
+                    self.0
+                        .entry(*address)
+                        .or_default()
+                        .state_diff
+                        .insert(*slot, H256::zero());
+                }
+            }
+        }
+
+        for (address, post) in diff.post {
+            let account = self.0.entry(address).or_default();
+            if post.balance.is_some() {
+                account.balance = post.balance;
+            }
+            if let Some(nonce) = post.nonce {
+                account.nonce = Some(nonce.into());
+            }
+            if post.code.is_some() {
+                account.code = post.code;
+            }
+            account.state_diff.extend(post.storage);
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Estimates the gas of `tx` as if the manager's own transactions at lower nonces had
+    /// already been mined, e.g. a swap depending on an approval which is still queued.
+    /// Broadcast transactions are covered by estimating against the pending block, while
+    /// the state changes of scheduled ones are traced with geth's `prestateTracer` and
+    /// passed to `eth_estimateGas` as state overrides, which needs a node supporting both.
+    /// A transaction without a nonce is estimated at the next one to be assigned. Fails with
+    /// [`NonceManagerError::UndecodableTransaction`] rather than estimating without a
+    /// scheduled transaction which can't be decoded.
+    pub async fn estimate_gas_with_pending(
+        &self,
+        tx: &TypedTransaction,
+    ) -> Result<U256, NonceManagerError<M>> {
+        let nonce = match tx.nonce() {
+            Some(nonce) => Nonce::new(*nonce).map_err(NonceManagerError::InvalidNonce)?,
+            None => self.next().await,
+        };
+        let scheduled: Vec<(Nonce, Bytes)> = self
+            .state
+            .schedule()
+            .lock()
+            .await
+            .iter()
+            .take_while(|(reserved, _)| **reserved < nonce)
+            .filter_map(|(reserved, raw_tx)| Some((*reserved, raw_tx.clone()?)))
+            .collect();
+
+        let mut tx = tx.clone();
+        if tx.from().is_none() {
+            tx.set_from(self.address);
+        }
+        let block = BlockNumber::Pending;
+        if scheduled.is_empty() {
+            return self
+                .provider()
+                .request("eth_estimateGas", (&tx, block))
+                .await
+                .map_err(NonceManagerError::ProviderError);
+        }
+
+        let mut overrides = StateOverride::default();
+        for (scheduled_nonce, raw_tx) in scheduled {
+            let mut scheduled_tx = TypedTransaction::decode_signed(&Rlp::new(&raw_tx))
+                .map_err(|_| NonceManagerError::UndecodableTransaction(scheduled_nonce))?
+                .0;
+            scheduled_tx.set_from(self.address);
+            let config = TraceConfig {
+                tracer: "prestateTracer",
+                tracer_config: DiffMode { diff_mode: true },
+                state_overrides: &overrides,
+            };
+            let diff: PrestateDiff = self
+                .provider()
+                .request("debug_traceCall", (&scheduled_tx, block, config))
+                .await
+                .map_err(NonceManagerError::ProviderError)?;
+            overrides.apply(diff);
+        }
+
+        self.provider()
+            .request("eth_estimateGas", (&tx, block, &overrides))
+            .await
+            .map_err(NonceManagerError::ProviderError)
+    }
+}
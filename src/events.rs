@@ -0,0 +1,138 @@
+use crate::Nonce;
+use ethers::types::{Address, TxHash, U256, U64};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Debug;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+/// A step in the lifecycle of a managed nonce. Events of a transaction sent with a label,
+/// see [`crate::LockedNonceManagerMiddleware::send_labeled_transaction`], carry it along.
+pub enum NonceEvent {
+    /// A nonce was handed out to a transaction, which is about to be broadcast
+    Assigned {
+        address: Address,
+        nonce: Nonce,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Value>,
+    },
+    /// The transaction at `nonce` was accepted by the node
+    Broadcast {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Value>,
+    },
+    /// The transaction at `nonce` was included in a block
+    Confirmed {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        block_number: Option<U64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Value>,
+    },
+    /// The transaction at `nonce` has been waiting for inclusion for longer than expected
+    Stuck {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        pending_secs: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Value>,
+    },
+    /// The transaction at `nonce` has been pending for longer than the configured
+    /// [`crate::Sla`] allows. `pending_blocks` is only set if the SLA bounds blocks.
+    SlaBreached {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        pending_blocks: Option<u64>,
+        pending_secs: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Value>,
+    },
+    /// A broadcast failed and the chain reported a higher nonce than the local counter
+    Conflict {
+        address: Address,
+        local_nonce: Nonce,
+        chain_nonce: Nonce,
+    },
+    /// The local counter was moved to the chain's pending transaction count by a resync or
+    /// a reconnect. `previous` is `None` if the counter wasn't initialized.
+    Resynced {
+        address: Address,
+        previous: Option<Nonce>,
+        nonce: Nonce,
+    },
+    /// A nonce held with [`crate::LockedNonceManagerMiddleware::hold_nonce`] expired without
+    /// a signed transaction. `rolled_back` tells whether the counter was rolled back to it,
+    /// otherwise a transaction filling the gap was scheduled at it if it could be signed.
+    HoldExpired {
+        address: Address,
+        nonce: Nonce,
+        rolled_back: bool,
+    },
+    /// The transaction at `nonce` sent with
+    /// [`crate::LockedNonceManagerMiddleware::send_private_transaction`] was not included
+    /// before the relay dropped it. `rolled_back` tells whether the counter was rolled back
+    /// to its nonce, otherwise a cancellation was scheduled at it if it could be signed.
+    PrivateExpired {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        rolled_back: bool,
+    },
+    /// The transaction at `nonce` was given up because the one at `dependency`, which it
+    /// depends on, won't make it, see
+    /// [`crate::LockedNonceManagerMiddleware::schedule_dependent`]. `rolled_back` tells
+    /// whether the counter was rolled back to its nonce, otherwise it was cancelled or an
+    /// empty transfer was scheduled in its place.
+    DependencyFailed {
+        address: Address,
+        nonce: Nonce,
+        dependency: Nonce,
+        rolled_back: bool,
+    },
+    /// [`crate::LockedNonceManagerMiddleware::resubmit_all`] skipped the transaction at
+    /// `nonce`, an EIP-4844 blob transaction, which can't be replaced, see
+    /// [`crate::NonceManagerError::BlobNotReplaceable`]
+    BlobNotReplaced {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+    },
+    /// The transaction at `nonce` breached the [`crate::Sla`] but wasn't sped up, as its
+    /// replacement would have paid more than `cap` per gas, see
+    /// [`crate::NonceManagerError::FeeCapReached`]
+    FeeCapReached {
+        address: Address,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        cap: U256,
+    },
+    /// The simulation gate, see [`crate::LockedNonceManagerMiddleware::with_simulation`],
+    /// couldn't reach the node, so the transaction was sent without being simulated
+    SimulationSkipped { address: Address, error: String },
+    /// In shadow mode, the inner middleware picked `actual` where the manager would have
+    /// assigned `predicted`, see [`crate::LockedNonceManagerMiddleware::with_shadow_mode`]
+    ShadowDiverged {
+        address: Address,
+        predicted: Nonce,
+        actual: Nonce,
+    },
+    /// The nonce of an address followed by a [`crate::NonceTracker`] changed
+    Changed {
+        address: Address,
+        previous: Option<Nonce>,
+        nonce: Nonce,
+    },
+}
+
+/// Receives the [`NonceEvent`]s emitted by a nonce manager. Observers are called inline, so
+/// implementations which do I/O should hand the event off to a background task.
+pub trait NonceObserver: Send + Sync + Debug {
+    /// Called for every event, in the order the events happen
+    fn on_event(&self, event: &NonceEvent);
+}
@@ -0,0 +1,17 @@
+use crate::LockedNonceManagerMiddleware;
+use ethers::{providers::Middleware, types::Address};
+
+/// Extension trait to wrap any [`Middleware`] in a [`LockedNonceManagerMiddleware`], chaining
+/// like ethers' own `MiddlewareBuilder`:
+///
+/// ```ignore
+/// let client = provider.with_signer(wallet).locked_nonce_manager(address);
+/// ```
+pub trait NonceManagerExt: Middleware + Sized {
+    /// Wraps `self` in a [`LockedNonceManagerMiddleware`] managing the nonce of `address`
+    fn locked_nonce_manager(self, address: Address) -> LockedNonceManagerMiddleware<Self> {
+        LockedNonceManagerMiddleware::new(self, address)
+    }
+}
+
+impl<M: Middleware> NonceManagerExt for M {}
@@ -0,0 +1,274 @@
+use crate::{rpc_error, LockedNonceManagerMiddleware, SendEndpoint};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, Middleware, PendingTransaction, Provider};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, H256};
+use ethers::utils::keccak256;
+use futures_util::future::join_all;
+use std::{error::Error, fmt::Debug, sync::Arc};
+
+/// An additional RPC endpoint managed transactions are broadcast to
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait BroadcastEndpoint: Send + Sync + Debug {
+    /// Submits the signed transaction. A node which already knows the transaction, e.g.
+    /// because another endpoint's node gossiped it first, should be reported as a success.
+    async fn send_raw(&self, raw_tx: Bytes) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<P: JsonRpcClient + 'static> BroadcastEndpoint for Provider<P> {
+    async fn send_raw(&self, raw_tx: Bytes) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.send_raw_transaction(raw_tx).await {
+            Ok(_) => Ok(()),
+            Err(err) if is_already_known(&err) => Ok(()),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+}
+
+/// Whether the node rejected a transaction because it is already in its pool, for which
+/// geth, erigon and nethermind use slightly different wordings
+fn is_already_known(err: &impl Error) -> bool {
+    let message = err.to_string().to_lowercase();
+    message.contains("already known") || message.contains("known transaction")
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Also broadcasts every managed transaction to `endpoint`. Once at least one endpoint is
+    /// added, transactions are filled and signed through the inner middleware and the same
+    /// signed transaction is submitted to the inner middleware and every endpoint
+    /// concurrently, so it still consumes a single nonce.
+    pub fn with_broadcast_endpoint(mut self, endpoint: impl BroadcastEndpoint + 'static) -> Self {
+        self.endpoints.push(Arc::new(endpoint));
+        self
+    }
+
+    /// Submits every managed transaction through both the inner middleware and `endpoint`
+    /// at once, e.g. an Arbitrum chain's sequencer and its delayed Inbox on L1, see
+    /// [`crate::ArbitrumDelayedInbox`]. The same signed transaction goes down both paths, so
+    /// only one of them can execute it: while the sequencer is up it is included right away
+    /// and the delayed copy fails on its used nonce, and if the sequencer goes down, even
+    /// after accepting it, the delayed copy still gets it included. The send succeeds if
+    /// either path accepted it. Every transaction pays for both paths, see
+    /// [`Self::with_fallback_endpoint`] to only use `endpoint` while the inner middleware
+    /// can't be reached.
+    pub fn with_dual_submission(self, endpoint: impl BroadcastEndpoint + 'static) -> Self {
+        self.with_broadcast_endpoint(endpoint)
+    }
+
+    /// Broadcasts managed transactions to `endpoint` only when the inner middleware couldn't
+    /// be reached and no endpoint added with [`Self::with_broadcast_endpoint`] accepted them,
+    /// e.g. an L2's L1 submission path while its sequencer is down, see
+    /// [`crate::ArbitrumDelayedInbox`]. Transactions the inner middleware's node rejected,
+    /// e.g. for their nonce or fees, or which failed before reaching it, e.g. in a signer,
+    /// aren't sent to fallbacks. Fallbacks are tried one at a
+    /// time in the order they were added, until one accepts the transaction. Like broadcast
+    /// endpoints, they make the manager sign through the inner middleware, so the same
+    /// signed transaction and nonce are used on every path.
+    pub fn with_fallback_endpoint(mut self, endpoint: impl BroadcastEndpoint + 'static) -> Self {
+        self.fallback_endpoints.push(Arc::new(endpoint));
+        self
+    }
+
+    /// Sends `tx` through the inner middleware and, if configured, every broadcast endpoint.
+    /// The send succeeds if any of them accepted the transaction; if none did because the
+    /// inner middleware couldn't be reached, the fallback endpoints are tried, and if none of
+    /// those accepts it either, the inner middleware's error is returned. `signed` is `tx`
+    /// already signed, if it was, e.g. by [`Self::write_ahead`].
+    pub(crate) async fn broadcast(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+        signed: Option<Bytes>,
+    ) -> Result<PendingTransaction<'_, M::Provider>, M::Error> {
+        let raw_tx = match signed {
+            Some(raw_tx) => raw_tx,
+            None if self.endpoints.is_empty() && self.fallback_endpoints.is_empty() => {
+                let send = self.inner.send_transaction(tx.clone(), block);
+                return self.timed_inner_send(send).await;
+            }
+            None => {
+                let mut tx = tx.clone();
+                self.inner.fill_transaction(&mut tx, block).await?;
+                let signature = self.inner.sign_transaction(&tx, self.address).await?;
+                tx.rlp_signed(&signature)
+            }
+        };
+
+        let endpoints = join_all(self.endpoints.iter().enumerate().map(|(i, endpoint)| {
+            self.timed_send(
+                SendEndpoint::Broadcast(i),
+                endpoint.send_raw(raw_tx.clone()),
+            )
+        }));
+        let sent = self.timed_inner_send(self.inner.send_raw_transaction(raw_tx.clone()));
+        let (sent, accepted) = futures_util::join!(sent, endpoints);
+
+        let err = match sent {
+            Ok(pending) => return Ok(pending),
+            Err(err) => err,
+        };
+        let mut accepted = accepted.iter().any(Result::is_ok);
+        // a node which rejected the transaction would reject it from any other path too
+        let unreachable = rpc_error::is_unreachable(&err);
+        for (i, fallback) in self.fallback_endpoints.iter().enumerate() {
+            if accepted || !unreachable {
+                break;
+            }
+            let send = fallback.send_raw(raw_tx.clone());
+            accepted = self
+                .timed_send(SendEndpoint::Fallback(i), send)
+                .await
+                .is_ok();
+        }
+        if accepted {
+            let tx_hash = H256::from(keccak256(&raw_tx));
+            Ok(PendingTransaction::new(tx_hash, self.provider()))
+        } else {
+            Err(err)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, Client, SENDER};
+    use crate::{LockedNonceManagerMiddleware, SimulatedChain};
+    use async_trait::async_trait;
+    use ethers::providers::{
+        Middleware, MiddlewareError, PendingTransaction, Provider, ProviderError,
+    };
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::{
+        transaction::eip2718::TypedTransaction, Address, Bytes, TransactionRequest,
+    };
+
+    #[derive(Debug)]
+    /// A middleware failing every raw send before it reaches the node, like a broken signer
+    /// or policy on the way would
+    struct Refusing(Provider<SimulatedChain>);
+
+    #[derive(Debug, thiserror::Error)]
+    enum RefusingError {
+        #[error("refused locally")]
+        Refused,
+        #[error(transparent)]
+        Provider(#[from] ProviderError),
+    }
+
+    impl MiddlewareError for RefusingError {
+        type Inner = ProviderError;
+
+        fn from_err(err: ProviderError) -> Self {
+            RefusingError::Provider(err)
+        }
+
+        fn as_inner(&self) -> Option<&ProviderError> {
+            match self {
+                RefusingError::Provider(err) => Some(err),
+                RefusingError::Refused => None,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Middleware for Refusing {
+        type Error = RefusingError;
+        type Provider = SimulatedChain;
+        type Inner = Provider<SimulatedChain>;
+
+        fn inner(&self) -> &Self::Inner {
+            &self.0
+        }
+
+        async fn send_raw_transaction<'a>(
+            &'a self,
+            _: Bytes,
+        ) -> Result<PendingTransaction<'a, SimulatedChain>, RefusingError> {
+            Err(RefusingError::Refused)
+        }
+    }
+
+    /// A transfer at `nonce`, signed by a local key, and the key's address
+    async fn signed(nonce: u64) -> (TypedTransaction, Bytes, Address) {
+        let wallet: LocalWallet = "22".repeat(32).parse().unwrap();
+        let tx: TypedTransaction = TransactionRequest::pay(Address::repeat_byte(1), 1u64)
+            .from(wallet.address())
+            .nonce(nonce)
+            .gas(21_000u64)
+            .gas_price(1u64)
+            .chain_id(31337u64)
+            .into();
+        let signature = wallet.sign_transaction(&tx).await.unwrap();
+        let raw_tx = tx.rlp_signed(&signature);
+        (tx, raw_tx, wallet.address())
+    }
+
+    async fn broadcast(client: &Client, tx: &TypedTransaction, raw_tx: &Bytes) -> bool {
+        client
+            .broadcast(tx, None, Some(raw_tx.clone()))
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn fallbacks_take_over_while_the_inner_node_is_unreachable() {
+        let chain = SimulatedChain::new(6);
+        let backup = SimulatedChain::new(7);
+        let client = client(&chain).with_fallback_endpoint(backup.provider());
+        let (tx, raw_tx, from) = signed(0).await;
+
+        chain.fail_next(1);
+        assert!(broadcast(&client, &tx, &raw_tx).await);
+        assert!(chain.pending_nonces(from).is_empty());
+        assert_eq!(backup.pending_nonces(from), [0]);
+    }
+
+    #[tokio::test]
+    async fn rejected_transactions_are_not_sent_to_fallbacks() {
+        let chain = SimulatedChain::new(8);
+        let backup = SimulatedChain::new(9);
+        let client = client(&chain).with_fallback_endpoint(backup.provider());
+        let (tx, raw_tx, from) = signed(0).await;
+        assert!(broadcast(&client, &tx, &raw_tx).await);
+        chain.mine();
+
+        // nonce too low
+        let (tx, raw_tx, _) = signed(0).await;
+        assert!(!broadcast(&client, &tx, &raw_tx).await);
+        assert!(backup.pending_nonces(from).is_empty());
+    }
+
+    #[tokio::test]
+    async fn local_failures_are_not_sent_to_fallbacks() {
+        let chain = SimulatedChain::new(12);
+        let backup = SimulatedChain::new(13);
+        let client = LockedNonceManagerMiddleware::new(Refusing(chain.provider()), SENDER)
+            .with_fallback_endpoint(backup.provider());
+        let (tx, raw_tx, from) = signed(0).await;
+
+        assert!(client.broadcast(&tx, None, Some(raw_tx)).await.is_err());
+        assert!(backup.pending_nonces(from).is_empty());
+    }
+
+    #[tokio::test]
+    async fn dual_submission_sends_down_both_paths() {
+        let chain = SimulatedChain::new(10);
+        let backup = SimulatedChain::new(11);
+        let client = client(&chain).with_dual_submission(backup.provider());
+        let (tx, raw_tx, from) = signed(0).await;
+
+        assert!(broadcast(&client, &tx, &raw_tx).await);
+        assert_eq!(chain.pending_nonces(from), [0]);
+        assert_eq!(backup.pending_nonces(from), [0]);
+
+        chain.fail_next(1);
+        let (tx, raw_tx, _) = signed(1).await;
+        assert!(broadcast(&client, &tx, &raw_tx).await);
+        assert_eq!(backup.pending_nonces(from), [0, 1]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::LockedNonceManagerMiddleware;
+use ethers::providers::{Middleware, ProviderError, RpcError};
+use ethers::types::{transaction::eip2718::TypedTransaction, U256};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Default)]
+/// Remembers whether the node turned out not to implement `eth_fillTransaction`
+pub(crate) struct FillSupport {
+    unsupported: AtomicBool,
+}
+
+#[derive(Debug, Deserialize)]
+/// The result of `eth_fillTransaction`, of which only the filled transaction is used
+struct FillResult {
+    tx: FilledTx,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// The fields of a transaction filled by the node which are copied over
+struct FilledTx {
+    gas: Option<U256>,
+    gas_price: Option<U256>,
+    max_fee_per_gas: Option<U256>,
+    max_priority_fee_per_gas: Option<U256>,
+}
+
+/// JSON-RPC error code for a method the node doesn't implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Whether the node rejected a call because it doesn't implement the method, as opposed to
+/// rejecting this particular transaction or failing to answer at all
+fn is_unsupported(err: &ProviderError) -> bool {
+    err.as_error_response()
+        .is_some_and(|err| err.code == METHOD_NOT_FOUND)
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Fills gas and fees with a single `eth_fillTransaction` call on nodes which implement
+    /// it, such as geth, instead of separate price and estimation requests. The nonce is
+    /// still assigned by the manager. Nodes without the method are detected on first use and
+    /// filled the usual way from then on.
+    pub fn with_fill_transaction(mut self) -> Self {
+        self.config_mut().use_fill_transaction = true;
+        self
+    }
+
+    /// Fills the gas limit and fees `tx` is missing through `eth_fillTransaction`, if enabled
+    /// and supported. Failures are left to the inner middleware's filling, which reports
+    /// them in its own terms.
+    pub(crate) async fn fill_from_node(&self, tx: &mut TypedTransaction) {
+        if !self.config().use_fill_transaction
+            || self.fill_support.unsupported.load(Ordering::SeqCst)
+        {
+            return;
+        }
+        if tx.from().is_none() {
+            tx.set_from(self.address);
+        }
+
+        let filled = match self
+            .provider()
+            .request::<_, FillResult>("eth_fillTransaction", [&*tx])
+            .await
+        {
+            Ok(filled) => filled.tx,
+            Err(err) => {
+                if is_unsupported(&err) {
+                    self.fill_support.unsupported.store(true, Ordering::SeqCst);
+                }
+                return;
+            }
+        };
+
+        if let (None, Some(gas)) = (tx.gas(), filled.gas) {
+            tx.set_gas(gas);
+        }
+        match *tx {
+            TypedTransaction::Eip1559(ref mut inner) => {
+                if inner.max_fee_per_gas.is_none() {
+                    inner.max_fee_per_gas = filled.max_fee_per_gas;
+                }
+                if inner.max_priority_fee_per_gas.is_none() {
+                    inner.max_priority_fee_per_gas = filled.max_priority_fee_per_gas;
+                }
+            }
+            _ => {
+                if let (None, Some(gas_price)) = (tx.gas_price(), filled.gas_price) {
+                    tx.set_gas_price(gas_price);
+                }
+            }
+        }
+    }
+}
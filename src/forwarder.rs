@@ -0,0 +1,79 @@
+use crate::{contract::call_uint, sequences::Sequences, NonceManagerError};
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use ethers::utils::id;
+
+#[derive(Debug)]
+/// Nonce manager for meta-transaction relayers. An ERC-2771 trusted forwarder keeps a nonce
+/// per user which every forwarded request must match, so requests for the same user are
+/// handed consecutive nonces locally and can be relayed without waiting on each other.
+pub struct ForwarderNonceManager<M> {
+    inner: M,
+    forwarder: Address,
+    selector: [u8; 4],
+    users: Sequences<Address>,
+}
+
+impl<M> ForwarderNonceManager<M>
+where
+    M: Middleware,
+{
+    /// Instantiates the manager for the forwarder deployed at `forwarder`, reading nonces
+    /// with `getNonce(address)`
+    pub fn new(inner: M, forwarder: Address) -> Self {
+        Self {
+            inner,
+            forwarder,
+            selector: id("getNonce(address)"),
+            users: Sequences::new(),
+        }
+    }
+
+    /// Reads nonces with another function of the forwarder taking the user's address, e.g.
+    /// `"nonces(address)"`
+    pub fn with_nonce_function(mut self, signature: &str) -> Self {
+        self.selector = id(signature);
+        self
+    }
+
+    /// The forwarder the nonces are read from
+    pub fn forwarder(&self) -> Address {
+        self.forwarder
+    }
+
+    /// Returns the nonce for the next request forwarded on behalf of `user`
+    pub async fn next_nonce(&self, user: Address) -> Result<U256, NonceManagerError<M>> {
+        self.users.next(user, || self.chain_nonce(user)).await
+    }
+
+    /// Returns the nonce the next request of `user` would get, without reserving it
+    pub async fn peek(&self, user: Address) -> Result<U256, NonceManagerError<M>> {
+        self.users.peek(user, || self.chain_nonce(user)).await
+    }
+
+    /// Gives back `nonce` of `user` if the request it was handed out for is never relayed.
+    /// Only the last nonce handed out for the user can be released.
+    pub async fn release(&self, user: Address, nonce: U256) -> bool {
+        self.users.release(user, nonce).await
+    }
+
+    /// Brings the nonce of `user` up to date with the forwarder and returns the next one. It
+    /// never moves backwards, so requests which are still being relayed are not reused.
+    pub async fn resync(&self, user: Address) -> Result<U256, NonceManagerError<M>> {
+        let chain_nonce = self.chain_nonce(user).await?;
+        Ok(self.users.resync(user, chain_nonce).await)
+    }
+
+    /// The users whose nonces are tracked
+    pub async fn users(&self) -> Vec<Address> {
+        self.users.keys().await
+    }
+
+    /// Reads the nonce of `user` from the forwarder
+    pub async fn chain_nonce(&self, user: Address) -> Result<U256, NonceManagerError<M>> {
+        let mut data = self.selector.to_vec();
+        data.extend(abi::encode(&[Token::Address(user)]));
+        call_uint(&self.inner, self.forwarder, data).await
+    }
+}
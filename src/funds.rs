@@ -0,0 +1,200 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockNumber, U256};
+
+/// Whether the node rejected a transaction because its sender can't pay for it. Nodes word it
+/// as "insufficient funds for gas * price + value" (geth, erigon, anvil) or close to it.
+fn is_insufficient_funds(message: &str) -> bool {
+    message.to_lowercase().contains("insufficient funds")
+}
+
+/// The `have` and `want` amounts geth appends to the error, e.g. "insufficient funds for gas *
+/// price + value: address 0x... have 1000 want 2000"
+fn have_want(message: &str) -> Option<(U256, U256)> {
+    let amount = |key: &str| {
+        let start = message.find(key)? + key.len();
+        let digits = message[start..]
+            .split(|c: char| !c.is_ascii_digit())
+            .next()?;
+        U256::from_dec_str(digits).ok()
+    };
+    Some((amount(" have ")?, amount(" want ")?))
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Converts the error a fill or broadcast of `tx` failed with, turning a rejection for
+    /// lack of funds into [`NonceManagerError::InsufficientFunds`]. The amounts are taken from
+    /// the node's message if it has them, and from the transaction and the pending balance of
+    /// the managed address otherwise. Other errors, and rejections whose balance can't be
+    /// looked up, are passed through.
+    pub(crate) async fn rejected(
+        &self,
+        tx: &TypedTransaction,
+        err: M::Error,
+        nonce_consumed: bool,
+    ) -> NonceManagerError<M> {
+        let message = err.to_string();
+        if !is_insufficient_funds(&message) {
+            return FromErr::from(err);
+        }
+        if let Some((available, required)) = have_want(&message) {
+            return NonceManagerError::InsufficientFunds {
+                required: Some(required),
+                available,
+                nonce_consumed,
+            };
+        }
+
+        let available = match self
+            .inner
+            .get_balance(self.address, Some(BlockNumber::Pending.into()))
+            .await
+        {
+            Ok(available) => available,
+            Err(_) => return FromErr::from(err),
+        };
+        NonceManagerError::InsufficientFunds {
+            required: max_cost(tx),
+            available,
+            nonce_consumed,
+        }
+    }
+
+    /// Checks the balance against `tx` before a nonce is assigned to it, so that sends
+    /// fail with [`NonceManagerError::InsufficientFunds`] up front rather than once their
+    /// nonce is held and it is the node rejecting them. Costs a request per send.
+    pub fn with_balance_check(mut self) -> Self {
+        self.config_mut().check_balance = true;
+        self
+    }
+
+    /// Runs the balance check for `tx`, if enabled. The gas is only counted if the caller
+    /// already set it and its price, as neither is filled yet, so the value alone is checked
+    /// otherwise.
+    pub(crate) async fn check_balance(
+        &self,
+        tx: &TypedTransaction,
+    ) -> Result<(), NonceManagerError<M>> {
+        if !self.config().check_balance {
+            return Ok(());
+        }
+        let available = self
+            .inner
+            .get_balance(self.address, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(FromErr::from)?;
+        let value = tx.value().copied().unwrap_or_default();
+        let required = max_cost(tx).unwrap_or(value);
+        if available < required {
+            return Err(NonceManagerError::InsufficientFunds {
+                required: Some(required),
+                available,
+                nonce_consumed: false,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The most `tx` can cost, its value plus its gas at the fee cap, which is what the node
+/// checks the balance against. `None` if its gas or price isn't set.
+fn max_cost(tx: &TypedTransaction) -> Option<U256> {
+    let gas_cost = tx.gas()?.checked_mul(tx.gas_price()?)?;
+    gas_cost.checked_add(tx.value().copied().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{MockProvider, Provider};
+
+    type Error = NonceManagerError<Provider<MockProvider>>;
+
+    #[test]
+    fn amounts_are_read_from_geth_messages() {
+        let message =
+            "insufficient funds for gas * price + value: address 0x11 have 1000 want 2000";
+        assert!(is_insufficient_funds(message));
+        assert_eq!(have_want(message), Some((1000.into(), 2000.into())));
+        assert_eq!(have_want("insufficient funds"), None);
+    }
+
+    #[test]
+    fn the_error_shows_both_amounts() {
+        let err: Error = NonceManagerError::InsufficientFunds {
+            required: Some(2000.into()),
+            available: 1000.into(),
+            nonce_consumed: false,
+        };
+        assert_eq!(
+            err.to_string(),
+            "insufficient funds: 2000 wei required, 1000 wei available"
+        );
+        let err: Error = NonceManagerError::InsufficientFunds {
+            required: None,
+            available: 1000.into(),
+            nonce_consumed: false,
+        };
+        assert_eq!(
+            err.to_string(),
+            "insufficient funds: unknown wei required, 1000 wei available"
+        );
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn rejections_for_lack_of_funds_leave_the_nonce_free() {
+        use crate::testkit::fixtures::{client, transfer, SENDER};
+        use crate::SimulatedChain;
+
+        let chain = SimulatedChain::new(1);
+        chain.set_balance(SENDER, 1000.into());
+        let client = client(&chain);
+        let err = client.send_transaction(transfer(), None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::InsufficientFunds {
+                required: Some(_),
+                nonce_consumed: false,
+                ..
+            }
+        ));
+        assert_eq!(client.next().await.as_u64(), 0);
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn the_balance_check_fails_before_a_nonce_is_assigned() {
+        use crate::testkit::fixtures::{client, transfer, SENDER};
+        use crate::SimulatedChain;
+
+        let chain = SimulatedChain::new(2);
+        chain.set_balance(SENDER, 1000.into());
+        let client = client(&chain).with_balance_check();
+        let err = client
+            .send_transaction(transfer().value(2000), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::InsufficientFunds { required: Some(required), .. }
+                if required == 2000.into()
+        ));
+        // the gas counts once its price is set
+        let priced = transfer().gas(21_000).gas_price(1);
+        let err = client.send_transaction(priced, None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::InsufficientFunds { required: Some(required), .. }
+                if required == 21_001.into()
+        ));
+        assert_eq!(client.try_next(), None);
+        assert!(chain.pending_nonces(SENDER).is_empty());
+
+        chain.set_balance(SENDER, U256::MAX);
+        client.send_transaction(transfer(), None).await.unwrap();
+    }
+}
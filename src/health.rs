@@ -0,0 +1,72 @@
+use crate::{LockedNonceManagerMiddleware, Nonce};
+use ethers::providers::Middleware;
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// Health of a nonce manager, as returned by [`LockedNonceManagerMiddleware::health_check`].
+/// It serializes to JSON for readiness endpoints.
+pub struct HealthReport {
+    /// Whether the pending transaction count could be fetched
+    pub rpc_reachable: bool,
+    /// The error returned by the RPC, if it wasn't reachable
+    pub rpc_error: Option<String>,
+    /// Whether the counter has been initialized from the chain
+    pub initialized: bool,
+    /// The next nonce to be assigned
+    pub next_nonce: Nonce,
+    /// The pending transaction count on chain, if the RPC was reachable
+    pub chain_nonce: Option<Nonce>,
+    /// How far the local counter is ahead of the chain, negative if it is behind
+    pub drift: Option<i128>,
+    /// Number of broadcast transactions which have not been confirmed yet
+    pub in_flight: usize,
+    /// Seconds the oldest unconfirmed transaction has been in flight
+    pub oldest_in_flight_secs: Option<u64>,
+}
+
+impl HealthReport {
+    /// Whether the manager can serve sends: the RPC is reachable, the counter initialized, it
+    /// drifted from the chain by at most `max_drift` nonces, and no transaction has been in
+    /// flight for longer than `stuck_after`
+    pub fn is_healthy(&self, max_drift: u64, stuck_after: Duration) -> bool {
+        self.rpc_reachable
+            && self.initialized
+            && matches!(self.drift, Some(drift) if drift.unsigned_abs() <= max_drift.into())
+            && !matches!(self.oldest_in_flight_secs, Some(secs) if secs >= stuck_after.as_secs())
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Reports the health of the manager, e.g. for the readiness probe of a relayer. The
+    /// pending transaction count is fetched to check that the RPC is reachable and to
+    /// measure the drift of the local counter. An unreachable RPC is reported rather than
+    /// returned as an error.
+    pub async fn health_check(&self) -> HealthReport {
+        let chain_nonce = self.pending_chain_nonce().await;
+        let next_nonce = self.next().await;
+        let (oldest, in_flight) = {
+            let in_flight = self.state.in_flight();
+            (in_flight.oldest_age(self.now()), in_flight.iter().count())
+        };
+
+        let (chain_nonce, rpc_error) = match chain_nonce {
+            Ok(nonce) => (Some(nonce), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        HealthReport {
+            rpc_reachable: rpc_error.is_none(),
+            rpc_error,
+            initialized: self.state.is_initialized(),
+            next_nonce,
+            chain_nonce,
+            drift: chain_nonce
+                .map(|chain| i128::from(next_nonce.as_u64()) - i128::from(chain.as_u64())),
+            in_flight,
+            oldest_in_flight_secs: oldest.map(|age| age.as_secs()),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// Distribution of durations over fixed buckets, as kept for the mine, send and lock wait
+/// latencies
+pub struct Histogram {
+    /// Upper bounds of the buckets, in microseconds
+    pub bounds_us: Vec<u64>,
+    /// Recorded durations per bucket, with one more entry than `bounds_us` for the ones
+    /// longer than the last bound
+    pub counts: Vec<u64>,
+    /// Number of durations recorded
+    pub count: u64,
+    /// Sum of the recorded durations, in microseconds
+    pub sum_us: u64,
+    /// Longest recorded duration, in microseconds
+    pub max_us: u64,
+}
+
+impl Histogram {
+    /// An empty histogram with buckets up to each of `bounds`, which have to be ascending
+    pub(crate) fn new(bounds: &[Duration]) -> Self {
+        Self {
+            bounds_us: bounds.iter().map(|bound| micros(*bound)).collect(),
+            counts: vec![0; bounds.len() + 1],
+            count: 0,
+            sum_us: 0,
+            max_us: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, duration: Duration) {
+        let us = micros(duration);
+        let bucket = self
+            .bounds_us
+            .iter()
+            .position(|bound| us <= *bound)
+            .unwrap_or(self.bounds_us.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_us = self.sum_us.saturating_add(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// Mean of the recorded durations
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.sum_us / self.count))
+    }
+
+    /// Longest recorded duration
+    pub fn max(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.max_us))
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, e.g. `0.99` for the duration 99%
+    /// of the recorded ones stayed within. `None` if nothing was recorded or the quantile
+    /// falls into the unbounded bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return self
+                    .bounds_us
+                    .get(bucket)
+                    .copied()
+                    .map(Duration::from_micros);
+            }
+        }
+        None
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(bounds: &[u64]) -> Histogram {
+        let bounds: Vec<_> = bounds.iter().copied().map(Duration::from_secs).collect();
+        Histogram::new(&bounds)
+    }
+
+    #[test]
+    fn durations_land_in_the_bucket_of_their_upper_bound() {
+        let mut histogram = secs(&[6, 12]);
+        histogram.record(Duration::from_secs(6));
+        histogram.record(Duration::from_millis(6_001));
+        histogram.record(Duration::from_secs(3_600));
+        assert_eq!(histogram.counts, [1, 1, 1]);
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.max(), Some(Duration::from_secs(3_600)));
+    }
+
+    #[test]
+    fn quantiles_report_bucket_bounds() {
+        let mut histogram = secs(&[6, 12, 24]);
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.max(), None);
+        assert_eq!(histogram.quantile(0.5), None);
+        for secs in [1, 2, 3, 20] {
+            histogram.record(Duration::from_secs(secs));
+        }
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(6_500)));
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_secs(6)));
+        assert_eq!(histogram.quantile(0.75), Some(Duration::from_secs(6)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_secs(24)));
+
+        histogram.record(Duration::from_secs(3_600));
+        assert_eq!(histogram.quantile(1.0), None);
+    }
+
+    #[test]
+    fn sub_millisecond_durations_keep_their_precision() {
+        let mut histogram = Histogram::new(&[Duration::from_micros(10), Duration::from_millis(1)]);
+        histogram.record(Duration::from_micros(7));
+        histogram.record(Duration::from_micros(900));
+        assert_eq!(histogram.counts, [1, 1, 0]);
+        assert_eq!(histogram.sum_us, 907);
+    }
+
+    #[test]
+    fn overlong_durations_saturate() {
+        let mut histogram = secs(&[1]);
+        histogram.record(Duration::MAX);
+        histogram.record(Duration::MAX);
+        assert_eq!(histogram.counts, [0, 2]);
+        assert_eq!(histogram.sum_us, u64::MAX);
+        assert_eq!(histogram.max_us, u64::MAX);
+    }
+}
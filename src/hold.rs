@@ -0,0 +1,128 @@
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::{BlockId, TransactionRequest};
+use std::time::Duration;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Reserves the next nonce like [`Self::reserve_nonce`], but only for `ttl`, e.g. while
+    /// the signatures of a multisig transaction are collected. Once the hold expired without
+    /// a signed transaction scheduled for it, it is given up on the next
+    /// [`Self::broadcast_scheduled`] or [`Self::expire_holds`]: the counter is rolled back if
+    /// no later nonce was handed out, otherwise an empty transfer to the managed address is
+    /// signed and scheduled at the nonce, so the transactions after it aren't held up.
+    pub async fn hold_nonce(
+        &self,
+        ttl: Duration,
+        block: Option<BlockId>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        self.get_or_init_nonce(block).await?;
+
+        let mut write_guard = self.lock_for_assignment(None, arrival).await?;
+        let nonce = *write_guard;
+        *write_guard = Self::increment(nonce)?;
+        let expires_at = self.now() + ttl;
+        self.state.schedule().lock().await.hold(nonce, expires_at);
+        self.journal(JournalEntry::Reserved { nonce });
+        self.emit(NonceEvent::Assigned {
+            address: self.address,
+            nonce,
+            label: None,
+        });
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+
+        Ok(nonce)
+    }
+
+    /// Extends the hold of `nonce` by `extra_ttl`, e.g. from a coordination layer seeing the
+    /// signatures come in, and returns how long it has left. Fails with
+    /// [`NonceManagerError::NotHeld`] if `nonce` isn't held, because it was never held, a
+    /// signed transaction was scheduled for it or it was already given up. A hold which
+    /// expired but wasn't given up yet can still be renewed, for `extra_ttl` from now.
+    pub async fn renew_hold(
+        &self,
+        nonce: Nonce,
+        extra_ttl: Duration,
+    ) -> Result<Duration, NonceManagerError<M>> {
+        let now = self.now();
+        let mut schedule = self.state.schedule().lock().await;
+        let expires_at = schedule
+            .extend(nonce, extra_ttl, now)
+            .ok_or(NonceManagerError::NotHeld(nonce))?;
+        Ok(expires_at.saturating_duration_since(now))
+    }
+
+    /// Gives up the holds which expired unused, as described in [`Self::hold_nonce`], and
+    /// returns their nonces, emitting a [`NonceEvent::HoldExpired`] for each. Transactions
+    /// depending on an expired hold, see [`Self::schedule_dependent`], are given up. A gap
+    /// whose filler can't be signed, e.g. when air-gapped, is released all the same, so the
+    /// transactions after it are broadcast, and the signing error is returned once every
+    /// expired hold was given up: the nonce has to be filled some other way, e.g. by sending
+    /// a transaction at it with [`Self::apply_nonce`].
+    pub async fn expire_holds(&self) -> Result<Vec<Nonce>, NonceManagerError<M>> {
+        let mut gaps = Vec::new();
+        let expired = {
+            let mut counter = self.lock_nonce().await;
+            let mut schedule = self.state.schedule().lock().await;
+            let expired = schedule.take_expired(self.now());
+            // highest first, so a run of expired holds at the top is rolled back as a whole
+            for &nonce in &expired {
+                let rolled_back = nonce.checked_increment() == Some(*counter);
+                if rolled_back {
+                    schedule.release(nonce);
+                    *counter = nonce;
+                    self.journal(JournalEntry::Released { nonce, rolled_back });
+                } else {
+                    gaps.push(nonce);
+                }
+                self.emit(NonceEvent::HoldExpired {
+                    address: self.address,
+                    nonce,
+                    rolled_back,
+                });
+            }
+            expired
+        };
+
+        let filled = self.fill_gaps(gaps).await;
+        for &nonce in &expired {
+            self.drop_dependents(nonce).await;
+        }
+        self.checkpoint_if_due().await;
+        filled.map(|_| expired)
+    }
+
+    /// Schedules an empty transfer to the managed address at each of the reserved `gaps`, so
+    /// the transactions after them aren't held up. A gap whose filler can't be signed is
+    /// released unless its own transaction came in meanwhile, and the first signing error
+    /// is returned once every gap was handled.
+    pub(crate) async fn fill_gaps(&self, gaps: Vec<Nonce>) -> Result<(), NonceManagerError<M>> {
+        let mut failed = None;
+        for nonce in gaps {
+            let mut filler = self.cancellation(&TransactionRequest::new().into());
+            filler.set_nonce(nonce);
+            match self.fill_and_sign(&mut filler, None).await {
+                // fails if the signed transaction came in while the filler was signed
+                Ok(raw_tx) => {
+                    let _ = self.schedule_signed(nonce, raw_tx).await;
+                }
+                Err(err) => {
+                    let mut schedule = self.state.schedule().lock().await;
+                    if schedule.scheduled(nonce) == Some(false) {
+                        schedule.release(nonce);
+                        self.journal(JournalEntry::Released {
+                            nonce,
+                            rolled_back: false,
+                        });
+                    }
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+}
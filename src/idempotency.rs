@@ -0,0 +1,394 @@
+use crate::durability::Syncer;
+use crate::{Durability, LockedNonceManagerMiddleware, Nonce, NonceManagerError, StateCipher};
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, TxHash};
+use ethers::utils::hex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::OwnedMutexGuard;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// What was sent for an idempotency key
+pub struct SentRecord {
+    /// The nonce the transaction was sent at
+    pub nonce: Nonce,
+    /// The hash of the transaction as first broadcast. It may since have been replaced at the
+    /// same nonce, e.g. by [`LockedNonceManagerMiddleware::speed_up`].
+    pub tx_hash: TxHash,
+}
+
+/// Maps the idempotency keys of [`LockedNonceManagerMiddleware::send_idempotent`] to what was
+/// sent for them. Implementations backed by durable storage let a restarted relayer tell
+/// whether a request it was processing made it to the chain.
+pub trait IdempotencyStore: Send + Sync + Debug {
+    /// What was sent for `key`, if anything
+    fn get(&self, key: &str) -> io::Result<Option<SentRecord>>;
+
+    /// Records that `record` was sent for `key`
+    fn put(&self, key: &str, record: SentRecord) -> io::Result<()>;
+
+    /// Records that a send for `key` is about to be broadcast, so that a process restarted
+    /// before the record of the send was put knows it may have gone out. Stores which don't
+    /// outlive the process may ignore it.
+    fn begin(&self, key: &str) -> io::Result<()> {
+        let _ = key;
+        Ok(())
+    }
+
+    /// Drops what [`Self::begin`] recorded for `key`, as nothing was sent for it
+    fn abort(&self, key: &str) -> io::Result<()> {
+        let _ = key;
+        Ok(())
+    }
+
+    /// Whether a send for `key` was begun, but neither put nor aborted
+    fn in_doubt(&self, key: &str) -> io::Result<bool> {
+        let _ = key;
+        Ok(false)
+    }
+}
+
+#[derive(Debug, Default)]
+/// An [`IdempotencyStore`] in memory, deduplicating requests for the lifetime of the process
+pub struct MemoryIdempotencyStore {
+    records: Mutex<HashMap<String, SentRecord>>,
+    begun: Mutex<HashSet<String>>,
+}
+
+impl IdempotencyStore for MemoryIdempotencyStore {
+    fn get(&self, key: &str) -> io::Result<Option<SentRecord>> {
+        Ok(self.records().get(key).copied())
+    }
+
+    fn put(&self, key: &str, record: SentRecord) -> io::Result<()> {
+        self.records().insert(key.to_owned(), record);
+        self.begun().remove(key);
+        Ok(())
+    }
+
+    fn begin(&self, key: &str) -> io::Result<()> {
+        self.begun().insert(key.to_owned());
+        Ok(())
+    }
+
+    fn abort(&self, key: &str) -> io::Result<()> {
+        self.begun().remove(key);
+        Ok(())
+    }
+
+    fn in_doubt(&self, key: &str) -> io::Result<bool> {
+        Ok(self.begun().contains(key))
+    }
+}
+
+impl MemoryIdempotencyStore {
+    fn records(&self) -> MutexGuard<'_, HashMap<String, SentRecord>> {
+        self.records
+            .lock()
+            .expect("idempotency store lock poisoned")
+    }
+
+    fn begun(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.begun.lock().expect("idempotency store lock poisoned")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// A line of a [`FileIdempotencyStore`]: the record of what was sent for `key`, or whether a
+/// send for it was begun or aborted
+struct Entry {
+    key: String,
+    #[serde(flatten)]
+    record: Option<SentRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    begun: Option<bool>,
+}
+
+#[derive(Debug)]
+/// An [`IdempotencyStore`] appending every record as a line of JSON to a file, which is read
+/// back when it is opened. Each record is flushed and synced before
+/// [`IdempotencyStore::put`] returns, unless [`Self::with_durability`] says otherwise, and so
+/// are the sends begun and aborted. A line left incomplete by a crash in the middle of a
+/// write is dropped when the store is opened.
+pub struct FileIdempotencyStore {
+    memory: MemoryIdempotencyStore,
+    file: Mutex<File>,
+    cipher: Option<Arc<dyn StateCipher>>,
+    syncer: Syncer,
+}
+
+impl FileIdempotencyStore {
+    /// Opens the store at `path`, creating the file if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with(path, None)
+    }
+
+    /// Opens the store at `path` like [`Self::open`], with each line encrypted by `cipher`
+    /// and hex encoded
+    pub fn open_encrypted(
+        path: impl AsRef<Path>,
+        cipher: impl StateCipher + 'static,
+    ) -> io::Result<Self> {
+        Self::open_with(path, Some(Arc::new(cipher)))
+    }
+
+    fn open_with(path: impl AsRef<Path>, cipher: Option<Arc<dyn StateCipher>>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        // every line is written along with its newline, so whatever follows the last one is
+        // what a crash left of a write, and its entry was never relied on
+        let written = contents
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |last| last + 1);
+        if written < contents.len() {
+            file.set_len(written as u64)?;
+        }
+
+        let memory = MemoryIdempotencyStore::default();
+        for line in contents[..written].split(|byte| *byte == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let line = match &cipher {
+                Some(cipher) => {
+                    let ciphertext = hex::decode(line).map_err(invalid_data)?;
+                    cipher.decrypt(&ciphertext).map_err(invalid_data)?
+                }
+                None => line.to_vec(),
+            };
+            let entry: Entry = serde_json::from_slice(&line)?;
+            match (entry.record, entry.begun) {
+                (Some(record), _) => memory.put(&entry.key, record)?,
+                (None, Some(true)) => memory.begin(&entry.key)?,
+                (None, _) => memory.abort(&entry.key)?,
+            }
+        }
+        Ok(Self {
+            memory,
+            file: Mutex::new(file),
+            cipher,
+            syncer: Syncer::default(),
+        })
+    }
+
+    /// Syncs records as `durability` requires, instead of on every put
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.syncer = Syncer::new(durability);
+        self
+    }
+
+    /// Appends `entry` to the file as a line
+    fn write(&self, entry: &Entry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        if let Some(cipher) = &self.cipher {
+            let ciphertext = cipher.encrypt(&line).map_err(invalid_data)?;
+            line = hex::encode(ciphertext).into_bytes();
+        }
+        line.push(b'\n');
+
+        let mut file = self.file.lock().expect("idempotency file lock poisoned");
+        file.write_all(&line)?;
+        self.syncer.sync_data(&file)
+    }
+}
+
+impl IdempotencyStore for FileIdempotencyStore {
+    fn get(&self, key: &str) -> io::Result<Option<SentRecord>> {
+        self.memory.get(key)
+    }
+
+    fn put(&self, key: &str, record: SentRecord) -> io::Result<()> {
+        self.write(&Entry {
+            key: key.to_owned(),
+            record: Some(record),
+            begun: None,
+        })?;
+        self.memory.put(key, record)
+    }
+
+    fn begin(&self, key: &str) -> io::Result<()> {
+        self.write(&Entry {
+            key: key.to_owned(),
+            record: None,
+            begun: Some(true),
+        })?;
+        self.memory.begin(key)
+    }
+
+    fn abort(&self, key: &str) -> io::Result<()> {
+        self.write(&Entry {
+            key: key.to_owned(),
+            record: None,
+            begun: Some(false),
+        })?;
+        self.memory.abort(key)
+    }
+
+    fn in_doubt(&self, key: &str) -> io::Result<bool> {
+        self.memory.in_doubt(key)
+    }
+}
+
+fn invalid_data(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// The locks making the lookup and the send of each key atomic, kept while a send holds or
+/// waits for them
+type KeyLocks = Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
+#[derive(Debug)]
+/// The store of the middleware, and the locks of the keys being sent
+pub(crate) struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    locks: KeyLocks,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self {
+            store: Arc::new(MemoryIdempotencyStore::default()),
+            locks: Default::default(),
+        }
+    }
+}
+
+impl Idempotency {
+    /// Waits for the sends of `key` in progress to finish, leaving other keys alone
+    async fn lock(&self, key: &str) -> KeyGuard<'_> {
+        let lock = self.key_locks().entry(key.to_owned()).or_default().clone();
+        KeyGuard {
+            locks: &self.locks,
+            key: key.to_owned(),
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    fn key_locks(&self) -> MutexGuard<'_, HashMap<String, Arc<tokio::sync::Mutex<()>>>> {
+        self.locks.lock().expect("idempotency lock poisoned")
+    }
+}
+
+/// Holds the lock of a key, dropping it once nothing else waits for it
+struct KeyGuard<'a> {
+    locks: &'a KeyLocks,
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().expect("idempotency lock poisoned");
+        self.guard.take();
+        // the map holds the only reference left if no other send waits for the key
+        if locks
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Records what [`Self::send_idempotent`] sends in `store`, by default in memory
+    pub fn with_idempotency_store(mut self, store: impl IdempotencyStore + 'static) -> Self {
+        self.idempotency.store = Arc::new(store);
+        self
+    }
+
+    /// What was sent for the idempotency `key`, if anything. Fails with
+    /// [`NonceManagerError::IdempotencyKeyInDoubt`] if a send for it is in progress, or was
+    /// by a process which stopped before knowing whether it went out.
+    pub fn lookup(&self, key: &str) -> Result<Option<SentRecord>, NonceManagerError<M>> {
+        let store = &self.idempotency.store;
+        if let Some(record) = store
+            .get(key)
+            .map_err(NonceManagerError::IdempotencyStoreError)?
+        {
+            return Ok(Some(record));
+        }
+        if store
+            .in_doubt(key)
+            .map_err(NonceManagerError::IdempotencyStoreError)?
+        {
+            return Err(NonceManagerError::IdempotencyKeyInDoubt(key.to_owned()));
+        }
+        Ok(None)
+    }
+
+    /// Sends `tx` unless a transaction was already sent for `key`, e.g. the id of the request
+    /// a relayer is processing, and returns what was sent for it. Sends of the same key wait
+    /// for each other, while those of different keys don't. That the send began is recorded
+    /// before the transaction is broadcast, and what was sent right after the node accepts
+    /// it, so with a persistent store a restarted process knows about every send which
+    /// returned, and fails with [`NonceManagerError::IdempotencyKeyInDoubt`] for the one it
+    /// crashed in the middle of, until it is settled with [`Self::resolve_idempotent`].
+    pub async fn send_idempotent<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        key: &str,
+        tx: T,
+        block: Option<BlockId>,
+    ) -> Result<SentRecord, NonceManagerError<M>> {
+        let _guard = self.idempotency.lock(key).await;
+        if let Some(record) = self.lookup(key)? {
+            return Ok(record);
+        }
+
+        let store = &self.idempotency.store;
+        store
+            .begin(key)
+            .map_err(NonceManagerError::IdempotencyStoreError)?;
+        let (nonce, pending) = match self.send_managed(tx.into(), block, None).await {
+            Ok(sent) => sent,
+            Err(err) => {
+                // the transaction wasn't broadcast, and if that can't be recorded the key
+                // stays in doubt, which is only cautious
+                let _ = store.abort(key);
+                return Err(err);
+            }
+        };
+        let record = SentRecord {
+            nonce,
+            tx_hash: pending.tx_hash(),
+        };
+        store
+            .put(key, record)
+            .map_err(NonceManagerError::IdempotencyStoreError)?;
+        Ok(record)
+    }
+
+    /// Settles the idempotency `key` left in doubt by a send which didn't finish, see
+    /// [`NonceManagerError::IdempotencyKeyInDoubt`], once the caller found out whether it
+    /// made it to the chain: `sent` is recorded as what was sent for it, or if `None`, the
+    /// key is free to be sent again.
+    pub async fn resolve_idempotent(
+        &self,
+        key: &str,
+        sent: Option<SentRecord>,
+    ) -> Result<(), NonceManagerError<M>> {
+        let _guard = self.idempotency.lock(key).await;
+        let store = &self.idempotency.store;
+        match sent {
+            Some(record) => store.put(key, record),
+            None => store.abort(key),
+        }
+        .map_err(NonceManagerError::IdempotencyStoreError)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError, SendEndpoint};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, TransactionReceipt, TxHash, U64};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct InFlightTx {
+    pub(crate) tx_hash: TxHash,
+    /// The transaction as sent, if known, for resubmitting it
+    pub(crate) tx: Option<TypedTransaction>,
+    /// How many times the transaction at this nonce has been replaced
+    pub(crate) attempt: u32,
+    /// Caller-provided metadata identifying the transaction
+    pub(crate) label: Option<Value>,
+    pub(crate) sent_at: Instant,
+    /// Block this transaction was broadcast at, or else the first one seen while it was
+    /// pending, for checking the SLA
+    pub(crate) first_seen_block: Option<U64>,
+    /// Whether this transaction was reported as breaching the SLA
+    pub(crate) breached_sla: bool,
+    /// When the first transaction at this nonce was sent, kept across replacements
+    pub(crate) first_sent_at: Instant,
+    /// [`Self::first_sent_at`] in seconds since the epoch, for comparing with the timestamp
+    /// of the block the transaction was mined in
+    pub(crate) first_sent_unix: u64,
+    /// Where the transaction was submitted to. Those sent through the
+    /// [`crate::PrivateRelay`] are never replaced, as that would reveal them.
+    pub(crate) endpoint: SendEndpoint,
+    reported_stuck: bool,
+}
+
+#[derive(Debug, Default)]
+/// Transactions broadcast by the manager which have not been seen in a block yet
+pub(crate) struct InFlight {
+    txs: BTreeMap<Nonce, InFlightTx>,
+}
+
+impl InFlight {
+    pub(crate) fn insert(
+        &mut self,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        tx: Option<TypedTransaction>,
+        label: Option<Value>,
+        now: Instant,
+        now_unix: u64,
+    ) {
+        let (first_sent_at, first_sent_unix) = self
+            .txs
+            .get(&nonce)
+            .map_or((now, now_unix), |tx| (tx.first_sent_at, tx.first_sent_unix));
+        self.txs.insert(
+            nonce,
+            InFlightTx {
+                tx_hash,
+                tx,
+                attempt: 0,
+                label,
+                sent_at: now,
+                first_sent_at,
+                first_sent_unix,
+                first_seen_block: None,
+                breached_sla: false,
+                endpoint: SendEndpoint::Inner,
+                reported_stuck: false,
+            },
+        );
+    }
+
+    pub(crate) fn get(&self, nonce: Nonce) -> Option<&InFlightTx> {
+        self.txs.get(&nonce)
+    }
+
+    pub(crate) fn get_mut(&mut self, nonce: Nonce) -> Option<&mut InFlightTx> {
+        self.txs.get_mut(&nonce)
+    }
+
+    /// Records how many times the transaction at `nonce` has been replaced
+    pub(crate) fn set_attempt(&mut self, nonce: Nonce, attempt: u32) {
+        if let Some(tx) = self.txs.get_mut(&nonce) {
+            tx.attempt = attempt;
+        }
+    }
+
+    /// Stops tracking the transaction at `nonce`
+    pub(crate) fn forget(&mut self, nonce: Nonce) {
+        self.txs.remove(&nonce);
+    }
+
+    /// Stops tracking the transactions at `nonce` and above
+    pub(crate) fn forget_from(&mut self, nonce: Nonce) {
+        self.txs.split_off(&nonce);
+    }
+
+    /// How long the longest-waiting transaction has been in flight at `now`
+    pub(crate) fn oldest_age(&self, now: Instant) -> Option<Duration> {
+        self.txs
+            .values()
+            .map(|tx| now.saturating_duration_since(tx.sent_at))
+            .max()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Nonce, &InFlightTx)> {
+        self.txs.iter()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Returns the nonces and hashes of the transactions broadcast by the manager which have
+    /// not been confirmed yet
+    pub fn in_flight(&self) -> Vec<(Nonce, TxHash)> {
+        self.state
+            .in_flight()
+            .iter()
+            .map(|(nonce, tx)| (*nonce, tx.tx_hash))
+            .collect()
+    }
+
+    /// Only considers transactions final, i.e. reports them as confirmed and stops tracking
+    /// them, once they are `confirmations` blocks deep, like
+    /// [`PendingTransaction::confirmations`](ethers::providers::PendingTransaction::confirmations).
+    /// By default first inclusion is enough.
+    pub fn with_confirmations(mut self, confirmations: usize) -> Self {
+        self.config_mut().confirmations = confirmations;
+        self
+    }
+
+    /// Whether a transaction mined in `block_number` has the configured number of
+    /// confirmations, given the `latest` block from
+    /// [`Self::latest_block_for_confirmations`]
+    pub(crate) fn is_final(&self, block_number: Option<U64>, latest: Option<U64>) -> bool {
+        let confirmations = self.config().confirmations;
+        if confirmations <= 1 {
+            return true;
+        }
+        match (block_number, latest) {
+            (Some(block_number), Some(latest)) => {
+                latest + 1 >= block_number + U64::from(confirmations)
+            }
+            _ => false,
+        }
+    }
+
+    /// The latest block number, if more than one confirmation is required to tell whether a
+    /// transaction is final
+    pub(crate) async fn latest_block_for_confirmations(
+        &self,
+    ) -> Result<Option<U64>, NonceManagerError<M>> {
+        if self.config().confirmations <= 1 {
+            return Ok(None);
+        }
+        self.inner
+            .get_block_number()
+            .await
+            .map(Some)
+            .map_err(FromErr::from)
+    }
+
+    /// Updates the statistics kept on confirmed transactions with the one at `nonce`, before
+    /// it stops being tracked
+    pub(crate) async fn on_confirmed(&self, nonce: Nonce, receipt: &TransactionReceipt) {
+        self.account_confirmed(nonce, receipt);
+        self.record_mine_latency(nonce, receipt).await;
+    }
+
+    /// The label the in-flight transaction at `nonce` was sent with, if any
+    pub fn label(&self, nonce: Nonce) -> Option<Value> {
+        self.state
+            .in_flight()
+            .get(nonce)
+            .and_then(|tx| tx.label.clone())
+    }
+
+    /// Looks up the receipts of the in-flight transactions. Mined transactions emit
+    /// [`NonceEvent::Confirmed`] once final and stop being tracked, while transactions
+    /// pending for longer than `stuck_after` emit a single [`NonceEvent::Stuck`], and those
+    /// breaching the [`crate::Sla`] a [`NonceEvent::SlaBreached`]. Transactions whose nonce
+    /// was consumed by something else are dropped from tracking.
+    pub async fn poll_in_flight(&self, stuck_after: Duration) -> Result<(), NonceManagerError<M>> {
+        let txs: Vec<(Nonce, InFlightTx)> = self
+            .state
+            .in_flight()
+            .iter()
+            .map(|(nonce, tx)| (*nonce, tx.clone()))
+            .collect();
+        if txs.is_empty() {
+            return Ok(());
+        }
+
+        let mined_count = self
+            .inner
+            .get_transaction_count(self.address, None)
+            .await
+            .map_err(FromErr::from)?;
+        let mut latest = self.latest_block_for_confirmations().await?;
+
+        for (nonce, tx) in txs {
+            let receipt = self.fetch_receipt(nonce, tx.tx_hash).await?;
+
+            if let Some(receipt) = receipt {
+                if !self.is_final(receipt.block_number, latest) {
+                    continue;
+                }
+                self.on_confirmed(nonce, &receipt).await;
+                self.state.in_flight().txs.remove(&nonce);
+                self.state.schedule().lock().await.settle(nonce);
+                self.emit(NonceEvent::Confirmed {
+                    address: self.address,
+                    nonce,
+                    tx_hash: tx.tx_hash,
+                    block_number: receipt.block_number,
+                    label: tx.label,
+                });
+            } else if nonce.as_u256() < mined_count {
+                // the nonce was used by a different transaction
+                self.state.in_flight().txs.remove(&nonce);
+                self.drop_dependents(nonce).await;
+            } else {
+                if !tx.reported_stuck && self.elapsed(tx.sent_at) >= stuck_after {
+                    if let Some(tracked) = self.state.in_flight().txs.get_mut(&nonce) {
+                        tracked.reported_stuck = true;
+                    }
+                    self.emit(NonceEvent::Stuck {
+                        address: self.address,
+                        nonce,
+                        tx_hash: tx.tx_hash,
+                        pending_secs: self.elapsed(tx.sent_at).as_secs(),
+                        label: tx.label,
+                    });
+                }
+                self.check_sla(nonce, &mut latest).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{NonceEvent, NonceObserver};
+use serde::Serialize;
+use std::{
+    fmt,
+    io::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// [`NonceObserver`] which writes one JSON object per line for every event, so that alerts
+/// can be built on log queries instead of parsing messages. Every line carries
+///
+/// - `ts_ms`: when the event happened, in milliseconds since the Unix epoch
+/// - `level`: `warn` for `stuck`, `sla_breached` and `conflict`, `info` otherwise
+/// - `target`: always `ethers_nonce`
+/// - `event` and the fields of the serialized [`NonceEvent`]
+///
+/// ```json
+/// {"ts_ms":1700000000000,"level":"info","target":"ethers_nonce","event":"resynced",
+///  "address":"0x…","previous":"0x5","nonce":"0x7"}
+/// ```
+///
+/// (wrapped here, written on a single line)
+pub struct JsonLogger {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl fmt::Debug for JsonLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLogger").finish_non_exhaustive()
+    }
+}
+
+impl JsonLogger {
+    /// Logs to `writer`, e.g. a file. Lines are written unbuffered, one `write` each.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Logs to standard error
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts_ms: u128,
+    level: &'static str,
+    target: &'static str,
+    #[serde(flatten)]
+    event: &'a NonceEvent,
+}
+
+impl NonceObserver for JsonLogger {
+    fn on_event(&self, event: &NonceEvent) {
+        let level = match event {
+            NonceEvent::Stuck { .. }
+            | NonceEvent::SlaBreached { .. }
+            | NonceEvent::Conflict { .. }
+            | NonceEvent::HoldExpired { .. }
+            | NonceEvent::PrivateExpired { .. }
+            | NonceEvent::DependencyFailed { .. }
+            | NonceEvent::BlobNotReplaced { .. }
+            | NonceEvent::FeeCapReached { .. }
+            | NonceEvent::SimulationSkipped { .. }
+            | NonceEvent::ShadowDiverged { .. } => "warn",
+            _ => "info",
+        };
+        let line = Line {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis()),
+            level,
+            target: "ethers_nonce",
+            event,
+        };
+        let mut line = match serde_json::to_vec(&line) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        line.push(b'\n');
+        // losing a log line is no reason to fail a transaction
+        let _ = self
+            .writer
+            .lock()
+            .expect("log writer lock poisoned")
+            .write_all(&line);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{Histogram, LockedNonceManagerMiddleware, Nonce};
+use ethers::providers::Middleware;
+use ethers::types::TransactionReceipt;
+use std::{sync::Mutex, time::Duration};
+
+/// Upper bounds of the histogram buckets. Mainnet blocks come every 12 seconds, so the
+/// buckets are roughly one to fifty blocks wide.
+const BUCKET_BOUNDS: [Duration; 10] = [
+    Duration::from_secs(6),
+    Duration::from_secs(12),
+    Duration::from_secs(24),
+    Duration::from_secs(36),
+    Duration::from_secs(60),
+    Duration::from_secs(120),
+    Duration::from_secs(180),
+    Duration::from_secs(300),
+    Duration::from_secs(600),
+    Duration::from_secs(1800),
+];
+
+#[derive(Debug)]
+pub(crate) struct LatencyRecorder {
+    histogram: Mutex<Histogram>,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        Self {
+            histogram: Mutex::new(Histogram::new(&BUCKET_BOUNDS)),
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// How long the confirmed transactions took from being sent at their nonce to the
+    /// timestamp of the block they were first confirmed in, for tuning the fee policy or
+    /// noticing inclusion slowing down before the queue jams. Replacements count from the
+    /// first transaction at the nonce. Only transactions seen confirmed by
+    /// [`Self::poll_in_flight`] or [`Self::reconcile`] are recorded.
+    pub fn mine_latency(&self) -> Histogram {
+        self.latency
+            .histogram
+            .lock()
+            .expect("latency histogram lock poisoned")
+            .clone()
+    }
+
+    /// Records the latency of the confirmed transaction at `nonce`, up to the timestamp of
+    /// the block of its `receipt`, or up to now if the block can't be fetched. Must be
+    /// called before the transaction stops being tracked.
+    pub(crate) async fn record_mine_latency(&self, nonce: Nonce, receipt: &TransactionReceipt) {
+        let (first_sent_at, first_sent_unix) = match self.state.in_flight().get(nonce) {
+            Some(tracked) => (tracked.first_sent_at, tracked.first_sent_unix),
+            None => return,
+        };
+        let block = match receipt.block_number {
+            Some(block_number) => self.inner.get_block(block_number).await.ok().flatten(),
+            None => None,
+        };
+        let latency = match block {
+            Some(block) => {
+                let mined_at = block.timestamp.try_into().unwrap_or(u64::MAX);
+                Duration::from_secs(mined_at.saturating_sub(first_sent_unix))
+            }
+            None => self.elapsed(first_sent_at),
+        };
+        self.latency
+            .histogram
+            .lock()
+            .expect("latency histogram lock poisoned")
+            .record(latency);
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send};
+    use crate::{MockClock, SimulatedChain};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn latency_runs_up_to_the_block_timestamp() {
+        let chain = SimulatedChain::new(3);
+        let clock = MockClock::new();
+        let client = client(&chain).with_clock(clock.clone());
+        send(&client).await;
+        chain.mine_blocks(3);
+
+        // polled long after the block, which is what counts
+        clock.advance(Duration::from_secs(600));
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        let latency = client.mine_latency();
+        assert_eq!(latency.count, 1);
+        assert!(
+            latency.max() <= Some(Duration::from_secs(13)),
+            "{latency:?}"
+        );
+    }
+}
@@ -1,18 +1,265 @@
 use async_trait::async_trait;
 use ethers::providers::{FromErr, Middleware, PendingTransaction};
 use ethers::types::{transaction::eip2718::TypedTransaction, *};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::RwLock;
+
+#[cfg(feature = "encryption")]
+mod aes;
+#[cfg(feature = "encryption")]
+pub use aes::AesGcmCipher;
+
+mod air_gap;
+
+mod arbitrum;
+pub use arbitrum::{ArbitrumDelayedInbox, ARBITRUM_ONE_INBOX};
+
+mod authorization;
+pub use authorization::Authorization;
+
+mod block_cap;
+
+mod budget;
+pub use budget::{BudgetUnit, GasBudget, SpendLimit};
+
+mod bump;
+pub use bump::{BumpPolicy, BumpPreset, EscalationSchedule, MarketBump, TxFees};
+
+mod cancel;
+
+mod chain_id;
+
+mod cipher;
+pub use cipher::{CipherError, StateCipher};
+
+mod clock;
+pub use clock::{Clock, MockClock, SystemClock};
+
+mod concurrency;
+pub use concurrency::AdaptiveConcurrency;
+
+mod config;
+pub use config::{
+    BackwardNoncePolicy, ChainProfile, NonceManagerConfig, PresetNoncePolicy, RetryPolicy,
+};
+
+mod conflict;
+
+mod contention;
+pub use contention::LockContention;
+
+mod contract;
+
+mod costs;
+pub use costs::LabelCost;
+
+mod dependency;
+
+mod drift;
+use drift::Drift;
+pub use drift::{DriftThrottle, PendingAheadLimit};
+
+mod durability;
+pub use durability::Durability;
+
+mod entry_point;
+pub use entry_point::EntryPointNonceManager;
+
+mod estimate;
+
+mod events;
+pub use events::{NonceEvent, NonceObserver};
+
+mod ext;
+pub use ext::NonceManagerExt;
+
+mod fanout;
+pub use fanout::BroadcastEndpoint;
+
+mod fill;
+
+mod forwarder;
+pub use forwarder::ForwarderNonceManager;
+
+mod funds;
+
+mod health;
+pub use health::HealthReport;
+
+mod histogram;
+pub use histogram::Histogram;
+
+mod hold;
+
+mod idempotency;
+pub use idempotency::{FileIdempotencyStore, IdempotencyStore, MemoryIdempotencyStore, SentRecord};
+
+mod in_flight;
+
+#[cfg(feature = "json-logs")]
+mod json_log;
+#[cfg(feature = "json-logs")]
+pub use json_log::JsonLogger;
+
+mod latency;
+
+mod managed;
+pub use managed::ManagedPendingTransaction;
+
+mod multicall;
+pub use multicall::{BatchedCall, MulticallBatching, MULTICALL3_ADDRESS};
+
+mod nonce;
+pub use nonce::{InvalidNonce, Nonce};
+
+mod offline;
+
+mod options;
+pub use options::SendOptions;
+
+#[cfg(feature = "opentelemetry")]
+mod otel;
+#[cfg(feature = "opentelemetry")]
+pub use otel::TracedPendingTransaction;
+
+mod permit;
+pub use permit::PermitNonceTracker;
+
+mod policy;
+pub use policy::{
+    AllowedDestinations, AllowedSelectors, Denylist, MaxCalldataSize, PolicyViolation, TxPolicy,
+};
+
+mod priority;
+use priority::Ticket;
+
+mod private;
+pub use private::PrivateRelay;
+#[cfg(feature = "mev-share")]
+pub use private::{MevShareHint, MevShareRelay};
+
+mod receipts;
+pub use receipts::ReceiptCacheConfig;
+
+mod reconcile;
+pub use reconcile::ReconciliationReport;
+
+mod resubmit;
+
+mod resync;
+
+mod rewind;
+
+mod rpc_batch;
+pub use rpc_batch::BatchTransport;
+#[cfg(feature = "http-batch")]
+pub use rpc_batch::HttpBatchTransport;
+
+mod rpc_error;
+
+mod rpc_latency;
+pub use rpc_latency::{SendEndpoint, SendLatency};
+
+mod rt;
+
+mod safe;
+pub use safe::SafeNonceManager;
+
+mod scheduler;
+
+mod sequences;
+
+mod shadow;
+
+mod shutdown;
+pub use shutdown::{LeftoverPolicy, ShutdownReport};
+
+mod signing;
+
+mod simulation;
+pub use simulation::{QueueSimulation, RevertReason};
+
+mod sla;
+pub use sla::Sla;
+
+mod snapshot;
+pub use snapshot::{PrivateTransaction, ReservedNonce, StateSnapshot};
+
+mod source;
+pub use source::{HighestTransactionCount, NonceSource, TransactionCount};
+
+mod state;
+pub use state::SharedNonceState;
+
+mod status;
+pub use status::NonceStatus;
+
+mod store;
+pub use store::{FileStateStore, JournalEntry, StateStore};
+
+mod sync;
+
+mod template;
+pub use template::{CalldataBuilder, TxTemplate};
+
+#[cfg(feature = "testkit")]
+mod testkit;
+#[cfg(feature = "testkit")]
+pub use testkit::{CallOutcome, SimError, SimulatedChain};
+
+mod tracker;
+pub use tracker::NonceTracker;
+
+mod trigger;
+pub use trigger::Trigger;
+
+mod upstream;
+
+mod wal;
+
+#[cfg(feature = "webhook")]
+mod webhook;
+#[cfg(feature = "webhook")]
+pub use webhook::{WebhookConfig, WebhookNotifier};
 
 #[derive(Debug)]
 /// Middleware used for calculating nonces locally, useful for signing multiple
 /// consecutive transactions without waiting for them to hit the mempool.
 pub struct LockedNonceManagerMiddleware<M> {
     inner: M,
-    initialized: AtomicBool,
-    nonce: RwLock<U256>,
+    state: SharedNonceState,
     address: Address,
+    observers: Vec<Arc<dyn NonceObserver>>,
+    config: std::sync::RwLock<NonceManagerConfig>,
+    source: Arc<dyn NonceSource<M>>,
+    endpoints: Vec<Arc<dyn BroadcastEndpoint>>,
+    fallback_endpoints: Vec<Arc<dyn BroadcastEndpoint>>,
+    block_window: block_cap::BlockWindow,
+    gas_window: budget::Window,
+    spend_window: budget::Window,
+    policies: Vec<Arc<dyn TxPolicy>>,
+    signer: tokio::sync::Mutex<()>,
+    fill_support: fill::FillSupport,
+    gas_oracle: Option<Arc<dyn ethers::middleware::gas_oracle::GasOracle>>,
+    bump_policy: Arc<dyn BumpPolicy>,
+    receipts: receipts::ReceiptCache,
+    costs: costs::CostLedger,
+    latency: latency::LatencyRecorder,
+    multicall: multicall::Batcher,
+    fee_queue: priority::FeeQueue,
+    arrivals: priority::FeeQueue,
+    contention: contention::ContentionRecorder,
+    clock: Arc<dyn Clock>,
+    idempotency: idempotency::Idempotency,
+    chain_id_check: chain_id::ChainIdCheck,
+    state_store: Option<Arc<dyn StateStore>>,
+    shutdown: shutdown::Shutdown,
+    batch_transport: Option<Arc<dyn BatchTransport>>,
+    checkpoints: store::Checkpoints,
+    send_latency: rpc_latency::SendLatencyRecorder,
+    concurrency: concurrency::ConcurrencyWindow,
+    private_relay: Option<Arc<dyn PrivateRelay>>,
+    templates: std::collections::HashMap<String, TxTemplate>,
 }
 
 impl<M> LockedNonceManagerMiddleware<M>
@@ -22,46 +269,541 @@ where
     /// Instantiates the nonce manager with a 0 nonce. The `address` should be the
     /// address which you'll be sending transactions from
     pub fn new(inner: M, address: Address) -> Self {
+        Self::new_with_state(inner, SharedNonceState::new(address))
+    }
+
+    /// Instantiates the nonce manager on top of an existing nonce state. All middleware
+    /// instances created from clones of the same `state` share a single counter, so they
+    /// can wrap different inner middlewares while sending from the same address.
+    pub fn new_with_state(inner: M, state: SharedNonceState) -> Self {
+        let address = state.address();
         Self {
-            initialized: false.into(),
-            nonce: RwLock::new(U256::zero()),
-            inner,
             address,
+            inner,
+            state,
+            observers: Vec::new(),
+            config: std::sync::RwLock::new(NonceManagerConfig::new(address)),
+            source: Arc::new(TransactionCount),
+            endpoints: Vec::new(),
+            fallback_endpoints: Vec::new(),
+            block_window: Default::default(),
+            gas_window: Default::default(),
+            spend_window: Default::default(),
+            policies: Vec::new(),
+            signer: Default::default(),
+            fill_support: Default::default(),
+            gas_oracle: None,
+            bump_policy: Arc::new(MarketBump),
+            receipts: Default::default(),
+            costs: Default::default(),
+            latency: Default::default(),
+            multicall: Default::default(),
+            fee_queue: Default::default(),
+            arrivals: Default::default(),
+            contention: Default::default(),
+            clock: Arc::new(SystemClock),
+            idempotency: Default::default(),
+            chain_id_check: Default::default(),
+            state_store: None,
+            shutdown: Default::default(),
+            batch_transport: None,
+            checkpoints: Default::default(),
+            send_latency: Default::default(),
+            concurrency: Default::default(),
+            private_relay: None,
+            templates: Default::default(),
         }
     }
 
+    /// Instantiates the nonce manager for the address `inner` sends from, for signers which
+    /// only learn their address once connected, e.g. AWS or GCP KMS keys. The address is
+    /// taken from [`Middleware::default_sender`], as set by a `SignerMiddleware` built around
+    /// the connected signer. Fails with [`NonceManagerError::NoSender`] if there is none,
+    /// rather than guessing among the accounts the node manages.
+    pub async fn connect(inner: M) -> Result<Self, NonceManagerError<M>> {
+        let address = inner.default_sender().ok_or(NonceManagerError::NoSender)?;
+        Ok(Self::new(inner, address))
+    }
+
+    /// Registers an observer which is notified of every [`NonceEvent`] emitted by this
+    /// middleware
+    pub fn with_observer(mut self, observer: impl NonceObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Reads the chain nonce from `source` instead of the inner middleware's
+    /// `eth_getTransactionCount`
+    pub fn with_nonce_source(mut self, source: impl NonceSource<M> + 'static) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    /// Returns a handle to the nonce state, which can be passed to
+    /// [`LockedNonceManagerMiddleware::new_with_state`] to share it with another stack
+    pub fn state(&self) -> SharedNonceState {
+        self.state.clone()
+    }
+
+    /// Unwraps the inner middleware, dropping the nonce state of this middleware
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Unwraps the inner middleware and the nonce state, e.g. to rebuild the stack around
+    /// another middleware with [`LockedNonceManagerMiddleware::new_with_state`] without losing
+    /// the counter
+    pub fn into_parts(self) -> (M, SharedNonceState) {
+        (self.inner, self.state)
+    }
+
     /// initialize the nonce
     pub async fn initialize_nonce(
         &self,
         block: Option<BlockId>,
-    ) -> Result<U256, NonceManagerError<M>> {
+    ) -> Result<Nonce, NonceManagerError<M>> {
         self.get_or_init_nonce(block).await
     }
 
     /// Returns the next nonce to be used
-    pub async fn next(&self) -> U256 {
-        let read_guard = self.nonce.read().await;
-        *read_guard
+    pub async fn next(&self) -> Nonce {
+        self.state.next().await
+    }
+
+    /// Returns the next nonce to be used without waiting, or `None` if the counter isn't
+    /// initialized yet or a send holds it, for latency-sensitive paths which would rather skip
+    /// than wait
+    pub fn try_next(&self) -> Option<Nonce> {
+        self.state.try_next()
+    }
+
+    /// Assigns the next nonce to `tx` and advances the counter, without filling anything else
+    /// or broadcasting, for callers which price and submit transactions themselves. Submitted
+    /// through this manager's [`Middleware::send_raw_transaction`], the transaction is tracked
+    /// as in flight like any other managed one; submitted elsewhere, it is only seen once
+    /// mined. The nonce is used up either way, so a transaction which ends up not being sent
+    /// leaves a gap, which [`Self::notify_reconnected`] closes.
+    pub async fn apply_nonce(
+        &self,
+        tx: &mut TypedTransaction,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        self.get_or_init_nonce(None).await?;
+        let mut write_guard = self.lock_for_assignment(Some(tx), arrival).await?;
+        let nonce = *write_guard;
+        tx.set_nonce(nonce);
+        *write_guard = Self::increment(nonce)?;
+        self.journal(JournalEntry::Assigned { nonce });
+        self.emit(NonceEvent::Assigned {
+            address: self.address,
+            nonce,
+            label: None,
+        });
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+        Ok(nonce)
+    }
+
+    /// Runs `f` with the next nonce, committing it if `f` succeeds and handing it out again
+    /// otherwise. No other nonce is assigned while `f` runs, so `f` must not send through
+    /// this manager, but can sign and submit with [`Middleware::send_raw_transaction`]. If
+    /// `f` panics or is cancelled it may have broadcast already, so the counter is resynced
+    /// with the chain before the next assignment, which hands the nonce out again unless the
+    /// chain saw it used.
+    ///
+    /// ```ignore
+    /// let tx_hash = nonce_manager
+    ///     .with_nonce(|nonce| async move {
+    ///         let raw_tx = sign(tx.nonce(nonce)).await?;
+    ///         let pending = nonce_manager.send_raw_transaction(raw_tx).await?;
+    ///         Ok::<_, MyError>(pending.tx_hash())
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn with_nonce<F, Fut, T, E>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(Nonce) -> Fut,
+        Fut: std::future::Future<Output = Result<T, E>>,
+        E: From<NonceManagerError<M>>,
+    {
+        let arrival = self.arrival_ticket();
+        self.get_or_init_nonce(None).await?;
+        let mut write_guard = self.lock_for_assignment(None, arrival).await?;
+        let nonce = *write_guard;
+
+        let broadcast_guard = self.state.broadcast_guard();
+        let result = f(nonce).await;
+        broadcast_guard.disarm();
+        if result.is_ok() {
+            *write_guard = Self::increment(nonce)?;
+            self.journal(JournalEntry::Assigned { nonce });
+            self.emit(NonceEvent::Assigned {
+                address: self.address,
+                nonce,
+                label: None,
+            });
+        }
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+        result
     }
 
     async fn get_or_init_nonce(
         &self,
         block: Option<BlockId>,
-    ) -> Result<U256, NonceManagerError<M>> {
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        self.check_chain_id().await?;
         // initialize the nonce the first time the manager is called
-        if !self.initialized.load(Ordering::SeqCst) {
-            let nonce = self
-                .inner
-                .get_transaction_count(self.address, block)
-                .await
-                .map_err(FromErr::from)?;
-            let mut write_guard = self.nonce.write().await;
-            *write_guard = nonce;
-            self.initialized.store(true, Ordering::SeqCst);
+        if !self.state.is_initialized() {
+            let nonce = match self.config().initial_nonce {
+                Some(nonce) => nonce,
+                None => self.chain_nonce(self.block_or_default(block)).await?,
+            };
+            let mut write_guard = self.lock_nonce().await;
+            // another middleware sharing the state may have won the race and already
+            // handed out nonces, in which case its view is the authoritative one
+            if !self.state.is_initialized() {
+                *write_guard = nonce;
+                self.state.set_initialized();
+            }
         }
         // return current nonce
         Ok(self.next().await)
     }
+
+    fn block_or_default(&self, block: Option<BlockId>) -> Option<BlockId> {
+        block.or_else(|| self.config().block_tag.map(Into::into))
+    }
+
+    /// Fetches the transaction count of the managed address from the [`NonceSource`]
+    pub(crate) async fn chain_nonce(
+        &self,
+        block: Option<BlockId>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        self.ensure_connected()?;
+        let nonce = self
+            .source
+            .transaction_count(&self.inner, self.address, block)
+            .await?;
+        Nonce::new(nonce).map_err(NonceManagerError::InvalidNonce)
+    }
+
+    /// Takes the nonce lock to assign the nonce behind it, once it is the turn of `tx` if fee
+    /// or FIFO ordering is enabled, where `arrival` is the ticket taken by
+    /// [`Self::arrival_ticket`] and calls without a transaction bid nothing. Every path which
+    /// moves the counter goes through here, and fails with [`NonceManagerError::ShuttingDown`]
+    /// once [`Self::shutdown`] was called. If an earlier send was cancelled while its
+    /// transaction may have reached the node, the counter is first brought up to date with
+    /// the pending transaction count. With a [`PendingAheadLimit`], a [`DriftThrottle`] or
+    /// [`AdaptiveConcurrency`] configured, the nonce has to be within them: the chain is read
+    /// and any wait or delay sits out without the lock, which is only held to compare the
+    /// counter against the chain.
+    pub(crate) async fn lock_for_assignment<'a>(
+        &'a self,
+        tx: Option<&TypedTransaction>,
+        arrival: Option<Ticket<'a>>,
+    ) -> Result<sync::RwLockWriteGuard<'a, Nonce>, NonceManagerError<M>> {
+        let _ticket = self.fee_ticket(tx, arrival).await;
+        let interval = self.provider().get_interval();
+        let mut throttled = false;
+        loop {
+            let confirmed = self.confirmed_for_drift().await?;
+            let mut counter = self.lock_nonce().await;
+            // a shutdown closes under the lock, so no assignment slips past it
+            self.ensure_accepting()?;
+            if self.state.needs_resync() {
+                let chain_nonce = self.pending_chain_nonce().await?;
+                if chain_nonce > *counter {
+                    *counter = chain_nonce;
+                }
+                // only cleared once the resync went through, so that cancelling it is harmless
+                self.state.clear_needs_resync();
+            }
+            let confirmed = match confirmed {
+                Some(confirmed) => confirmed,
+                None => return Ok(counter),
+            };
+            let wait = match self.check_drift(*counter, confirmed)? {
+                Drift::Within => return Ok(counter),
+                // a send is only throttled once, the nonce it ends up with may be higher
+                Drift::Throttled(_) if throttled => return Ok(counter),
+                Drift::Throttled(delay) => {
+                    throttled = true;
+                    delay
+                }
+                Drift::Ahead => interval,
+            };
+            drop(counter);
+            self.sleep(wait).await;
+        }
+    }
+
+    /// Sends `tx` through the inner middleware, assigning it the next nonce unless the caller
+    /// already set one. Returns the nonce the transaction was sent with. If the node rejects
+    /// the transaction because the chain nonce raced ahead, it is resent at the chain's nonce
+    /// as allowed by the [`RetryPolicy`].
+    ///
+    /// The counter is only advanced once the node accepted the transaction, or once it was
+    /// queued while the RPC is down if offline queueing is enabled. If the returned
+    /// future is dropped while the transaction may already have been broadcast, the state is
+    /// flagged so that the next assignment resyncs with the chain instead of reusing the nonce.
+    pub(crate) async fn send_managed(
+        &self,
+        tx: TypedTransaction,
+        block: Option<BlockId>,
+        label: Option<serde_json::Value>,
+    ) -> Result<(Nonce, PendingTransaction<'_, M::Provider>), NonceManagerError<M>> {
+        let retry = self.config().retry;
+        self.send_managed_with(tx, block, label, retry).await
+    }
+
+    /// Sends `tx` through the same checks as the managed sends, without waiting for its turn
+    /// or touching the counter, as the nonce is left to the caller or the inner middleware.
+    /// The transaction is tracked in flight if its nonce is given in `recorded`.
+    pub(crate) async fn send_unassigned(
+        &self,
+        mut tx: TypedTransaction,
+        block: Option<BlockId>,
+        recorded: Option<(Nonce, Option<serde_json::Value>)>,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        self.check_chain_id().await?;
+        let slot = self.wait_for_block_slot().await?;
+        let write_guard = self.lock_nonce().await;
+        self.ensure_accepting()?;
+        let charges = self.check_limits(&mut tx, block).await?;
+        self.simulate(&tx).await?;
+        let signed = self.write_ahead(&mut tx, block).await?;
+        let pending = match self.broadcast(&tx, block, signed).await {
+            Ok(pending) => pending,
+            Err(err) => return Err(self.rejected(&tx, err, false).await),
+        };
+        slot.used();
+        self.charge(charges);
+        if let Some((nonce, label)) = recorded {
+            self.record_broadcast(nonce, pending.tx_hash(), Some(&tx), label)
+                .await;
+        }
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+        Ok(pending)
+    }
+
+    /// Sends `tx` like [`Self::send_managed`], recovering from conflicts as allowed by `retry`
+    /// instead of the configured [`RetryPolicy`]
+    pub(crate) async fn send_managed_with(
+        &self,
+        mut tx: TypedTransaction,
+        block: Option<BlockId>,
+        label: Option<serde_json::Value>,
+        retry: RetryPolicy,
+    ) -> Result<(Nonce, PendingTransaction<'_, M::Provider>), NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        self.ensure_connected()?;
+        self.ensure_accepting()?;
+        let block = self.block_or_default(block);
+        self.check_policies(&mut tx).await?;
+        self.check_balance(&tx).await?;
+        if let Some(nonce) = Self::preset_nonce(&tx)? {
+            match self.config().preset_nonce {
+                PresetNoncePolicy::Skip => {}
+                PresetNoncePolicy::Record => {
+                    let pending = self.send_recorded(tx, nonce, block, label, arrival).await?;
+                    return Ok((nonce, pending));
+                }
+                PresetNoncePolicy::Reject => {
+                    return Err(NonceManagerError::PresetNonceRejected(nonce))
+                }
+            }
+            // the caller picked the nonce, so the counter is left untouched and the send
+            // doesn't wait for its turn
+            drop(arrival);
+            let pending = self
+                .send_unassigned(tx, block, Some((nonce, label)))
+                .await?;
+            return Ok((nonce, pending));
+        }
+
+        if self.config().shadow {
+            drop(arrival);
+            return self.send_shadowed(tx, block, label).await;
+        }
+
+        // queued transactions are capped when they are flushed
+        let offline = self.still_offline().await;
+        let slot = if offline {
+            None
+        } else {
+            Some(self.wait_for_block_slot().await?)
+        };
+        self.get_or_init_nonce(block).await?;
+        let mut write_guard = self.lock_for_assignment(Some(&tx), arrival).await?;
+        let mut nonce = *write_guard;
+        tx.set_nonce(nonce);
+        self.fill_from_node(&mut tx).await;
+        let charges = self.check_limits(&mut tx, block).await?;
+        self.simulate(&tx).await?;
+        self.emit(NonceEvent::Assigned {
+            address: self.address,
+            nonce,
+            label: label.clone(),
+        });
+
+        let broadcast_guard = self.state.broadcast_guard();
+        let mut retries = 0;
+        let mut queued = offline;
+        let pending = loop {
+            if queued {
+                match self.queue_offline(&mut tx, nonce, block).await {
+                    Ok(pending) => break pending,
+                    Err(err) => {
+                        if offline {
+                            // nothing was broadcast, so the nonce is still free
+                            broadcast_guard.disarm();
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+            let signed = match self.write_ahead(&mut tx, block).await {
+                Ok(signed) => signed,
+                Err(err) => {
+                    // the transaction wasn't sent without its journal entry
+                    broadcast_guard.disarm();
+                    return Err(err);
+                }
+            };
+            let err = match self.broadcast(&tx, block, signed).await {
+                Ok(pending) => break pending,
+                Err(err) => err,
+            };
+
+            let chain_nonce = match self.pending_chain_nonce().await {
+                Ok(chain_nonce) => chain_nonce,
+                // neither the broadcast nor the lookup went through, so the RPC is taken to
+                // be down
+                Err(_) if self.config().queue_when_offline => {
+                    queued = true;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let conflict = self.is_conflict(nonce, chain_nonce);
+            // a chain behind the counter only matters if that is what the node rejected
+            let rewound = if conflict || !rpc_error::is_nonce_error(&err) {
+                false
+            } else {
+                match self.follow_backwards(nonce, chain_nonce).await {
+                    Ok(rewound) => rewound,
+                    Err(behind) => {
+                        broadcast_guard.disarm();
+                        return Err(behind);
+                    }
+                }
+            };
+            if !conflict && !rewound {
+                // the node rejected the transaction for another reason
+                broadcast_guard.disarm();
+                return Err(self.rejected(&tx, err, false).await);
+            }
+            // nonces below the chain's count are used up either way, and after a rewind the
+            // ones above it are free again
+            *write_guard = chain_nonce;
+            if retries == retry.max_conflict_retries {
+                broadcast_guard.disarm();
+                return Err(FromErr::from(err));
+            }
+            retries += 1;
+
+            nonce = chain_nonce;
+            tx.set_nonce(nonce);
+            let backoff = retry.backoff();
+            if !backoff.is_zero() {
+                self.sleep(backoff).await;
+            }
+        };
+
+        *write_guard = Self::increment(nonce)?;
+        broadcast_guard.disarm();
+        self.charge(charges);
+        if !queued {
+            if let Some(slot) = slot {
+                slot.used();
+            }
+            self.record_broadcast(nonce, pending.tx_hash(), Some(&tx), label)
+                .await;
+        }
+        drop(write_guard);
+        self.checkpoint_if_due().await;
+
+        Ok((nonce, pending))
+    }
+
+    /// Tracks the transaction at `nonce` as in flight, keeping `tx` around to resubmit it
+    pub(crate) async fn record_broadcast(
+        &self,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        tx: Option<&TypedTransaction>,
+        label: Option<serde_json::Value>,
+    ) {
+        self.record_sent(nonce, tx_hash, tx, label, SendEndpoint::Inner)
+            .await
+    }
+
+    /// Tracks the transaction at `nonce` as in flight like [`Self::record_broadcast`], as
+    /// submitted to `endpoint`
+    pub(crate) async fn record_sent(
+        &self,
+        nonce: Nonce,
+        tx_hash: TxHash,
+        tx: Option<&TypedTransaction>,
+        label: Option<serde_json::Value>,
+        endpoint: SendEndpoint,
+    ) {
+        let broadcast_block = self.sla_block().await;
+        {
+            let mut in_flight = self.state.in_flight();
+            in_flight.insert(
+                nonce,
+                tx_hash,
+                tx.cloned(),
+                label.clone(),
+                self.now(),
+                self.unix_secs(),
+            );
+            if let Some(tracked) = in_flight.get_mut(nonce) {
+                tracked.first_seen_block = broadcast_block;
+                tracked.endpoint = endpoint;
+            }
+        }
+        self.journal(JournalEntry::Broadcast { nonce, tx_hash });
+        self.emit(NonceEvent::Broadcast {
+            address: self.address,
+            nonce,
+            tx_hash,
+            label,
+        });
+    }
+
+    pub(crate) fn emit(&self, event: NonceEvent) {
+        for observer in &self.observers {
+            observer.on_event(&event);
+        }
+    }
+
+    fn increment(nonce: Nonce) -> Result<Nonce, NonceManagerError<M>> {
+        nonce
+            .checked_increment()
+            .ok_or_else(|| NonceManagerError::InvalidNonce(InvalidNonce(nonce.as_u256() + 1)))
+    }
+
+    /// Returns the nonce set by the caller, if any, rejecting values which could never be
+    /// mined
+    fn preset_nonce(tx: &TypedTransaction) -> Result<Option<Nonce>, NonceManagerError<M>> {
+        tx.nonce()
+            .map(|nonce| Nonce::new(*nonce).map_err(NonceManagerError::InvalidNonce))
+            .transpose()
+    }
 }
 
 #[derive(Error, Debug)]
@@ -70,6 +812,172 @@ pub enum NonceManagerError<M: Middleware> {
     /// Thrown when the internal middleware errors
     #[error("{0}")]
     MiddlewareError(M::Error),
+    /// Thrown when a nonce read from the chain or provided by the caller, or the result of
+    /// incrementing the local counter, is outside of the valid range
+    #[error("{0}")]
+    InvalidNonce(InvalidNonce),
+    /// Thrown when renewing a nonce which isn't held, see
+    /// [`LockedNonceManagerMiddleware::renew_hold`]
+    #[error("nonce {0} is not held")]
+    NotHeld(Nonce),
+    /// Thrown when scheduling a transaction at a nonce which was not reserved
+    #[error("nonce {0} is not reserved")]
+    NotReserved(Nonce),
+    /// Thrown when a transaction's nonce was set by the caller and the
+    /// [`PresetNoncePolicy`] is to reject those
+    #[error("nonce {0} was set by the caller")]
+    PresetNonceRejected(Nonce),
+    /// Thrown when sending at a caller-chosen nonce which is reserved
+    #[error("nonce {0} is reserved")]
+    NonceReserved(Nonce),
+    /// Thrown when sending at a caller-chosen nonce which a managed transaction is already
+    /// in flight at
+    #[error("a managed transaction is already in flight at nonce {0}")]
+    NonceInFlight(Nonce),
+    /// Thrown when there is no in-flight transaction at the nonce which could be replaced
+    #[error("no replaceable transaction is in flight at nonce {0}")]
+    NotReplaceable(Nonce),
+    /// Thrown when the in-flight transaction at the nonce is an EIP-4844 blob transaction.
+    /// Nodes only replace those with another blob transaction paying at least twice the blob
+    /// fee, which can't be built with ethers' transaction types.
+    #[error("the transaction at nonce {0} carries blobs, which can't be replaced")]
+    BlobNotReplaceable(Nonce),
+    /// Thrown when replacing the transaction at a nonce would take fees above the cap of the
+    /// [`BumpPolicy`]
+    #[error("replacing the transaction at nonce {nonce} would pay more than {cap} per gas")]
+    FeeCapReached { nonce: Nonce, cap: U256 },
+    /// Thrown when a signed transaction scheduled at a nonce can't be decoded. Its reservation
+    /// was released.
+    #[error("the transaction scheduled at nonce {0} can't be decoded")]
+    UndecodableTransaction(Nonce),
+    /// Thrown when scheduling a second transaction at the same reserved nonce
+    #[error("a transaction is already scheduled at nonce {0}")]
+    AlreadyScheduled(Nonce),
+    /// Thrown when an ERC-4337 nonce key does not fit in 192 bits
+    #[error("nonce key {0} exceeds 192 bits")]
+    InvalidNonceKey(U256),
+    /// Thrown when the 64-bit sequence of an ERC-4337 nonce key has been used up
+    #[error("the nonce sequence of key {0} is exhausted")]
+    SequenceExhausted(U256),
+    /// Thrown when a nonce kept by a contract, e.g. of a forwarder or a Safe, can't be
+    /// handed out because the one after it would overflow
+    #[error("nonce {0} is the last one the contract can hand out")]
+    NonceOverflow(U256),
+    /// Thrown when a contract returned data which could not be decoded
+    #[error("{0}")]
+    AbiError(ethers::abi::Error),
+    /// Thrown when a transaction would exceed the gas budget of the current window
+    #[error(
+        "gas budget exceeded: {required} required but only {remaining} left in the current window"
+    )]
+    BudgetExceeded { required: U256, remaining: U256 },
+    /// Thrown when a transaction would exceed the value spend limit of the current window
+    #[error("spend limit exceeded: {required} wei required but only {remaining} left in the current window")]
+    SpendLimitExceeded { required: U256, remaining: U256 },
+    /// Thrown when a [`TxPolicy`] rejected the transaction before it was assigned a nonce
+    #[error("rejected by policy: {0}")]
+    PolicyRejected(PolicyViolation),
+    /// Thrown when the [`StateStore`] can't be read or written
+    #[error("state store: {0}")]
+    StateStoreError(std::io::Error),
+    /// Thrown when a send with a [`SendOptions::deadline`] didn't complete in time. If the
+    /// node may already have received the transaction, the next assignment resyncs first.
+    #[error("the send did not complete before its deadline")]
+    DeadlineExceeded,
+    /// Thrown when sending or filling after [`LockedNonceManagerMiddleware::shutdown`] was
+    /// called
+    #[error("the nonce manager is shutting down")]
+    ShuttingDown,
+    /// Thrown when restoring a [`StateSnapshot`] taken for another address
+    #[error("snapshot of {0:?} does not belong to the managed address")]
+    SnapshotAddressMismatch(Address),
+    /// Thrown when a nonce would be assigned further ahead of the confirmed chain nonce
+    /// than the [`PendingAheadLimit`] allows
+    #[error("nonce {nonce} is more than {max} ahead of the confirmed nonce {confirmed}")]
+    PendingTooFarAhead {
+        nonce: Nonce,
+        confirmed: Nonce,
+        max: u64,
+    },
+    /// Thrown when a request the manager makes to the provider directly fails
+    #[error("{0}")]
+    ProviderError(ethers::providers::ProviderError),
+    /// Thrown when the gas oracle consulted for replacement fees fails
+    #[error("{0}")]
+    GasOracleError(ethers::middleware::gas_oracle::GasOracleError),
+    /// Thrown when an operation needing the RPC is attempted in air-gapped mode
+    #[error("the nonce manager is air-gapped")]
+    AirGapped,
+    /// Thrown when the Multicall3 transaction carrying a batched call couldn't be sent, with
+    /// the error sending it
+    #[error("multicall batch failed: {0}")]
+    MulticallFailed(#[source] Arc<dyn std::error::Error + Send + Sync>),
+    /// Thrown when the Multicall3 batch carrying a call was dropped before it was sent, e.g.
+    /// because the call sending it was cancelled
+    #[error("multicall batch abandoned")]
+    MulticallAbandoned,
+    /// Thrown when the provider reports another chain than the one the manager expects
+    #[error("expected chain id {expected}, but the provider is on chain {actual}")]
+    ChainIdMismatch { expected: u64, actual: U256 },
+    /// Thrown when the chain nonce is below the counter and the [`BackwardNoncePolicy`] doesn't
+    /// allow it
+    #[error("the chain is at nonce {chain_nonce}, behind the counter at {nonce}")]
+    ChainNonceBehind { nonce: Nonce, chain_nonce: Nonce },
+    /// Thrown when the node rejected a transaction because the managed address can't pay for
+    /// its value and gas, or the balance check enabled with
+    /// [`LockedNonceManagerMiddleware::with_balance_check`] found it can't. `required` is
+    /// unknown if the node didn't say and the transaction's gas wasn't filled in yet.
+    /// `nonce_consumed` tells whether the nonce is used up anyway, which is only the case for
+    /// replacements of a transaction already sent.
+    #[error(
+        "insufficient funds: {} wei required, {available} wei available",
+        .required.map_or_else(|| "unknown".to_owned(), |required| required.to_string())
+    )]
+    InsufficientFunds {
+        required: Option<U256>,
+        available: U256,
+        nonce_consumed: bool,
+    },
+    /// Thrown when the simulation gate enabled with
+    /// [`LockedNonceManagerMiddleware::with_simulation`] found that a transaction reverts. Its
+    /// nonce was not used.
+    #[error("simulation reverted: {0}")]
+    SimulationReverted(RevertReason),
+    /// Thrown when [`LockedNonceManagerMiddleware::connect`] can't tell which address the
+    /// inner middleware sends from
+    #[error("the inner middleware has no default sender")]
+    NoSender,
+    /// Thrown when the [`IdempotencyStore`] can't be read or written
+    #[error("idempotency store: {0}")]
+    IdempotencyStoreError(std::io::Error),
+    /// Thrown when a send for an idempotency key began without its outcome being recorded,
+    /// so that it may or may not have been broadcast, see
+    /// [`LockedNonceManagerMiddleware::resolve_idempotent`]
+    #[error("the send for idempotency key {0:?} may have been broadcast")]
+    IdempotencyKeyInDoubt(String),
+    /// Thrown when a custom [`NonceSource`] fails
+    #[error("{0}")]
+    SourceError(Box<dyn std::error::Error + Send + Sync>),
+    /// Thrown when sending privately without a [`PrivateRelay`] configured
+    #[error("no private relay is configured")]
+    NoPrivateRelay,
+    /// Thrown when sending a template which isn't registered
+    #[error("no template is registered as {0:?}")]
+    UnknownTemplate(String),
+    /// Thrown when a [`CalldataBuilder`] couldn't build the calldata for the parameters given
+    #[error("calldata: {0}")]
+    CalldataError(Box<dyn std::error::Error + Send + Sync>),
+    /// Thrown when the [`PrivateRelay`] didn't accept a transaction. Its nonce was released.
+    #[error("private relay: {0}")]
+    PrivateRelayError(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl<M> std::ops::Deref for LockedNonceManagerMiddleware<M> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        &self.inner
+    }
 }
 
 impl<M: Middleware> FromErr<M::Error> for NonceManagerError<M> {
@@ -97,27 +1005,132 @@ where
         tx: &mut TypedTransaction,
         block: Option<BlockId>,
     ) -> Result<(), Self::Error> {
-        let nonce_set = tx.nonce().is_some();
-        
-        if !nonce_set {
-            let nonce = self.get_or_init_nonce(block).await?;
-            tx.set_nonce(nonce);
+        let arrival = self.arrival_ticket();
+        self.ensure_connected()?;
+        self.ensure_accepting()?;
+        let block = self.block_or_default(block);
+        self.check_policies(tx).await?;
+        if let Some(nonce) = Self::preset_nonce(tx)? {
+            drop(arrival);
+            match self.config().preset_nonce {
+                PresetNoncePolicy::Skip => {
+                    return match self.inner().fill_transaction(tx, block).await {
+                        Ok(()) => Ok(()),
+                        Err(err) => Err(self.rejected(tx, err, false).await),
+                    };
+                }
+                PresetNoncePolicy::Record => {
+                    // a filled transaction may never be broadcast, so the counter only moves
+                    // once it is sent through the manager
+                    self.get_or_init_nonce(block).await?;
+                    let counter = self.lock_nonce().await;
+                    self.ensure_unclaimed(nonce).await?;
+                    drop(counter);
+                    return match self.inner().fill_transaction(tx, block).await {
+                        Ok(()) => Ok(()),
+                        Err(err) => Err(self.rejected(tx, err, false).await),
+                    };
+                }
+                PresetNoncePolicy::Reject => {
+                    return Err(NonceManagerError::PresetNonceRejected(nonce))
+                }
+            }
+        }
+
+        if self.config().shadow {
+            drop(arrival);
+            self.fill_shadowed(tx, block).await?;
+            return Ok(());
         }
 
-        let mut write_guard = self.nonce.write().await;
+        self.get_or_init_nonce(block).await?;
+        let mut write_guard = self.lock_for_assignment(Some(tx), arrival).await?;
         let mut nonce = *write_guard;
+        let retry = self.config().retry;
+        let mut retries = 0;
+        loop {
+            tx.set_nonce(nonce);
+            self.fill_from_node(tx).await;
+            let err = match self.inner().fill_transaction(tx, block).await {
+                Ok(()) => break,
+                Err(err) => err,
+            };
 
-        let res = self
-            .inner()
-            .fill_transaction(tx, block)
-            .await
-            .map_err(FromErr::from)?;
+            // like sends, fills rejected because the chain raced ahead are retried at the
+            // chain's nonce
+            let chain_nonce = self.pending_chain_nonce().await?;
+            if !self.is_conflict(nonce, chain_nonce) {
+                return Err(self.rejected(tx, err, false).await);
+            }
+            *write_guard = chain_nonce;
+            if retries == retry.max_conflict_retries {
+                return Err(FromErr::from(err));
+            }
+            retries += 1;
+            nonce = chain_nonce;
+            let backoff = retry.backoff();
+            if !backoff.is_zero() {
+                self.sleep(backoff).await;
+            }
+        }
+
+        *write_guard = Self::increment(nonce)?;
+        self.journal(JournalEntry::Assigned { nonce });
+        self.emit(NonceEvent::Assigned {
+            address: self.address,
+            nonce,
+            label: None,
+        });
+        drop(write_guard);
+        self.checkpoint_if_due().await;
 
-        if !nonce_set {
-            *write_guard = nonce + U256::from(1u32);        
+        Ok(())
+    }
+
+    /// For the managed address at the `pending` block tag this answers from the local counter,
+    /// i.e. the nonce the manager is about to assign, so that callers see a view consistent
+    /// with the transactions it has in flight. Every other query goes to the inner middleware.
+    async fn get_transaction_count<T: Into<NameOrAddress> + Send + Sync>(
+        &self,
+        from: T,
+        block: Option<BlockId>,
+    ) -> Result<U256, Self::Error> {
+        let from = from.into();
+        if from == NameOrAddress::Address(self.address)
+            && matches!(block, Some(BlockId::Number(BlockNumber::Pending)))
+        {
+            return self.get_or_init_nonce(block).await.map(Into::into);
         }
 
-        Ok(res)
+        self.inner
+            .get_transaction_count(from, block)
+            .await
+            .map_err(FromErr::from)
+    }
+
+    /// Broadcasts a transaction signed elsewhere, e.g. after [`Middleware::fill_transaction`].
+    /// A transaction from the managed address is then tracked as in flight. If the node
+    /// rejects one whose nonce the chain has already moved past, the conflict is reported and
+    /// the counter catches up with the chain, so that the next fill assigns a fresh nonce.
+    async fn send_raw_transaction<'a>(
+        &'a self,
+        tx: Bytes,
+    ) -> Result<PendingTransaction<'a, Self::Provider>, Self::Error> {
+        let send = self.inner.send_raw_transaction(tx.clone());
+        match self.timed_inner_send(send).await {
+            Ok(pending) => {
+                // e.g. signed at a nonce from `apply_nonce` or `with_nonce`
+                if let Some((nonce, decoded)) = self.decode_own(&tx) {
+                    self.record_broadcast(nonce, pending.tx_hash(), Some(&decoded), None)
+                        .await;
+                }
+                Ok(pending)
+            }
+            Err(err) => {
+                self.recover_raw_conflict(&tx).await;
+                Err(FromErr::from(err))
+            }
+        }
     }
 
     /// Signs and broadcasts the transaction. The optional parameter `block` can be passed so that
@@ -128,40 +1141,7 @@ where
         tx: T,
         block: Option<BlockId>,
     ) -> Result<PendingTransaction<'_, Self::Provider>, Self::Error> {
-        let mut tx = tx.into();
-
-        let nonce_set = tx.nonce().is_some();
-     
-        if !nonce_set {
-            let nonce = self.get_or_init_nonce(block).await?;
-            tx.set_nonce(nonce);
-        }
-
-        let mut write_guard = self.nonce.write().await;
-        let mut nonce = *write_guard;
-        
-        let res = match self.inner.send_transaction(tx.clone(), block).await {
-            Ok(tx_hash) => Ok(tx_hash),
-            Err(err) => {
-                let current_nonce = self.get_transaction_count(self.address, block).await?;
-                if current_nonce > nonce {
-                    *write_guard = current_nonce;
-                    tx.set_nonce(nonce);
-                    self.inner
-                        .send_transaction(tx, block)
-                        .await
-                        .map_err(FromErr::from)
-                } else {
-                    // propagate the error otherwise
-                    Err(FromErr::from(err))
-                }
-            }
-        }?;
-
-        if !nonce_set {
-            *write_guard = nonce + U256::from(1u32);
-        }        
-
-        Ok(res)
+        let (_, pending) = self.send_managed(tx.into(), block, None).await?;
+        Ok(pending)
     }
 }
@@ -0,0 +1,182 @@
+use crate::priority::Ticket;
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceManagerError, NonceStatus};
+use ethers::providers::{Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, TxHash};
+use serde_json::Value;
+
+#[derive(Debug)]
+/// A transaction sent through [`LockedNonceManagerMiddleware::send_managed_transaction`],
+/// tying the operations on it back to the manager's state for its nonce
+pub struct ManagedPendingTransaction<'a, M: Middleware> {
+    manager: &'a LockedNonceManagerMiddleware<M>,
+    nonce: Nonce,
+    pending: PendingTransaction<'a, M::Provider>,
+}
+
+impl<'a, M> ManagedPendingTransaction<'a, M>
+where
+    M: Middleware,
+{
+    /// The nonce the transaction was sent at
+    pub fn nonce(&self) -> Nonce {
+        self.nonce
+    }
+
+    /// The hash of the transaction
+    pub fn tx_hash(&self) -> TxHash {
+        self.pending.tx_hash()
+    }
+
+    /// The underlying pending transaction, to wait for it to be mined. It waits for the
+    /// number of confirmations the manager requires before considering a nonce final.
+    pub fn into_pending(self) -> PendingTransaction<'a, M::Provider> {
+        let confirmations = self.manager.config().confirmations;
+        self.pending.confirmations(confirmations)
+    }
+
+    /// Where the transaction's nonce stands, see [`NonceStatus`]
+    pub async fn status(&self) -> Result<NonceStatus, NonceManagerError<M>> {
+        self.manager.broadcast_status(self.nonce).await
+    }
+
+    /// Re-prices and rebroadcasts the transaction, see
+    /// [`LockedNonceManagerMiddleware::speed_up`]. Returns the replacement.
+    pub async fn speed_up(&self) -> Result<Self, NonceManagerError<M>> {
+        let pending = self.manager.speed_up(self.nonce).await?;
+        Ok(self.manager.managed(self.nonce, pending))
+    }
+
+    /// Replaces the transaction with an empty transfer, see
+    /// [`LockedNonceManagerMiddleware::cancel`]. Returns the cancellation.
+    pub async fn cancel(&self) -> Result<Self, NonceManagerError<M>> {
+        let pending = self.manager.cancel(self.nonce).await?;
+        Ok(self.manager.managed(self.nonce, pending))
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Sends `tx` like [`Middleware::send_transaction`], but returns a
+    /// [`ManagedPendingTransaction`] which knows its nonce and can be sped up or cancelled
+    pub async fn send_managed_transaction<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        block: Option<BlockId>,
+    ) -> Result<ManagedPendingTransaction<'_, M>, NonceManagerError<M>> {
+        let (nonce, pending) = self.send_managed(tx.into(), block, None).await?;
+        Ok(self.managed(nonce, pending))
+    }
+
+    /// Sends `tx` like [`Self::send_managed_transaction`], attaching `label` to it. The label
+    /// is carried by the transaction's [`crate::NonceEvent`]s and kept while it is in flight,
+    /// see [`Self::label`], e.g. to tell which subsystem sent a nonce.
+    pub async fn send_labeled_transaction<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        label: Value,
+        block: Option<BlockId>,
+    ) -> Result<ManagedPendingTransaction<'_, M>, NonceManagerError<M>> {
+        let (nonce, pending) = self.send_managed(tx.into(), block, Some(label)).await?;
+        Ok(self.managed(nonce, pending))
+    }
+
+    /// Sends `tx` at the caller-chosen `nonce`, e.g. one agreed on with an external system,
+    /// and records it with the manager: the transaction is tracked as in flight, and the
+    /// counter moves past `nonce` if it wasn't already. The nonces skipped in between are
+    /// reserved, as the transaction can't be mined until they are used: schedule
+    /// transactions at them with [`Self::schedule_signed`], or give them up with
+    /// [`Self::release_reservation`]. Nonces which are reserved or already have a managed
+    /// transaction in flight are refused with
+    /// [`NonceManagerError::NonceReserved`] and [`NonceManagerError::NonceInFlight`], see
+    /// [`Self::speed_up`] to replace the latter, and nonces further ahead of the chain than
+    /// [`Self::with_max_pending_ahead`] allows with
+    /// [`NonceManagerError::PendingTooFarAhead`].
+    pub async fn send_transaction_with_nonce<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        nonce: Nonce,
+        block: Option<BlockId>,
+    ) -> Result<ManagedPendingTransaction<'_, M>, NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        self.ensure_connected()?;
+        let block = self.block_or_default(block);
+        let mut tx = tx.into();
+        self.check_policies(&mut tx).await?;
+        tx.set_nonce(nonce);
+        let pending = self.send_recorded(tx, nonce, block, None, arrival).await?;
+        Ok(self.managed(nonce, pending))
+    }
+
+    /// Sends `tx`, whose nonce was set to `nonce` by the caller, recording it like
+    /// [`Self::send_transaction_with_nonce`]
+    pub(crate) async fn send_recorded(
+        &self,
+        mut tx: TypedTransaction,
+        nonce: Nonce,
+        block: Option<BlockId>,
+        label: Option<Value>,
+        arrival: Option<Ticket<'_>>,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        self.ensure_accepting()?;
+        self.get_or_init_nonce(block).await?;
+        let slot = self.wait_for_block_slot().await?;
+        let confirmed = self.confirmed_for_drift().await?;
+
+        let mut counter = self.lock_for_assignment(Some(&tx), arrival).await?;
+        self.ensure_unclaimed(nonce).await?;
+        if let Some(confirmed) = confirmed {
+            self.check_chosen_drift(nonce, confirmed)?;
+        }
+        let charges = self.check_limits(&mut tx, block).await?;
+        self.simulate(&tx).await?;
+        let signed = self.write_ahead(&mut tx, block).await?;
+        let pending = match self.broadcast(&tx, block, signed).await {
+            Ok(pending) => pending,
+            Err(err) => return Err(self.rejected(&tx, err, false).await),
+        };
+        slot.used();
+        self.charge(charges);
+        if nonce >= *counter {
+            let mut schedule = self.state.schedule().lock().await;
+            let mut skipped = *counter;
+            while skipped < nonce {
+                schedule.reserve(skipped);
+                self.journal(JournalEntry::Reserved { nonce: skipped });
+                skipped = Self::increment(skipped)?;
+            }
+            drop(schedule);
+            *counter = Self::increment(nonce)?;
+        }
+        self.record_broadcast(nonce, pending.tx_hash(), Some(&tx), label)
+            .await;
+        drop(counter);
+        self.checkpoint_if_due().await;
+        Ok(pending)
+    }
+
+    /// Fails if `nonce` is reserved or a managed transaction is in flight at it. Needs to be
+    /// called with the write lock held.
+    pub(crate) async fn ensure_unclaimed(&self, nonce: Nonce) -> Result<(), NonceManagerError<M>> {
+        if self.state.schedule().lock().await.is_reserved(nonce) {
+            return Err(NonceManagerError::NonceReserved(nonce));
+        }
+        if self.state.in_flight().get(nonce).is_some() {
+            return Err(NonceManagerError::NonceInFlight(nonce));
+        }
+        Ok(())
+    }
+
+    fn managed<'a>(
+        &'a self,
+        nonce: Nonce,
+        pending: PendingTransaction<'a, M::Provider>,
+    ) -> ManagedPendingTransaction<'a, M> {
+        ManagedPendingTransaction {
+            manager: self,
+            nonce,
+            pending,
+        }
+    }
+}
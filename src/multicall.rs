@@ -0,0 +1,314 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, Address, Bytes, Eip1559TransactionRequest,
+    NameOrAddress, TransactionRequest, TxHash, U256,
+};
+use ethers::utils::id;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+
+/// Address Multicall3 is deployed at on most chains, 0xcA11bde05977b3631167028862bE2a173976CA11
+pub const MULTICALL3_ADDRESS: Address = ethers::types::H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Batching of calls sent with [`LockedNonceManagerMiddleware::send_batched`] into single
+/// Multicall3 `aggregate3Value` transactions
+pub struct MulticallBatching {
+    /// How long a batch collects calls after the first one, in milliseconds
+    pub window_ms: u64,
+    /// Number of calls which flush a batch right away
+    pub max_calls: usize,
+    /// Address of the Multicall3 contract
+    #[serde(default = "default_multicall")]
+    pub multicall: Address,
+    /// Whether a reverting call is skipped instead of reverting the whole batch
+    #[serde(default)]
+    pub allow_failure: bool,
+}
+
+fn default_multicall() -> Address {
+    MULTICALL3_ADDRESS
+}
+
+impl MulticallBatching {
+    /// Batches the calls sent within `window` of each other, at most `max_calls` at a time,
+    /// through the canonical Multicall3 deployment
+    pub fn new(window: Duration, max_calls: usize) -> Self {
+        Self {
+            window_ms: window.as_millis().try_into().unwrap_or(u64::MAX),
+            max_calls,
+            multicall: MULTICALL3_ADDRESS,
+            allow_failure: false,
+        }
+    }
+
+    /// How long a batch collects calls after the first one
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+/// A call sent through [`LockedNonceManagerMiddleware::send_batched`]
+pub struct BatchedCall {
+    /// The transaction carrying the call
+    pub tx_hash: TxHash,
+    /// Position of the call in the Multicall3 batch, `None` if it was sent on its own
+    pub index: Option<usize>,
+}
+
+type Outcome = Result<TxHash, Arc<dyn Error + Send + Sync>>;
+
+#[derive(Debug)]
+struct OpenBatch {
+    id: u64,
+    deadline: Instant,
+    /// Whether the batch is sent as a legacy transaction, because any of its calls isn't an
+    /// EIP-1559 one
+    legacy: bool,
+    chain_id: Option<u64>,
+    calls: Vec<Token>,
+    value: U256,
+    waiters: Vec<oneshot::Sender<Outcome>>,
+}
+
+#[derive(Debug, Default)]
+/// The batch currently collecting calls
+pub(crate) struct Batcher {
+    open: Mutex<Option<OpenBatch>>,
+    next_id: AtomicU64,
+}
+
+impl Batcher {
+    /// Adds a call to the open batch, opening one if needed. Returns the batch's id and
+    /// deadline, the call's index and whether the batch is full.
+    fn join(
+        &self,
+        tx: &TypedTransaction,
+        to: Address,
+        config: MulticallBatching,
+        waiter: oneshot::Sender<Outcome>,
+        now: Instant,
+    ) -> (u64, Instant, usize, bool) {
+        let mut open = self.open.lock().expect("multicall batch lock poisoned");
+        let batch = open.get_or_insert_with(|| OpenBatch {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            deadline: now + config.window(),
+            legacy: false,
+            chain_id: tx.chain_id().map(|chain_id| chain_id.as_u64()),
+            calls: Vec::new(),
+            value: U256::zero(),
+            waiters: Vec::new(),
+        });
+        batch.legacy |= !matches!(tx, TypedTransaction::Eip1559(_));
+        let value = tx.value().copied().unwrap_or_default();
+        batch.calls.push(Token::Tuple(vec![
+            Token::Address(to),
+            Token::Bool(config.allow_failure),
+            Token::Uint(value),
+            Token::Bytes(tx.data().map(|data| data.to_vec()).unwrap_or_default()),
+        ]));
+        batch.value = batch.value.saturating_add(value);
+        batch.waiters.push(waiter);
+        (
+            batch.id,
+            batch.deadline,
+            batch.calls.len() - 1,
+            batch.calls.len() >= config.max_calls,
+        )
+    }
+
+    /// Closes the batch `id` if it is still open
+    fn take(&self, id: u64) -> Option<OpenBatch> {
+        let mut open = self.open.lock().expect("multicall batch lock poisoned");
+        match &*open {
+            Some(batch) if batch.id == id => open.take(),
+            _ => None,
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Batches the calls sent with [`Self::send_batched`] into Multicall3 transactions,
+    /// consuming a single nonce per batch
+    pub fn with_multicall_batching(mut self, batching: MulticallBatching) -> Self {
+        self.config_mut().multicall_batching = Some(batching);
+        self
+    }
+
+    /// Sends `tx` as part of a Multicall3 batch, which is broadcast once the batching window
+    /// elapsed or it holds `max_calls` calls. Calls are executed by the Multicall3 contract,
+    /// so only calls which don't rely on `msg.sender` should be batched. Transactions which
+    /// can't be batched, because they deploy a contract or carry their own nonce, or any
+    /// transaction while batching is disabled, are sent on their own. A batch is an EIP-1559
+    /// transaction if all of its calls are, and a legacy one otherwise.
+    pub async fn send_batched<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+    ) -> Result<BatchedCall, NonceManagerError<M>>
+    where
+        M: 'static,
+    {
+        let tx = tx.into();
+        let (config, to) = match (self.config().multicall_batching, tx.to()) {
+            (Some(config), Some(NameOrAddress::Address(to))) if tx.nonce().is_none() => {
+                (config, *to)
+            }
+            _ => {
+                let (_, pending) = self.send_managed(tx, None, None).await?;
+                return Ok(BatchedCall {
+                    tx_hash: pending.tx_hash(),
+                    index: None,
+                });
+            }
+        };
+
+        let (sender, receiver) = oneshot::channel();
+        let (id, deadline, index, full) = self.multicall.join(&tx, to, config, sender, self.now());
+        if !full {
+            self.sleep(deadline.saturating_duration_since(self.now()))
+                .await;
+        }
+        // whichever call of the batch gets here first sends it
+        if let Some(batch) = self.multicall.take(id) {
+            self.send_batch(batch, config).await;
+        }
+
+        let tx_hash = receiver
+            .await
+            .map_err(|_| NonceManagerError::MulticallAbandoned)?
+            .map_err(NonceManagerError::MulticallFailed)?;
+        Ok(BatchedCall {
+            tx_hash,
+            index: Some(index),
+        })
+    }
+
+    /// Sends the calls of `batch` as one `aggregate3Value` transaction and hands the outcome
+    /// to every call in it
+    async fn send_batch(&self, batch: OpenBatch, config: MulticallBatching)
+    where
+        M: 'static,
+    {
+        let mut data = id("aggregate3Value((address,bool,uint256,bytes)[])").to_vec();
+        data.extend(abi::encode(&[Token::Array(batch.calls)]));
+        let mut tx: TypedTransaction = if batch.legacy {
+            TransactionRequest::new().into()
+        } else {
+            Eip1559TransactionRequest::new().into()
+        };
+        tx.set_to(config.multicall);
+        tx.set_data(Bytes::from(data));
+        tx.set_value(batch.value);
+        if let Some(chain_id) = batch.chain_id {
+            tx.set_chain_id(chain_id);
+        }
+
+        let outcome = self
+            .send_managed(tx, None, None)
+            .await
+            .map(|(_, pending)| pending.tx_hash())
+            .map_err(|err| Arc::new(err) as Arc<dyn Error + Send + Sync>);
+        for waiter in batch.waiters {
+            // the call may have been cancelled in the meantime
+            let _ = waiter.send(outcome.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: MulticallBatching = MulticallBatching {
+        window_ms: 1_000,
+        max_calls: 2,
+        multicall: MULTICALL3_ADDRESS,
+        allow_failure: false,
+    };
+
+    fn join(batcher: &Batcher, tx: impl Into<TypedTransaction>) -> (u64, usize, bool) {
+        let (waiter, _) = oneshot::channel();
+        let (id, _, index, full) =
+            batcher.join(&tx.into(), Address::zero(), CONFIG, waiter, Instant::now());
+        (id, index, full)
+    }
+
+    #[test]
+    fn batches_fill_up_to_max_calls() {
+        let batcher = Batcher::default();
+        let (id, index, full) = join(&batcher, Eip1559TransactionRequest::new().value(1));
+        assert_eq!((index, full), (0, false));
+        assert_eq!(
+            join(&batcher, Eip1559TransactionRequest::new().value(2)),
+            (id, 1, true)
+        );
+
+        let batch = batcher.take(id).unwrap();
+        assert_eq!(batch.calls.len(), 2);
+        assert_eq!(batch.value, U256::from(3));
+        assert!(!batch.legacy);
+        assert!(batcher.take(id).is_none());
+    }
+
+    #[test]
+    fn any_legacy_call_makes_the_batch_legacy() {
+        let batcher = Batcher::default();
+        let (id, _, _) = join(&batcher, Eip1559TransactionRequest::new());
+        join(&batcher, TransactionRequest::new());
+        assert!(batcher.take(id).unwrap().legacy);
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn failed_batches_carry_the_error_sending_them() {
+        use crate::testkit::fixtures::{client, transfer};
+        use crate::SimulatedChain;
+
+        let chain = SimulatedChain::new(4);
+        let client = client(&chain).with_multicall_batching(CONFIG);
+        // every request of the batch fails, however many it takes
+        chain.fail_next(100);
+        let (first, second) = tokio::join!(
+            client.send_batched(transfer()),
+            client.send_batched(transfer())
+        );
+        for result in [first, second] {
+            let err = result.unwrap_err();
+            assert!(matches!(err, NonceManagerError::MulticallFailed(_)));
+            assert!(err.source().is_some());
+        }
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn calls_share_one_transaction() {
+        use crate::testkit::fixtures::{client, transfer, SENDER};
+        use crate::SimulatedChain;
+
+        let chain = SimulatedChain::new(5);
+        let client = client(&chain).with_multicall_batching(CONFIG);
+        let (first, second) = tokio::join!(
+            client.send_batched(transfer()),
+            client.send_batched(transfer())
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(first.tx_hash, second.tx_hash);
+        assert_eq!([first.index, second.index], [Some(0), Some(1)]);
+        assert_eq!(chain.pending_nonces(SENDER), [0]);
+    }
+}
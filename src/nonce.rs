@@ -0,0 +1,140 @@
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "U256", into = "U256")]
+/// A transaction nonce. Using a dedicated type instead of a raw `U256` keeps nonces from
+/// being confused with the other quantities flowing through a transaction.
+///
+/// Nonces are bounded by [`Nonce::MAX`] as specified in EIP-2681, so a `Nonce` can only be
+/// built from values that an account can actually use.
+pub struct Nonce(U256);
+
+impl Nonce {
+    /// The largest nonce an account can use, `2^64 - 2` (EIP-2681)
+    pub const MAX: Nonce = Nonce(U256([u64::MAX - 1, 0, 0, 0]));
+
+    /// Wraps a raw nonce value, failing if it lies outside of the valid range
+    pub fn new(nonce: U256) -> Result<Self, InvalidNonce> {
+        if nonce > Self::MAX.0 {
+            return Err(InvalidNonce(nonce));
+        }
+        Ok(Self(nonce))
+    }
+
+    /// The zero nonce, used by accounts which never sent a transaction
+    pub fn zero() -> Self {
+        Self(U256::zero())
+    }
+
+    /// Returns the raw value
+    pub fn as_u256(&self) -> U256 {
+        self.0
+    }
+
+    /// Returns the value as a `u64`, which is lossless for every valid nonce
+    pub fn as_u64(&self) -> u64 {
+        self.0.as_u64()
+    }
+
+    /// Returns the nonce following this one, or `None` if this is [`Nonce::MAX`]
+    pub fn checked_increment(self) -> Option<Self> {
+        self.checked_add(1)
+    }
+
+    /// Returns the nonce `n` positions after this one, or `None` if it would exceed
+    /// [`Nonce::MAX`]
+    pub fn checked_add(self, n: u64) -> Option<Self> {
+        self.0
+            .checked_add(n.into())
+            .and_then(|nonce| Self::new(nonce).ok())
+    }
+
+    /// Returns the nonce `n` positions before this one, or `None` on underflow
+    pub fn checked_sub(self, n: u64) -> Option<Self> {
+        self.0.checked_sub(n.into()).map(Self)
+    }
+
+    /// Returns how many nonces lie between `other` and this nonce, or `None` if `other`
+    /// is the larger one
+    pub fn distance_from(self, other: Nonce) -> Option<U256> {
+        self.0.checked_sub(other.0)
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("nonce {0} exceeds the maximum of 2^64 - 2 (EIP-2681)")]
+/// Thrown when a value does not fit in the valid nonce range
+pub struct InvalidNonce(pub U256);
+
+impl TryFrom<U256> for Nonce {
+    type Error = InvalidNonce;
+
+    fn try_from(nonce: U256) -> Result<Self, Self::Error> {
+        Self::new(nonce)
+    }
+}
+
+impl TryFrom<u64> for Nonce {
+    type Error = InvalidNonce;
+
+    fn try_from(nonce: u64) -> Result<Self, Self::Error> {
+        Self::new(nonce.into())
+    }
+}
+
+impl From<Nonce> for U256 {
+    fn from(nonce: Nonce) -> Self {
+        nonce.0
+    }
+}
+
+impl fmt::Display for Nonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_by_eip_2681() {
+        let max = U256::from(u64::MAX - 1);
+        assert_eq!(Nonce::MAX.as_u256(), max);
+        assert_eq!(Nonce::new(max), Ok(Nonce::MAX));
+        assert_eq!(
+            Nonce::new(U256::from(u64::MAX)),
+            Err(InvalidNonce(U256::from(u64::MAX)))
+        );
+        assert!(Nonce::try_from(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn increments_stop_at_the_maximum() {
+        assert_eq!(
+            Nonce::zero().checked_increment(),
+            Nonce::try_from(1u64).ok()
+        );
+        assert_eq!(Nonce::MAX.checked_increment(), None);
+        assert_eq!(
+            Nonce::MAX.checked_sub(1).unwrap().checked_add(1),
+            Some(Nonce::MAX)
+        );
+        assert_eq!(Nonce::MAX.checked_sub(1).unwrap().checked_add(2), None);
+        assert_eq!(Nonce::zero().checked_sub(1), None);
+    }
+
+    #[test]
+    fn invalid_nonces_are_not_deserialized() {
+        let json = serde_json::to_string(&U256::from(u64::MAX)).unwrap();
+        assert!(serde_json::from_str::<Nonce>(&json).is_err());
+        let json = serde_json::to_string(&Nonce::MAX).unwrap();
+        assert_eq!(serde_json::from_str::<Nonce>(&json).unwrap(), Nonce::MAX);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::{FromErr, Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, H256};
+use ethers::utils::keccak256;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Keeps accepting sends while the RPC is down instead of failing them. Once a broadcast
+    /// and the following nonce lookup both fail, the transaction is signed through the inner
+    /// middleware at its nonce and queued locally, as are the sends after it. The queue is
+    /// flushed in nonce order by the next send once the node answers again, or explicitly
+    /// with [`Self::broadcast_scheduled`]. Queued transactions have to be complete enough to
+    /// be filled without the RPC, i.e. carry their gas limit and fees.
+    pub fn with_offline_queue(mut self) -> Self {
+        self.config_mut().queue_when_offline = true;
+        self
+    }
+
+    /// Whether sends are currently being queued because the RPC was unreachable
+    pub fn is_offline(&self) -> bool {
+        self.state.is_offline()
+    }
+
+    /// Whether the RPC is still taken to be down. While it is, every send first tries to
+    /// flush the queue, which brings the manager back online if it goes through.
+    pub(crate) async fn still_offline(&self) -> bool {
+        self.state.is_offline() && self.flush_offline().await.is_err()
+    }
+
+    /// Broadcasts the transactions queued while the RPC was down, in nonce order. Nonces the
+    /// caller reserved or held, and the transactions scheduled for them, are left to
+    /// [`Self::broadcast_scheduled`], so the flush stops below the first of them and the
+    /// queued transactions above it go out with them. With nothing to flush, the node is
+    /// asked for the block number to tell whether it is back.
+    async fn flush_offline(&self) -> Result<(), NonceManagerError<M>> {
+        let mut schedule = self.state.schedule().lock().await;
+        let queued = schedule.queued_offline();
+        if queued.is_empty() {
+            self.inner.get_block_number().await.map_err(FromErr::from)?;
+        }
+        for (nonce, raw_tx) in queued {
+            self.send_scheduled(nonce, raw_tx).await?;
+            schedule.release(nonce);
+        }
+
+        self.state.clear_offline();
+        drop(schedule);
+        self.checkpoint_if_due().await;
+        Ok(())
+    }
+
+    /// Signs `tx` at `nonce` and queues it for broadcasting once the RPC is back. Needs to be
+    /// called with the write lock held. The returned pending transaction resolves once the
+    /// queued transaction is flushed and mined.
+    pub(crate) async fn queue_offline(
+        &self,
+        tx: &mut TypedTransaction,
+        nonce: Nonce,
+        block: Option<BlockId>,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        tx.set_nonce(nonce);
+        self.inner
+            .fill_transaction(tx, block)
+            .await
+            .map_err(FromErr::from)?;
+        let signature = self
+            .inner
+            .sign_transaction(tx, self.address)
+            .await
+            .map_err(FromErr::from)?;
+        let raw_tx = tx.rlp_signed(&signature);
+        let tx_hash = H256::from(keccak256(&raw_tx));
+
+        self.state
+            .schedule()
+            .lock()
+            .await
+            .queue(nonce, raw_tx.clone());
+        self.journal(JournalEntry::Reserved { nonce });
+        self.journal(JournalEntry::Scheduled { nonce, raw_tx });
+        self.state.mark_offline();
+        Ok(PendingTransaction::new(tx_hash, self.provider()))
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError, RetryPolicy};
+use ethers::providers::{Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId};
+use futures_util::future::{select, Either};
+use serde_json::Value;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+/// Per-transaction settings for [`LockedNonceManagerMiddleware::send_transaction_with_opts`],
+/// overriding the manager's configuration for a single send
+pub struct SendOptions {
+    /// Block the nonce is looked up at when the counter isn't initialized yet, instead of the
+    /// configured block tag
+    pub block_tag: Option<BlockId>,
+    /// Label carried by the transaction's events while it is in flight, see
+    /// [`LockedNonceManagerMiddleware::send_labeled_transaction`]
+    pub label: Option<Value>,
+    /// Sends the transaction without assigning it a nonce, leaving it to the caller or the
+    /// inner middleware and the counter untouched. The transaction still goes through the
+    /// limits, the chain id check, the simulation and the write-ahead log.
+    pub bypass_nonce: bool,
+    /// Recovers from nonce conflicts as allowed by this policy instead of the configured one
+    pub retry_policy_override: Option<RetryPolicy>,
+    /// Fails the send with [`NonceManagerError::DeadlineExceeded`] if it doesn't complete
+    /// within this time
+    pub deadline: Option<Duration>,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Sends `tx` like [`Middleware::send_transaction`], with the behavior adjusted for this
+    /// transaction by `opts`
+    pub async fn send_transaction_with_opts<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        opts: SendOptions,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let send = self.send_with_opts(tx.into(), opts.clone());
+        let deadline = match opts.deadline {
+            Some(deadline) => deadline,
+            None => return send.await,
+        };
+        // dropping the send is safe, it flags the state for a resync if the transaction may
+        // have reached the node
+        match select(Box::pin(send), Box::pin(self.sleep(deadline))).await {
+            Either::Left((sent, _)) => sent,
+            Either::Right(_) => Err(NonceManagerError::DeadlineExceeded),
+        }
+    }
+
+    async fn send_with_opts(
+        &self,
+        mut tx: TypedTransaction,
+        opts: SendOptions,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        if !opts.bypass_nonce {
+            let retry = opts
+                .retry_policy_override
+                .unwrap_or_else(|| self.config().retry);
+            let (_, pending) = self
+                .send_managed_with(tx, opts.block_tag, opts.label, retry)
+                .await?;
+            return Ok(pending);
+        }
+
+        self.ensure_connected()?;
+        self.ensure_accepting()?;
+        self.check_policies(&mut tx).await?;
+        self.check_balance(&tx).await?;
+        let block = self.block_or_default(opts.block_tag);
+        self.send_unassigned(tx, block, None).await
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::providers::{JsonRpcClient, Middleware, PendingTransaction, ProviderError};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, TransactionReceipt, TxHash};
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    trace::{Span, StatusCode, Tracer},
+    Context, KeyValue,
+};
+use std::{
+    future::Future,
+    ops::Deref,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+
+const TRACER_NAME: &str = "ethers-nonce";
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Same as `send_transaction`, but records the transaction's lifecycle in an OpenTelemetry
+    /// span which is a child of `cx`. The span carries an event for the nonce assignment and
+    /// for the broadcast, and ends with a `mined` event once the returned future resolves.
+    pub async fn send_transaction_with_context<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        block: Option<BlockId>,
+        cx: &Context,
+    ) -> Result<TracedPendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let mut span = global::tracer(TRACER_NAME).start_with_context("send_transaction", cx);
+        span.set_attribute(KeyValue::new(
+            "ethereum.address",
+            format!("{:?}", self.address),
+        ));
+
+        match self.send_managed(tx.into(), block, None).await {
+            Ok((nonce, pending)) => {
+                span.set_attribute(KeyValue::new("ethereum.nonce", nonce.as_u64() as i64));
+                span.add_event(
+                    "nonce_assigned",
+                    vec![KeyValue::new("ethereum.nonce", nonce.as_u64() as i64)],
+                );
+                span.add_event(
+                    "broadcast",
+                    vec![KeyValue::new(
+                        "ethereum.tx_hash",
+                        format!("{:?}", pending.tx_hash()),
+                    )],
+                );
+                Ok(TracedPendingTransaction { pending, span })
+            }
+            Err(err) => {
+                span.set_status(StatusCode::Error, err.to_string());
+                span.end();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A [`PendingTransaction`] whose OpenTelemetry span is ended once the transaction is mined
+/// (or dropped from the mempool)
+pub struct TracedPendingTransaction<'a, P> {
+    pending: PendingTransaction<'a, P>,
+    span: BoxedSpan,
+}
+
+impl<'a, P> TracedPendingTransaction<'a, P> {
+    /// Returns the wrapped pending transaction, ending the span without a `mined` event
+    pub fn into_inner(mut self) -> PendingTransaction<'a, P> {
+        self.span.end();
+        self.pending
+    }
+}
+
+impl<P> std::fmt::Debug for TracedPendingTransaction<'_, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TracedPendingTransaction")
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl<P> Deref for TracedPendingTransaction<'_, P> {
+    type Target = TxHash;
+
+    fn deref(&self) -> &TxHash {
+        &self.pending
+    }
+}
+
+impl<P: JsonRpcClient> Future for TracedPendingTransaction<'_, P> {
+    type Output = Result<Option<TransactionReceipt>, ProviderError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let res = match Pin::new(&mut this.pending).poll(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        match &res {
+            Ok(Some(receipt)) => {
+                let mut attributes = vec![KeyValue::new(
+                    "ethereum.tx_hash",
+                    format!("{:?}", receipt.transaction_hash),
+                )];
+                if let Some(block) = receipt.block_number {
+                    attributes.push(KeyValue::new(
+                        "ethereum.block_number",
+                        block.as_u64() as i64,
+                    ));
+                }
+                if let Some(status) = receipt.status {
+                    attributes.push(KeyValue::new("ethereum.tx_status", status.as_u64() as i64));
+                }
+                this.span.add_event("mined", attributes);
+            }
+            Ok(None) => this.span.add_event("dropped", vec![]),
+            Err(err) => this.span.set_status(StatusCode::Error, err.to_string()),
+        }
+        this.span.end();
+
+        Poll::Ready(res)
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{contract::call_uint, sequences::Sequences, NonceManagerError};
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use ethers::utils::id;
+
+#[derive(Debug)]
+/// Tracker for the nonces of off-chain permit signatures, EIP-2612 and DAI-style alike, which
+/// tokens keep per owner in `nonces(address)`. Each `(token, owner)` pair is an independent
+/// sequence, so a backend batching permits with regular transactions can sign several
+/// permits ahead of their use while keeping both sequence spaces in one place.
+pub struct PermitNonceTracker<M> {
+    inner: M,
+    permits: Sequences<(Address, Address)>,
+}
+
+impl<M> PermitNonceTracker<M>
+where
+    M: Middleware,
+{
+    /// Creates a tracker which reads nonces through `inner`
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            permits: Sequences::new(),
+        }
+    }
+
+    /// Returns the nonce to sign the next permit of `owner` on `token` with
+    pub async fn next_nonce(
+        &self,
+        token: Address,
+        owner: Address,
+    ) -> Result<U256, NonceManagerError<M>> {
+        self.permits
+            .next((token, owner), || self.chain_nonce(token, owner))
+            .await
+    }
+
+    /// Returns the nonce the next permit of `owner` on `token` would get, without reserving it
+    pub async fn peek(&self, token: Address, owner: Address) -> Result<U256, NonceManagerError<M>> {
+        self.permits
+            .peek((token, owner), || self.chain_nonce(token, owner))
+            .await
+    }
+
+    /// Gives back `nonce` if the permit signed with it is discarded before being submitted.
+    /// Only the last nonce handed out for the pair can be released.
+    pub async fn release(&self, token: Address, owner: Address, nonce: U256) -> bool {
+        self.permits.release((token, owner), nonce).await
+    }
+
+    /// Brings the nonce of `owner` on `token` up to date with the token and returns the next
+    /// one, never moving it backwards
+    pub async fn resync(
+        &self,
+        token: Address,
+        owner: Address,
+    ) -> Result<U256, NonceManagerError<M>> {
+        let chain_nonce = self.chain_nonce(token, owner).await?;
+        Ok(self.permits.resync((token, owner), chain_nonce).await)
+    }
+
+    /// The `(token, owner)` pairs whose nonces are tracked
+    pub async fn tracked(&self) -> Vec<(Address, Address)> {
+        self.permits.keys().await
+    }
+
+    /// Reads the permit nonce of `owner` from `token`
+    pub async fn chain_nonce(
+        &self,
+        token: Address,
+        owner: Address,
+    ) -> Result<U256, NonceManagerError<M>> {
+        let mut data = id("nonces(address)").to_vec();
+        data.extend(abi::encode(&[Token::Address(owner)]));
+        call_uint(&self.inner, token, data).await
+    }
+}
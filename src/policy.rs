@@ -0,0 +1,282 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::middleware::policy::{Policy, PolicyMiddleware};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, Address, NameOrAddress};
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+/// Why a [`TxPolicy`] rejected a transaction
+pub enum PolicyViolation {
+    /// The recipient is not allowed, `None` standing for a contract deployment
+    #[error("destination {0:?} is not allowed")]
+    DestinationNotAllowed(Option<NameOrAddress>),
+    /// The recipient is on the denylist
+    #[error("destination {0:?} is denied")]
+    DestinationDenied(Address),
+    /// The calldata is larger than allowed
+    #[error("calldata of {size} bytes exceeds the maximum of {max}")]
+    CalldataTooLarge { size: usize, max: usize },
+    /// The called method is not allowed, `None` standing for calldata shorter than a selector
+    #[error("method selector {0:?} is not allowed")]
+    SelectorNotAllowed(Option<[u8; 4]>),
+    /// Rejection by a custom policy
+    #[error("{0}")]
+    Other(String),
+}
+
+/// A safety rule evaluated against every transaction before it is assigned a nonce. A
+/// rejected transaction fails with [`crate::NonceManagerError::PolicyRejected`] without
+/// consuming a nonce. ENS names of recipients are resolved before the policies run, so they
+/// only see addresses.
+pub trait TxPolicy: Send + Sync + Debug {
+    /// Checks `tx`, returning why it must not be sent
+    fn check(&self, tx: &TypedTransaction) -> Result<(), PolicyViolation>;
+}
+
+#[derive(Debug, Clone, Default)]
+/// Only allows transactions to the listed addresses, rejecting contract deployments and
+/// unresolved ENS names
+pub struct AllowedDestinations(pub HashSet<Address>);
+
+impl TxPolicy for AllowedDestinations {
+    fn check(&self, tx: &TypedTransaction) -> Result<(), PolicyViolation> {
+        match tx.to() {
+            Some(NameOrAddress::Address(to)) if self.0.contains(to) => Ok(()),
+            to => Err(PolicyViolation::DestinationNotAllowed(to.cloned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Rejects transactions carrying more than the given number of bytes of calldata
+pub struct MaxCalldataSize(pub usize);
+
+impl TxPolicy for MaxCalldataSize {
+    fn check(&self, tx: &TypedTransaction) -> Result<(), PolicyViolation> {
+        let size = tx.data().map_or(0, |data| data.len());
+        if size > self.0 {
+            return Err(PolicyViolation::CalldataTooLarge { size, max: self.0 });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Only allows calls to the listed 4-byte method selectors. Plain transfers without calldata
+/// are let through.
+pub struct AllowedSelectors(pub HashSet<[u8; 4]>);
+
+impl TxPolicy for AllowedSelectors {
+    fn check(&self, tx: &TypedTransaction) -> Result<(), PolicyViolation> {
+        let data = match tx.data() {
+            Some(data) if !data.is_empty() => data,
+            _ => return Ok(()),
+        };
+        match data.get(..4).and_then(|selector| selector.try_into().ok()) {
+            Some(selector) if self.0.contains(&selector) => Ok(()),
+            selector => Err(PolicyViolation::SelectorNotAllowed(selector)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Rejects transactions to the listed addresses, and to ENS names which weren't resolved. The
+/// list can be updated at runtime, e.g. to block a sanctioned address or contain an incident,
+/// and every clone shares it, so a clone kept aside after registering the policy keeps
+/// controlling it.
+pub struct Denylist(Arc<RwLock<HashSet<Address>>>);
+
+impl Denylist {
+    /// Creates a denylist of `addresses`
+    pub fn new(addresses: impl IntoIterator<Item = Address>) -> Self {
+        Self(Arc::new(RwLock::new(addresses.into_iter().collect())))
+    }
+
+    /// Adds `address` to the list, returning whether it was not denied yet
+    pub fn deny(&self, address: Address) -> bool {
+        self.0
+            .write()
+            .expect("denylist lock poisoned")
+            .insert(address)
+    }
+
+    /// Removes `address` from the list, returning whether it was denied
+    pub fn allow(&self, address: Address) -> bool {
+        self.0
+            .write()
+            .expect("denylist lock poisoned")
+            .remove(&address)
+    }
+
+    /// Whether `address` is denied
+    pub fn contains(&self, address: &Address) -> bool {
+        self.0
+            .read()
+            .expect("denylist lock poisoned")
+            .contains(address)
+    }
+}
+
+impl TxPolicy for Denylist {
+    fn check(&self, tx: &TypedTransaction) -> Result<(), PolicyViolation> {
+        match tx.to() {
+            Some(NameOrAddress::Address(to)) if self.contains(to) => {
+                Err(PolicyViolation::DestinationDenied(*to))
+            }
+            // a name could stand for any address, denied ones included
+            to @ Some(NameOrAddress::Name(_)) => {
+                Err(PolicyViolation::DestinationNotAllowed(to.cloned()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Registers a policy every transaction has to pass before it is assigned a nonce.
+    /// Policies are evaluated in the order they were added.
+    pub fn with_policy(mut self, policy: impl TxPolicy + 'static) -> Self {
+        self.policies.push(Arc::new(policy));
+        self
+    }
+
+    /// Wraps the manager in ethers' [`PolicyMiddleware`], the order in which `policy` rejects
+    /// transactions before they are assigned a nonce. Stacked the other way around, the
+    /// policy only sees what the manager sends through the inner middleware's
+    /// `send_transaction`: a rejection then leaves the counter alone, but transactions the
+    /// manager signs itself, e.g. with [`Self::with_write_ahead_log`], bypass the policy, and
+    /// ones filled with `fill_transaction` are assigned their nonce before it runs.
+    pub fn behind_policy<P: Policy>(self, policy: P) -> PolicyMiddleware<Self, P> {
+        PolicyMiddleware::new(self, policy)
+    }
+
+    /// Runs `tx` through the registered policies, first resolving the ENS name of its
+    /// recipient, so that the address checked is the one the transaction is sent to
+    pub(crate) async fn check_policies(
+        &self,
+        tx: &mut TypedTransaction,
+    ) -> Result<(), NonceManagerError<M>> {
+        if self.policies.is_empty() {
+            return Ok(());
+        }
+        if let Some(NameOrAddress::Name(name)) = tx.to().cloned() {
+            let to = self
+                .inner
+                .resolve_name(&name)
+                .await
+                .map_err(FromErr::from)?;
+            tx.set_to(to);
+        }
+        self.policies
+            .iter()
+            .try_for_each(|policy| policy.check(tx))
+            .map_err(NonceManagerError::PolicyRejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::TransactionRequest;
+
+    fn call(to: Address, data: Vec<u8>) -> TypedTransaction {
+        TransactionRequest::new().to(to).data(data).into()
+    }
+
+    #[test]
+    fn only_listed_destinations_are_allowed() {
+        let allowed = Address::repeat_byte(1);
+        let policy = AllowedDestinations([allowed].into());
+        assert_eq!(policy.check(&call(allowed, vec![])), Ok(()));
+
+        let other = Address::repeat_byte(2);
+        assert_eq!(
+            policy.check(&call(other, vec![])),
+            Err(PolicyViolation::DestinationNotAllowed(Some(other.into())))
+        );
+        let deployment = TransactionRequest::new().data(vec![0x60]).into();
+        assert_eq!(
+            policy.check(&deployment),
+            Err(PolicyViolation::DestinationNotAllowed(None))
+        );
+        let name = NameOrAddress::Name("vitalik.eth".to_owned());
+        assert_eq!(
+            policy.check(&TransactionRequest::new().to(name.clone()).into()),
+            Err(PolicyViolation::DestinationNotAllowed(Some(name)))
+        );
+    }
+
+    #[test]
+    fn calldata_is_capped() {
+        let policy = MaxCalldataSize(4);
+        assert_eq!(policy.check(&TransactionRequest::new().into()), Ok(()));
+        assert_eq!(policy.check(&call(Address::zero(), vec![0; 4])), Ok(()));
+        assert_eq!(
+            policy.check(&call(Address::zero(), vec![0; 5])),
+            Err(PolicyViolation::CalldataTooLarge { size: 5, max: 4 })
+        );
+    }
+
+    #[test]
+    fn only_listed_selectors_are_allowed() {
+        let transfer = [0xa9, 0x05, 0x9c, 0xbb];
+        let policy = AllowedSelectors([transfer].into());
+        assert_eq!(policy.check(&call(Address::zero(), vec![])), Ok(()));
+        assert_eq!(
+            policy.check(&call(Address::zero(), [&transfer[..], &[0; 64]].concat())),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check(&call(Address::zero(), vec![0x09, 0x5e, 0xa7, 0xb3])),
+            Err(PolicyViolation::SelectorNotAllowed(Some([
+                0x09, 0x5e, 0xa7, 0xb3
+            ])))
+        );
+        assert_eq!(
+            policy.check(&call(Address::zero(), transfer[..3].to_vec())),
+            Err(PolicyViolation::SelectorNotAllowed(None))
+        );
+    }
+
+    #[test]
+    fn denylist_updates_reach_every_clone() {
+        let denied = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+        let denylist = Denylist::new([denied]);
+        let policy = denylist.clone();
+        assert_eq!(
+            policy.check(&call(denied, vec![])),
+            Err(PolicyViolation::DestinationDenied(denied))
+        );
+        assert_eq!(policy.check(&call(other, vec![])), Ok(()));
+        assert_eq!(policy.check(&TransactionRequest::new().into()), Ok(()));
+
+        assert!(denylist.deny(other));
+        assert!(!denylist.deny(other));
+        assert_eq!(
+            policy.check(&call(other, vec![])),
+            Err(PolicyViolation::DestinationDenied(other))
+        );
+        assert!(denylist.allow(denied));
+        assert!(!denylist.allow(denied));
+        assert!(!policy.contains(&denied));
+        assert_eq!(policy.check(&call(denied, vec![])), Ok(()));
+    }
+
+    #[test]
+    fn denylist_rejects_unresolved_names() {
+        let name = NameOrAddress::Name("vitalik.eth".to_owned());
+        assert_eq!(
+            Denylist::default().check(&TransactionRequest::new().to(name.clone()).into()),
+            Err(PolicyViolation::DestinationNotAllowed(Some(name)))
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::LockedNonceManagerMiddleware;
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, U256};
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::{Mutex, MutexGuard},
+};
+use tokio::sync::Notify;
+
+/// Position of a send in the queue, highest bid first and oldest first among equal bids
+type Key = (Reverse<U256>, u64);
+
+#[derive(Debug, Default)]
+struct Waiting {
+    queue: BTreeSet<Key>,
+    /// Whether an admitted send is still on its way to the nonce lock
+    busy: bool,
+}
+
+#[derive(Debug, Default)]
+/// Sends waiting for a nonce, admitted to the nonce lock one at a time in the order of their
+/// bids. With equal bids it is a FIFO queue.
+pub(crate) struct FeeQueue {
+    waiting: Mutex<Waiting>,
+    changed: Notify,
+    next_seq: AtomicU64,
+}
+
+impl FeeQueue {
+    /// Takes the place of a send bidding `bid` in the queue, without waiting
+    fn enqueue(&self, bid: U256) -> Ticket<'_> {
+        let key = (Reverse(bid), self.next_seq.fetch_add(1, Ordering::Relaxed));
+        self.waiting().queue.insert(key);
+        Ticket {
+            queue: self,
+            key,
+            admitted: false,
+        }
+    }
+
+    /// Waits until the send bidding `bid` is the highest bidder and no other admitted send
+    /// is still waiting for the nonce lock
+    async fn admit(&self, bid: U256) -> Ticket<'_> {
+        self.enqueue(bid).admitted().await
+    }
+
+    fn waiting(&self) -> MutexGuard<'_, Waiting> {
+        self.waiting.lock().expect("fee queue lock poisoned")
+    }
+}
+
+#[derive(Debug)]
+/// A send's place in the [`FeeQueue`], given up when dropped
+pub(crate) struct Ticket<'a> {
+    queue: &'a FeeQueue,
+    key: Key,
+    admitted: bool,
+}
+
+impl Ticket<'_> {
+    /// Waits until the ticket is first in the queue and no other admitted send is still
+    /// waiting for the nonce lock
+    async fn admitted(mut self) -> Self {
+        let queue = self.queue;
+        loop {
+            let changed = queue.changed.notified();
+            {
+                let mut waiting = queue.waiting();
+                if !waiting.busy && waiting.queue.iter().next() == Some(&self.key) {
+                    waiting.busy = true;
+                    self.admitted = true;
+                    return self;
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        {
+            let mut waiting = self.queue.waiting();
+            waiting.queue.remove(&self.key);
+            if self.admitted {
+                waiting.busy = false;
+            }
+        }
+        self.queue.changed.notify_waiters();
+    }
+}
+
+/// What `tx` is willing to pay the block producer per gas: the max priority fee of EIP-1559
+/// transactions, and the gas price of the others
+fn bid(tx: &TypedTransaction) -> U256 {
+    match tx {
+        TypedTransaction::Eip1559(inner) => inner.max_priority_fee_per_gas,
+        _ => tx.gas_price(),
+    }
+    .unwrap_or_default()
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Hands out the nonces to concurrent sends by their willingness to pay instead of their
+    /// arrival, so that an urgent, well paying transaction gets the lowest free nonce instead
+    /// of waiting behind cheap housekeeping ones. Sends without fees set bid nothing.
+    pub fn with_fee_ordering(mut self) -> Self {
+        self.config_mut().fee_ordering = true;
+        self
+    }
+
+    /// Hands out the nonces to concurrent sends and fills strictly in the order they were
+    /// called (more precisely, first polled), even if some of them spend longer getting to
+    /// the nonce lock, e.g. waiting for the counter to be initialized or for a block slot.
+    /// Ignored if fee ordering is enabled.
+    pub fn with_fifo_ordering(mut self) -> Self {
+        self.config_mut().fifo_ordering = true;
+        self
+    }
+
+    /// Takes the place of a call assigning a nonce in the queue of arrivals if FIFO ordering
+    /// is enabled. Has to be called before the call first awaits anything, and the ticket
+    /// dropped as soon as the call turns out not to assign a nonce, as it holds up the calls
+    /// behind it until it is handed to [`Self::lock_for_assignment`].
+    pub(crate) fn arrival_ticket(&self) -> Option<Ticket<'_>> {
+        let config = self.config();
+        if !config.fifo_ordering || config.fee_ordering {
+            return None;
+        }
+        Some(self.arrivals.enqueue(U256::zero()))
+    }
+
+    /// Waits for the turn of `tx` to take the nonce lock if fee or FIFO ordering is enabled,
+    /// where `arrival` is the ticket taken by [`Self::arrival_ticket`]. The ticket returned
+    /// has to be held until the lock is taken.
+    pub(crate) async fn fee_ticket<'a>(
+        &'a self,
+        tx: Option<&TypedTransaction>,
+        arrival: Option<Ticket<'a>>,
+    ) -> Option<Ticket<'a>> {
+        if self.config().fee_ordering {
+            return Some(self.fee_queue.admit(tx.map(bid).unwrap_or_default()).await);
+        }
+        match arrival {
+            Some(arrival) => Some(arrival.admitted().await),
+            None => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, transfer, SENDER};
+    use crate::{NonceManagerError, SimulatedChain};
+    use ethers::providers::{Middleware, Provider};
+    use ethers::types::{transaction::eip2718::TypedTransaction, U256};
+    use futures_util::FutureExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn every_assignment_waits_for_its_turn() {
+        type Error = NonceManagerError<Provider<SimulatedChain>>;
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain).with_fifo_ordering();
+        let mut tx: TypedTransaction = transfer().into();
+        let earlier = client.arrival_ticket();
+        assert!(client.apply_nonce(&mut tx).now_or_never().is_none());
+        assert!(client.reserve_nonce(None).now_or_never().is_none());
+        let hold = client.hold_nonce(Duration::from_secs(60), None);
+        assert!(hold.now_or_never().is_none());
+        let with_nonce = client.with_nonce(|nonce| async move { Ok::<_, Error>(nonce) });
+        assert!(with_nonce.now_or_never().is_none());
+        let scheduled = client.schedule_transaction(transfer(), None);
+        assert!(scheduled.now_or_never().is_none());
+
+        // the calls given up above left the queue, so only the earlier ticket was in the way
+        drop(earlier);
+        assert_eq!(client.apply_nonce(&mut tx).await.unwrap().as_u64(), 0);
+    }
+
+    #[tokio::test]
+    async fn fee_ordering_admits_every_assignment() {
+        let chain = SimulatedChain::new(3);
+        let client = client(&chain).with_fee_ordering();
+        let mut tx: TypedTransaction = transfer().gas_price(1).into();
+        let admitted = client.fee_queue.admit(U256::MAX).await;
+        assert!(client.apply_nonce(&mut tx).now_or_never().is_none());
+        let scheduled = client.schedule_transaction(transfer().gas_price(1), None);
+        assert!(scheduled.now_or_never().is_none());
+
+        drop(admitted);
+        assert_eq!(client.apply_nonce(&mut tx).await.unwrap().as_u64(), 0);
+    }
+
+    #[tokio::test]
+    async fn sends_which_assign_no_nonce_skip_the_queue() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain).with_fifo_ordering();
+        let earlier = client.arrival_ticket();
+        client
+            .send_transaction(transfer().nonce(0), None)
+            .await
+            .unwrap();
+        assert_eq!(chain.pending_nonces(SENDER), [0]);
+        chain.mine();
+
+        drop(earlier);
+        send(&client).await;
+        assert_eq!(chain.pending_nonces(SENDER), [1]);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError, SendEndpoint,
+};
+use async_trait::async_trait;
+use ethers::providers::{FromErr, JsonRpcClient, Middleware, Provider};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, TxHash, H256, U64};
+use ethers::utils::keccak256;
+use serde_json::json;
+use std::{error::Error, fmt::Debug, sync::Arc};
+
+/// A relay handing signed transactions straight to block builders, e.g. Flashbots Protect,
+/// so that they never show up in the public mempool
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait PrivateRelay: Send + Sync + Debug {
+    /// Submits the signed transaction for inclusion up to block `max_block_number`, after
+    /// which the relay drops it. Returns the transaction's hash.
+    async fn send_private_transaction(
+        &self,
+        raw_tx: Bytes,
+        max_block_number: U64,
+    ) -> Result<TxHash, Box<dyn Error + Send + Sync>>;
+}
+
+/// `eth_sendPrivateTransaction`, as served by Flashbots Protect and compatible relays. Relays
+/// authenticating their callers, like the Flashbots relay with its `X-Flashbots-Signature`
+/// header, need a transport adding the authentication to every request.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<P: JsonRpcClient + 'static> PrivateRelay for Provider<P> {
+    async fn send_private_transaction(
+        &self,
+        raw_tx: Bytes,
+        max_block_number: U64,
+    ) -> Result<TxHash, Box<dyn Error + Send + Sync>> {
+        let params = json!({ "tx": raw_tx, "maxBlockNumber": max_block_number });
+        Ok(self.request("eth_sendPrivateTransaction", [params]).await?)
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Submits the transactions sent with [`Self::send_private_transaction`] through
+    /// `relay`, which drops those it couldn't get included within `max_blocks` blocks
+    pub fn with_private_relay(
+        mut self,
+        relay: impl PrivateRelay + 'static,
+        max_blocks: u64,
+    ) -> Self {
+        self.private_relay = Some(Arc::new(relay));
+        self.config_mut().private_max_blocks = max_blocks;
+        self
+    }
+
+    /// Signs `tx` at the next nonce and submits it through the [`PrivateRelay`] instead of
+    /// broadcasting it. The transaction is then tracked as in flight like a broadcast one,
+    /// except that it is never replaced, and the transactions scheduled above it are
+    /// flushed without waiting for it. A private transaction may never be included though,
+    /// so its nonce is reclaimed if the relay dropped it, see
+    /// [`Self::expire_private_transactions`]. If the relay doesn't accept it, the
+    /// reservation is released as described in [`Self::release_reservation`]. Returns the
+    /// nonce and the hash of the transaction.
+    pub async fn send_private_transaction<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        block: Option<BlockId>,
+    ) -> Result<(Nonce, TxHash), NonceManagerError<M>> {
+        let relay = self
+            .private_relay
+            .clone()
+            .ok_or(NonceManagerError::NoPrivateRelay)?;
+        self.ensure_connected()?;
+        self.ensure_accepting()?;
+        let mut tx = tx.into();
+        self.check_policies(&mut tx).await?;
+        let nonce = self.reserve_nonce(block).await?;
+        tx.set_nonce(nonce);
+
+        match self.submit_private(&*relay, &mut tx, nonce, block).await {
+            Ok(tx_hash) => Ok((nonce, tx_hash)),
+            Err(err) => {
+                self.release_reservation(nonce).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Checks on the transactions sent with [`Self::send_private_transaction`]. Those whose
+    /// nonce was used, by them or otherwise, stop being tracked, emitting a
+    /// [`NonceEvent::Confirmed`] if they were included and [`Self::poll_in_flight`] didn't
+    /// report them already. Those still unincluded past their
+    /// last block were dropped by the relay, and their nonce is reclaimed like the one of an
+    /// expired hold, see [`Self::hold_nonce`], and the transactions depending on them are
+    /// given up. Returns the reclaimed nonces, emitting a [`NonceEvent::PrivateExpired`] for
+    /// each, or fails like [`Self::expire_holds`] if a reclaimed nonce couldn't be filled.
+    pub async fn expire_private_transactions(&self) -> Result<Vec<Nonce>, NonceManagerError<M>> {
+        let submitted = self.state.schedule().lock().await.private();
+        if submitted.is_empty() {
+            return Ok(Vec::new());
+        }
+        let latest = self.inner.get_block_number().await.map_err(FromErr::from)?;
+        let mined_count = self
+            .inner
+            .get_transaction_count(self.address, Some(latest.into()))
+            .await
+            .map_err(FromErr::from)?;
+
+        let mut expired = Vec::new();
+        for (nonce, tx_hash, max_block_number) in submitted {
+            if nonce.as_u256() < mined_count {
+                let receipt = self
+                    .inner
+                    .get_transaction_receipt(tx_hash)
+                    .await
+                    .map_err(FromErr::from)?;
+                if !self.state.schedule().lock().await.take_private(nonce) {
+                    continue;
+                }
+                let label = self
+                    .state
+                    .in_flight()
+                    .get(nonce)
+                    .map(|tracked| tracked.label.clone());
+                match receipt {
+                    Some(receipt) => {
+                        self.state.schedule().lock().await.settle(nonce);
+                        // poll_in_flight reported it already if it stopped tracking it
+                        if let Some(label) = label {
+                            self.on_confirmed(nonce, &receipt).await;
+                            self.state.in_flight().forget(nonce);
+                            self.emit(NonceEvent::Confirmed {
+                                address: self.address,
+                                nonce,
+                                tx_hash,
+                                block_number: receipt.block_number,
+                                label,
+                            });
+                        }
+                    }
+                    // the nonce was used by a different transaction
+                    None => {
+                        self.state.in_flight().forget(nonce);
+                        self.drop_dependents(nonce).await;
+                    }
+                }
+            } else if latest > max_block_number {
+                expired.push((nonce, tx_hash));
+            }
+        }
+
+        let mut gaps = Vec::new();
+        let mut reclaimed = Vec::new();
+        {
+            let mut counter = self.lock_nonce().await;
+            let mut schedule = self.state.schedule().lock().await;
+            // highest first, so a run of expired transactions at the top is rolled back as a
+            // whole
+            for (nonce, tx_hash) in expired.into_iter().rev() {
+                if !schedule.take_private(nonce) {
+                    continue;
+                }
+                self.state.in_flight().forget(nonce);
+                let rolled_back = nonce.checked_increment() == Some(*counter);
+                if rolled_back {
+                    *counter = nonce;
+                    self.journal(JournalEntry::Released { nonce, rolled_back });
+                } else {
+                    // reserved again for the filler
+                    schedule.reserve(nonce);
+                    self.journal(JournalEntry::Reserved { nonce });
+                    gaps.push(nonce);
+                }
+                reclaimed.push(nonce);
+                self.emit(NonceEvent::PrivateExpired {
+                    address: self.address,
+                    nonce,
+                    tx_hash,
+                    rolled_back,
+                });
+            }
+        }
+
+        let filled = self.fill_gaps(gaps).await;
+        for &nonce in &reclaimed {
+            self.drop_dependents(nonce).await;
+        }
+        self.checkpoint_if_due().await;
+        filled.map(|_| reclaimed)
+    }
+
+    /// Signs `tx` at the reserved `nonce` and submits it through `relay`, tracking it as in
+    /// flight until it is included or dropped
+    async fn submit_private(
+        &self,
+        relay: &dyn PrivateRelay,
+        tx: &mut TypedTransaction,
+        nonce: Nonce,
+        block: Option<BlockId>,
+    ) -> Result<TxHash, NonceManagerError<M>> {
+        let raw_tx = self.fill_and_sign(tx, block).await?;
+        let latest = self.inner.get_block_number().await.map_err(FromErr::from)?;
+        let max_block_number = latest + self.config().private_max_blocks;
+        self.log_broadcasting(nonce, H256::from(keccak256(&raw_tx)))?;
+        let send = relay.send_private_transaction(raw_tx, max_block_number);
+        let tx_hash = self
+            .timed_send(SendEndpoint::Private, send)
+            .await
+            .map_err(NonceManagerError::PrivateRelayError)?;
+        self.state
+            .schedule()
+            .lock()
+            .await
+            .submit_private(nonce, tx_hash, max_block_number);
+        self.record_sent(nonce, tx_hash, Some(tx), None, SendEndpoint::Private)
+            .await;
+        self.journal(JournalEntry::Private {
+            nonce,
+            tx_hash,
+            max_block_number,
+        });
+        self.checkpoint_if_due().await;
+        Ok(tx_hash)
+    }
+}
+
+#[cfg(feature = "mev-share")]
+pub use self::mev_share::{MevShareHint, MevShareRelay};
+
+#[cfg(feature = "mev-share")]
+mod mev_share {
+    use super::PrivateRelay;
+    use async_trait::async_trait;
+    use ethers::providers::{JsonRpcClient, Middleware, Provider};
+    use ethers::types::{Bytes, TxHash, H256, U64};
+    use ethers::utils::keccak256;
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::error::Error;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    /// Part of a transaction revealed to MEV-Share searchers, who need some of it to backrun
+    /// the transaction
+    pub enum MevShareHint {
+        /// The whole calldata
+        Calldata,
+        /// The address of the called contract
+        ContractAddress,
+        /// The 4-byte selector of the called method
+        FunctionSelector,
+        /// All logs the transaction emits
+        Logs,
+        /// The logs of well-known events, e.g. swaps on popular exchanges
+        DefaultLogs,
+        /// The transaction's hash
+        Hash,
+    }
+
+    #[derive(Debug, Clone)]
+    /// [`PrivateRelay`] submitting every transaction as a bundle of its own to a MEV-Share
+    /// node with `mev_sendBundle`, so that searchers can backrun it based on the hints shared
+    /// with them and refund part of the MEV they extract. Like for other relays, the nonce
+    /// is reclaimed if the bundle isn't included by its last block.
+    pub struct MevShareRelay<P> {
+        provider: Provider<P>,
+        hints: Vec<MevShareHint>,
+        builders: Vec<String>,
+    }
+
+    impl<P> MevShareRelay<P> {
+        /// Submits bundles to the MEV-Share node behind `provider`, sharing no hints, which
+        /// keeps the transactions private like [`PrivateRelay`] for [`Provider`] does. The
+        /// Flashbots node needs a transport signing every request with the
+        /// `X-Flashbots-Signature` header.
+        pub fn new(provider: Provider<P>) -> Self {
+            Self {
+                provider,
+                hints: Vec::new(),
+                builders: Vec::new(),
+            }
+        }
+
+        /// Shares `hint` about every transaction with searchers
+        pub fn with_hint(mut self, hint: MevShareHint) -> Self {
+            self.hints.push(hint);
+            self
+        }
+
+        /// Lets the builder registered as `builder` include the bundles, on top of the ones
+        /// the node picks by default
+        pub fn with_builder(mut self, builder: impl Into<String>) -> Self {
+            self.builders.push(builder.into());
+            self
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+    #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+    impl<P: JsonRpcClient + 'static> PrivateRelay for MevShareRelay<P> {
+        async fn send_private_transaction(
+            &self,
+            raw_tx: Bytes,
+            max_block_number: U64,
+        ) -> Result<TxHash, Box<dyn Error + Send + Sync>> {
+            let block = self.provider.get_block_number().await? + 1;
+            let tx_hash = H256::from(keccak256(&raw_tx));
+            let bundle = json!({
+                "version": "v0.1",
+                "inclusion": { "block": block.min(max_block_number), "maxBlock": max_block_number },
+                "body": [{ "tx": raw_tx, "canRevert": false }],
+                "privacy": { "hints": self.hints, "builders": self.builders },
+            });
+            // answers with the bundle's hash, the transaction's is what the manager tracks
+            let _: Value = self.provider.request("mev_sendBundle", [bundle]).await?;
+            Ok(tx_hash)
+        }
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ethers::providers::Provider;
+
+        #[tokio::test]
+        async fn bundles_end_at_the_last_block_and_share_the_hints() {
+            let (provider, mock) = Provider::mocked();
+            let relay = MevShareRelay::new(provider)
+                .with_hint(MevShareHint::Calldata)
+                .with_builder("flashbots");
+            // answered last pushed first
+            mock.push(json!({ "bundleHash": H256::zero() })).unwrap();
+            mock.push(U64::from(100)).unwrap();
+
+            let raw_tx = Bytes::from(vec![1, 2, 3]);
+            let max_block_number = U64::from(100);
+            let tx_hash = relay
+                .send_private_transaction(raw_tx.clone(), max_block_number)
+                .await
+                .unwrap();
+            assert_eq!(tx_hash, H256::from(keccak256(&raw_tx)));
+
+            mock.assert_request("eth_blockNumber", ()).unwrap();
+            // the next block is past the last one, so the bundle only targets the last one
+            let bundle = json!({
+                "version": "v0.1",
+                "inclusion": { "block": max_block_number, "maxBlock": max_block_number },
+                "body": [{ "tx": raw_tx, "canRevert": false }],
+                "privacy": { "hints": ["calldata"], "builders": ["flashbots"] },
+            });
+            mock.assert_request("mev_sendBundle", [bundle]).unwrap();
+        }
+    }
+}
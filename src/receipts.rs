@@ -0,0 +1,243 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{TransactionReceipt, TxHash};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Bounds of the cache of receipts fetched by the manager
+pub struct ReceiptCacheConfig {
+    /// Maximum number of receipts kept, the oldest are evicted first
+    pub capacity: usize,
+    /// How long a receipt is kept after it was fetched
+    #[serde(rename = "ttl_secs", with = "crate::config::duration_secs")]
+    pub ttl: Duration,
+}
+
+#[derive(Debug)]
+struct CachedReceipt {
+    nonce: Nonce,
+    fetched_at: Instant,
+    receipt: TransactionReceipt,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    receipts: HashMap<TxHash, CachedReceipt>,
+    by_nonce: HashMap<Nonce, TxHash>,
+    /// Hashes in the order they were cached, oldest first
+    order: VecDeque<TxHash>,
+}
+
+#[derive(Debug, Default)]
+/// Receipts fetched by the manager, bounded and evicted after a TTL. The bounds and the time
+/// by the manager's clock are passed in on every call, so that they can be reconfigured at
+/// runtime.
+pub(crate) struct ReceiptCache {
+    entries: Mutex<Entries>,
+}
+
+impl ReceiptCache {
+    fn insert(
+        &self,
+        nonce: Nonce,
+        receipt: TransactionReceipt,
+        config: ReceiptCacheConfig,
+        now: Instant,
+    ) {
+        let mut entries = self.entries();
+        let tx_hash = receipt.transaction_hash;
+        let cached = CachedReceipt {
+            nonce,
+            fetched_at: now,
+            receipt,
+        };
+        if entries.receipts.insert(tx_hash, cached).is_none() {
+            entries.order.push_back(tx_hash);
+        }
+        entries.by_nonce.insert(nonce, tx_hash);
+        entries.evict(config, now);
+    }
+
+    fn get(
+        &self,
+        tx_hash: TxHash,
+        config: ReceiptCacheConfig,
+        now: Instant,
+    ) -> Option<TransactionReceipt> {
+        let mut entries = self.entries();
+        entries.evict(config, now);
+        entries
+            .receipts
+            .get(&tx_hash)
+            .map(|cached| cached.receipt.clone())
+    }
+
+    fn hash_of(&self, nonce: Nonce) -> Option<TxHash> {
+        self.entries().by_nonce.get(&nonce).copied()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().expect("receipt cache lock poisoned")
+    }
+}
+
+impl Entries {
+    /// Drops expired receipts and the oldest ones over capacity
+    fn evict(&mut self, config: ReceiptCacheConfig, now: Instant) {
+        while let Some(oldest) = self.order.front().copied() {
+            let expired = self.receipts.get(&oldest).is_none_or(|cached| {
+                now.saturating_duration_since(cached.fetched_at) >= config.ttl
+            });
+            if !expired && self.order.len() <= config.capacity {
+                break;
+            }
+            self.order.pop_front();
+            if let Some(cached) = self.receipts.remove(&oldest) {
+                if self.by_nonce.get(&cached.nonce) == Some(&oldest) {
+                    self.by_nonce.remove(&cached.nonce);
+                }
+            }
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Caches the receipts the manager fetches, e.g. while polling the in-flight
+    /// transactions, so that [`Self::receipt`] and [`Self::receipt_by_nonce`] answer
+    /// repeated lookups without another `eth_getTransactionReceipt`
+    pub fn with_receipt_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.config_mut().receipt_cache = Some(ReceiptCacheConfig { capacity, ttl });
+        self
+    }
+
+    /// The receipt of `tx_hash`, from the cache if it holds it
+    pub async fn receipt(
+        &self,
+        tx_hash: TxHash,
+    ) -> Result<Option<TransactionReceipt>, NonceManagerError<M>> {
+        if let Some(config) = self.config().receipt_cache {
+            if let Some(receipt) = self.receipts.get(tx_hash, config, self.now()) {
+                return Ok(Some(receipt));
+            }
+        }
+        self.inner
+            .get_transaction_receipt(tx_hash)
+            .await
+            .map_err(FromErr::from)
+    }
+
+    /// The receipt of the managed transaction at `nonce`, from the cache if it holds it.
+    /// `None` if the transaction isn't mined, or the manager doesn't know which one it is.
+    pub async fn receipt_by_nonce(
+        &self,
+        nonce: Nonce,
+    ) -> Result<Option<TransactionReceipt>, NonceManagerError<M>> {
+        let tx_hash = match self.state.in_flight().get(nonce) {
+            Some(tracked) => Some(tracked.tx_hash),
+            None => self.receipts.hash_of(nonce),
+        };
+        match tx_hash {
+            Some(tx_hash) => self.fetch_receipt(nonce, tx_hash).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up the receipt of the managed transaction at `nonce`, going through the cache
+    /// if one is configured
+    pub(crate) async fn fetch_receipt(
+        &self,
+        nonce: Nonce,
+        tx_hash: TxHash,
+    ) -> Result<Option<TransactionReceipt>, NonceManagerError<M>> {
+        let config = match self.config().receipt_cache {
+            Some(config) => config,
+            None => {
+                return self
+                    .inner
+                    .get_transaction_receipt(tx_hash)
+                    .await
+                    .map_err(FromErr::from)
+            }
+        };
+        if let Some(receipt) = self.receipts.get(tx_hash, config, self.now()) {
+            return Ok(Some(receipt));
+        }
+
+        let receipt = self
+            .inner
+            .get_transaction_receipt(tx_hash)
+            .await
+            .map_err(FromErr::from)?;
+        if let Some(receipt) = &receipt {
+            self.receipts
+                .insert(nonce, receipt.clone(), config, self.now());
+        }
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(byte: u8) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: TxHash::repeat_byte(byte),
+            ..Default::default()
+        }
+    }
+
+    fn nonce(nonce: u64) -> Nonce {
+        Nonce::new(nonce.into()).unwrap()
+    }
+
+    const CONFIG: ReceiptCacheConfig = ReceiptCacheConfig {
+        capacity: 2,
+        ttl: Duration::from_secs(60),
+    };
+
+    #[test]
+    fn the_oldest_receipts_are_evicted_over_capacity() {
+        let cache = ReceiptCache::default();
+        let now = Instant::now();
+        for byte in 1..=3 {
+            cache.insert(nonce(byte.into()), receipt(byte), CONFIG, now);
+        }
+        assert!(cache.get(TxHash::repeat_byte(1), CONFIG, now).is_none());
+        assert_eq!(cache.hash_of(nonce(1)), None);
+        assert!(cache.get(TxHash::repeat_byte(2), CONFIG, now).is_some());
+        assert_eq!(cache.hash_of(nonce(3)), Some(TxHash::repeat_byte(3)));
+    }
+
+    #[test]
+    fn receipts_expire_after_their_ttl() {
+        let cache = ReceiptCache::default();
+        let now = Instant::now();
+        cache.insert(nonce(0), receipt(1), CONFIG, now);
+        let later = now + Duration::from_secs(59);
+        assert!(cache.get(TxHash::repeat_byte(1), CONFIG, later).is_some());
+        let expired = now + Duration::from_secs(60);
+        assert!(cache.get(TxHash::repeat_byte(1), CONFIG, expired).is_none());
+        assert_eq!(cache.hash_of(nonce(0)), None);
+    }
+
+    #[test]
+    fn a_replacement_takes_over_the_nonce() {
+        let cache = ReceiptCache::default();
+        let now = Instant::now();
+        cache.insert(nonce(0), receipt(1), CONFIG, now);
+        cache.insert(nonce(0), receipt(2), CONFIG, now);
+        assert_eq!(cache.hash_of(nonce(0)), Some(TxHash::repeat_byte(2)));
+        // evicting the replaced receipt leaves the replacement's entry alone
+        cache.insert(nonce(1), receipt(3), CONFIG, now);
+        assert_eq!(cache.hash_of(nonce(0)), Some(TxHash::repeat_byte(2)));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{TxHash, U64};
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+/// Outcome of comparing the manager's state against the chain, as returned by
+/// [`LockedNonceManagerMiddleware::reconcile`]
+pub struct ReconciliationReport {
+    /// In-flight transactions which were mined with the configured number of
+    /// confirmations, along with their block
+    pub confirmed: Vec<(Nonce, TxHash, Option<U64>)>,
+    /// In-flight transactions whose nonce was used by a different transaction
+    pub replaced: Vec<(Nonce, TxHash)>,
+    /// In-flight transactions the node no longer knows, while their nonce is still unused
+    pub dropped: Vec<(Nonce, TxHash)>,
+    /// The local counter and the chain's transaction count, if the counter was behind
+    pub counter_behind: Option<(Nonce, Nonce)>,
+}
+
+impl ReconciliationReport {
+    /// Whether the state matched the chain, i.e. nothing but confirmations were found
+    pub fn is_consistent(&self) -> bool {
+        self.replaced.is_empty() && self.dropped.is_empty() && self.counter_behind.is_none()
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Checks every in-flight transaction against its receipt and the mined transaction
+    /// count, and corrects the state: mined transactions emit [`NonceEvent::Confirmed`] and
+    /// stop being tracked, as do transactions whose nonce was used by another one, and a
+    /// counter behind the chain is moved up to it. Dropped transactions are only reported,
+    /// since they may still be rebroadcast.
+    pub async fn reconcile(&self) -> Result<ReconciliationReport, NonceManagerError<M>> {
+        let mut report = ReconciliationReport::default();
+        let mined_count = self
+            .inner
+            .get_transaction_count(self.address, None)
+            .await
+            .map_err(FromErr::from)?;
+        let mined_count = Nonce::new(mined_count).map_err(NonceManagerError::InvalidNonce)?;
+        let latest = self.latest_block_for_confirmations().await?;
+
+        for (nonce, tx_hash) in self.in_flight() {
+            let receipt = self.fetch_receipt(nonce, tx_hash).await?;
+
+            if let Some(receipt) = receipt {
+                if !self.is_final(receipt.block_number, latest) {
+                    continue;
+                }
+                let label = self.label(nonce);
+                self.on_confirmed(nonce, &receipt).await;
+                self.state.in_flight().forget(nonce);
+                self.emit(NonceEvent::Confirmed {
+                    address: self.address,
+                    nonce,
+                    tx_hash,
+                    block_number: receipt.block_number,
+                    label,
+                });
+                report
+                    .confirmed
+                    .push((nonce, tx_hash, receipt.block_number));
+            } else if nonce < mined_count {
+                self.state.in_flight().forget(nonce);
+                report.replaced.push((nonce, tx_hash));
+            } else if self
+                .inner
+                .get_transaction(tx_hash)
+                .await
+                .map_err(FromErr::from)?
+                .is_none()
+            {
+                report.dropped.push((nonce, tx_hash));
+            }
+        }
+
+        if self.state.is_initialized() {
+            let mut counter = self.lock_nonce().await;
+            if *counter < mined_count {
+                report.counter_behind = Some((*counter, mined_count));
+                *counter = mined_count;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Reconciles the state every `interval`, handing each report to `on_report`, until a
+    /// reconciliation fails
+    pub async fn run_reconciliation(
+        &self,
+        interval: Duration,
+        mut on_report: impl FnMut(ReconciliationReport) + Send,
+    ) -> Result<(), NonceManagerError<M>> {
+        loop {
+            on_report(self.reconcile().await?);
+            self.sleep(interval).await;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, SENDER};
+    use crate::{Nonce, SimulatedChain};
+
+    #[tokio::test]
+    async fn mined_transactions_are_confirmed_and_forgotten() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain);
+        let tx_hash = send(&client).await;
+        chain.mine();
+
+        let report = client.reconcile().await.unwrap();
+        assert_eq!(report.confirmed, [(Nonce::zero(), tx_hash, Some(1.into()))]);
+        assert!(report.is_consistent());
+        assert!(client.in_flight().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nonces_used_by_another_transaction_are_replaced() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain);
+        let tx_hash = send(&client).await;
+        chain.drop_pending(SENDER, 0);
+        chain.send_external(SENDER);
+        chain.mine();
+
+        let report = client.reconcile().await.unwrap();
+        assert_eq!(report.replaced, [(Nonce::zero(), tx_hash)]);
+        assert!(!report.is_consistent());
+        assert!(client.in_flight().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropped_transactions_are_reported_but_kept() {
+        let chain = SimulatedChain::new(3);
+        let client = client(&chain);
+        let tx_hash = send(&client).await;
+        chain.drop_pending(SENDER, 0);
+
+        let report = client.reconcile().await.unwrap();
+        assert_eq!(report.dropped, [(Nonce::zero(), tx_hash)]);
+        assert_eq!(client.in_flight(), [(Nonce::zero(), tx_hash)]);
+    }
+
+    #[tokio::test]
+    async fn a_counter_behind_the_chain_is_moved_up() {
+        let chain = SimulatedChain::new(4);
+        let client = client(&chain);
+        send(&client).await;
+        chain.send_external(SENDER);
+        chain.send_external(SENDER);
+        chain.mine();
+
+        let report = client.reconcile().await.unwrap();
+        let (counter, chain_nonce) = report.counter_behind.unwrap();
+        assert_eq!((counter.as_u64(), chain_nonce.as_u64()), (1, 3));
+        assert_eq!(client.next().await.as_u64(), 3);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::{
+    BumpPolicy, EscalationSchedule, LockedNonceManagerMiddleware, Nonce, NonceEvent,
+    NonceManagerError, SendEndpoint, Sla, TxFees,
+};
+use ethers::middleware::gas_oracle::GasOracle;
+use ethers::providers::{FromErr, Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockNumber, TxHash, U256};
+use std::sync::Arc;
+
+/// The EIP-2718 type of EIP-4844 blob transactions
+const BLOB_TX_TYPE: u64 = 3;
+
+#[derive(Debug, Default)]
+/// Market fees and base fee, fetched at most once for a batch of replacements
+pub(crate) struct Market {
+    base_fee: Option<Option<U256>>,
+    gas_price: Option<U256>,
+    eip1559_fees: Option<(U256, U256)>,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Takes the market fees for replacements from `oracle` instead of the inner
+    /// middleware's gas price and fee history, e.g. an ethers `Etherscan` or
+    /// `ProviderOracle`, or an implementation tailored to the chain
+    pub fn with_gas_oracle(mut self, oracle: impl GasOracle + 'static) -> Self {
+        self.gas_oracle = Some(Arc::new(oracle));
+        self
+    }
+
+    /// Decides the fees of replacements with `policy` instead of [`crate::MarketBump`],
+    /// replacing a bump schedule set before
+    pub fn with_bump_policy(mut self, policy: impl BumpPolicy + 'static) -> Self {
+        self.bump_policy = Arc::new(policy);
+        self.config_mut().bump_schedule = None;
+        self
+    }
+
+    /// Escalates the fees of stuck transactions following `schedule`, e.g. a [`crate::BumpPreset`]:
+    /// the schedule decides the fees of replacements in place of the bump policy, and
+    /// transactions pending for its `every_blocks` breach the [`Sla`] and are sped up. Other
+    /// SLA bounds already set are kept. Needs [`Self::poll_in_flight`] to be called
+    /// regularly, as with any SLA. Set as `bump_schedule` in a [`crate::NonceManagerConfig`],
+    /// the schedule only decides the fees, and the SLA has to be configured along with it.
+    pub fn with_bump_schedule(mut self, schedule: impl Into<EscalationSchedule>) -> Self {
+        let schedule = schedule.into();
+        let sla = match self.config().sla {
+            Some(sla) => Sla {
+                max_blocks: Some(schedule.every_blocks),
+                ..sla
+            },
+            None => Sla::blocks(schedule.every_blocks),
+        };
+        self.config_mut().bump_schedule = Some(schedule);
+        self.with_sla(sla.with_auto_bump())
+    }
+
+    /// Re-prices every unmined managed transaction and rebroadcasts it in nonce order, e.g.
+    /// after the base fee doubled and the whole queue got stuck. The fees are decided by the
+    /// [`BumpPolicy`], by default the current market fees as reported by the gas oracle if
+    /// one is set, but never below the minimum the node accepts for a replacement at the
+    /// current base fee, see [`TxFees::min_replacement`]. Transactions which were mined in
+    /// the meantime stop being tracked, and those whose contents aren't known, e.g. ones
+    /// recorded with [`Self::mark_broadcast`] and no longer known to the node, are skipped,
+    /// like those submitted through the [`crate::PrivateRelay`], which a replacement would
+    /// reveal.
+    /// So are EIP-4844 blob transactions, see [`NonceManagerError::BlobNotReplaceable`],
+    /// each with a [`NonceEvent::BlobNotReplaced`]. Returns the nonces and hashes of the
+    /// replacements.
+    pub async fn resubmit_all(&self) -> Result<Vec<(Nonce, TxHash)>, NonceManagerError<M>> {
+        let mined_count = self
+            .inner
+            .get_transaction_count(self.address, None)
+            .await
+            .map_err(FromErr::from)?;
+        let nonces: Vec<Nonce> = self
+            .state
+            .in_flight()
+            .iter()
+            .map(|(nonce, _)| *nonce)
+            .collect();
+
+        let mut market = Market::default();
+        let mut resubmitted = Vec::new();
+        for nonce in nonces {
+            if nonce.as_u256() < mined_count {
+                self.state.in_flight().forget(nonce);
+                continue;
+            }
+            let (original, attempt) = match self.replaceable(nonce).await {
+                Ok(Some(replaceable)) => replaceable,
+                Ok(None) => continue,
+                Err(NonceManagerError::BlobNotReplaceable(_)) => {
+                    if let Some(tracked) = self.state.in_flight().get(nonce) {
+                        self.emit(NonceEvent::BlobNotReplaced {
+                            address: self.address,
+                            nonce,
+                            tx_hash: tracked.tx_hash,
+                        });
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let pending = self
+                .replace(nonce, &original, original.clone(), attempt, &mut market)
+                .await?;
+            resubmitted.push((nonce, pending.tx_hash()));
+        }
+
+        Ok(resubmitted)
+    }
+
+    /// Re-prices and rebroadcasts the in-flight transaction at `nonce`, like
+    /// [`Self::resubmit_all`] does for every one of them. Fails with
+    /// [`NonceManagerError::NotReplaceable`] if there is no transaction at `nonce` which can
+    /// be replaced, and with [`NonceManagerError::BlobNotReplaceable`] if it carries blobs.
+    pub async fn speed_up(
+        &self,
+        nonce: Nonce,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let (original, attempt) = self
+            .replaceable(nonce)
+            .await?
+            .ok_or(NonceManagerError::NotReplaceable(nonce))?;
+        self.replace(
+            nonce,
+            &original,
+            original.clone(),
+            attempt,
+            &mut Market::default(),
+        )
+        .await
+    }
+
+    /// The in-flight transaction at `nonce` as it was sent, carrying the fees it paid, and
+    /// how many times it was replaced. `None` if it can't be replaced because it isn't in
+    /// flight, was submitted privately or its contents aren't known,
+    /// [`NonceManagerError::BlobNotReplaceable`] if it carries blobs.
+    pub(crate) async fn replaceable(
+        &self,
+        nonce: Nonce,
+    ) -> Result<Option<(TypedTransaction, u32)>, NonceManagerError<M>> {
+        let (tx, tx_hash, attempt) = match self.state.in_flight().get(nonce) {
+            Some(tracked) if tracked.endpoint != SendEndpoint::Private => {
+                (tracked.tx.clone(), tracked.tx_hash, tracked.attempt)
+            }
+            _ => return Ok(None),
+        };
+        let sent = self
+            .inner
+            .get_transaction(tx_hash)
+            .await
+            .map_err(FromErr::from)?;
+        // blob transactions can only be replaced by blob transactions, which ethers can't
+        // build, so any replacement would be rejected
+        if sent
+            .as_ref()
+            .is_some_and(|sent| sent.transaction_type == Some(BLOB_TX_TYPE.into()))
+        {
+            return Err(NonceManagerError::BlobNotReplaceable(nonce));
+        }
+
+        let mut tx = match (tx, &sent) {
+            (Some(tx), _) => tx,
+            (None, Some(sent)) => sent.into(),
+            (None, None) => return Ok(None),
+        };
+        tx.set_nonce(nonce);
+        // the node's copy carries the fees which were actually paid
+        let paid = sent.as_ref().map(TypedTransaction::from);
+        if let Some(fees) = paid.as_ref().and_then(TxFees::of) {
+            fees.apply(&mut tx);
+        }
+        Ok(Some((tx, attempt)))
+    }
+
+    /// Prices `replacement` with the [`BumpPolicy`], raised to the minimum the node accepts
+    /// in place of `original`, and broadcasts it at `nonce`. The limits are charged what the
+    /// replacement costs on top of `original`.
+    pub(crate) async fn replace(
+        &self,
+        nonce: Nonce,
+        original: &TypedTransaction,
+        mut replacement: TypedTransaction,
+        attempt: u32,
+        market: &mut Market,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let base_fee = self.base_fee(market).await?;
+        let market_fees = self.market_fees(market, &replacement).await?;
+        let attempt = attempt + 1;
+
+        let policy: Arc<dyn BumpPolicy> = match self.config().bump_schedule {
+            Some(schedule) => Arc::new(schedule),
+            None => self.bump_policy.clone(),
+        };
+        let mut fees = policy.replacement_fees(original, attempt, base_fee, market_fees);
+        if let Some(paid) = TxFees::of(original) {
+            let price_bump_percent = self.price_bump_percent(original);
+            fees = fees.at_least(paid.min_replacement(price_bump_percent, base_fee));
+        }
+        if let Some(cap) = policy.max_fee_per_gas() {
+            if fees.max_fee() > cap {
+                return Err(NonceManagerError::FeeCapReached { nonce, cap });
+            }
+        }
+        fees.apply(&mut replacement);
+        replacement.set_nonce(nonce);
+        let charges = self
+            .check_replacement_limits(&mut replacement, original)
+            .await?;
+        let signed = self.write_ahead(&mut replacement, None).await?;
+
+        // the original still holds the nonce if its replacement can't be paid for
+        let pending = match self.broadcast(&replacement, None, signed).await {
+            Ok(pending) => pending,
+            Err(err) => return Err(self.rejected(&replacement, err, true).await),
+        };
+        self.charge(charges);
+        // the replacement still belongs to whatever sent the original
+        let label = self.label(nonce);
+        self.record_broadcast(nonce, pending.tx_hash(), Some(&replacement), label)
+            .await;
+        self.state.in_flight().set_attempt(nonce, attempt);
+        Ok(pending)
+    }
+
+    /// The percentage a replacement of `original` has to raise its fees by, as set for its
+    /// chain
+    fn price_bump_percent(&self, original: &TypedTransaction) -> u64 {
+        let config = self.config();
+        let chain_id = original
+            .chain_id()
+            .map(|chain_id| chain_id.as_u64())
+            .or(config.expected_chain_id);
+        config
+            .chain_profiles
+            .iter()
+            .find(|profile| Some(profile.chain_id) == chain_id)
+            .map_or(config.price_bump_percent, |profile| {
+                profile.price_bump_percent
+            })
+    }
+
+    /// The base fee of the latest block, `None` on chains without one
+    async fn base_fee(&self, market: &mut Market) -> Result<Option<U256>, NonceManagerError<M>> {
+        if let Some(base_fee) = market.base_fee {
+            return Ok(base_fee);
+        }
+        let base_fee = self
+            .inner
+            .get_block(BlockNumber::Latest)
+            .await
+            .map_err(FromErr::from)?
+            .and_then(|block| block.base_fee_per_gas);
+        Ok(*market.base_fee.insert(base_fee))
+    }
+
+    /// The current market fees for the type of `tx`, from the gas oracle if one is set
+    async fn market_fees(
+        &self,
+        market: &mut Market,
+        tx: &TypedTransaction,
+    ) -> Result<TxFees, NonceManagerError<M>> {
+        if let TypedTransaction::Eip1559(_) = tx {
+            let (max_fee_per_gas, max_priority_fee_per_gas) = match market.eip1559_fees {
+                Some(fees) => fees,
+                None => *market
+                    .eip1559_fees
+                    .insert(self.market_eip1559_fees().await?),
+            };
+            return Ok(TxFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            });
+        }
+        let gas_price = match market.gas_price {
+            Some(gas_price) => gas_price,
+            None => *market.gas_price.insert(self.market_gas_price().await?),
+        };
+        Ok(TxFees::Legacy(gas_price))
+    }
+
+    /// The current legacy gas price, from the gas oracle if one is set
+    async fn market_gas_price(&self) -> Result<U256, NonceManagerError<M>> {
+        match &self.gas_oracle {
+            Some(oracle) => oracle
+                .fetch()
+                .await
+                .map_err(NonceManagerError::GasOracleError),
+            None => self.inner.get_gas_price().await.map_err(FromErr::from),
+        }
+    }
+
+    /// The current EIP-1559 max fee and priority fee, from the gas oracle if one is set
+    async fn market_eip1559_fees(&self) -> Result<(U256, U256), NonceManagerError<M>> {
+        match &self.gas_oracle {
+            Some(oracle) => oracle
+                .estimate_eip1559_fees()
+                .await
+                .map_err(NonceManagerError::GasOracleError),
+            None => self
+                .inner
+                .estimate_eip1559_fees(None)
+                .await
+                .map_err(FromErr::from),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, SENDER};
+    use crate::{
+        BumpPreset, ChainProfile, EscalationSchedule, Nonce, NonceManagerError, NonceStatus,
+        SimulatedChain, Sla,
+    };
+    use ethers::providers::Middleware;
+    use ethers::types::U256;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn stuck_transactions_are_repriced_past_the_replacement_minimum() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain);
+        let original = send(&client).await;
+
+        let resubmitted = client.resubmit_all().await.unwrap();
+        let [(nonce, tx_hash)] = resubmitted[..] else {
+            panic!("expected one replacement, got {resubmitted:?}");
+        };
+        assert_eq!(nonce, Nonce::zero());
+        assert_ne!(tx_hash, original);
+        let sent = client.get_transaction(tx_hash).await.unwrap().unwrap();
+        // the market price the original paid, raised by the 10% nodes require
+        assert_eq!(sent.gas_price, Some(U256::exp10(9) * 11 / 10));
+        assert_eq!(client.in_flight(), [(nonce, tx_hash)]);
+        assert_eq!(
+            client.status(nonce).await.unwrap(),
+            NonceStatus::Broadcast {
+                tx_hash,
+                attempts: 1
+            }
+        );
+
+        chain.mine();
+        assert_eq!(chain.mined_nonces(SENDER), [0]);
+    }
+
+    #[tokio::test]
+    async fn replacements_raise_fees_as_the_profile_of_their_chain_requires() {
+        let chain = SimulatedChain::new(6);
+        let profile = |chain_id, price_bump_percent| ChainProfile {
+            chain_id,
+            price_bump_percent,
+        };
+        let client = client(&chain)
+            .with_expected_chain_id(31337)
+            .with_chain_profile(profile(10, 50))
+            .with_chain_profile(profile(31337, 20))
+            // replaces the earlier profile of the same chain
+            .with_chain_profile(profile(31337, 25));
+        assert_eq!(client.config().chain_profiles.len(), 2);
+        send(&client).await;
+
+        let replacement = client.speed_up(Nonce::zero()).await.unwrap();
+        let sent = client
+            .get_transaction(replacement.tx_hash())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sent.gas_price, Some(U256::exp10(9) * 125 / 100));
+    }
+
+    #[tokio::test]
+    async fn mined_transactions_are_not_resubmitted() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain);
+        send(&client).await;
+        chain.mine();
+
+        assert!(client.resubmit_all().await.unwrap().is_empty());
+        assert!(client.in_flight().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_transactions_in_flight_can_be_sped_up() {
+        let chain = SimulatedChain::new(3);
+        let client = client(&chain);
+        let result = client.speed_up(Nonce::zero()).await;
+        assert!(matches!(result, Err(NonceManagerError::NotReplaceable(_))));
+    }
+
+    #[tokio::test]
+    async fn replacements_above_the_fee_cap_are_refused() {
+        let chain = SimulatedChain::new(4);
+        let client = client(&chain)
+            .with_bump_policy(EscalationSchedule::new(1250, 1).capped_at(U256::exp10(9)));
+        let tx_hash = send(&client).await;
+
+        let result = client.speed_up(Nonce::zero()).await;
+        assert!(matches!(
+            result,
+            Err(NonceManagerError::FeeCapReached { .. })
+        ));
+        assert_eq!(client.in_flight(), [(Nonce::zero(), tx_hash)]);
+    }
+
+    #[tokio::test]
+    async fn bump_schedules_set_the_block_bound_of_the_sla() {
+        let chain = SimulatedChain::new(5);
+        let client = client(&chain)
+            .with_sla(Sla::within(Duration::from_secs(60)))
+            .with_bump_schedule(BumpPreset::Aggressive);
+        let config = client.config();
+        let sla = config.sla.unwrap();
+        assert_eq!(sla.max_blocks, Some(1));
+        assert_eq!(sla.max_wait(), Some(Duration::from_secs(60)));
+        assert!(sla.auto_bump);
+        assert_eq!(config.bump_schedule, Some(EscalationSchedule::new(2500, 1)));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::sync::{AtomicU64, Mutex, Ordering};
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::BlockNumber;
+use std::future::Future;
+
+#[derive(Debug, Default)]
+/// Coalesces concurrent chain nonce lookups: callers arriving while a lookup is running wait
+/// for it and share its result instead of issuing their own request
+pub(crate) struct SingleFlight {
+    generation: AtomicU64,
+    last: Mutex<Option<Nonce>>,
+}
+
+impl SingleFlight {
+    pub(crate) async fn run<F, Fut, E>(&self, fetch: F) -> Result<Nonce, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Nonce, E>>,
+    {
+        let observed = self.generation.load(Ordering::SeqCst);
+        let mut last = self.last.lock().await;
+        // a lookup completed while we were waiting for the lock, which is at least as fresh
+        // as the one we were about to make
+        if self.generation.load(Ordering::SeqCst) != observed {
+            if let Some(nonce) = *last {
+                return Ok(nonce);
+            }
+        }
+
+        // the generation is only bumped on success, so if this lookup fails or is cancelled
+        // the next waiter performs its own
+        let nonce = fetch().await?;
+        *last = Some(nonce);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(nonce)
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Brings the local counter up to date with the pending transaction count on chain and
+    /// returns the next nonce. Concurrent calls share a single request, and the counter never
+    /// moves backwards unless the [`BackwardNoncePolicy`](crate::BackwardNoncePolicy) says so.
+    pub async fn resync(&self) -> Result<Nonce, NonceManagerError<M>> {
+        let chain_nonce = self.pending_chain_nonce().await?;
+
+        let mut write_guard = self.lock_nonce().await;
+        if chain_nonce > *write_guard || !self.state.is_initialized() {
+            let previous = self.state.is_initialized().then_some(*write_guard);
+            *write_guard = chain_nonce;
+            self.state.set_initialized();
+            self.emit_resynced(previous, chain_nonce);
+        } else if self.follow_backwards(*write_guard, chain_nonce).await? {
+            *write_guard = chain_nonce;
+        }
+        self.state.clear_needs_resync();
+
+        Ok(*write_guard)
+    }
+
+    /// Tells the manager that the inner provider reconnected, e.g. a WebSocket client which
+    /// re-established its connection. Transactions sent during the outage may have been
+    /// dropped without an error, so the counter is reset to the pending transaction count
+    /// on chain, moving it backwards if that is needed to close the gap, and the lost
+    /// transactions stop being tracked as in flight. Reserved nonces are never handed out
+    /// twice, so the counter is kept above the highest reservation. Returns the next nonce.
+    pub async fn notify_reconnected(&self) -> Result<Nonce, NonceManagerError<M>> {
+        // if the lookup fails, the next assignment at least catches up with the chain
+        self.state.mark_needs_resync();
+
+        let mut write_guard = self.lock_nonce().await;
+        let chain_nonce = self.pending_chain_nonce().await?;
+        let reserved = self
+            .state
+            .schedule()
+            .lock()
+            .await
+            .iter()
+            .last()
+            .map(|(n, _)| *n);
+        let next = match reserved {
+            Some(reserved) => chain_nonce.max(Self::increment(reserved)?),
+            None => chain_nonce,
+        };
+
+        let previous = self.state.is_initialized().then_some(*write_guard);
+        *write_guard = next;
+        self.state.set_initialized();
+        self.emit_resynced(previous, next);
+        self.state.clear_needs_resync();
+        self.state.in_flight().forget_from(next);
+
+        Ok(next)
+    }
+
+    fn emit_resynced(&self, previous: Option<Nonce>, nonce: Nonce) {
+        if previous != Some(nonce) {
+            self.emit(NonceEvent::Resynced {
+                address: self.address,
+                previous,
+                nonce,
+            });
+        }
+    }
+
+    /// The pending transaction count of the managed address, fetched through the single-flight
+    /// slot shared by every resync
+    pub(crate) async fn pending_chain_nonce(&self) -> Result<Nonce, NonceManagerError<M>> {
+        self.state
+            .resync()
+            .run(|| self.chain_nonce(Some(BlockNumber::Pending.into())))
+            .await
+    }
+}
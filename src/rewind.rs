@@ -0,0 +1,74 @@
+use crate::{
+    BackwardNoncePolicy, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError,
+};
+use ethers::providers::Middleware;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Sets what happens when the chain nonce is found below the counter, see
+    /// [`BackwardNoncePolicy`]
+    pub fn with_backward_nonce_policy(mut self, policy: BackwardNoncePolicy) -> Self {
+        self.config_mut().backward_nonce = policy;
+        self
+    }
+
+    /// Follows the chain nonce backwards when a dev node was rewound, e.g. by `evm_revert`
+    /// or `anvil_reset`, instead of failing every send after it. Shorthand for
+    /// [`BackwardNoncePolicy::Adopt`].
+    pub fn with_dev_node_rewinds(self) -> Self {
+        self.with_backward_nonce_policy(BackwardNoncePolicy::Adopt)
+    }
+
+    /// Applies the [`BackwardNoncePolicy`] to the counter being at `nonce` while the chain is
+    /// at `chain_nonce`. Returns whether the counter is to move back to the chain nonce, in
+    /// which case the in-flight transactions it dropped are forgotten and the reservations
+    /// and holds above it released, as their nonces are handed out again. The caller holds
+    /// the nonce lock and moves the counter.
+    pub(crate) async fn follow_backwards(
+        &self,
+        nonce: Nonce,
+        chain_nonce: Nonce,
+    ) -> Result<bool, NonceManagerError<M>> {
+        if chain_nonce >= nonce {
+            return Ok(false);
+        }
+        let behind = || NonceManagerError::ChainNonceBehind { nonce, chain_nonce };
+        match self.config().backward_nonce {
+            BackwardNoncePolicy::Keep => return Ok(false),
+            BackwardNoncePolicy::IgnoreWithin(threshold) => {
+                return match nonce.distance_from(chain_nonce) {
+                    Some(distance) if distance <= threshold.into() => Ok(false),
+                    _ => Err(behind()),
+                };
+            }
+            BackwardNoncePolicy::Error => return Err(behind()),
+            BackwardNoncePolicy::Adopt => {}
+        }
+
+        let dropped: Vec<_> = self
+            .state
+            .in_flight()
+            .iter()
+            .filter(|(in_flight, _)| **in_flight >= chain_nonce)
+            .map(|(_, tx)| tx.tx_hash)
+            .collect();
+        for tx_hash in dropped {
+            match self.inner.get_transaction(tx_hash).await {
+                Ok(None) => {}
+                // the node still knows it, so it's lagging rather than rewound
+                Ok(Some(_)) | Err(_) => return Ok(false),
+            }
+        }
+
+        self.state.in_flight().forget_from(chain_nonce);
+        self.state.schedule().lock().await.release_from(chain_nonce);
+        self.emit(NonceEvent::Resynced {
+            address: self.address,
+            previous: Some(nonce),
+            nonce: chain_nonce,
+        });
+        Ok(true)
+    }
+}
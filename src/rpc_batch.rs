@@ -0,0 +1,360 @@
+use crate::{scheduler::Schedule, LockedNonceManagerMiddleware, Nonce, SendEndpoint};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcError, Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, Bytes, TxHash, H256};
+use ethers::utils::{keccak256, rlp::Rlp};
+use std::{error::Error, fmt::Debug, sync::Arc};
+
+/// A transport able to submit several signed transactions in a single JSON-RPC batch request
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait BatchTransport: Send + Sync + Debug {
+    /// Submits every transaction with `eth_sendRawTransaction` in one batch and returns the
+    /// result of each, in the order they were given. Fails as a whole if the batch couldn't
+    /// be submitted, e.g. because the node doesn't accept batches.
+    async fn send_raw_batch(
+        &self,
+        raw_txs: &[Bytes],
+    ) -> Result<Vec<Result<TxHash, JsonRpcError>>, Box<dyn Error + Send + Sync>>;
+}
+
+/// The outcome of a transaction submitted in a batch, at its nonce
+pub(crate) type BatchResult<'a, P> = (Nonce, Result<PendingTransaction<'a, P>, JsonRpcError>);
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Submits the scheduled transactions through `transport` as a single JSON-RPC batch
+    /// whenever [`Self::broadcast_scheduled`] flushes more than one at once, e.g. after an
+    /// RPC outage, so that they reach the node in nonce order in one round trip. Flushes
+    /// spread over several blocks by [`Self::with_block_cap`] are still sent one at a time.
+    pub fn with_batch_transport(mut self, transport: impl BatchTransport + 'static) -> Self {
+        self.batch_transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Broadcasts the leading run of signed transactions of `schedule` as a single batch, if
+    /// there is a batch transport and the run has more than one transaction, and returns the
+    /// result for each of them, in nonce order. The run stops below the first transaction
+    /// depending on another one in it, so that it isn't sent if that one is rejected.
+    /// Transactions the node accepted are taken off
+    /// the schedule, while those it rejected stay scheduled. `None` if no batch went out,
+    /// including when it couldn't be submitted, for the flush to fall back to sending the
+    /// transactions one at a time.
+    pub(crate) async fn broadcast_batch(
+        &self,
+        schedule: &mut Schedule,
+    ) -> Option<Vec<BatchResult<'_, M::Provider>>> {
+        let transport = match &self.batch_transport {
+            Some(transport) if self.config().max_txs_per_block.is_none() => transport,
+            _ => return None,
+        };
+        let (nonces, raw_txs): (Vec<_>, Vec<_>) = schedule.batchable().into_iter().unzip();
+        if raw_txs.len() < 2 {
+            return None;
+        }
+        for (nonce, raw_tx) in nonces.iter().zip(&raw_txs) {
+            self.log_broadcasting(*nonce, H256::from(keccak256(raw_tx)))
+                .ok()?;
+        }
+        let send = transport.send_raw_batch(&raw_txs);
+        let results = self.timed_send(SendEndpoint::Batch, send).await.ok()?;
+
+        let mut outcome = Vec::with_capacity(results.len());
+        for ((nonce, raw_tx), result) in nonces.into_iter().zip(&raw_txs).zip(results) {
+            let tx_hash = match result {
+                Ok(tx_hash) => tx_hash,
+                Err(err) => {
+                    outcome.push((nonce, Err(err)));
+                    continue;
+                }
+            };
+            let decoded = TypedTransaction::decode_signed(&Rlp::new(raw_tx))
+                .ok()
+                .map(|(tx, _)| tx);
+            schedule.release(nonce);
+            self.record_broadcast(nonce, tx_hash, decoded.as_ref(), None)
+                .await;
+            outcome.push((nonce, Ok(PendingTransaction::new(tx_hash, self.provider()))));
+        }
+        Some(outcome)
+    }
+}
+
+#[cfg(feature = "http-batch")]
+pub use self::http::HttpBatchTransport;
+
+#[cfg(feature = "http-batch")]
+mod http {
+    use super::BatchTransport;
+    use async_trait::async_trait;
+    use ethers::providers::JsonRpcError;
+    use ethers::types::{Bytes, TxHash};
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::error::Error;
+
+    #[derive(Debug, Clone)]
+    /// [`BatchTransport`] POSTing JSON-RPC batches to an HTTP endpoint
+    pub struct HttpBatchTransport {
+        client: reqwest::Client,
+        url: String,
+    }
+
+    impl HttpBatchTransport {
+        /// Batches requests to the node at `url`
+        pub fn new(url: impl Into<String>) -> Self {
+            Self {
+                client: reqwest::Client::new(),
+                url: url.into(),
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Response {
+        id: usize,
+        #[serde(default)]
+        result: Option<TxHash>,
+        #[serde(default)]
+        error: Option<JsonRpcError>,
+    }
+
+    #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+    #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+    impl BatchTransport for HttpBatchTransport {
+        async fn send_raw_batch(
+            &self,
+            raw_txs: &[Bytes],
+        ) -> Result<Vec<Result<TxHash, JsonRpcError>>, Box<dyn Error + Send + Sync>> {
+            let requests: Vec<_> = raw_txs
+                .iter()
+                .enumerate()
+                .map(|(id, raw_tx)| {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "method": "eth_sendRawTransaction",
+                        "params": [raw_tx],
+                    })
+                })
+                .collect();
+            // a node without batch support answers with a single error object, which fails
+            // to parse as a list
+            let responses: Vec<Response> = self
+                .client
+                .post(&self.url)
+                .json(&requests)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            // responses to a batch may come back in any order
+            let mut results: Vec<_> = raw_txs
+                .iter()
+                .map(|_| {
+                    Err(JsonRpcError {
+                        code: -32603,
+                        message: "no response in batch".to_owned(),
+                        data: None,
+                    })
+                })
+                .collect();
+            for response in responses {
+                let result = match (response.result, response.error) {
+                    (Some(tx_hash), _) => Ok(tx_hash),
+                    (None, Some(err)) => Err(err),
+                    (None, None) => continue,
+                };
+                if let Some(slot) = results.get_mut(response.id) {
+                    *slot = result;
+                }
+            }
+            Ok(results)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use super::BatchTransport;
+    use crate::{LockedNonceManagerMiddleware, Nonce, SimulatedChain};
+    use async_trait::async_trait;
+    use ethers::providers::{JsonRpcError, Middleware, Provider};
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::{
+        transaction::eip2718::TypedTransaction, Address, Bytes, TransactionRequest, TxHash,
+    };
+    use std::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    /// Submits batches to the simulator, rejecting the transaction at `rejected` without
+    /// sending it, or the whole batch if `unsupported`
+    struct Batches {
+        chain: SimulatedChain,
+        rejected: Option<usize>,
+        unsupported: bool,
+        submitted: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl BatchTransport for Batches {
+        async fn send_raw_batch(
+            &self,
+            raw_txs: &[Bytes],
+        ) -> Result<Vec<Result<TxHash, JsonRpcError>>, Box<dyn Error + Send + Sync>> {
+            if self.unsupported {
+                return Err("batches are not supported".into());
+            }
+            self.submitted.fetch_add(1, Ordering::SeqCst);
+            let mut results = Vec::new();
+            for (index, raw_tx) in raw_txs.iter().enumerate() {
+                let rejection = JsonRpcError {
+                    code: -32000,
+                    message: "rejected".to_owned(),
+                    data: None,
+                };
+                if self.rejected == Some(index) {
+                    results.push(Err(rejection));
+                    continue;
+                }
+                let sent = self
+                    .chain
+                    .provider()
+                    .send_raw_transaction(raw_tx.clone())
+                    .await;
+                results.push(sent.map(|pending| pending.tx_hash()).map_err(|_| rejection));
+            }
+            Ok(results)
+        }
+    }
+
+    type Client = LockedNonceManagerMiddleware<Provider<SimulatedChain>>;
+
+    /// A manager for a local key with transfers signed and scheduled at nonces 0 to 2, and
+    /// the key's address
+    async fn scheduled(chain: &SimulatedChain, batches: Batches) -> (Client, Address) {
+        let wallet: LocalWallet = "22".repeat(32).parse().unwrap();
+        let client = LockedNonceManagerMiddleware::new(chain.provider(), wallet.address())
+            .with_batch_transport(batches);
+        for _ in 0..3 {
+            let nonce = client.reserve_nonce(None).await.unwrap();
+            let tx: TypedTransaction = TransactionRequest::pay(wallet.address(), 1u64)
+                .from(wallet.address())
+                .nonce(nonce.as_u64())
+                .gas(21_000u64)
+                .gas_price(1u64)
+                .chain_id(31337u64)
+                .into();
+            let signature = wallet.sign_transaction(&tx).await.unwrap();
+            client
+                .schedule_signed(nonce, tx.rlp_signed(&signature))
+                .await
+                .unwrap();
+        }
+        (client, wallet.address())
+    }
+
+    fn batches(chain: &SimulatedChain) -> (Batches, Arc<AtomicUsize>) {
+        let submitted = Arc::new(AtomicUsize::new(0));
+        let batches = Batches {
+            chain: chain.clone(),
+            rejected: None,
+            unsupported: false,
+            submitted: submitted.clone(),
+        };
+        (batches, submitted)
+    }
+
+    #[tokio::test]
+    async fn flushes_go_out_as_one_batch() {
+        let chain = SimulatedChain::new(1);
+        let (batches, submitted) = batches(&chain);
+        let (client, from) = scheduled(&chain, batches).await;
+
+        assert_eq!(client.broadcast_scheduled().await.unwrap().len(), 3);
+        assert_eq!(submitted.load(Ordering::SeqCst), 1);
+        assert_eq!(chain.pending_nonces(from), [0, 1, 2]);
+        assert!(client.reservations().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_transactions_are_sent_again_on_their_own() {
+        let chain = SimulatedChain::new(2);
+        let (batches, _) = batches(&chain);
+        let batches = Batches {
+            rejected: Some(1),
+            ..batches
+        };
+        let (client, from) = scheduled(&chain, batches).await;
+
+        let mut schedule = client.state.schedule().lock().await;
+        let results = client.broadcast_batch(&mut schedule).await.unwrap();
+        let outcome: Vec<_> = results
+            .iter()
+            .map(|(nonce, result)| (nonce.as_u64(), result.is_ok()))
+            .collect();
+        assert_eq!(outcome, [(0, true), (1, false), (2, true)]);
+        assert_eq!(schedule.ready().len(), 1);
+        drop(schedule);
+
+        // the node takes it on its own
+        client.broadcast_scheduled().await.unwrap();
+        assert_eq!(chain.pending_nonces(from), [0, 1, 2]);
+        assert!(client.reservations().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dependents_wait_for_their_dependency_to_go_out() {
+        let chain = SimulatedChain::new(4);
+        let (batches, _) = batches(&chain);
+        let batches = Batches {
+            rejected: Some(1),
+            ..batches
+        };
+        let (client, from) = scheduled(&chain, batches).await;
+        let dependency = Nonce::zero().checked_add(1).unwrap();
+        let dependent = Nonce::zero().checked_add(2).unwrap();
+        client
+            .state
+            .schedule()
+            .lock()
+            .await
+            .depend(dependent, dependency);
+
+        let mut schedule = client.state.schedule().lock().await;
+        let results = client.broadcast_batch(&mut schedule).await.unwrap();
+        let outcome: Vec<_> = results
+            .iter()
+            .map(|(nonce, result)| (nonce.as_u64(), result.is_ok()))
+            .collect();
+        // the dependent isn't sent along, as its dependency may be rejected
+        assert_eq!(outcome, [(0, true), (1, false)]);
+        assert_eq!(schedule.scheduled(dependent), Some(true));
+        drop(schedule);
+        assert_eq!(chain.pending_nonces(from), [0]);
+
+        client.broadcast_scheduled().await.unwrap();
+        assert_eq!(chain.pending_nonces(from), [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn failed_batches_fall_back_to_single_sends() {
+        let chain = SimulatedChain::new(3);
+        let (batches, submitted) = batches(&chain);
+        let batches = Batches {
+            unsupported: true,
+            ..batches
+        };
+        let (client, from) = scheduled(&chain, batches).await;
+
+        assert_eq!(client.broadcast_scheduled().await.unwrap().len(), 3);
+        assert_eq!(submitted.load(Ordering::SeqCst), 0);
+        assert_eq!(chain.pending_nonces(from), [0, 1, 2]);
+    }
+}
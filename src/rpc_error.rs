@@ -0,0 +1,134 @@
+use ethers::providers::{MiddlewareError, ProviderError, RpcError};
+use std::fmt::Display;
+
+/// JSON-RPC error code of a request over the provider's rate or resource limits
+const LIMIT_EXCEEDED: i64 = -32005;
+
+/// What nodes reject transactions with when their nonce doesn't fit
+const NONCE_ERRORS: [&str; 4] = [
+    "nonce too low",
+    "nonce too high",
+    "already known",
+    "replacement transaction underpriced",
+];
+
+/// The JSON-RPC error code the node answered `err` with, `None` if the request failed
+/// before the node answered it
+pub(crate) fn error_code(err: &impl Display) -> Option<i64> {
+    let message = err.to_string();
+    let code = message.split("code: ").nth(1)?;
+    let end = code
+        .find(|c: char| c != '-' && !c.is_ascii_digit())
+        .unwrap_or(code.len());
+    code[..end].parse().ok()
+}
+
+/// Whether the request failing with `err` didn't reach the node or get its answer, e.g. a
+/// refused connection or a timeout. Rejections by the node, answers which couldn't be decoded
+/// and failures of the middlewares on the way, e.g. a signer's, all tell that it is up.
+pub(crate) fn is_unreachable(err: &impl MiddlewareError) -> bool {
+    if err.as_error_response().is_some() {
+        return false;
+    }
+    match err.as_provider_error() {
+        Some(ProviderError::JsonRpcClientError(err)) => err.as_serde_error().is_none(),
+        _ => false,
+    }
+}
+
+/// Whether `err` tells that the provider is over capacity, i.e. rate limited or out of
+/// resources
+pub(crate) fn is_capacity_error(err: &impl Display) -> bool {
+    if error_code(err) == Some(LIMIT_EXCEEDED) {
+        return true;
+    }
+    let message = err.to_string().to_lowercase();
+    message.contains("429")
+        || message.contains("rate limit")
+        || message.contains("too many requests")
+}
+
+/// Whether the node rejected a transaction over its nonce, i.e. too low, too high or already
+/// taken by a transaction in its pool, going by the messages of geth and the clients which
+/// copy them
+pub(crate) fn is_nonce_error(err: &impl Display) -> bool {
+    let message = err.to_string().to_lowercase();
+    NONCE_ERRORS.iter().any(|known| message.contains(known))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{JsonRpcError, MockError};
+
+    fn rpc_error(code: i64, message: &str) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.to_owned(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn rejections_carry_their_code() {
+        assert_eq!(
+            error_code(&rpc_error(-32000, "nonce too low")),
+            Some(-32000)
+        );
+        assert!(!is_capacity_error(&rpc_error(-32000, "nonce too low")));
+    }
+
+    #[test]
+    fn nonce_errors() {
+        assert!(is_nonce_error(&rpc_error(-32000, "nonce too low")));
+        assert!(is_nonce_error(&rpc_error(-32000, "Nonce too high")));
+        assert!(is_nonce_error(&rpc_error(-32000, "already known")));
+        assert!(is_nonce_error(&rpc_error(
+            -32000,
+            "replacement transaction underpriced"
+        )));
+        assert!(!is_nonce_error(&rpc_error(-32000, "insufficient funds")));
+        assert!(!is_nonce_error(&rpc_error(
+            -32000,
+            "invalid nonce signature"
+        )));
+        assert!(!is_nonce_error(&rpc_error(
+            3,
+            "execution reverted: InvalidNonce"
+        )));
+    }
+
+    #[test]
+    fn capacity_errors() {
+        assert!(is_capacity_error(&rpc_error(
+            LIMIT_EXCEEDED,
+            "limit exceeded"
+        )));
+        assert!(is_capacity_error(&rpc_error(-32000, "Too Many Requests")));
+        assert!(is_capacity_error(&"HTTP error 429"));
+    }
+
+    #[test]
+    fn only_failed_requests_are_unreachable() {
+        let client_error = |err| ProviderError::JsonRpcClientError(Box::new(err));
+        assert!(is_unreachable(&client_error(MockError::EmptyResponses)));
+
+        let rejected = MockError::JsonRpcError(rpc_error(-32000, "nonce too low"));
+        assert!(!is_unreachable(&client_error(rejected)));
+        let undecodable = serde_json::from_str::<u64>("\"0x1\"").unwrap_err();
+        assert!(!is_unreachable(&client_error(MockError::SerdeJson(
+            undecodable
+        ))));
+        assert!(!is_unreachable(&ProviderError::CustomError(
+            "signer unavailable".to_owned()
+        )));
+    }
+
+    #[test]
+    fn transport_errors_have_no_code() {
+        assert_eq!(
+            error_code(&"error sending request: connection refused"),
+            None
+        );
+    }
+}
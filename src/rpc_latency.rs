@@ -0,0 +1,162 @@
+use crate::{rpc_error, Histogram, LockedNonceManagerMiddleware};
+use ethers::providers::Middleware;
+use serde::Serialize;
+use std::{collections::BTreeMap, future::Future, sync::Mutex, time::Duration};
+
+/// Upper bounds of the histogram buckets, from a node next door up to a request about to
+/// time out
+const BUCKET_BOUNDS: [Duration; 11] = [
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2_500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(tag = "kind", content = "index", rename_all = "snake_case")]
+/// Where the manager submitted transactions to
+pub enum SendEndpoint {
+    /// The inner middleware
+    Inner,
+    /// A broadcast endpoint, by its position among the ones added with
+    /// [`LockedNonceManagerMiddleware::with_broadcast_endpoint`]
+    Broadcast(usize),
+    /// A fallback endpoint, by its position among the ones added with
+    /// [`LockedNonceManagerMiddleware::with_fallback_endpoint`]
+    Fallback(usize),
+    /// The [`crate::BatchTransport`], once per batch
+    Batch,
+    /// The [`crate::PrivateRelay`]
+    Private,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// How the requests submitting transactions to an endpoint went
+pub struct SendLatency {
+    /// How long the requests took, failed ones included
+    pub latency: Histogram,
+    /// Number of the recorded requests which failed
+    pub failures: u64,
+}
+
+impl Default for SendLatency {
+    fn default() -> Self {
+        Self {
+            latency: Histogram::new(&BUCKET_BOUNDS),
+            failures: 0,
+        }
+    }
+}
+
+impl SendLatency {
+    fn record(&mut self, latency: Duration, failed: bool) {
+        self.latency.record(latency);
+        self.failures += u64::from(failed);
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SendLatencyRecorder {
+    histograms: Mutex<BTreeMap<SendEndpoint, SendLatency>>,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// How long the `eth_sendTransaction` and `eth_sendRawTransaction` requests the manager
+    /// made took, per endpoint they went to, failed ones included. Sends hold the nonce lock
+    /// until their request returns, so a slow endpoint directly caps throughput, see
+    /// [`Self::lock_contention`] and [`Self::with_adaptive_concurrency`]. Endpoints which
+    /// were never sent to are left out.
+    pub fn send_latency(&self) -> BTreeMap<SendEndpoint, SendLatency> {
+        self.send_latency
+            .histograms
+            .lock()
+            .expect("send latency lock poisoned")
+            .clone()
+    }
+
+    /// Runs the request `send` submitting transactions to `endpoint`, recording how long it
+    /// took
+    pub(crate) async fn timed_send<T, E>(
+        &self,
+        endpoint: SendEndpoint,
+        send: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        self.timed(endpoint, send).await.0
+    }
+
+    /// Runs the request `send` submitting transactions to the inner middleware like
+    /// [`Self::timed_send`], and adjusts the concurrency window to how it went. Only requests
+    /// which didn't get an answer or were turned away for capacity count as the provider
+    /// being overloaded, not the ones which failed on the way, e.g. in a signer.
+    pub(crate) async fn timed_inner_send<T>(
+        &self,
+        send: impl Future<Output = Result<T, M::Error>>,
+    ) -> Result<T, M::Error> {
+        let (result, latency) = self.timed(SendEndpoint::Inner, send).await;
+        let overloaded = result
+            .as_ref()
+            .err()
+            .is_some_and(|err| rpc_error::is_unreachable(err) || rpc_error::is_capacity_error(err));
+        self.adjust_concurrency(latency, overloaded);
+        result
+    }
+
+    async fn timed<T, E>(
+        &self,
+        endpoint: SendEndpoint,
+        send: impl Future<Output = Result<T, E>>,
+    ) -> (Result<T, E>, Duration) {
+        let started = self.now();
+        let result = send.await;
+        let latency = self.elapsed(started);
+        self.send_latency
+            .histograms
+            .lock()
+            .expect("send latency lock poisoned")
+            .entry(endpoint)
+            .or_default()
+            .record(latency, result.is_err());
+        (result, latency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AdaptiveConcurrency, LockedNonceManagerMiddleware, SendEndpoint};
+    use ethers::providers::{Middleware, Provider};
+    use ethers::types::{Address, Bytes, H256};
+
+    #[tokio::test]
+    async fn only_sends_without_an_answer_shrink_the_window() {
+        let (provider, mock) = Provider::mocked();
+        let client = LockedNonceManagerMiddleware::new(provider, Address::zero())
+            .with_adaptive_concurrency(AdaptiveConcurrency::new(2, 8));
+        let send =
+            || client.timed_inner_send(client.inner().send_raw_transaction(Bytes::default()));
+
+        mock.push(H256::zero()).unwrap();
+        send().await.unwrap();
+        assert_eq!(client.concurrency_window(), Some(3));
+        // an answer which can't be decoded still came from a node which is up
+        mock.push("not a hash").unwrap();
+        send().await.unwrap_err();
+        assert_eq!(client.concurrency_window(), Some(4));
+        // the mock has no response to give, as if the request never got one
+        send().await.unwrap_err();
+        assert_eq!(client.concurrency_window(), Some(2));
+
+        let inner = &client.send_latency()[&SendEndpoint::Inner];
+        assert_eq!(inner.latency.count, 3);
+        assert_eq!(inner.failures, 2);
+    }
+}
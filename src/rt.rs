@@ -0,0 +1,16 @@
+//! Timers of the async runtime the crate is built for. The locks come from `tokio::sync`,
+//! which doesn't depend on the tokio runtime and works on any executor, so only the timers
+//! are runtime specific.
+
+use std::time::Duration;
+
+#[cfg(not(any(feature = "tokio-runtime", feature = "async-io-runtime")))]
+compile_error!("either the `tokio-runtime` or the `async-io-runtime` feature must be enabled");
+
+/// Waits for `duration` to elapse
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio-runtime")]
+    tokio::time::sleep(duration).await;
+    #[cfg(all(feature = "async-io-runtime", not(feature = "tokio-runtime")))]
+    async_io::Timer::after(duration).await;
+}
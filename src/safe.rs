@@ -0,0 +1,104 @@
+use crate::{contract::call_uint, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use ethers::utils::id;
+use std::collections::BTreeSet;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct Proposals {
+    /// The nonce the next proposal gets, `None` until read from the Safe
+    next: Option<U256>,
+    /// Nonces handed out to proposals which have not been executed yet
+    queued: BTreeSet<U256>,
+}
+
+#[derive(Debug)]
+/// Nonce manager for a Gnosis Safe. A Safe executes transactions in the order of its own
+/// on-chain `nonce()` rather than the nonce of the account submitting them, so every proposer
+/// of the same Safe has to go through one manager. Nonces are reserved locally for queued
+/// multisig proposals and released by [`SafeNonceManager::reconcile`] once the Safe executed
+/// them.
+pub struct SafeNonceManager<M> {
+    inner: M,
+    safe: Address,
+    proposals: Mutex<Proposals>,
+}
+
+impl<M> SafeNonceManager<M>
+where
+    M: Middleware,
+{
+    /// Instantiates the manager for the Safe deployed at `safe`
+    pub fn new(inner: M, safe: Address) -> Self {
+        Self {
+            inner,
+            safe,
+            proposals: Mutex::new(Proposals::default()),
+        }
+    }
+
+    /// The Safe whose nonces are managed
+    pub fn safe(&self) -> Address {
+        self.safe
+    }
+
+    /// Reserves the nonce for the next proposal. It stays queued until the Safe's nonce moves
+    /// past it. Fails with [`NonceManagerError::NonceOverflow`] if the Safe's nonce can't go
+    /// any further.
+    pub async fn reserve(&self) -> Result<U256, NonceManagerError<M>> {
+        let mut proposals = self.proposals.lock().await;
+        let nonce = match proposals.next {
+            Some(nonce) => nonce,
+            None => self.chain_nonce().await?,
+        };
+        let next = nonce
+            .checked_add(U256::one())
+            .ok_or(NonceManagerError::NonceOverflow(nonce))?;
+        proposals.next = Some(next);
+        proposals.queued.insert(nonce);
+
+        Ok(nonce)
+    }
+
+    /// Gives back the reservation of a proposal which was abandoned before being signed. Only
+    /// the most recent reservation can be released, as the Safe could not execute later
+    /// proposals with a gap below them; replace an earlier one with a rejection at the same
+    /// nonce instead. Returns whether the nonce was released.
+    pub async fn release(&self, nonce: U256) -> bool {
+        let mut proposals = self.proposals.lock().await;
+        if proposals.queued.iter().next_back() != Some(&nonce)
+            || proposals.next != nonce.checked_add(U256::one())
+        {
+            return false;
+        }
+        proposals.queued.remove(&nonce);
+        proposals.next = Some(nonce);
+        true
+    }
+
+    /// Reads the Safe's nonce, drops the queued proposals it executed and moves the local
+    /// counter up if proposals were queued elsewhere. Returns the Safe's nonce.
+    pub async fn reconcile(&self) -> Result<U256, NonceManagerError<M>> {
+        let chain_nonce = self.chain_nonce().await?;
+
+        let mut proposals = self.proposals.lock().await;
+        proposals.queued = proposals.queued.split_off(&chain_nonce);
+        match proposals.next {
+            Some(next) if next >= chain_nonce => {}
+            _ => proposals.next = Some(chain_nonce),
+        }
+
+        Ok(chain_nonce)
+    }
+
+    /// The nonces reserved for proposals which have not been executed yet, in execution order
+    pub async fn queued(&self) -> Vec<U256> {
+        self.proposals.lock().await.queued.iter().copied().collect()
+    }
+
+    /// Reads the Safe's `nonce()`
+    pub async fn chain_nonce(&self) -> Result<U256, NonceManagerError<M>> {
+        call_uint(&self.inner, self.safe, id("nonce()").to_vec()).await
+    }
+}
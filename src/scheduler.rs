@@ -0,0 +1,407 @@
+use crate::priority::Ticket;
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::{FromErr, Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, TxHash, H256, U64};
+use ethers::utils::{keccak256, rlp::Rlp};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+/// Nonces handed out ahead of time, along with the signed transaction for each of them once
+/// the caller provides it, and when the holds among them expire if it doesn't
+pub(crate) struct Schedule {
+    slots: BTreeMap<Nonce, Option<Bytes>>,
+    expiries: BTreeMap<Nonce, Instant>,
+    /// Transactions submitted privately, with their hash and the last block they may be
+    /// included in, whose nonce is reclaimed if they aren't
+    private: BTreeMap<Nonce, (TxHash, U64)>,
+    /// The nonce of the transaction each dependent transaction waits for, by the nonce of
+    /// the dependent one
+    dependencies: BTreeMap<Nonce, Nonce>,
+    /// Nonces whose transactions were queued while the RPC was down, rather than scheduled
+    /// by the caller
+    offline: BTreeSet<Nonce>,
+}
+
+impl Schedule {
+    pub(crate) fn reserve(&mut self, nonce: Nonce) {
+        self.slots.insert(nonce, None);
+    }
+
+    /// Reserves `nonce` with its signed transaction, queued while the RPC is down
+    pub(crate) fn queue(&mut self, nonce: Nonce, raw_tx: Bytes) {
+        self.slots.insert(nonce, Some(raw_tx));
+        self.offline.insert(nonce);
+    }
+
+    /// Reserves `nonce` until `expires_at`, unless a signed transaction is scheduled for it
+    /// by then
+    pub(crate) fn hold(&mut self, nonce: Nonce, expires_at: Instant) {
+        self.slots.insert(nonce, None);
+        self.expiries.insert(nonce, expires_at);
+    }
+
+    /// Pushes the expiry of the hold of `nonce` back by `extra`, counted from `now` if it
+    /// already expired, returning the new expiry, or `None` if `nonce` isn't held
+    pub(crate) fn extend(
+        &mut self,
+        nonce: Nonce,
+        extra: Duration,
+        now: Instant,
+    ) -> Option<Instant> {
+        let expires_at = self.expiries.get_mut(&nonce)?;
+        *expires_at = (*expires_at).max(now) + extra;
+        Some(*expires_at)
+    }
+
+    /// Takes the holds which expired at `now`, highest nonce first
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Vec<Nonce> {
+        let expired: Vec<Nonce> = self
+            .expiries
+            .iter()
+            .rev()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(nonce, _)| *nonce)
+            .collect();
+        for nonce in &expired {
+            self.expiries.remove(nonce);
+        }
+        expired
+    }
+
+    /// Takes the reservation of `nonce` off the schedule, as the transaction `tx_hash` was
+    /// submitted privately at it, so that it doesn't hold up the scheduled transactions
+    /// above it. It is tracked until it is included or the relay drops it after
+    /// `max_block_number`.
+    pub(crate) fn submit_private(&mut self, nonce: Nonce, tx_hash: TxHash, max_block_number: U64) {
+        self.slots.remove(&nonce);
+        self.expiries.remove(&nonce);
+        self.private.insert(nonce, (tx_hash, max_block_number));
+    }
+
+    /// The transactions submitted privately, in nonce order
+    pub(crate) fn private(&self) -> Vec<(Nonce, TxHash, U64)> {
+        self.private
+            .iter()
+            .map(|(nonce, (tx_hash, max_block_number))| (*nonce, *tx_hash, *max_block_number))
+            .collect()
+    }
+
+    /// Stops tracking the private transaction at `nonce`. Returns whether it was tracked.
+    pub(crate) fn take_private(&mut self, nonce: Nonce) -> bool {
+        self.private.remove(&nonce).is_some()
+    }
+
+    /// Whether `nonce` is reserved and not broadcast yet
+    pub(crate) fn is_reserved(&self, nonce: Nonce) -> bool {
+        self.slots.contains_key(&nonce)
+    }
+
+    /// Whether a signed transaction is scheduled at `nonce`, `None` if it isn't reserved
+    pub(crate) fn scheduled(&self, nonce: Nonce) -> Option<bool> {
+        self.slots.get(&nonce).map(Option::is_some)
+    }
+
+    /// Drops the signed transaction scheduled at `nonce`, keeping the reservation. Returns
+    /// whether `nonce` is reserved.
+    pub(crate) fn unschedule(&mut self, nonce: Nonce) -> bool {
+        match self.slots.get_mut(&nonce) {
+            Some(slot) => {
+                *slot = None;
+                self.offline.remove(&nonce);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records that the transaction at `nonce` depends on the one at `dependency`
+    pub(crate) fn depend(&mut self, nonce: Nonce, dependency: Nonce) {
+        self.dependencies.insert(nonce, dependency);
+    }
+
+    /// Takes the transactions depending on the one at `nonce`, directly or through others,
+    /// highest nonce first
+    pub(crate) fn take_dependents(&mut self, nonce: Nonce) -> Vec<Nonce> {
+        let mut dependents = Vec::new();
+        let mut failed = vec![nonce];
+        while let Some(failed_nonce) = failed.pop() {
+            let direct: Vec<Nonce> = self
+                .dependencies
+                .iter()
+                .filter(|(_, dependency)| **dependency == failed_nonce)
+                .map(|(dependent, _)| *dependent)
+                .collect();
+            for dependent in direct {
+                self.dependencies.remove(&dependent);
+                failed.push(dependent);
+                dependents.push(dependent);
+            }
+        }
+        dependents.sort_unstable_by(|a, b| b.cmp(a));
+        dependents
+    }
+
+    /// Forgets the dependencies of and on the transaction at `nonce`, once it was mined or
+    /// its nonce given up
+    pub(crate) fn settle(&mut self, nonce: Nonce) {
+        self.dependencies.remove(&nonce);
+        self.dependencies
+            .retain(|_, dependency| *dependency != nonce);
+    }
+
+    /// Drops the reservation of `nonce`, returning whether there was one
+    pub(crate) fn release(&mut self, nonce: Nonce) -> bool {
+        self.expiries.remove(&nonce);
+        self.private.remove(&nonce);
+        self.offline.remove(&nonce);
+        self.slots.remove(&nonce).is_some()
+    }
+
+    /// Drops the reservations and holds at and above `nonce`, along with their dependencies,
+    /// once the chain rewound past them
+    pub(crate) fn release_from(&mut self, nonce: Nonce) {
+        self.slots.retain(|reserved, _| *reserved < nonce);
+        self.expiries.retain(|held, _| *held < nonce);
+        self.private.retain(|private, _| *private < nonce);
+        self.offline.retain(|queued| *queued < nonce);
+        self.dependencies
+            .retain(|dependent, dependency| *dependent < nonce && *dependency < nonce);
+    }
+
+    /// The reserved nonces in order, along with their signed transactions
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Nonce, &Option<Bytes>)> {
+        self.slots.iter()
+    }
+
+    /// The signed transactions from the lowest reserved nonce up to the first one which
+    /// hasn't been scheduled yet, in order
+    pub(crate) fn ready(&self) -> Vec<(Nonce, Bytes)> {
+        self.slots
+            .iter()
+            .map_while(|(nonce, raw)| Some((*nonce, raw.clone()?)))
+            .collect()
+    }
+
+    /// The [`Self::ready`] transactions up to the first one depending on another among them,
+    /// which only goes out once that one was broadcast
+    pub(crate) fn batchable(&self) -> Vec<(Nonce, Bytes)> {
+        let mut ready = self.ready();
+        let waiting = ready.iter().position(|(nonce, _)| {
+            self.dependencies
+                .get(nonce)
+                .is_some_and(|dependency| self.slots.contains_key(dependency))
+        });
+        if let Some(waiting) = waiting {
+            ready.truncate(waiting);
+        }
+        ready
+    }
+
+    /// The transactions queued while the RPC was down, from the lowest reserved nonce up to
+    /// the first one which wasn't queued that way, in order
+    pub(crate) fn queued_offline(&self) -> Vec<(Nonce, Bytes)> {
+        self.slots
+            .iter()
+            .map_while(|(nonce, raw)| match raw {
+                Some(raw) if self.offline.contains(nonce) => Some((*nonce, raw.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The lowest reserved nonce and its signed transaction, if it has been scheduled
+    fn first(&self) -> Option<(Nonce, Option<&Bytes>)> {
+        self.slots
+            .iter()
+            .next()
+            .map(|(nonce, raw)| (*nonce, raw.as_ref()))
+    }
+}
+
+impl FromIterator<(Nonce, Option<Bytes>)> for Schedule {
+    fn from_iter<I: IntoIterator<Item = (Nonce, Option<Bytes>)>>(iter: I) -> Self {
+        Self {
+            slots: iter.into_iter().collect(),
+            expiries: BTreeMap::new(),
+            private: BTreeMap::new(),
+            dependencies: BTreeMap::new(),
+            offline: BTreeSet::new(),
+        }
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Reserves the next nonce without sending anything. The reservation stays open until a
+    /// signed transaction is scheduled for it with [`Self::schedule_signed`] and broadcast by
+    /// [`Self::broadcast_scheduled`].
+    pub async fn reserve_nonce(
+        &self,
+        block: Option<BlockId>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        let nonce = self.reserve_nonce_for(None, block, arrival).await?;
+        self.checkpoint_if_due().await;
+        Ok(nonce)
+    }
+
+    /// Reserves the next nonce like [`Self::reserve_nonce`] once it is the turn of `tx`, see
+    /// [`Self::lock_for_assignment`]
+    async fn reserve_nonce_for<'a>(
+        &'a self,
+        tx: Option<&TypedTransaction>,
+        block: Option<BlockId>,
+        arrival: Option<Ticket<'a>>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        self.get_or_init_nonce(block).await?;
+
+        let mut write_guard = self.lock_for_assignment(tx, arrival).await?;
+        let nonce = *write_guard;
+        *write_guard = Self::increment(nonce)?;
+        self.state.schedule().lock().await.reserve(nonce);
+        self.journal(JournalEntry::Reserved { nonce });
+        self.emit(NonceEvent::Assigned {
+            address: self.address,
+            nonce,
+            label: None,
+        });
+
+        Ok(nonce)
+    }
+
+    /// Schedules a transaction signed at a nonce previously returned by
+    /// [`Self::reserve_nonce`]. It is broadcast on the next call to
+    /// [`Self::broadcast_scheduled`] once every lower reserved nonce has been broadcast. A
+    /// transaction which can't be decoded is rejected and its reservation released, as it
+    /// would otherwise leave a gap nothing fills.
+    pub async fn schedule_signed(
+        &self,
+        nonce: Nonce,
+        raw_tx: Bytes,
+    ) -> Result<(), NonceManagerError<M>> {
+        let mut schedule = self.state.schedule().lock().await;
+        if schedule.private.contains_key(&nonce) {
+            return Err(NonceManagerError::AlreadyScheduled(nonce));
+        }
+        match schedule.slots.get_mut(&nonce) {
+            Some(None) if TypedTransaction::decode_signed(&Rlp::new(&raw_tx)).is_err() => {
+                drop(schedule);
+                self.release_reservation(nonce).await;
+                Err(NonceManagerError::UndecodableTransaction(nonce))
+            }
+            Some(slot @ None) => {
+                *slot = Some(raw_tx.clone());
+                schedule.expiries.remove(&nonce);
+                self.journal(JournalEntry::Scheduled { nonce, raw_tx });
+                drop(schedule);
+                self.checkpoint_if_due().await;
+                Ok(())
+            }
+            Some(Some(_)) => Err(NonceManagerError::AlreadyScheduled(nonce)),
+            None => Err(NonceManagerError::NotReserved(nonce)),
+        }
+    }
+
+    /// Reserves a nonce for `tx`, fills and signs it through the inner middleware and
+    /// schedules it for a later [`Self::broadcast_scheduled`]. Returns the reserved nonce.
+    /// If filling or signing fails, e.g. because the transaction was rejected on a hardware
+    /// wallet, the reservation is released as described in [`Self::release_reservation`].
+    pub async fn schedule_transaction<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        block: Option<BlockId>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        let arrival = self.arrival_ticket();
+        let mut tx = tx.into();
+        self.check_policies(&mut tx).await?;
+        self.check_balance(&tx).await?;
+        let nonce = self.reserve_nonce_for(Some(&tx), block, arrival).await?;
+        tx.set_nonce(nonce);
+
+        let raw_tx = match self.fill_and_sign(&mut tx, block).await {
+            Ok(raw_tx) => raw_tx,
+            Err(err) => {
+                self.release_reservation(nonce).await;
+                return Err(err);
+            }
+        };
+        self.schedule_signed(nonce, raw_tx).await?;
+        Ok(nonce)
+    }
+
+    /// Broadcasts the scheduled transactions in nonce order. Broadcasting stops at the first
+    /// reserved nonce which has no signed transaction yet, so that no transaction is sent
+    /// while a lower nonce is still missing and it would only sit in the queued pool. With a
+    /// block cap configured, the flush spreads over as many blocks as the cap requires.
+    /// Transactions queued during an RPC outage are flushed the same way. With a
+    /// [`crate::BatchTransport`], they are submitted in a single batch request. Fails before
+    /// broadcasting anything if an expired hold couldn't be filled, see
+    /// [`Self::expire_holds`], or with [`NonceManagerError::ShuttingDown`] once
+    /// [`Self::shutdown`] was called.
+    pub async fn broadcast_scheduled(
+        &self,
+    ) -> Result<Vec<PendingTransaction<'_, M::Provider>>, NonceManagerError<M>> {
+        self.ensure_connected()?;
+        self.ensure_accepting()?;
+        self.expire_holds().await?;
+        // holding the lock for the whole flush keeps concurrent flushes from sending the
+        // same transaction twice
+        let mut schedule = self.state.schedule().lock().await;
+        // the state is persisted under the lock, so no flush slips past a shutdown
+        self.ensure_accepting()?;
+        let mut pending = Vec::new();
+        // without a batch, or if it couldn't be submitted, every transaction is sent one at
+        // a time below. Those the node rejected in the batch stay scheduled and are sent
+        // again the same way, failing the flush if the node still refuses them.
+        if let Some(results) = self.broadcast_batch(&mut schedule).await {
+            pending.extend(results.into_iter().filter_map(|(_, result)| result.ok()));
+        }
+
+        while let Some((nonce, Some(raw_tx))) = schedule.first() {
+            let tx = self.send_scheduled(nonce, raw_tx.clone()).await?;
+            schedule.release(nonce);
+            pending.push(tx);
+        }
+
+        // everything due went through, so sends stop being queued for now
+        self.state.clear_offline();
+        drop(schedule);
+        self.checkpoint_if_due().await;
+        Ok(pending)
+    }
+
+    /// Broadcasts the signed transaction scheduled at `nonce`, once the block cap allows, and
+    /// tracks it as in flight. Needs to be called with the schedule lock held, and the
+    /// transaction taken off the schedule once it went through.
+    pub(crate) async fn send_scheduled(
+        &self,
+        nonce: Nonce,
+        raw_tx: Bytes,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let decoded = TypedTransaction::decode_signed(&Rlp::new(&raw_tx))
+            .ok()
+            .map(|(tx, _)| tx);
+        let slot = self.wait_for_block_slot().await?;
+        self.log_broadcasting(nonce, H256::from(keccak256(&raw_tx)))?;
+        let send = self.inner.send_raw_transaction(raw_tx);
+        let tx = self.timed_inner_send(send).await.map_err(FromErr::from)?;
+        slot.used();
+        self.record_broadcast(nonce, tx.tx_hash(), decoded.as_ref(), None)
+            .await;
+        Ok(tx)
+    }
+
+    /// Returns the reserved nonces which have not been broadcast yet, along with whether a
+    /// signed transaction has been scheduled for each
+    pub async fn reservations(&self) -> Vec<(Nonce, bool)> {
+        let schedule = self.state.schedule().lock().await;
+        schedule
+            .slots
+            .iter()
+            .map(|(nonce, raw)| (*nonce, raw.is_some()))
+            .collect()
+    }
+}
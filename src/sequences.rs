@@ -0,0 +1,87 @@
+use crate::NonceManagerError;
+use ethers::providers::Middleware;
+use ethers::types::U256;
+use std::{collections::BTreeMap, future::Future};
+use tokio::sync::Mutex;
+
+#[derive(Debug)]
+/// Locally tracked sequential nonces kept by a contract rather than by the account itself,
+/// one sequence per key. Each sequence is read from the contract on first use and handed out
+/// from memory afterwards.
+pub(crate) struct Sequences<K> {
+    next: Mutex<BTreeMap<K, U256>>,
+}
+
+impl<K: Ord + Copy> Sequences<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            next: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Hands out the next nonce of `key`, reading it with `fetch` if the key is new. Fails
+    /// with [`NonceManagerError::NonceOverflow`] once the sequence can't go any further.
+    pub(crate) async fn next<M, F, Fut>(
+        &self,
+        key: K,
+        fetch: F,
+    ) -> Result<U256, NonceManagerError<M>>
+    where
+        M: Middleware,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<U256, NonceManagerError<M>>>,
+    {
+        let mut next = self.next.lock().await;
+        let nonce = match next.get(&key) {
+            Some(nonce) => *nonce,
+            None => fetch().await?,
+        };
+        let following = nonce
+            .checked_add(U256::one())
+            .ok_or(NonceManagerError::NonceOverflow(nonce))?;
+        next.insert(key, following);
+        Ok(nonce)
+    }
+
+    /// The nonce `key` would get next, reading it with `fetch` if the key is new
+    pub(crate) async fn peek<F, Fut, E>(&self, key: K, fetch: F) -> Result<U256, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<U256, E>>,
+    {
+        let mut next = self.next.lock().await;
+        if let Some(nonce) = next.get(&key) {
+            return Ok(*nonce);
+        }
+        let nonce = fetch().await?;
+        next.insert(key, nonce);
+        Ok(nonce)
+    }
+
+    /// Raises the sequence of `key` to `chain_nonce` if it is behind, returning the next nonce
+    pub(crate) async fn resync(&self, key: K, chain_nonce: U256) -> U256 {
+        let mut next = self.next.lock().await;
+        let nonce = next.entry(key).or_insert(chain_nonce);
+        if chain_nonce > *nonce {
+            *nonce = chain_nonce;
+        }
+        *nonce
+    }
+
+    /// Takes back `nonce` if it is the last one handed out for `key`
+    pub(crate) async fn release(&self, key: K, nonce: U256) -> bool {
+        let mut next = self.next.lock().await;
+        match next.get_mut(&key) {
+            Some(next) if nonce.checked_add(U256::one()) == Some(*next) => {
+                *next = nonce;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The keys whose sequences are tracked
+    pub(crate) async fn keys(&self) -> Vec<K> {
+        self.next.lock().await.keys().copied().collect()
+    }
+}
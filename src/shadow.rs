@@ -0,0 +1,91 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::{Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId};
+use serde_json::Value;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Leaves nonce assignment to the inner middleware, e.g. ethers' own nonce manager or the
+    /// node, while still working out the nonce this manager would have assigned. Whenever the
+    /// two differ, a [`NonceEvent::ShadowDiverged`] is emitted, and the counter follows the
+    /// inner middleware's nonce, so that every divergence is reported once. Running in shadow
+    /// mode for a while validates the manager against an existing setup before switching
+    /// over. Transactions whose nonce the caller set aren't compared.
+    pub fn with_shadow_mode(mut self) -> Self {
+        self.config_mut().shadow = true;
+        self
+    }
+
+    /// Whether the manager only shadows the inner middleware's nonce assignment
+    pub fn is_shadowing(&self) -> bool {
+        self.config().shadow
+    }
+
+    /// Fills `tx` through the inner middleware, which picks its nonce, and sets the counter
+    /// past it. Returns the nonce.
+    pub(crate) async fn fill_shadowed(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        self.get_or_init_nonce(block).await?;
+        let mut counter = self.lock_nonce().await;
+        let nonce = self.shadow_fill(tx, block, *counter).await?;
+        *counter = Self::increment(nonce)?;
+        Ok(nonce)
+    }
+
+    /// Sends `tx` through the inner middleware, which picks its nonce, and sets the counter
+    /// past it once the transaction was accepted
+    pub(crate) async fn send_shadowed(
+        &self,
+        mut tx: TypedTransaction,
+        block: Option<BlockId>,
+        label: Option<Value>,
+    ) -> Result<(Nonce, PendingTransaction<'_, M::Provider>), NonceManagerError<M>> {
+        self.get_or_init_nonce(block).await?;
+        let mut counter = self.lock_nonce().await;
+        let nonce = self.shadow_fill(&mut tx, block, *counter).await?;
+        let send = self.inner.send_transaction(tx.clone(), block);
+        let pending = match self.timed_inner_send(send).await {
+            Ok(pending) => pending,
+            Err(err) => return Err(self.rejected(&tx, err, false).await),
+        };
+        *counter = Self::increment(nonce)?;
+        self.record_broadcast(nonce, pending.tx_hash(), Some(&tx), label)
+            .await;
+        Ok((nonce, pending))
+    }
+
+    /// Lets the inner middleware fill `tx`, and reports if the nonce it picked isn't
+    /// `predicted`. If it leaves the nonce to the node, the node's pending transaction count
+    /// is what it picked.
+    async fn shadow_fill(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+        predicted: Nonce,
+    ) -> Result<Nonce, NonceManagerError<M>> {
+        if let Err(err) = self.inner.fill_transaction(tx, block).await {
+            return Err(self.rejected(tx, err, false).await);
+        }
+        let actual = match tx.nonce() {
+            Some(nonce) => Nonce::new(*nonce).map_err(NonceManagerError::InvalidNonce)?,
+            None => {
+                let nonce = self.pending_chain_nonce().await?;
+                tx.set_nonce(nonce);
+                nonce
+            }
+        };
+        if actual != predicted {
+            self.emit(NonceEvent::ShadowDiverged {
+                address: self.address,
+                predicted,
+                actual,
+            });
+        }
+        Ok(actual)
+    }
+}
@@ -0,0 +1,186 @@
+use crate::sync::{AtomicBool, Ordering};
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::Middleware;
+use ethers::types::TxHash;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What [`LockedNonceManagerMiddleware::shutdown`] does with transactions still unconfirmed
+/// at its deadline
+pub enum LeftoverPolicy {
+    /// Leaves them pending, tracked in the persisted state for the next process to pick up
+    #[default]
+    Keep,
+    /// Replaces each with a cancellation, see [`LockedNonceManagerMiddleware::cancel`]
+    Cancel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Outcome of a [`LockedNonceManagerMiddleware::shutdown`]
+pub struct ShutdownReport {
+    /// Transactions still unconfirmed at the deadline, with the hash of their cancellation if
+    /// one was sent
+    pub leftovers: Vec<(Nonce, TxHash)>,
+    /// Nonces whose cancellation was broadcast
+    pub cancelled: Vec<Nonce>,
+    /// Whether the state was saved to the state store
+    pub persisted: bool,
+}
+
+impl ShutdownReport {
+    /// Whether every transaction was confirmed before the deadline
+    pub fn is_drained(&self) -> bool {
+        self.leftovers.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+/// Whether the middleware stopped accepting sends
+pub(crate) struct Shutdown {
+    closed: AtomicBool,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Sets what [`Self::shutdown`] does with transactions still unconfirmed at its deadline
+    pub fn with_leftover_policy(mut self, policy: LeftoverPolicy) -> Self {
+        self.config_mut().leftovers = policy;
+        self
+    }
+
+    /// Shuts the manager down gracefully. New sends and fills fail with
+    /// [`NonceManagerError::ShuttingDown`] from now on, and the ones already holding a nonce
+    /// finish. The in-flight transactions are then polled for confirmations until none are
+    /// left or `deadline` passed, the leftovers are handled according to the
+    /// [`LeftoverPolicy`] and the state is saved to the state store, if one is configured.
+    /// Failing cancellations leave their transaction pending, and don't fail the shutdown.
+    /// If polling fails, the state is still saved before the error is returned.
+    pub async fn shutdown(
+        &self,
+        deadline: Duration,
+    ) -> Result<ShutdownReport, NonceManagerError<M>> {
+        let started = self.now();
+        // sends hold the lock until their transaction was broadcast, and the ones waiting for
+        // it find the manager closed once they get it
+        let counter = self.lock_nonce().await;
+        self.shutdown.closed.store(true, Ordering::SeqCst);
+        drop(counter);
+
+        let interval = self.provider().get_interval();
+        let mut polled = Ok(());
+        while !self.state.in_flight().is_empty() && self.elapsed(started) < deadline {
+            // stuck transactions are what the leftover policy is for
+            if let Err(err) = self.poll_in_flight(Duration::MAX).await {
+                polled = Err(err);
+                break;
+            }
+            if !self.state.in_flight().is_empty() {
+                self.sleep(interval.min(deadline.saturating_sub(self.elapsed(started))))
+                    .await;
+            }
+        }
+
+        let mut report = ShutdownReport::default();
+        if self.config().leftovers == LeftoverPolicy::Cancel {
+            for (nonce, _) in self.in_flight() {
+                if self.cancel(nonce).await.is_ok() {
+                    report.cancelled.push(nonce);
+                }
+            }
+        }
+        report.leftovers = self.in_flight();
+        if self.state_store.is_some() {
+            self.persist().await?;
+            report.persisted = true;
+        }
+        polled?;
+        Ok(report)
+    }
+
+    /// Fails once [`Self::shutdown`] was called
+    pub(crate) fn ensure_accepting(&self) -> Result<(), NonceManagerError<M>> {
+        if self.shutdown.closed.load(Ordering::SeqCst) {
+            Err(NonceManagerError::ShuttingDown)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, transfer, SENDER};
+    use crate::{FileStateStore, NonceManagerError, SimulatedChain};
+    use ethers::providers::Middleware;
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::transaction::eip2718::TypedTransaction;
+    use futures_util::FutureExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn sends_waiting_for_the_lock_find_the_manager_closed() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain);
+        send(&client).await;
+
+        let held = client.lock_nonce().await;
+        let mut shutdown = Box::pin(client.shutdown(Duration::ZERO));
+        assert!((&mut shutdown).now_or_never().is_none());
+        // past the check on the way in, waiting behind the shutdown
+        let mut late = Box::pin(client.send_transaction(transfer(), None));
+        assert!((&mut late).now_or_never().is_none());
+        drop(held);
+
+        shutdown.await.unwrap();
+        let err = late.await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::ShuttingDown));
+        assert_eq!(chain.pending_nonces(SENDER), [0]);
+    }
+
+    #[tokio::test]
+    async fn scheduled_transactions_are_not_flushed_once_closed() {
+        let chain = SimulatedChain::new(3);
+        let client = client(&chain);
+        let nonce = client.reserve_nonce(None).await.unwrap();
+        let wallet: LocalWallet = "22".repeat(32).parse().unwrap();
+        let tx: TypedTransaction = transfer()
+            .nonce(nonce.as_u64())
+            .gas(21_000u64)
+            .gas_price(1u64)
+            .chain_id(31337u64)
+            .into();
+        let signature = wallet.sign_transaction(&tx).await.unwrap();
+        client
+            .schedule_signed(nonce, tx.rlp_signed(&signature))
+            .await
+            .unwrap();
+
+        client.shutdown(Duration::ZERO).await.unwrap();
+        let err = client.broadcast_scheduled().await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::ShuttingDown));
+        assert!(chain.pending_nonces(wallet.address()).is_empty());
+        assert_eq!(client.reservations().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_state_is_saved_even_if_polling_fails() {
+        let path =
+            std::env::temp_dir().join(format!("ethers-nonce-shutdown-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain).with_state_store(FileStateStore::new(&path));
+        send(&client).await;
+
+        chain.fail_next(1);
+        assert!(client.shutdown(Duration::from_secs(1)).await.is_err());
+        let restarted = client(&chain).with_state_store(FileStateStore::new(&path));
+        assert!(restarted.restore_from_store().await.unwrap());
+        assert_eq!(restarted.try_next().map(|nonce| nonce.as_u64()), Some(1));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, Bytes};
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Signs the transactions passed to [`Self::schedule_transaction`] one at a time, for
+    /// signers such as Ledger or Trezor devices which can only handle a single request. Nonces
+    /// are still reserved as soon as the transactions come in, while signing waits its turn.
+    pub fn with_sequential_signing(mut self) -> Self {
+        self.config_mut().sequential_signing = true;
+        self
+    }
+
+    /// Gives up the reservation of `nonce` after its transaction could not be signed. If no
+    /// later nonce has been handed out in the meantime, the counter is rolled back so that
+    /// the nonce is reused. Otherwise the reservation stays open so that no later
+    /// transaction is broadcast over the gap, and a replacement can be scheduled at it with
+    /// [`Self::schedule_signed`]. Transactions depending on it, see
+    /// [`Self::schedule_dependent`], are given up first.
+    pub async fn release_reservation(&self, nonce: Nonce) {
+        self.drop_dependents(nonce).await;
+        let mut counter = self.lock_nonce().await;
+        let mut schedule = self.state.schedule().lock().await;
+        schedule.settle(nonce);
+        if nonce.checked_increment() == Some(*counter) && schedule.release(nonce) {
+            *counter = nonce;
+            self.journal(JournalEntry::Released {
+                nonce,
+                rolled_back: true,
+            });
+        }
+        drop(schedule);
+        drop(counter);
+        self.checkpoint_if_due().await;
+    }
+
+    /// Fills `tx` and signs it through the inner middleware, waiting for the signer if
+    /// signing is sequential. Returns the signed transaction.
+    pub(crate) async fn fill_and_sign(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Bytes, NonceManagerError<M>> {
+        let _signer = if self.config().sequential_signing {
+            Some(self.signer.lock().await)
+        } else {
+            None
+        };
+
+        // scheduled transactions may depend on earlier ones which haven't been mined yet
+        if tx.gas().is_none() {
+            if let Ok(gas) = self.estimate_gas_with_pending(tx).await {
+                tx.set_gas(gas);
+            }
+        }
+        self.inner
+            .fill_transaction(tx, block)
+            .await
+            .map_err(FromErr::from)?;
+        let signature = self
+            .inner
+            .sign_transaction(&*tx, self.address)
+            .await
+            .map_err(FromErr::from)?;
+        Ok(tx.rlp_signed(&signature))
+    }
+}
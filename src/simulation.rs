@@ -0,0 +1,447 @@
+use crate::{rpc_error, LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::abi::{self, ParamType};
+use ethers::providers::{FromErr, JsonRpcError, Middleware, RpcError};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockNumber, Bytes, U256};
+use ethers::utils::{hex, rlp::Rlp};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Selector of `Error(string)`, what `require` and `revert` with a message revert with
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Selector of `Panic(uint256)`, what failed assertions, overflows and the like revert with
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why a simulated transaction reverted, decoded from its revert data
+pub enum RevertReason {
+    /// `require(condition, "message")` or `revert("message")`
+    Error(String),
+    /// A compiler-inserted check failed, with its panic code, e.g. `0x11` for an arithmetic
+    /// overflow
+    Panic(U256),
+    /// A custom error, to be decoded by the caller from its selector, e.g. with the error
+    /// types `abigen!` generates
+    Custom {
+        selector: [u8; 4],
+        /// The ABI-encoded arguments following the selector
+        args: Bytes,
+    },
+    /// The contract reverted without data
+    Empty,
+}
+
+impl RevertReason {
+    /// Decodes the data a call reverted with
+    pub fn decode(data: &[u8]) -> Self {
+        if data.len() < 4 {
+            return RevertReason::Empty;
+        }
+        let (selector, args) = data.split_at(4);
+        let selector: [u8; 4] = selector.try_into().expect("split at 4");
+        let decoded = match selector {
+            ERROR_SELECTOR => abi::decode(&[ParamType::String], args)
+                .ok()
+                .and_then(|mut tokens| tokens.remove(0).into_string())
+                .map(RevertReason::Error),
+            PANIC_SELECTOR => abi::decode(&[ParamType::Uint(256)], args)
+                .ok()
+                .and_then(|mut tokens| tokens.remove(0).into_uint())
+                .map(RevertReason::Panic),
+            _ => None,
+        };
+        // a standard selector with malformed arguments is left for the caller as well
+        decoded.unwrap_or_else(|| RevertReason::Custom {
+            selector,
+            args: args.to_vec().into(),
+        })
+    }
+
+    /// The reason a node's error response reports, `None` if it isn't a revert. Nodes put the
+    /// revert data in the `data` field, either as is or nested in an object, and some only
+    /// spell out the message.
+    fn from_response(err: &JsonRpcError) -> Option<Self> {
+        let data = match &err.data {
+            Some(Value::String(data)) => Some(data),
+            Some(Value::Object(object)) => object.get("data").and_then(Value::as_str),
+            _ => None,
+        };
+        if let Some(data) = data.and_then(|data| hex::decode(data.trim_start_matches("0x")).ok()) {
+            return Some(RevertReason::decode(&data));
+        }
+        let message = err.message.strip_prefix("execution reverted")?;
+        match message.strip_prefix(": ") {
+            Some(reason) => Some(RevertReason::Error(reason.to_owned())),
+            None => Some(RevertReason::Empty),
+        }
+    }
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::Error(message) => write!(f, "{message}"),
+            RevertReason::Panic(code) => write!(f, "panic {code:#x}"),
+            RevertReason::Custom { selector, .. } => {
+                write!(f, "custom error 0x{}", hex::encode(selector))
+            }
+            RevertReason::Empty => write!(f, "no reason given"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Outcome of simulating the queue of pending managed transactions with
+/// [`LockedNonceManagerMiddleware::simulate_queue`]
+pub struct QueueSimulation {
+    /// Nonces of the simulated transactions, in the order they were simulated
+    pub simulated: Vec<Nonce>,
+    /// The simulated transactions which would revert, with why, in nonce order
+    pub reverted: Vec<(Nonce, RevertReason)>,
+}
+
+impl QueueSimulation {
+    /// Whether every simulated transaction went through
+    pub fn is_clean(&self) -> bool {
+        self.reverted.is_empty()
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Simulates every transaction with `eth_call` against the pending block before it is
+    /// broadcast, so one which would revert fails with
+    /// [`NonceManagerError::SimulationReverted`] instead of using up its nonce on chain. This
+    /// costs a request per send, made while holding the nonce lock. A simulation which can't
+    /// reach the node doesn't hold the send back, it is let through with a
+    /// [`NonceEvent::SimulationSkipped`]; any other error, e.g. one the node answers with or
+    /// an answer which can't be decoded, fails the send.
+    pub fn with_simulation(mut self) -> Self {
+        self.config_mut().simulate = true;
+        self
+    }
+
+    /// Runs the simulation gate for `tx`, if enabled. Needs to be called before the nonce is
+    /// committed, so a rejection leaves it free.
+    pub(crate) async fn simulate(&self, tx: &TypedTransaction) -> Result<(), NonceManagerError<M>> {
+        if !self.config().simulate {
+            return Ok(());
+        }
+        let mut tx = tx.clone();
+        if tx.from().is_none() {
+            tx.set_from(self.address);
+        }
+
+        let err = match self
+            .provider()
+            .request::<_, Bytes>("eth_call", (&tx, BlockNumber::Pending))
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        if let Some(reason) = err
+            .as_error_response()
+            .and_then(RevertReason::from_response)
+        {
+            return Err(NonceManagerError::SimulationReverted(reason));
+        }
+        // the broadcast finds out whether the node is really gone
+        if rpc_error::is_unreachable(&err) {
+            self.emit(NonceEvent::SimulationSkipped {
+                address: self.address,
+                error: err.to_string(),
+            });
+            return Ok(());
+        }
+        Err(NonceManagerError::ProviderError(err))
+    }
+
+    /// Simulates the whole queue of managed transactions which are in flight but not mined
+    /// yet, followed by the scheduled ones, one after the other in nonce order on top of the
+    /// latest block, and reports which of them would revert. Each transaction sees the state
+    /// left by the ones before it, so this catches a queue going bad because of something
+    /// which landed in between, before more nonces are committed on top of it. Uses
+    /// `trace_callMany` (Erigon, Nethermind, Reth) or else `debug_traceCallMany`, and fails
+    /// with [`NonceManagerError::ProviderError`] on nodes serving neither. Simulation stops
+    /// short of the first transaction the manager doesn't know the contents of, e.g. a
+    /// reserved nonce without a signed transaction.
+    pub async fn simulate_queue(&self) -> Result<QueueSimulation, NonceManagerError<M>> {
+        let mined_count = self
+            .inner
+            .get_transaction_count(self.address, Some(BlockNumber::Latest.into()))
+            .await
+            .map_err(FromErr::from)?;
+        let mut queue: Vec<(Nonce, Option<TypedTransaction>)> = self
+            .state
+            .in_flight()
+            .iter()
+            .map(|(nonce, tx)| (*nonce, tx.tx.clone()))
+            .collect();
+        let schedule = self.state.schedule().lock().await;
+        queue.extend(schedule.iter().map(|(nonce, raw_tx)| {
+            let tx = raw_tx.as_ref().and_then(|raw_tx| {
+                TypedTransaction::decode_signed(&Rlp::new(raw_tx))
+                    .ok()
+                    .map(|(tx, _)| tx)
+            });
+            (*nonce, tx)
+        }));
+        drop(schedule);
+        queue.sort_by_key(|(nonce, _)| *nonce);
+
+        let mut simulated = Vec::new();
+        let mut txs = Vec::new();
+        for (nonce, tx) in queue {
+            if nonce.as_u256() < mined_count {
+                continue;
+            }
+            let mut tx = match tx {
+                Some(tx) => tx,
+                None => break,
+            };
+            tx.set_from(self.address);
+            tx.set_nonce(nonce);
+            simulated.push(nonce);
+            txs.push(tx);
+        }
+        if txs.is_empty() {
+            return Ok(QueueSimulation::default());
+        }
+
+        let failures = match self.trace_call_many(&txs).await {
+            Ok(failures) => failures,
+            Err(_) => self.debug_trace_call_many(&txs).await?,
+        };
+        let reverted = simulated
+            .iter()
+            .zip(failures)
+            .filter_map(|(nonce, failure)| Some((*nonce, failure?)))
+            .collect();
+        Ok(QueueSimulation {
+            simulated,
+            reverted,
+        })
+    }
+
+    /// Simulates `txs` with `trace_callMany`, returning why each of them reverted, if it did
+    async fn trace_call_many(
+        &self,
+        txs: &[TypedTransaction],
+    ) -> Result<Vec<Option<RevertReason>>, NonceManagerError<M>> {
+        let calls: Vec<_> = txs.iter().map(|tx| json!([tx, ["trace"]])).collect();
+        let results: Vec<Value> = self
+            .provider()
+            .request("trace_callMany", (calls, BlockNumber::Latest))
+            .await
+            .map_err(NonceManagerError::ProviderError)?;
+        // the outermost call's trace comes first, with an `error` if it failed
+        Ok(results
+            .iter()
+            .map(|result| {
+                result["trace"][0]["error"]
+                    .is_string()
+                    .then(|| Self::revert_reason(&result["output"]))
+            })
+            .collect())
+    }
+
+    /// Simulates `txs` as a single bundle with `debug_traceCallMany` and its call tracer,
+    /// returning why each of them reverted, if it did
+    async fn debug_trace_call_many(
+        &self,
+        txs: &[TypedTransaction],
+    ) -> Result<Vec<Option<RevertReason>>, NonceManagerError<M>> {
+        let bundles = json!([{ "transactions": txs }]);
+        let context = json!({ "blockNumber": BlockNumber::Latest });
+        let tracer = json!({ "tracer": "callTracer" });
+        let results: Vec<Vec<Value>> = self
+            .provider()
+            .request("debug_traceCallMany", (bundles, context, tracer))
+            .await
+            .map_err(NonceManagerError::ProviderError)?;
+        Ok(results
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+            .iter()
+            .map(|frame| {
+                frame["error"]
+                    .is_string()
+                    .then(|| Self::revert_reason(&frame["output"]))
+            })
+            .collect())
+    }
+
+    /// Decodes the revert data a trace reports as the output of a failed call
+    fn revert_reason(output: &Value) -> RevertReason {
+        output
+            .as_str()
+            .and_then(|output| hex::decode(output.trim_start_matches("0x")).ok())
+            .map_or(RevertReason::Empty, |data| RevertReason::decode(&data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Error(string)` revert data of `revert("too late")`
+    fn error_data(message: &str) -> Vec<u8> {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(abi::encode(&[abi::Token::String(message.to_owned())]));
+        data
+    }
+
+    #[test]
+    fn revert_data_is_decoded() {
+        assert_eq!(
+            RevertReason::decode(&error_data("too late")),
+            RevertReason::Error("too late".to_owned())
+        );
+        let mut panic = PANIC_SELECTOR.to_vec();
+        panic.extend(abi::encode(&[abi::Token::Uint(0x11.into())]));
+        assert_eq!(
+            RevertReason::decode(&panic),
+            RevertReason::Panic(0x11.into())
+        );
+        assert_eq!(RevertReason::decode(&[]), RevertReason::Empty);
+        assert!(matches!(
+            RevertReason::decode(&[1, 2, 3, 4, 5]),
+            RevertReason::Custom {
+                selector: [1, 2, 3, 4],
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn only_reverts_are_read_from_error_responses() {
+        let response = |message: &str, data: Option<Value>| JsonRpcError {
+            code: 3,
+            message: message.to_owned(),
+            data,
+        };
+        let data = json!(format!("0x{}", hex::encode(error_data("too late"))));
+        assert_eq!(
+            RevertReason::from_response(&response("execution reverted", Some(data.clone()))),
+            Some(RevertReason::Error("too late".to_owned()))
+        );
+        assert_eq!(
+            RevertReason::from_response(&response("reverted", Some(json!({ "data": data })))),
+            Some(RevertReason::Error("too late".to_owned()))
+        );
+        assert_eq!(
+            RevertReason::from_response(&response("execution reverted: too late", None)),
+            Some(RevertReason::Error("too late".to_owned()))
+        );
+        assert_eq!(
+            RevertReason::from_response(&response("out of gas", None)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn queues_are_simulated_up_to_the_first_unsigned_reservation() {
+        use ethers::providers::{MockResponse, Provider};
+        use ethers::signers::{LocalWallet, Signer};
+        use ethers::types::TransactionRequest;
+
+        let (provider, mock) = Provider::mocked();
+        let wallet: LocalWallet = "22".repeat(32).parse().unwrap();
+        let client = LockedNonceManagerMiddleware::new(provider, wallet.address());
+        mock.push(U256::zero()).unwrap();
+        let mut nonces = Vec::new();
+        for _ in 0..3 {
+            nonces.push(client.reserve_nonce(None).await.unwrap());
+        }
+        for &nonce in &nonces[..2] {
+            let tx: TypedTransaction = TransactionRequest::pay(wallet.address(), 1u64)
+                .nonce(nonce.as_u64())
+                .gas(21_000u64)
+                .gas_price(1u64)
+                .chain_id(31337u64)
+                .into();
+            let signature = wallet.sign_transaction(&tx).await.unwrap();
+            client
+                .schedule_signed(nonce, tx.rlp_signed(&signature))
+                .await
+                .unwrap();
+        }
+        let revert = json!(format!("0x{}", hex::encode(error_data("too late"))));
+        let reverted = vec![(nonces[1], RevertReason::Error("too late".to_owned()))];
+
+        // responses are answered last pushed first
+        let traces = json!([
+            { "output": "0x", "trace": [{}] },
+            { "output": revert, "trace": [{ "error": "Reverted" }] },
+        ]);
+        mock.push(traces).unwrap();
+        mock.push(U256::zero()).unwrap();
+        let simulation = client.simulate_queue().await.unwrap();
+        // the third nonce has nothing to simulate yet
+        assert_eq!(simulation.simulated, nonces[..2]);
+        assert_eq!(simulation.reverted, reverted);
+
+        // nodes without trace_callMany are asked for debug_traceCallMany
+        let frames =
+            json!([[{ "output": "0x" }, { "error": "execution reverted", "output": revert }]]);
+        mock.push(frames).unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32601,
+            message: "the method trace_callMany does not exist".to_owned(),
+            data: None,
+        }));
+        mock.push(U256::zero()).unwrap();
+        let simulation = client.simulate_queue().await.unwrap();
+        assert_eq!(simulation.reverted, reverted);
+    }
+
+    #[tokio::test]
+    async fn only_simulations_which_got_no_answer_are_skipped() {
+        use ethers::providers::Provider;
+        use ethers::types::Address;
+
+        let (provider, mock) = Provider::mocked();
+        let client = LockedNonceManagerMiddleware::new(provider, Address::zero()).with_simulation();
+        let tx = TypedTransaction::default();
+        // the mock has no response to give, as if the request never got one
+        client.simulate(&tx).await.unwrap();
+
+        mock.push(json!({ "not": "bytes" })).unwrap();
+        let err = client.simulate(&tx).await.unwrap_err();
+        assert!(matches!(err, NonceManagerError::ProviderError(_)));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[tokio::test]
+    async fn reverting_sends_fail_and_unreachable_simulations_are_skipped() {
+        use crate::testkit::fixtures::{client, transfer, Events, SENDER};
+        use crate::{CallOutcome, NonceEvent, SimulatedChain};
+
+        let chain = SimulatedChain::new(1);
+        let events = Events::default();
+        let client = client(&chain)
+            .with_simulation()
+            .with_observer(events.clone());
+        chain.set_call_outcome(CallOutcome::Revert(error_data("too late").into()));
+        let err = client.send_transaction(transfer(), None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::SimulationReverted(RevertReason::Error(message))
+                if message == "too late"
+        ));
+        assert!(chain.pending_nonces(SENDER).is_empty());
+        events.take();
+
+        chain.set_call_outcome(CallOutcome::Unreachable);
+        client.send_transaction(transfer(), None).await.unwrap();
+        assert_eq!(chain.pending_nonces(SENDER), [0]);
+        assert!(events
+            .take()
+            .iter()
+            .any(|event| matches!(event, NonceEvent::SimulationSkipped { .. })));
+    }
+}
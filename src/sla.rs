@@ -0,0 +1,268 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::U64;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// How quickly managed transactions are expected to be mined. A transaction pending for
+/// longer than either bound breaches the SLA.
+pub struct Sla {
+    /// Blocks a transaction may stay pending for, counted from the block it was broadcast at.
+    /// It breaches the SLA once it has been pending for that many blocks.
+    #[serde(default)]
+    pub max_blocks: Option<u64>,
+    /// Seconds a transaction may stay pending for after it was broadcast
+    #[serde(default)]
+    pub max_wait_secs: Option<u64>,
+    /// Whether a breaching transaction is sped up right away with the bump policy
+    #[serde(default)]
+    pub auto_bump: bool,
+}
+
+impl Sla {
+    /// Expects transactions to be mined within `blocks` blocks
+    pub fn blocks(blocks: u64) -> Self {
+        Self {
+            max_blocks: Some(blocks),
+            max_wait_secs: None,
+            auto_bump: false,
+        }
+    }
+
+    /// Expects transactions to be mined within `max_wait` of being broadcast
+    pub fn within(max_wait: Duration) -> Self {
+        Self {
+            max_blocks: None,
+            max_wait_secs: Some(max_wait.as_secs()),
+            auto_bump: false,
+        }
+    }
+
+    /// How long a transaction may stay pending for after it was broadcast
+    pub fn max_wait(&self) -> Option<Duration> {
+        self.max_wait_secs.map(Duration::from_secs)
+    }
+
+    /// Speeds breaching transactions up, see
+    /// [`LockedNonceManagerMiddleware::speed_up`]
+    pub fn with_auto_bump(mut self) -> Self {
+        self.auto_bump = true;
+        self
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Checks the pending transactions against `sla` on every
+    /// [`Self::poll_in_flight`], emitting a [`NonceEvent::SlaBreached`] for each one which
+    /// breaches it
+    pub fn with_sla(mut self, sla: Sla) -> Self {
+        self.config_mut().sla = Some(sla);
+        self
+    }
+
+    /// The latest block, if the SLA bounds how many blocks a transaction may be pending, for
+    /// counting them from the block it was broadcast at. `None` if it can't be fetched.
+    pub(crate) async fn sla_block(&self) -> Option<U64> {
+        self.config().sla?.max_blocks?;
+        self.inner.get_block_number().await.ok()
+    }
+
+    /// Checks the pending transaction at `nonce` against the configured SLA, reporting a
+    /// breach once per broadcast. A replacement sent because of a breach gets a fresh SLA,
+    /// while one refused by the fee cap is reported with a [`NonceEvent::FeeCapReached`].
+    pub(crate) async fn check_sla(
+        &self,
+        nonce: Nonce,
+        latest: &mut Option<U64>,
+    ) -> Result<(), NonceManagerError<M>> {
+        let sla = match self.config().sla {
+            Some(sla) => sla,
+            None => return Ok(()),
+        };
+        let block = match (sla.max_blocks, *latest) {
+            (Some(_), None) => {
+                let block = self.inner.get_block_number().await.map_err(FromErr::from)?;
+                *latest = Some(block);
+                Some(block)
+            }
+            (_, block) => block,
+        };
+
+        let (tx_hash, breach) = {
+            let mut in_flight = self.state.in_flight();
+            let tracked = match in_flight.get_mut(nonce) {
+                Some(tracked) if !tracked.breached_sla => tracked,
+                _ => return Ok(()),
+            };
+            let pending_blocks = block.map(|block| {
+                let first_seen = *tracked.first_seen_block.get_or_insert(block);
+                block.saturating_sub(first_seen).as_u64()
+            });
+            let pending = self.elapsed(tracked.sent_at);
+            let too_many_blocks = match (sla.max_blocks, pending_blocks) {
+                (Some(max), Some(pending_blocks)) => pending_blocks >= max,
+                _ => false,
+            };
+            let too_long = sla.max_wait().is_some_and(|max_wait| pending > max_wait);
+            if !too_many_blocks && !too_long {
+                return Ok(());
+            }
+            tracked.breached_sla = true;
+            let breach = NonceEvent::SlaBreached {
+                address: self.address,
+                nonce,
+                tx_hash: tracked.tx_hash,
+                pending_blocks,
+                pending_secs: pending.as_secs(),
+                label: tracked.label.clone(),
+            };
+            (tracked.tx_hash, breach)
+        };
+        self.emit(breach);
+
+        if sla.auto_bump {
+            match self.speed_up(nonce).await {
+                Ok(_)
+                | Err(NonceManagerError::NotReplaceable(_))
+                | Err(NonceManagerError::BlobNotReplaceable(_)) => {}
+                Err(NonceManagerError::FeeCapReached { cap, .. }) => {
+                    self.emit(NonceEvent::FeeCapReached {
+                        address: self.address,
+                        nonce,
+                        tx_hash,
+                        cap,
+                    })
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, Events, SENDER};
+    use crate::{
+        BumpPreset, EscalationSchedule, MockClock, Nonce, NonceEvent, SimulatedChain, Sla,
+    };
+    use ethers::types::U256;
+    use std::time::Duration;
+
+    fn breaches(events: &Events) -> usize {
+        events
+            .take()
+            .iter()
+            .filter(|event| matches!(event, NonceEvent::SlaBreached { .. }))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn breaches_are_reported_once_per_broadcast() {
+        let chain = SimulatedChain::new(1);
+        let clock = MockClock::new();
+        let events = Events::default();
+        let client = client(&chain)
+            .with_clock(clock.clone())
+            .with_observer(events.clone())
+            .with_sla(Sla::within(Duration::from_secs(30)));
+        send(&client).await;
+
+        clock.advance(Duration::from_secs(30));
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert_eq!(breaches(&events), 0);
+        clock.advance(Duration::from_secs(1));
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert_eq!(breaches(&events), 1);
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert_eq!(breaches(&events), 0);
+    }
+
+    #[tokio::test]
+    async fn blocks_count_from_the_broadcast() {
+        let chain = SimulatedChain::new(3);
+        let events = Events::default();
+        let client = client(&chain)
+            .with_observer(events.clone())
+            .with_sla(Sla::blocks(2));
+        send(&client).await;
+        chain.drop_pending(SENDER, 0);
+
+        chain.mine_blocks(1);
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert_eq!(breaches(&events), 0);
+        chain.mine_blocks(1);
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert_eq!(breaches(&events), 1);
+    }
+
+    #[tokio::test]
+    async fn bump_schedules_replace_transactions_after_their_blocks() {
+        let chain = SimulatedChain::new(4);
+        let client = client(&chain).with_bump_schedule(BumpPreset::Standard);
+        send(&client).await;
+        chain.drop_pending(SENDER, 0);
+
+        chain.mine_blocks(2);
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert!(chain.pending_nonces(SENDER).is_empty());
+        chain.mine_blocks(1);
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        assert_eq!(chain.pending_nonces(SENDER), [0]);
+    }
+
+    #[tokio::test]
+    async fn bumps_stopped_by_the_fee_cap_are_reported() {
+        let chain = SimulatedChain::new(5);
+        let clock = MockClock::new();
+        let events = Events::default();
+        let cap = U256::exp10(9);
+        let client = client(&chain)
+            .with_clock(clock.clone())
+            .with_observer(events.clone())
+            .with_bump_policy(EscalationSchedule::new(1250, 1).capped_at(cap))
+            .with_sla(Sla::within(Duration::from_secs(30)).with_auto_bump());
+        let first = send(&client).await;
+        let second = send(&client).await;
+
+        clock.advance(Duration::from_secs(31));
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        let capped: Vec<_> = events
+            .take()
+            .into_iter()
+            .filter_map(|event| match event {
+                NonceEvent::FeeCapReached { nonce, tx_hash, .. } => Some((nonce, tx_hash)),
+                _ => None,
+            })
+            .collect();
+        // both are left as they were
+        let in_flight = [
+            (Nonce::zero(), first),
+            (Nonce::new(U256::one()).unwrap(), second),
+        ];
+        assert_eq!(capped, in_flight);
+        assert_eq!(client.in_flight(), in_flight);
+    }
+
+    #[tokio::test]
+    async fn breaching_transactions_are_sped_up_with_auto_bump() {
+        let chain = SimulatedChain::new(2);
+        let clock = MockClock::new();
+        let client = client(&chain)
+            .with_clock(clock.clone())
+            .with_sla(Sla::within(Duration::from_secs(30)).with_auto_bump());
+        let original = send(&client).await;
+
+        clock.advance(Duration::from_secs(31));
+        client.poll_in_flight(Duration::MAX).await.unwrap();
+        let [(nonce, tx_hash)] = client.in_flight()[..] else {
+            panic!("expected one transaction in flight");
+        };
+        assert_eq!(nonce, Nonce::zero());
+        assert_ne!(tx_hash, original);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{
+    in_flight::InFlight, scheduler::Schedule, LockedNonceManagerMiddleware, Nonce,
+    NonceManagerError, SendEndpoint,
+};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Bytes, TxHash, U64};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Point-in-time copy of the nonce state of an address, used to hand over nonce ownership
+/// explicitly between deployments, e.g. from the blue to the green relayer
+pub struct StateSnapshot {
+    /// The address whose nonce was tracked
+    pub address: Address,
+    /// The next nonce to be assigned, `None` if the counter was never initialized
+    pub nonce: Option<Nonce>,
+    /// Whether the counter has to be resynced with the chain before the next assignment,
+    /// because a send was cancelled while its transaction may have been broadcast
+    #[serde(default)]
+    pub needs_resync: bool,
+    /// Nonces reserved ahead of time which have not been broadcast yet
+    #[serde(default)]
+    pub reservations: Vec<ReservedNonce>,
+    /// Transactions broadcast which have not been confirmed yet
+    #[serde(default)]
+    pub in_flight: Vec<(Nonce, TxHash)>,
+    /// The ones among them submitted through a [`crate::PrivateRelay`], whose nonce is
+    /// reclaimed if the relay drops them
+    #[serde(default)]
+    pub private: Vec<PrivateTransaction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A reserved nonce, along with its signed transaction once scheduled
+pub struct ReservedNonce {
+    /// The reserved nonce
+    pub nonce: Nonce,
+    /// The signed transaction to broadcast at `nonce`, if scheduled
+    #[serde(default)]
+    pub raw_tx: Option<Bytes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A transaction submitted through a [`crate::PrivateRelay`]
+pub struct PrivateTransaction {
+    /// The nonce of the transaction
+    pub nonce: Nonce,
+    /// The hash of the transaction
+    pub tx_hash: TxHash,
+    /// The last block the relay may include the transaction in
+    pub max_block_number: U64,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Takes a snapshot of the counter, the reservations and the in-flight transactions. It
+    /// waits for sends holding the counter to finish, so the parts are consistent with each
+    /// other.
+    pub async fn snapshot(&self) -> StateSnapshot {
+        let nonce = self.lock_nonce().await;
+        let schedule = self.state.schedule().lock().await;
+        self.snapshot_of(*nonce, &schedule)
+    }
+
+    /// The snapshot of the state with the counter at `nonce`, which has to be called with
+    /// the nonce and schedule locks held
+    pub(crate) fn snapshot_of(&self, nonce: Nonce, schedule: &Schedule) -> StateSnapshot {
+        StateSnapshot {
+            address: self.address,
+            nonce: self.state.is_initialized().then_some(nonce),
+            needs_resync: self.state.needs_resync(),
+            reservations: schedule
+                .iter()
+                .map(|(nonce, raw_tx)| ReservedNonce {
+                    nonce: *nonce,
+                    raw_tx: raw_tx.clone(),
+                })
+                .collect(),
+            in_flight: self.in_flight(),
+            private: schedule
+                .private()
+                .into_iter()
+                .map(|(nonce, tx_hash, max_block_number)| PrivateTransaction {
+                    nonce,
+                    tx_hash,
+                    max_block_number,
+                })
+                .collect(),
+        }
+    }
+
+    /// Replaces the nonce state with `snapshot`, which has to belong to the managed address.
+    /// The in-flight transactions are tracked as if they had just been broadcast, so their
+    /// [`crate::NonceEvent::Stuck`] timers start over.
+    pub async fn restore(&self, snapshot: StateSnapshot) -> Result<(), NonceManagerError<M>> {
+        if snapshot.address != self.address {
+            return Err(NonceManagerError::SnapshotAddressMismatch(snapshot.address));
+        }
+
+        let mut nonce = self.lock_nonce().await;
+        let mut schedule = self.state.schedule().lock().await;
+        match snapshot.nonce {
+            Some(restored) => {
+                *nonce = restored;
+                self.state.set_initialized();
+            }
+            None => {
+                *nonce = Nonce::zero();
+                self.state.clear_initialized();
+            }
+        }
+        if snapshot.needs_resync {
+            self.state.mark_needs_resync();
+        } else {
+            self.state.clear_needs_resync();
+        }
+        *schedule = snapshot
+            .reservations
+            .into_iter()
+            .map(|reserved| (reserved.nonce, reserved.raw_tx))
+            .collect();
+
+        let mut in_flight = InFlight::default();
+        for (nonce, tx_hash) in snapshot.in_flight {
+            in_flight.insert(nonce, tx_hash, None, None, self.now(), self.unix_secs());
+        }
+        for private in snapshot.private {
+            schedule.submit_private(private.nonce, private.tx_hash, private.max_block_number);
+            if let Some(tracked) = in_flight.get_mut(private.nonce) {
+                tracked.endpoint = SendEndpoint::Private;
+            }
+        }
+        *self.state.in_flight() = in_flight;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use super::*;
+    use crate::testkit::fixtures::{client, send};
+    use crate::SimulatedChain;
+
+    #[tokio::test]
+    async fn restores_what_was_snapshotted() {
+        let chain = SimulatedChain::new(1);
+        let (client, restored) = (client(&chain), client(&chain));
+        send(&client).await;
+        let tx_hash = send(&client).await;
+        let reserved = client.reserve_nonce(None).await.unwrap();
+        let snapshot = client.snapshot().await;
+        assert_eq!(snapshot.nonce, Nonce::try_from(3u64).ok());
+        assert_eq!(
+            snapshot.reservations,
+            [ReservedNonce {
+                nonce: reserved,
+                raw_tx: None
+            }]
+        );
+        assert!(snapshot
+            .in_flight
+            .contains(&(reserved.checked_sub(1).unwrap(), tx_hash)));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        restored
+            .restore(serde_json::from_str(&json).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(restored.snapshot().await, snapshot);
+    }
+
+    #[tokio::test]
+    async fn snapshots_of_other_addresses_are_refused() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain);
+        let snapshot = StateSnapshot {
+            address: Address::repeat_byte(2),
+            ..client.snapshot().await
+        };
+        let err = client.restore(snapshot).await.unwrap_err();
+        assert!(matches!(
+            err,
+            NonceManagerError::SnapshotAddressMismatch(address) if address == Address::repeat_byte(2)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::NonceManagerError;
+use async_trait::async_trait;
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{Address, BlockId, U256};
+use futures_util::future::join_all;
+use std::fmt::Debug;
+
+/// Where the authoritative chain nonce of the managed address comes from. It is consulted to
+/// initialize the counter, when resyncing and when checking for conflicts after a failed
+/// send. Implementations can read it from an indexer, the txpool or an archive node instead
+/// of the node the transactions are sent through.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait NonceSource<M: Middleware>: Send + Sync + Debug {
+    /// The number of transactions sent from `address` as of `block`, where `None` is left to
+    /// the source's default. `inner` is the middleware wrapped by the nonce manager.
+    async fn transaction_count(
+        &self,
+        inner: &M,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> Result<U256, NonceManagerError<M>>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// The default [`NonceSource`], asking the inner middleware for `eth_getTransactionCount`
+pub struct TransactionCount;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M: Middleware> NonceSource<M> for TransactionCount {
+    async fn transaction_count(
+        &self,
+        inner: &M,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> Result<U256, NonceManagerError<M>> {
+        inner
+            .get_transaction_count(address, block)
+            .await
+            .map_err(FromErr::from)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A [`NonceSource`] taking the highest transaction count reported by the inner middleware
+/// and a list of additional nodes, e.g. the endpoints behind a `QuorumProvider`, so that a
+/// single lagging RPC cannot seed a stale nonce. Nodes which fail to answer are skipped as
+/// long as at least one of them does.
+pub struct HighestTransactionCount<N> {
+    nodes: Vec<N>,
+}
+
+impl<N> HighestTransactionCount<N> {
+    /// Queries `nodes` alongside the inner middleware
+    pub fn new(nodes: Vec<N>) -> Self {
+        Self { nodes }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M, N> NonceSource<M> for HighestTransactionCount<N>
+where
+    M: Middleware,
+    N: Middleware + 'static,
+    N::Error: 'static,
+{
+    async fn transaction_count(
+        &self,
+        inner: &M,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> Result<U256, NonceManagerError<M>> {
+        let (mut highest, mut error) = match inner.get_transaction_count(address, block).await {
+            Ok(count) => (Some(count), None),
+            Err(err) => (None, Some(FromErr::from(err))),
+        };
+
+        let counts = join_all(
+            self.nodes
+                .iter()
+                .map(|node| node.get_transaction_count(address, block)),
+        )
+        .await;
+        for count in counts {
+            match count {
+                Ok(count) => highest = highest.max(Some(count)),
+                Err(err) if error.is_none() => {
+                    error = Some(NonceManagerError::SourceError(Box::new(err)))
+                }
+                Err(_) => {}
+            }
+        }
+
+        highest.ok_or_else(|| error.expect("the inner middleware is always queried"))
+    }
+}
@@ -0,0 +1,161 @@
+use crate::sync::{
+    Arc, AtomicBool, Mutex, Ordering, RwLock, StdMutex, StdMutexGuard as MutexGuard,
+};
+use crate::{in_flight::InFlight, resync::SingleFlight, scheduler::Schedule, Nonce};
+use ethers::types::Address;
+
+#[derive(Debug)]
+struct NonceState {
+    address: Address,
+    initialized: AtomicBool,
+    needs_resync: AtomicBool,
+    offline: AtomicBool,
+    nonce: RwLock<Nonce>,
+    schedule: Mutex<Schedule>,
+    in_flight: StdMutex<InFlight>,
+    resync: SingleFlight,
+}
+
+#[derive(Debug, Clone)]
+/// Handle to the nonce state of a single address. Cloning the handle is cheap and every
+/// clone refers to the same counter, which allows several middleware stacks (e.g. one over
+/// HTTP for sending and one over WebSockets for subscriptions) to hand out nonces for the
+/// same key without stepping on each other.
+pub struct SharedNonceState {
+    inner: Arc<NonceState>,
+}
+
+impl SharedNonceState {
+    /// Creates a new, uninitialized nonce state for `address`
+    pub fn new(address: Address) -> Self {
+        Self {
+            inner: Arc::new(NonceState {
+                address,
+                initialized: AtomicBool::new(false),
+                needs_resync: AtomicBool::new(false),
+                offline: AtomicBool::new(false),
+                nonce: RwLock::new(Nonce::zero()),
+                schedule: Mutex::new(Schedule::default()),
+                in_flight: StdMutex::new(InFlight::default()),
+                resync: SingleFlight::default(),
+            }),
+        }
+    }
+
+    /// The address whose nonce is being tracked
+    pub fn address(&self) -> Address {
+        self.inner.address
+    }
+
+    /// Returns true once the nonce has been fetched from the chain
+    pub fn is_initialized(&self) -> bool {
+        self.inner.initialized.load(Ordering::SeqCst)
+    }
+
+    /// Returns the next nonce to be used
+    pub async fn next(&self) -> Nonce {
+        *self.inner.nonce.read().await
+    }
+
+    /// Returns the next nonce to be used without waiting, or `None` if the counter isn't
+    /// initialized yet or is held, e.g. by a send in progress
+    pub fn try_next(&self) -> Option<Nonce> {
+        if !self.is_initialized() {
+            return None;
+        }
+        self.inner.nonce.try_read().ok().map(|nonce| *nonce)
+    }
+
+    /// Returns true if both handles refer to the same underlying state
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    pub(crate) fn set_initialized(&self) {
+        self.inner.initialized.store(true, Ordering::SeqCst);
+    }
+
+    /// Initializes the counter at `nonce` without asking the chain, waiting for assignments
+    /// in progress to finish
+    pub(crate) async fn seed(&self, nonce: Nonce) {
+        *self.inner.nonce.write().await = nonce;
+        self.set_initialized();
+    }
+
+    pub(crate) fn clear_initialized(&self) {
+        self.inner.initialized.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn needs_resync(&self) -> bool {
+        self.inner.needs_resync.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn mark_needs_resync(&self) {
+        self.inner.needs_resync.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn clear_needs_resync(&self) {
+        self.inner.needs_resync.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_offline(&self) -> bool {
+        self.inner.offline.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn mark_offline(&self) {
+        self.inner.offline.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn clear_offline(&self) {
+        self.inner.offline.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns a guard which flags the state for a resync when dropped without being
+    /// disarmed, covering the window where a transaction may have reached the node while the
+    /// counter has not been advanced yet
+    pub(crate) fn broadcast_guard(&self) -> BroadcastGuard<'_> {
+        BroadcastGuard {
+            state: self,
+            armed: true,
+        }
+    }
+
+    pub(crate) fn nonce(&self) -> &RwLock<Nonce> {
+        &self.inner.nonce
+    }
+
+    pub(crate) fn schedule(&self) -> &Mutex<Schedule> {
+        &self.inner.schedule
+    }
+
+    pub(crate) fn resync(&self) -> &SingleFlight {
+        &self.inner.resync
+    }
+
+    pub(crate) fn in_flight(&self) -> MutexGuard<'_, InFlight> {
+        self.inner
+            .in_flight
+            .lock()
+            .expect("in-flight lock poisoned")
+    }
+}
+
+pub(crate) struct BroadcastGuard<'a> {
+    state: &'a SharedNonceState,
+    armed: bool,
+}
+
+impl BroadcastGuard<'_> {
+    /// Called once the outcome of the broadcast is reflected in the counter
+    pub(crate) fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for BroadcastGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.state.mark_needs_resync();
+        }
+    }
+}
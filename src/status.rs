@@ -0,0 +1,148 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{TxHash, U64};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+/// Where a nonce stands in its lifecycle, according to the manager and the chain
+pub enum NonceStatus {
+    /// The nonce hasn't been handed out yet
+    Unassigned,
+    /// The nonce is reserved, and a signed transaction scheduled at it if `scheduled`
+    Reserved { scheduled: bool },
+    /// A transaction was broadcast at the nonce and is waiting to be mined
+    Broadcast {
+        tx_hash: TxHash,
+        /// How many times the transaction was replaced, e.g. sped up
+        attempts: u32,
+    },
+    /// The nonce was used on chain, by the tracked transaction if its block is known
+    Mined { block_number: Option<U64> },
+    /// The nonce was used on chain by a transaction the manager didn't track
+    Replaced,
+    /// The node no longer knows the transaction at the nonce, which is still unused
+    Dropped,
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Where `nonce` stands in its lifecycle, for building retry logic or UIs on top of the
+    /// manager's state. Reservations and unassigned nonces are answered locally, while
+    /// broadcast transactions are looked up on chain to tell whether they were mined,
+    /// replaced by another transaction or dropped by the node.
+    pub async fn status(&self, nonce: Nonce) -> Result<NonceStatus, NonceManagerError<M>> {
+        let reserved = self.state.schedule().lock().await.scheduled(nonce);
+        match reserved {
+            Some(scheduled) => Ok(NonceStatus::Reserved { scheduled }),
+            None => self.broadcast_status(nonce).await,
+        }
+    }
+
+    /// The status of a nonce which isn't reserved, looked up from the receipt of the
+    /// transaction broadcast at it and the mined transaction count
+    pub(crate) async fn broadcast_status(
+        &self,
+        nonce: Nonce,
+    ) -> Result<NonceStatus, NonceManagerError<M>> {
+        let tracked = self
+            .state
+            .in_flight()
+            .get(nonce)
+            .map(|tx| (tx.tx_hash, tx.attempt));
+        let mined_count = self
+            .inner
+            .get_transaction_count(self.address, None)
+            .await
+            .map_err(FromErr::from)?;
+
+        let (tx_hash, attempts) = match tracked {
+            Some(tracked) => tracked,
+            // no longer tracked, either confirmed or forgotten after a reconnect
+            None if nonce.as_u256() < mined_count => {
+                return Ok(NonceStatus::Mined { block_number: None })
+            }
+            None if nonce >= self.next().await => return Ok(NonceStatus::Unassigned),
+            None => return Ok(NonceStatus::Dropped),
+        };
+        let receipt = self.fetch_receipt(nonce, tx_hash).await?;
+        if let Some(receipt) = receipt {
+            return Ok(NonceStatus::Mined {
+                block_number: receipt.block_number,
+            });
+        }
+        if nonce.as_u256() < mined_count {
+            return Ok(NonceStatus::Replaced);
+        }
+
+        let known = self
+            .inner
+            .get_transaction(tx_hash)
+            .await
+            .map_err(FromErr::from)?
+            .is_some();
+        Ok(if known {
+            NonceStatus::Broadcast { tx_hash, attempts }
+        } else {
+            NonceStatus::Dropped
+        })
+    }
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use crate::testkit::fixtures::{client, send, SENDER};
+    use crate::{Nonce, NonceStatus, SimulatedChain};
+
+    #[tokio::test]
+    async fn statuses_follow_the_nonce_through_its_lifecycle() {
+        let chain = SimulatedChain::new(1);
+        let client = client(&chain);
+        let nonce = Nonce::zero();
+        assert_eq!(client.status(nonce).await.unwrap(), NonceStatus::Unassigned);
+
+        let tx_hash = send(&client).await;
+        assert_eq!(
+            client.status(nonce).await.unwrap(),
+            NonceStatus::Broadcast {
+                tx_hash,
+                attempts: 0
+            }
+        );
+
+        let reserved = client.reserve_nonce(None).await.unwrap();
+        assert_eq!(
+            client.status(reserved).await.unwrap(),
+            NonceStatus::Reserved { scheduled: false }
+        );
+
+        chain.mine();
+        assert_eq!(
+            client.status(nonce).await.unwrap(),
+            NonceStatus::Mined {
+                block_number: Some(1.into())
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn lost_transactions_are_told_apart() {
+        let chain = SimulatedChain::new(2);
+        let client = client(&chain);
+        send(&client).await;
+        send(&client).await;
+
+        chain.drop_pending(SENDER, 0);
+        let dropped = Nonce::zero().checked_increment().unwrap();
+        assert_eq!(client.status(dropped).await.unwrap(), NonceStatus::Dropped);
+
+        chain.send_external(SENDER);
+        chain.mine();
+        assert_eq!(
+            client.status(Nonce::zero()).await.unwrap(),
+            NonceStatus::Replaced
+        );
+    }
+}
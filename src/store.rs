@@ -0,0 +1,533 @@
+use crate::durability::Syncer;
+use crate::sync::{AtomicU64, Mutex, Ordering, StdMutex, StdMutexGuard};
+use crate::{
+    Durability, LockedNonceManagerMiddleware, Nonce, NonceManagerError, PrivateTransaction,
+    ReservedNonce, StateCipher, StateSnapshot,
+};
+use ethers::providers::Middleware;
+use ethers::types::{Bytes, TxHash, U64};
+use ethers::utils::hex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Keeps the latest [`StateSnapshot`] of a manager, so that a restarted process picks up where
+/// the previous one stopped instead of resyncing from the chain
+pub trait StateStore: Send + Sync + Debug {
+    /// The snapshot saved last, if any
+    fn load(&self) -> io::Result<Option<StateSnapshot>>;
+
+    /// Replaces the saved snapshot with `snapshot`, which covers every journal entry
+    /// appended so far, so the journal can be cleared
+    fn save(&self, snapshot: &StateSnapshot) -> io::Result<()>;
+
+    /// Appends `entry` to the journal of changes since the snapshot saved last. Stores
+    /// without a journal ignore it, and only recover their snapshots.
+    fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let _ = entry;
+        Ok(())
+    }
+
+    /// The journal entries appended since the snapshot saved last, in order
+    fn journal(&self) -> io::Result<Vec<JournalEntry>> {
+        Ok(Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+/// A change to the nonce state recorded in the journal of a [`StateStore`] between two
+/// snapshots, see [`LockedNonceManagerMiddleware::with_checkpoint_interval`] and
+/// [`LockedNonceManagerMiddleware::with_write_ahead_log`]
+pub enum JournalEntry {
+    /// The counter moved past `nonce`, which was handed out without a reservation
+    Assigned { nonce: Nonce },
+    /// `nonce` was reserved, moving the counter past it
+    Reserved { nonce: Nonce },
+    /// The signed transaction `raw_tx` was scheduled at the reserved `nonce`
+    Scheduled { nonce: Nonce, raw_tx: Bytes },
+    /// The reservation of `nonce` was given up without a broadcast. `rolled_back` tells
+    /// whether the counter was rolled back to it.
+    Released { nonce: Nonce, rolled_back: bool },
+    /// The transaction `tx_hash` is about to be broadcast at `nonce`
+    Broadcasting { nonce: Nonce, tx_hash: TxHash },
+    /// The transaction `tx_hash` was broadcast at `nonce`
+    Broadcast { nonce: Nonce, tx_hash: TxHash },
+    /// The transaction `tx_hash` broadcast at `nonce` went through a
+    /// [`crate::PrivateRelay`], which drops it after `max_block_number`
+    Private {
+        nonce: Nonce,
+        tx_hash: TxHash,
+        max_block_number: U64,
+    },
+}
+
+impl StateSnapshot {
+    /// Brings the snapshot up to date with the journal appended after it was taken. If a
+    /// broadcast was started but isn't known to have gone through, the counter is flagged
+    /// to be resynced with the chain, which knows whether it did.
+    fn replay(&mut self, journal: &[JournalEntry]) {
+        let mut maybe_broadcast = Vec::new();
+        for entry in journal {
+            match entry {
+                JournalEntry::Assigned { nonce } => {
+                    self.reservations
+                        .retain(|reserved| reserved.nonce != *nonce);
+                    self.advance_past(*nonce);
+                }
+                JournalEntry::Reserved { nonce } => {
+                    self.forget_private(*nonce);
+                    self.reservations
+                        .retain(|reserved| reserved.nonce != *nonce);
+                    self.reservations.push(ReservedNonce {
+                        nonce: *nonce,
+                        raw_tx: None,
+                    });
+                    self.reservations
+                        .sort_unstable_by_key(|reserved| reserved.nonce);
+                    self.advance_past(*nonce);
+                }
+                JournalEntry::Scheduled { nonce, raw_tx } => {
+                    let reserved = self
+                        .reservations
+                        .iter_mut()
+                        .find(|reserved| reserved.nonce == *nonce);
+                    if let Some(reserved) = reserved {
+                        reserved.raw_tx = Some(raw_tx.clone());
+                    }
+                }
+                JournalEntry::Released { nonce, rolled_back } => {
+                    self.forget_private(*nonce);
+                    self.reservations
+                        .retain(|reserved| reserved.nonce != *nonce);
+                    if *rolled_back {
+                        self.nonce = Some(*nonce);
+                    }
+                }
+                JournalEntry::Broadcasting { nonce, tx_hash } => {
+                    maybe_broadcast.push((*nonce, *tx_hash));
+                }
+                JournalEntry::Broadcast { nonce, tx_hash } => {
+                    let (nonce, tx_hash) = (*nonce, *tx_hash);
+                    maybe_broadcast.retain(|started| *started != (nonce, tx_hash));
+                    self.in_flight.retain(|(sent, _)| *sent != nonce);
+                    self.in_flight.push((nonce, tx_hash));
+                    self.in_flight.sort_unstable_by_key(|(nonce, _)| *nonce);
+                    self.reservations.retain(|reserved| reserved.nonce != nonce);
+                    self.advance_past(nonce);
+                }
+                JournalEntry::Private {
+                    nonce,
+                    tx_hash,
+                    max_block_number,
+                } => {
+                    self.private.retain(|private| private.nonce != *nonce);
+                    self.private.push(PrivateTransaction {
+                        nonce: *nonce,
+                        tx_hash: *tx_hash,
+                        max_block_number: *max_block_number,
+                    });
+                    self.private.sort_unstable_by_key(|private| private.nonce);
+                }
+            }
+        }
+        if !maybe_broadcast.is_empty() {
+            self.needs_resync = true;
+        }
+    }
+
+    /// Stops tracking the private transaction at `nonce`, whose nonce was reclaimed after
+    /// the relay dropped it
+    fn forget_private(&mut self, nonce: Nonce) {
+        if self.private.iter().any(|private| private.nonce == nonce) {
+            self.private.retain(|private| private.nonce != nonce);
+            self.in_flight.retain(|(sent, _)| *sent != nonce);
+        }
+    }
+
+    /// Moves the counter past `nonce`, unless it already is
+    fn advance_past(&mut self, nonce: Nonce) {
+        if let Some(next) = nonce.checked_increment() {
+            self.nonce = Some(self.nonce.map_or(next, |counter| counter.max(next)));
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// The journal entries recorded but not written yet, and the number written since the last
+/// checkpoint. Entries are recorded under the nonce and schedule locks, and written once
+/// they are released, so that no send waits on the store.
+pub(crate) struct Checkpoints {
+    since: AtomicU64,
+    unwritten: StdMutex<Vec<JournalEntry>>,
+    /// Held while writing entries, so that they reach the store in the order they were
+    /// recorded
+    writing: Mutex<()>,
+}
+
+impl Checkpoints {
+    fn unwritten(&self) -> StdMutexGuard<'_, Vec<JournalEntry>> {
+        self.unwritten.lock().expect("journal lock poisoned")
+    }
+
+    /// Puts back `entries` which couldn't be written, ahead of the ones recorded since
+    fn requeue(&self, mut entries: Vec<JournalEntry>) {
+        let mut unwritten = self.unwritten();
+        entries.append(&mut unwritten);
+        *unwritten = entries;
+    }
+}
+
+#[derive(Debug)]
+/// A [`StateStore`] keeping the snapshot as JSON in a file. Saving writes a temporary file
+/// next to it, syncs it and renames it over the previous one, so a crash leaves either the
+/// old or the new snapshot behind. The journal is kept as JSON lines in a file of the same
+/// name with a `.journal` extension appended, synced after every entry. How often journal
+/// entries are synced is set with [`Self::with_durability`], while snapshots are always
+/// synced, along with their directory, before the journal they cover is cleared.
+pub struct FileStateStore {
+    path: PathBuf,
+    cipher: Option<Arc<dyn StateCipher>>,
+    syncer: Syncer,
+}
+
+impl FileStateStore {
+    /// A store at `path`. The file is created on the first save.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            cipher: None,
+            syncer: Syncer::default(),
+        }
+    }
+
+    /// A store at `path` like [`Self::new`], with the snapshot encrypted by `cipher` and hex
+    /// encoded
+    pub fn new_encrypted(path: impl AsRef<Path>, cipher: impl StateCipher + 'static) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            cipher: Some(Arc::new(cipher)),
+            syncer: Syncer::default(),
+        }
+    }
+
+    /// Syncs journal entries as `durability` requires, instead of every time
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.syncer = Syncer::new(durability);
+        self
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".journal");
+        path.into()
+    }
+
+    /// Encrypts and hex encodes `contents` if a cipher is set
+    fn seal(&self, contents: Vec<u8>) -> io::Result<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => {
+                let ciphertext = cipher.encrypt(&contents).map_err(invalid_data)?;
+                Ok(hex::encode(ciphertext).into_bytes())
+            }
+            None => Ok(contents),
+        }
+    }
+
+    /// Reverses [`Self::seal`]
+    fn unseal(&self, contents: Vec<u8>) -> io::Result<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => {
+                let ciphertext = hex::decode(&contents).map_err(invalid_data)?;
+                cipher.decrypt(&ciphertext).map_err(invalid_data)
+            }
+            None => Ok(contents),
+        }
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn load(&self) -> io::Result<Option<StateSnapshot>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let contents = self.unseal(contents)?;
+        Ok(Some(serde_json::from_slice(&contents)?))
+    }
+
+    fn save(&self, snapshot: &StateSnapshot) -> io::Result<()> {
+        let contents = self.seal(serde_json::to_vec(snapshot)?)?;
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&contents)?;
+        // whatever the durability, the snapshot has to be on disk before it replaces the
+        // previous one and the journal is cleared, or a crash could lose both
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        sync_dir(&self.path)?;
+        match fs::remove_file(self.journal_path()) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = self.seal(serde_json::to_vec(entry)?)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.journal_path())?;
+        file.write_all(&line)?;
+        self.syncer.sync_data(&file)
+    }
+
+    fn journal(&self) -> io::Result<Vec<JournalEntry>> {
+        let contents = match fs::read(self.journal_path()) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let lines: Vec<_> = contents
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .collect();
+        let mut entries = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            let entry = self
+                .unseal(line.to_vec())
+                .and_then(|line| Ok(serde_json::from_slice(&line)?));
+            match entry {
+                Ok(entry) => entries.push(entry),
+                // a crash in the middle of an append tears the last line only
+                Err(_) if i + 1 == lines.len() => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Syncs the directory holding `path`, so that a rename into it survives a crash
+fn sync_dir(path: &Path) -> io::Result<()> {
+    // directories can't be opened as files on Windows, where renames are durable already
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn invalid_data(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Persists the nonce state to `store` whenever [`Self::persist`] or [`Self::shutdown`]
+    /// is called, and on every checkpoint with [`Self::with_checkpoint_interval`]. Nothing is
+    /// persisted by default.
+    pub fn with_state_store(mut self, store: impl StateStore + 'static) -> Self {
+        self.state_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Saves a snapshot to the state store every `every` journal entries instead of only when
+    /// persisting explicitly, journaling the nonces assigned, reserved, scheduled, released
+    /// and broadcast in between, which is cheaper than a snapshot for every nonce.
+    /// [`Self::restore_from_store`] replays the journal on top of the snapshot saved last.
+    /// Entries are written and checkpoints taken once the call making the change is done and
+    /// the locks are released, and a checkpoint which fails is retried after the next one. As
+    /// a crash may lose the entries not written yet, the restored counter is resynced with
+    /// the chain.
+    pub fn with_checkpoint_interval(mut self, every: NonZeroUsize) -> Self {
+        self.config_mut().checkpoint_every = Some(every);
+        self
+    }
+
+    /// Loads the snapshot saved in the state store into the manager, like [`Self::restore`],
+    /// with the journal appended since replayed on top of it. Returns whether there was
+    /// anything to load. With [`Self::with_checkpoint_interval`], the entries of a send are
+    /// written after its broadcast, so a crash in between may have lost them: the restored
+    /// counter is then resynced with the chain before its next assignment.
+    pub async fn restore_from_store(&self) -> Result<bool, NonceManagerError<M>> {
+        let store = match &self.state_store {
+            Some(store) => store,
+            None => return Ok(false),
+        };
+        let snapshot = store.load().map_err(NonceManagerError::StateStoreError)?;
+        let journal = store
+            .journal()
+            .map_err(NonceManagerError::StateStoreError)?;
+        if snapshot.is_none() && journal.is_empty() {
+            return Ok(false);
+        }
+
+        let mut snapshot = snapshot.unwrap_or_else(|| StateSnapshot {
+            address: self.address,
+            nonce: None,
+            needs_resync: false,
+            reservations: Vec::new(),
+            in_flight: Vec::new(),
+            private: Vec::new(),
+        });
+        snapshot.replay(&journal);
+        if self.config().checkpoint_every.is_some() {
+            snapshot.needs_resync = true;
+        }
+        self.restore(snapshot).await.map(|()| true)
+    }
+
+    /// Saves a [`Self::snapshot`] to the state store, if one is configured
+    pub async fn persist(&self) -> Result<(), NonceManagerError<M>> {
+        let store = match &self.state_store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        // no entry is written while saving, so none is cleared from the journal without being
+        // covered by the snapshot. Saving under the locks keeps the write-ahead log, which is
+        // written under them, from doing so.
+        let _writing = self.checkpoints.writing.lock().await;
+        let nonce = self.lock_nonce().await;
+        let schedule = self.state.schedule().lock().await;
+        // the entries not written yet record changes made before the snapshot is taken
+        let covered = std::mem::take(&mut *self.checkpoints.unwritten());
+        let saved = store.save(&self.snapshot_of(*nonce, &schedule));
+        if let Err(err) = saved {
+            self.checkpoints.requeue(covered);
+            return Err(NonceManagerError::StateStoreError(err));
+        }
+        self.checkpoints.since.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Records `entry` for the journal if checkpointing or the write-ahead log is enabled.
+    /// It is written by the next [`Self::checkpoint_if_due`].
+    pub(crate) fn journal(&self, entry: JournalEntry) {
+        let config = self.config();
+        if self.state_store.is_some() && (config.checkpoint_every.is_some() || config.write_ahead) {
+            self.checkpoints.unwritten().push(entry);
+        }
+    }
+
+    /// Writes the journal entries recorded since the last call, then saves a snapshot if the
+    /// checkpoint interval's worth of entries was journaled since the last one. If the journal
+    /// can't be written, the checkpoint is due right away. Has to be called without holding
+    /// the nonce or schedule lock.
+    pub(crate) async fn checkpoint_if_due(&self) {
+        let config = self.config();
+        if let Some(store) = &self.state_store {
+            let _writing = self.checkpoints.writing.lock().await;
+            let mut entries = std::mem::take(&mut *self.checkpoints.unwritten());
+            let written = entries
+                .iter()
+                .take_while(|entry| store.append(entry).is_ok())
+                .count();
+            self.checkpoints
+                .since
+                .fetch_add(written as u64, Ordering::SeqCst);
+            if written < entries.len() {
+                // kept for the snapshot to cover
+                self.checkpoints.requeue(entries.split_off(written));
+                if let Some(every) = config.checkpoint_every {
+                    self.checkpoints
+                        .since
+                        .store(every.get() as u64, Ordering::SeqCst);
+                }
+            }
+        }
+        let every = match config.checkpoint_every {
+            Some(every) => every.get() as u64,
+            None => return,
+        };
+        if self.checkpoints.since.load(Ordering::SeqCst) >= every {
+            // the journal still covers the broadcasts until a later checkpoint goes through
+            let _ = self.persist().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Address;
+
+    fn nonce(n: u64) -> Nonce {
+        Nonce::try_from(n).unwrap()
+    }
+
+    fn snapshot() -> StateSnapshot {
+        StateSnapshot {
+            address: Address::zero(),
+            nonce: Some(nonce(1)),
+            needs_resync: false,
+            reservations: Vec::new(),
+            in_flight: Vec::new(),
+            private: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finished_broadcasts_are_replayed() {
+        let tx_hash = TxHash::repeat_byte(1);
+        let mut replayed = snapshot();
+        replayed.replay(&[
+            JournalEntry::Broadcasting {
+                nonce: nonce(1),
+                tx_hash,
+            },
+            JournalEntry::Broadcast {
+                nonce: nonce(1),
+                tx_hash,
+            },
+        ]);
+        assert!(!replayed.needs_resync);
+        assert_eq!(replayed.nonce, Some(nonce(2)));
+        assert_eq!(replayed.in_flight, [(nonce(1), tx_hash)]);
+    }
+
+    #[test]
+    fn unfinished_broadcasts_need_a_resync() {
+        let mut replayed = snapshot();
+        replayed.replay(&[
+            JournalEntry::Broadcasting {
+                nonce: nonce(1),
+                tx_hash: TxHash::repeat_byte(1),
+            },
+            // a replacement finishing doesn't vouch for the original
+            JournalEntry::Broadcast {
+                nonce: nonce(1),
+                tx_hash: TxHash::repeat_byte(2),
+            },
+        ]);
+        assert!(replayed.needs_resync);
+
+        let mut replayed = snapshot();
+        replayed.replay(&[JournalEntry::Broadcasting {
+            nonce: nonce(1),
+            tx_hash: TxHash::repeat_byte(1),
+        }]);
+        assert!(replayed.needs_resync);
+        assert_eq!(replayed.nonce, Some(nonce(1)));
+        assert!(replayed.in_flight.is_empty());
+    }
+}
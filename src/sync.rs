@@ -0,0 +1,142 @@
+//! Synchronization primitives behind the nonce state. Under `cfg(loom)` they are swapped for
+//! loom's, so that the interleavings of concurrent initialization, assignment and recovery
+//! can be model checked, see `tests/loom.rs`.
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard,
+};
+#[cfg(not(loom))]
+pub(crate) use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
+
+#[cfg(loom)]
+pub(crate) use self::model::{Mutex, RwLock, RwLockWriteGuard};
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard,
+};
+
+#[cfg(loom)]
+mod model {
+    //! The async locks the crate uses, on top of loom's blocking ones. Every task of a loom
+    //! model runs on a thread of its own, so blocking until the lock is free is what awaiting
+    //! it amounts to from the model's point of view.
+
+    use loom::thread::{self, ThreadId};
+    use std::mem::ManuallyDrop;
+    use std::ops::{Deref, DerefMut};
+
+    #[derive(Debug)]
+    pub(crate) struct RwLock<T>(loom::sync::RwLock<T>);
+
+    #[derive(Debug)]
+    /// Returned by [`RwLock::try_write`] when the lock is held
+    pub(crate) struct WouldBlock;
+
+    impl<T> RwLock<T> {
+        pub(crate) fn new(value: T) -> Self {
+            Self(loom::sync::RwLock::new(value))
+        }
+
+        pub(crate) async fn read(&self) -> Guard<loom::sync::RwLockReadGuard<'_, T>> {
+            Guard::new(self.0.read().expect("loom lock poisoned"))
+        }
+
+        pub(crate) async fn write(&self) -> Guard<loom::sync::RwLockWriteGuard<'_, T>> {
+            Guard::new(self.0.write().expect("loom lock poisoned"))
+        }
+
+        pub(crate) fn try_read(
+            &self,
+        ) -> Result<Guard<loom::sync::RwLockReadGuard<'_, T>>, WouldBlock> {
+            self.0.try_read().map(Guard::new).map_err(|_| WouldBlock)
+        }
+
+        pub(crate) fn try_write(
+            &self,
+        ) -> Result<Guard<loom::sync::RwLockWriteGuard<'_, T>>, WouldBlock> {
+            self.0.try_write().map(Guard::new).map_err(|_| WouldBlock)
+        }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct Mutex<T>(loom::sync::Mutex<T>);
+
+    impl<T: Default> Default for Mutex<T> {
+        fn default() -> Self {
+            Self::new(T::default())
+        }
+    }
+
+    impl<T> Mutex<T> {
+        pub(crate) fn new(value: T) -> Self {
+            Self(loom::sync::Mutex::new(value))
+        }
+
+        pub(crate) async fn lock(&self) -> Guard<loom::sync::MutexGuard<'_, T>> {
+            Guard::new(self.0.lock().expect("loom lock poisoned"))
+        }
+    }
+
+    #[derive(Debug)]
+    /// A guard of one of loom's locks, which may only be used and dropped on the loom thread
+    /// which took it, checked on every access
+    pub(crate) struct Guard<G> {
+        guard: ManuallyDrop<G>,
+        thread: ThreadId,
+    }
+
+    pub(crate) type RwLockWriteGuard<'a, T> = Guard<loom::sync::RwLockWriteGuard<'a, T>>;
+
+    impl<G> Guard<G> {
+        fn new(guard: G) -> Self {
+            Self {
+                guard: ManuallyDrop::new(guard),
+                thread: thread::current().id(),
+            }
+        }
+
+        fn check_thread(&self) {
+            assert_eq!(
+                thread::current().id(),
+                self.thread,
+                "lock guard used on another thread than the one which took it"
+            );
+        }
+    }
+
+    // SAFETY: loom's guards aren't `Send`, as a lock has to be released by the thread which
+    // took it, but they are held across `.await`s, which the `Send` bounds of the middleware's
+    // futures require them to be. A `Guard` moved to another thread can't be used there, as
+    // every access checks the thread, and isn't released there either, see its `Drop`.
+    unsafe impl<G> Send for Guard<G> {}
+
+    impl<G> Drop for Guard<G> {
+        fn drop(&mut self) {
+            if thread::current().id() == self.thread {
+                // SAFETY: the guard isn't used after being dropped
+                unsafe { ManuallyDrop::drop(&mut self.guard) }
+            } else if !std::thread::panicking() {
+                panic!("lock guard dropped on another thread than the one which took it");
+            }
+        }
+    }
+
+    impl<G: Deref> Deref for Guard<G> {
+        type Target = G::Target;
+
+        fn deref(&self) -> &Self::Target {
+            self.check_thread();
+            &self.guard
+        }
+    }
+
+    impl<G: DerefMut> DerefMut for Guard<G> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            self.check_thread();
+            &mut self.guard
+        }
+    }
+}
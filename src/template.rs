@@ -0,0 +1,145 @@
+use crate::{LockedNonceManagerMiddleware, ManagedPendingTransaction, NonceManagerError};
+use ethers::abi::{Function, Token};
+use ethers::providers::Middleware;
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, Address, BlockId, Bytes, Eip1559TransactionRequest,
+    TransactionRequest, U256,
+};
+use serde_json::json;
+use std::{error::Error, fmt::Debug, sync::Arc};
+
+/// Builds the calldata of a [`TxTemplate`] from the parameters of a send
+pub trait CalldataBuilder: Send + Sync + Debug {
+    /// The calldata for `params`
+    fn build(&self, params: &[Token]) -> Result<Bytes, Box<dyn Error + Send + Sync>>;
+}
+
+/// Calls the function with `params` as its arguments
+impl CalldataBuilder for Function {
+    fn build(&self, params: &[Token]) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+        Ok(self.encode_input(params)?.into())
+    }
+}
+
+/// Always the same calldata, whatever the parameters
+impl CalldataBuilder for Bytes {
+    fn build(&self, _params: &[Token]) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+        Ok(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A transaction a bot sends over and over with different parameters, registered under a
+/// name with [`LockedNonceManagerMiddleware::with_template`] and sent with
+/// [`LockedNonceManagerMiddleware::send_template`]. Fees set as hints are used as is, the
+/// ones left unset are filled like for any other transaction.
+pub struct TxTemplate {
+    to: Address,
+    calldata: Arc<dyn CalldataBuilder>,
+    value: Option<U256>,
+    gas: Option<U256>,
+    gas_price: Option<U256>,
+    max_fees: Option<(U256, U256)>,
+}
+
+impl TxTemplate {
+    /// Calls `to` with the calldata `calldata` builds
+    pub fn new(to: Address, calldata: impl CalldataBuilder + 'static) -> Self {
+        Self {
+            to,
+            calldata: Arc::new(calldata),
+            value: None,
+            gas: None,
+            gas_price: None,
+            max_fees: None,
+        }
+    }
+
+    /// Sends `value` wei along
+    pub fn value(mut self, value: U256) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    /// Uses `gas` as the gas limit instead of estimating it
+    pub fn gas(mut self, gas: U256) -> Self {
+        self.gas = Some(gas);
+        self
+    }
+
+    /// Sends legacy transactions paying `gas_price`
+    pub fn gas_price(mut self, gas_price: U256) -> Self {
+        self.gas_price = Some(gas_price);
+        self.max_fees = None;
+        self
+    }
+
+    /// Sends EIP-1559 transactions with these fee caps
+    pub fn max_fees(mut self, max_fee_per_gas: U256, max_priority_fee_per_gas: U256) -> Self {
+        self.max_fees = Some((max_fee_per_gas, max_priority_fee_per_gas));
+        self.gas_price = None;
+        self
+    }
+
+    /// The transaction from `from` for `params`
+    fn build(
+        &self,
+        from: Address,
+        params: &[Token],
+    ) -> Result<TypedTransaction, Box<dyn Error + Send + Sync>> {
+        let data = self.calldata.build(params)?;
+        let mut tx: TypedTransaction = match self.max_fees {
+            Some((max_fee, priority_fee)) => Eip1559TransactionRequest::new()
+                .max_fee_per_gas(max_fee)
+                .max_priority_fee_per_gas(priority_fee)
+                .into(),
+            None => {
+                let mut tx = TransactionRequest::new();
+                tx.gas_price = self.gas_price;
+                tx.into()
+            }
+        };
+        tx.set_from(from);
+        tx.set_to(self.to);
+        tx.set_data(data);
+        if let Some(value) = self.value {
+            tx.set_value(value);
+        }
+        if let Some(gas) = self.gas {
+            tx.set_gas(gas);
+        }
+        Ok(tx)
+    }
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Registers `template` under `name`, replacing any template registered under it before
+    pub fn with_template(mut self, name: impl Into<String>, template: TxTemplate) -> Self {
+        self.templates.insert(name.into(), template);
+        self
+    }
+
+    /// Sends the transaction the template registered under `name` builds for `params`, like
+    /// any other managed transaction: it goes through the policies, nonce assignment and the
+    /// journal, and its events are labeled with `{"template": name}`. Fails with
+    /// [`NonceManagerError::UnknownTemplate`] if no template is registered under `name`.
+    pub async fn send_template(
+        &self,
+        name: &str,
+        params: &[Token],
+        block: Option<BlockId>,
+    ) -> Result<ManagedPendingTransaction<'_, M>, NonceManagerError<M>> {
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| NonceManagerError::UnknownTemplate(name.to_owned()))?;
+        let tx = template
+            .build(self.address, params)
+            .map_err(NonceManagerError::CalldataError)?;
+        self.send_labeled_transaction(tx, json!({ "template": name }), block)
+            .await
+    }
+}
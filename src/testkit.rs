@@ -0,0 +1,664 @@
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError};
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, Address, Block, BlockNumber, Bytes, Transaction,
+    TransactionReceipt, TxHash, H256, U256, U64,
+};
+use ethers::utils::{keccak256, rlp::Rlp};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+/// Chain id reported by the simulator, the same as anvil's
+const CHAIN_ID: u64 = 31337;
+
+/// Gas every simulated transaction uses
+const GAS_USED: u64 = 21_000;
+
+/// Seconds between the timestamps of consecutive blocks, starting from the time the chain was
+/// created at genesis
+const BLOCK_TIME_SECS: u64 = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SimTx {
+    hash: TxHash,
+    from: Address,
+    nonce: u64,
+    to: Option<Address>,
+    value: U256,
+    gas: U256,
+    gas_price: U256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    /// The request fails without reaching the node
+    Transport,
+    /// The node processes the request, but its response is lost
+    LostResponse,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// What `eth_call` answers on a [`SimulatedChain`], see [`SimulatedChain::set_call_outcome`]
+pub enum CallOutcome {
+    /// The call succeeds without return data
+    #[default]
+    Success,
+    /// The call reverts with the given data
+    Revert(Bytes),
+    /// The request fails without reaching the node
+    Unreachable,
+}
+
+#[derive(Debug)]
+struct SimState {
+    /// Mined blocks, block `n` at index `n - 1`
+    blocks: Vec<Vec<SimTx>>,
+    mempool: Vec<SimTx>,
+    faults: VecDeque<Fault>,
+    auto_mine: bool,
+    /// Probability that a pending transaction is dropped whenever a block is mined
+    drop_probability: f64,
+    /// Whether pending transactions are picked up in random order rather than arrival order
+    reorder: bool,
+    rng: u64,
+    sent: u64,
+    /// Timestamp of the genesis block, in seconds since the epoch
+    genesis_secs: u64,
+    /// Balances of the accounts which were given one, the others can pay for anything
+    balances: HashMap<Address, U256>,
+    call_outcome: CallOutcome,
+}
+
+impl SimState {
+    /// Next random number of a xorshift64* generator, deterministic for a given seed
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        // uniform in [0, 1) from the upper 53 bits
+        let sample = (self.random() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+
+    fn mined(&self) -> impl Iterator<Item = (u64, &SimTx)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(index, txs)| txs.iter().map(move |tx| (index as u64 + 1, tx)))
+    }
+
+    /// Transactions of `address` mined up to and including `block`
+    fn mined_count(&self, address: Address, block: u64) -> u64 {
+        self.mined()
+            .filter(|(number, tx)| *number <= block && tx.from == address)
+            .count() as u64
+    }
+
+    /// Mined transaction count of `address` plus the pending transactions following it
+    /// without a gap
+    fn pending_count(&self, address: Address) -> u64 {
+        let mut count = self.mined_count(address, u64::MAX);
+        while self
+            .mempool
+            .iter()
+            .any(|tx| tx.from == address && tx.nonce == count)
+        {
+            count += 1;
+        }
+        count
+    }
+
+    fn submit(&mut self, mut tx: SimTx) -> Result<TxHash, SimError> {
+        let mined = self.mined_count(tx.from, u64::MAX);
+        if tx.nonce < mined {
+            return Err(SimError::rejected("nonce too low"));
+        }
+        if let Some(balance) = self.balances.get(&tx.from) {
+            let cost = tx.gas.saturating_mul(tx.gas_price).saturating_add(tx.value);
+            if cost > *balance {
+                return Err(SimError::rejected(&format!(
+                    "insufficient funds for gas * price + value: address {:?} have {} want {}",
+                    tx.from, balance, cost
+                )));
+            }
+        }
+        let existing = self
+            .mempool
+            .iter()
+            .position(|pending| pending.from == tx.from && pending.nonce == tx.nonce);
+        if let Some(existing) = existing {
+            if tx.gas_price <= self.mempool[existing].gas_price {
+                return Err(SimError::rejected("replacement transaction underpriced"));
+            }
+        }
+        self.sent += 1;
+        if tx.hash.is_zero() {
+            tx.hash = keccak256(self.sent.to_be_bytes()).into();
+        }
+        match existing {
+            Some(existing) => self.mempool[existing] = tx.clone(),
+            None => self.mempool.push(tx.clone()),
+        }
+        if self.auto_mine {
+            self.mine();
+        }
+        Ok(tx.hash)
+    }
+
+    /// Mines a block with every pending transaction which is executable
+    fn mine(&mut self) {
+        let drop_probability = self.drop_probability;
+        let mut kept = Vec::with_capacity(self.mempool.len());
+        for tx in std::mem::take(&mut self.mempool) {
+            if !self.chance(drop_probability) {
+                kept.push(tx);
+            }
+        }
+        if self.reorder {
+            // Fisher-Yates with the seeded generator
+            for i in (1..kept.len()).rev() {
+                let j = (self.random() % (i as u64 + 1)) as usize;
+                kept.swap(i, j);
+            }
+        }
+
+        let mut block = Vec::new();
+        loop {
+            let next = kept.iter().position(|tx| {
+                let mined = self.mined_count(tx.from, u64::MAX)
+                    + block
+                        .iter()
+                        .filter(|mined: &&SimTx| mined.from == tx.from)
+                        .count() as u64;
+                tx.nonce == mined
+            });
+            match next {
+                Some(index) => block.push(kept.remove(index)),
+                None => break,
+            }
+        }
+        self.mempool = kept;
+        self.blocks.push(block);
+    }
+
+    fn find(&self, hash: TxHash) -> Option<(Option<u64>, &SimTx)> {
+        self.mined()
+            .find(|(_, tx)| tx.hash == hash)
+            .map(|(number, tx)| (Some(number), tx))
+            .or_else(|| {
+                self.mempool
+                    .iter()
+                    .find(|tx| tx.hash == hash)
+                    .map(|tx| (None, tx))
+            })
+    }
+}
+
+#[derive(Debug, Error)]
+/// Error returned by the [`SimulatedChain`]
+pub enum SimError {
+    /// A failure injected with [`SimulatedChain::fail_next`],
+    /// [`SimulatedChain::lose_next_responses`] or [`CallOutcome::Unreachable`]
+    #[error("connection reset")]
+    Transport,
+    /// The node rejected the request
+    #[error("{0}")]
+    Rpc(JsonRpcError),
+    /// The request's parameters or the response couldn't be (de)serialized
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+    /// The simulator doesn't implement the method
+    #[error("unsupported method {0}")]
+    Unsupported(String),
+}
+
+impl SimError {
+    fn rejected(message: &str) -> Self {
+        SimError::Rpc(JsonRpcError {
+            code: -32000,
+            message: message.to_owned(),
+            data: None,
+        })
+    }
+}
+
+impl RpcError for SimError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            SimError::Rpc(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            SimError::Serde(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<SimError> for ProviderError {
+    fn from(err: SimError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(err))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An in-memory chain with a mempool, deterministic for a given seed, to reproduce nonce
+/// desyncs without a node. Transactions wait in the mempool until a block is mined, and the
+/// chain can drop pending transactions, pick them up out of order, reorg blocks away and fail
+/// requests on demand. Clones share the same chain.
+///
+/// It serves `eth_chainId`, `eth_blockNumber`, `eth_getBlockByNumber` (a block every 12
+/// seconds, without a base fee), `eth_call` (see [`Self::set_call_outcome`]), `eth_gasPrice`,
+/// `eth_estimateGas`, `eth_getBalance`, `eth_getTransactionCount`, `eth_sendTransaction` (the
+/// sender is taken as unlocked), `eth_sendRawTransaction`, `eth_getTransactionByHash` and
+/// `eth_getTransactionReceipt`.
+pub struct SimulatedChain {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimulatedChain {
+    /// An empty chain whose random choices are derived from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SimState {
+                blocks: Vec::new(),
+                mempool: Vec::new(),
+                faults: VecDeque::new(),
+                auto_mine: false,
+                drop_probability: 0.0,
+                reorder: false,
+                // xorshift never leaves zero
+                rng: seed.max(1),
+                sent: 0,
+                genesis_secs: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs()),
+                balances: HashMap::new(),
+                call_outcome: CallOutcome::Success,
+            })),
+        }
+    }
+
+    /// A provider talking to this chain
+    pub fn provider(&self) -> Provider<SimulatedChain> {
+        Provider::new(self.clone()).interval(Duration::from_millis(1))
+    }
+
+    /// Mines a block after every accepted transaction, like anvil does by default
+    pub fn with_auto_mine(self, auto_mine: bool) -> Self {
+        self.state().auto_mine = auto_mine;
+        self
+    }
+
+    /// Drops each pending transaction with `probability` whenever a block is mined
+    pub fn with_drop_probability(self, probability: f64) -> Self {
+        self.state().drop_probability = probability;
+        self
+    }
+
+    /// Picks up pending transactions in random order rather than in arrival order. Within an
+    /// account, transactions are still only mined in nonce order.
+    pub fn with_reordering(self, reorder: bool) -> Self {
+        self.state().reorder = reorder;
+        self
+    }
+
+    /// Mines a block with every pending transaction which is executable
+    pub fn mine(&self) {
+        self.state().mine();
+    }
+
+    /// Mines `blocks` blocks
+    pub fn mine_blocks(&self, blocks: usize) {
+        let mut state = self.state();
+        for _ in 0..blocks {
+            state.mine();
+        }
+    }
+
+    /// The number of the latest block
+    pub fn block_number(&self) -> u64 {
+        self.state().blocks.len() as u64
+    }
+
+    /// Drops the pending transactions of `address` at `nonce` and above, as a node evicting
+    /// them from its pool would
+    pub fn drop_pending(&self, address: Address, nonce: u64) {
+        self.state()
+            .mempool
+            .retain(|tx| tx.from != address || tx.nonce < nonce);
+    }
+
+    /// Drops every pending transaction
+    pub fn drop_all_pending(&self) {
+        self.state().mempool.clear();
+    }
+
+    /// Removes the latest `depth` blocks, returning their transactions to the mempool
+    pub fn reorg(&self, depth: usize) {
+        self.unwind(depth, true);
+    }
+
+    /// Removes the latest `depth` blocks along with their transactions, as if the new fork
+    /// never saw them
+    pub fn reorg_and_drop(&self, depth: usize) {
+        self.unwind(depth, false);
+    }
+
+    /// Sends a transaction from `address` at its next pending nonce, as another wallet using
+    /// the same key would
+    pub fn send_external(&self, address: Address) -> TxHash {
+        let mut state = self.state();
+        let nonce = state.pending_count(address);
+        state
+            .submit(SimTx {
+                hash: TxHash::zero(),
+                from: address,
+                nonce,
+                to: Some(address),
+                value: U256::zero(),
+                gas: GAS_USED.into(),
+                gas_price: U256::one(),
+            })
+            .expect("the next pending nonce is always accepted")
+    }
+
+    /// Gives `address` a balance of `wei`, which transactions costing more than it are
+    /// rejected against. It stays the same as transactions are mined. Accounts without one
+    /// can pay for anything.
+    pub fn set_balance(&self, address: Address, wei: U256) {
+        self.state().balances.insert(address, wei);
+    }
+
+    /// Sets what `eth_call` answers from now on
+    pub fn set_call_outcome(&self, outcome: CallOutcome) {
+        self.state().call_outcome = outcome;
+    }
+
+    /// Fails the next `requests` requests without processing them
+    pub fn fail_next(&self, requests: usize) {
+        let mut state = self.state();
+        state
+            .faults
+            .extend(std::iter::repeat(Fault::Transport).take(requests));
+    }
+
+    /// Processes the next `requests` requests but fails them as if their responses were lost
+    pub fn lose_next_responses(&self, requests: usize) {
+        let mut state = self.state();
+        state
+            .faults
+            .extend(std::iter::repeat(Fault::LostResponse).take(requests));
+    }
+
+    /// Nonces of the mined transactions of `address`
+    pub fn mined_nonces(&self, address: Address) -> Vec<u64> {
+        let state = self.state();
+        let mut nonces: Vec<u64> = state
+            .mined()
+            .filter(|(_, tx)| tx.from == address)
+            .map(|(_, tx)| tx.nonce)
+            .collect();
+        nonces.sort_unstable();
+        nonces
+    }
+
+    /// Nonces of the pending transactions of `address`
+    pub fn pending_nonces(&self, address: Address) -> Vec<u64> {
+        let state = self.state();
+        let mut nonces: Vec<u64> = state
+            .mempool
+            .iter()
+            .filter(|tx| tx.from == address)
+            .map(|tx| tx.nonce)
+            .collect();
+        nonces.sort_unstable();
+        nonces
+    }
+
+    fn unwind(&self, depth: usize, keep: bool) {
+        let mut state = self.state();
+        let keep_from = state.blocks.len().saturating_sub(depth);
+        let removed: Vec<SimTx> = state.blocks.drain(keep_from..).flatten().collect();
+        if keep {
+            state.mempool.splice(0..0, removed);
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().expect("simulated chain lock poisoned")
+    }
+
+    fn handle(&self, method: &str, params: Value) -> Result<Value, SimError> {
+        let mut state = self.state();
+        let response = match method {
+            "eth_chainId" => json!(U64::from(CHAIN_ID)),
+            "eth_blockNumber" => json!(U64::from(state.blocks.len())),
+            "eth_gasPrice" => json!(U256::exp10(9)),
+            "eth_estimateGas" => json!(U256::from(GAS_USED)),
+            "eth_call" => match &state.call_outcome {
+                CallOutcome::Success => json!(Bytes::new()),
+                CallOutcome::Revert(data) => {
+                    return Err(SimError::Rpc(JsonRpcError {
+                        code: 3,
+                        message: "execution reverted".to_owned(),
+                        data: Some(json!(data)),
+                    }))
+                }
+                CallOutcome::Unreachable => return Err(SimError::Transport),
+            },
+            "eth_getBalance" => {
+                let address: Address = serde_json::from_value(params[0].clone())?;
+                json!(state.balances.get(&address).copied().unwrap_or(U256::MAX))
+            }
+            "eth_getTransactionCount" => {
+                let address: Address = serde_json::from_value(params[0].clone())?;
+                let block: Option<BlockNumber> = serde_json::from_value(params[1].clone())?;
+                let count = match block.unwrap_or(BlockNumber::Latest) {
+                    BlockNumber::Pending => state.pending_count(address),
+                    BlockNumber::Number(number) => state.mined_count(address, number.as_u64()),
+                    BlockNumber::Earliest => 0,
+                    _ => state.mined_count(address, u64::MAX),
+                };
+                json!(U256::from(count))
+            }
+            "eth_sendTransaction" => {
+                let request = &params[0];
+                let from: Address = serde_json::from_value(request["from"].clone())?;
+                let nonce: Option<U256> = serde_json::from_value(request["nonce"].clone())?;
+                let nonce = match nonce {
+                    Some(nonce) => nonce.as_u64(),
+                    None => state.pending_count(from),
+                };
+                let gas_price: Option<U256> = serde_json::from_value(request["gasPrice"].clone())?;
+                let max_fee: Option<U256> =
+                    serde_json::from_value(request["maxFeePerGas"].clone())?;
+                let gas: Option<U256> = serde_json::from_value(request["gas"].clone())?;
+                let value: Option<U256> = serde_json::from_value(request["value"].clone())?;
+                let tx = SimTx {
+                    hash: TxHash::zero(),
+                    from,
+                    nonce,
+                    to: serde_json::from_value(request["to"].clone())?,
+                    value: value.unwrap_or_default(),
+                    gas: gas.unwrap_or_else(|| GAS_USED.into()),
+                    gas_price: gas_price.or(max_fee).unwrap_or_default(),
+                };
+                json!(state.submit(tx)?)
+            }
+            "eth_sendRawTransaction" => {
+                let raw: Bytes = serde_json::from_value(params[0].clone())?;
+                let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(&raw))
+                    .map_err(|_| SimError::rejected("invalid transaction"))?;
+                let from = signature
+                    .recover(tx.sighash())
+                    .map_err(|_| SimError::rejected("invalid signature"))?;
+                let fee = match &tx {
+                    TypedTransaction::Eip1559(inner) => inner.max_fee_per_gas,
+                    _ => tx.gas_price(),
+                };
+                let tx = SimTx {
+                    hash: keccak256(&raw).into(),
+                    from,
+                    nonce: tx.nonce().map_or(0, |nonce| nonce.as_u64()),
+                    to: tx.to().and_then(|to| to.as_address().copied()),
+                    value: tx.value().copied().unwrap_or_default(),
+                    gas: tx.gas().copied().unwrap_or_else(|| GAS_USED.into()),
+                    gas_price: fee.unwrap_or_default(),
+                };
+                json!(state.submit(tx)?)
+            }
+            "eth_getBlockByNumber" => {
+                let block: BlockNumber = serde_json::from_value(params[0].clone())?;
+                let latest = state.blocks.len() as u64;
+                let number = match block {
+                    BlockNumber::Number(number) => number.as_u64(),
+                    BlockNumber::Earliest => 0,
+                    _ => latest,
+                };
+                if number > latest {
+                    return Ok(Value::Null);
+                }
+                let transactions = match number {
+                    0 => Vec::new(),
+                    number => state.blocks[number as usize - 1]
+                        .iter()
+                        .map(|tx| tx.hash)
+                        .collect(),
+                };
+                json!(Block::<TxHash> {
+                    hash: Some(H256::from_low_u64_be(number + 1)),
+                    number: Some(number.into()),
+                    timestamp: (state.genesis_secs + number * BLOCK_TIME_SECS).into(),
+                    transactions,
+                    ..Default::default()
+                })
+            }
+            "eth_getTransactionByHash" => {
+                let hash: TxHash = serde_json::from_value(params[0].clone())?;
+                match state.find(hash) {
+                    Some((block_number, tx)) => json!(Transaction {
+                        hash: tx.hash,
+                        nonce: tx.nonce.into(),
+                        block_number: block_number.map(U64::from),
+                        from: tx.from,
+                        to: tx.to,
+                        value: tx.value,
+                        gas: tx.gas,
+                        gas_price: Some(tx.gas_price),
+                        chain_id: Some(CHAIN_ID.into()),
+                        ..Default::default()
+                    }),
+                    None => Value::Null,
+                }
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: TxHash = serde_json::from_value(params[0].clone())?;
+                match state.find(hash) {
+                    Some((Some(block_number), tx)) => json!(TransactionReceipt {
+                        transaction_hash: tx.hash,
+                        block_number: Some(block_number.into()),
+                        from: tx.from,
+                        to: tx.to,
+                        gas_used: Some(GAS_USED.into()),
+                        cumulative_gas_used: GAS_USED.into(),
+                        effective_gas_price: Some(tx.gas_price),
+                        status: Some(1u64.into()),
+                        ..Default::default()
+                    }),
+                    _ => Value::Null,
+                }
+            }
+            method => return Err(SimError::Unsupported(method.to_owned())),
+        };
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for SimulatedChain {
+    type Error = SimError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let fault = self.state().faults.pop_front();
+        if fault == Some(Fault::Transport) {
+            return Err(SimError::Transport);
+        }
+        let response = self.handle(method, serde_json::to_value(&params)?)?;
+        if fault == Some(Fault::LostResponse) {
+            return Err(SimError::Transport);
+        }
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+/// Shared setup of the unit tests running against the simulator
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::SimulatedChain;
+    use crate::{LockedNonceManagerMiddleware, NonceEvent, NonceObserver};
+    use ethers::providers::{Middleware, Provider};
+    use ethers::types::{Address, TransactionRequest, TxHash, H160};
+    use std::sync::{Arc, Mutex};
+
+    pub(crate) type Client = LockedNonceManagerMiddleware<Provider<SimulatedChain>>;
+
+    /// The address the manager sends from, unlocked on the simulated node
+    pub(crate) const SENDER: Address = H160([0x11; 20]);
+
+    pub(crate) fn client(chain: &SimulatedChain) -> Client {
+        LockedNonceManagerMiddleware::new(chain.provider(), SENDER)
+    }
+
+    /// A legacy transfer from the managed address
+    pub(crate) fn transfer() -> TransactionRequest {
+        TransactionRequest::pay(Address::repeat_byte(1), 1u64).from(SENDER)
+    }
+
+    /// Sends a [`transfer`] through `client`, returning its hash
+    pub(crate) async fn send(client: &Client) -> TxHash {
+        client
+            .send_transaction(transfer(), None)
+            .await
+            .unwrap()
+            .tx_hash()
+    }
+
+    #[derive(Debug, Clone, Default)]
+    /// Collects the events a manager emits
+    pub(crate) struct Events(Arc<Mutex<Vec<NonceEvent>>>);
+
+    impl Events {
+        /// The events emitted since the last call
+        pub(crate) fn take(&self) -> Vec<NonceEvent> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl NonceObserver for Events {
+        fn on_event(&self, event: &NonceEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{Nonce, NonceEvent, NonceManagerError, NonceObserver};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{Address, BlockId};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug)]
+/// Read-only tracker following the nonces of addresses which are not sent from, e.g. a
+/// counterparty or a retired hot wallet. Every observed change is reported to the registered
+/// observers as a [`NonceEvent::Changed`].
+pub struct NonceTracker<M> {
+    inner: M,
+    nonces: Mutex<BTreeMap<Address, Option<Nonce>>>,
+    observers: Vec<Arc<dyn NonceObserver>>,
+}
+
+impl<M> NonceTracker<M>
+where
+    M: Middleware,
+{
+    /// Creates a tracker which queries `inner` and does not follow any address yet
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            nonces: Mutex::new(BTreeMap::new()),
+            observers: Vec::new(),
+        }
+    }
+
+    /// Registers an observer which is notified of every change seen by the tracker
+    pub fn with_observer(mut self, observer: impl NonceObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Starts following `address`. Its first poll reports a change from `None`.
+    pub fn track(&self, address: Address) {
+        self.nonces().entry(address).or_insert(None);
+    }
+
+    /// Stops following `address`
+    pub fn untrack(&self, address: Address) {
+        self.nonces().remove(&address);
+    }
+
+    /// The addresses currently followed
+    pub fn tracked(&self) -> Vec<Address> {
+        self.nonces().keys().copied().collect()
+    }
+
+    /// The last nonce observed for `address`, if it is tracked and was polled at least once
+    pub fn latest(&self, address: Address) -> Option<Nonce> {
+        self.nonces().get(&address).copied().flatten()
+    }
+
+    /// Fetches the transaction count of every tracked address at `block` and reports the
+    /// ones which changed since the previous poll
+    pub async fn poll(
+        &self,
+        block: Option<BlockId>,
+    ) -> Result<Vec<NonceEvent>, NonceManagerError<M>> {
+        let mut changes = Vec::new();
+        for address in self.tracked() {
+            let nonce = self
+                .inner
+                .get_transaction_count(address, block)
+                .await
+                .map_err(FromErr::from)?;
+            let nonce = Nonce::new(nonce).map_err(NonceManagerError::InvalidNonce)?;
+
+            let previous = match self.nonces().get_mut(&address) {
+                Some(last) if *last != Some(nonce) => last.replace(nonce),
+                // unchanged, or untracked while we were polling
+                _ => continue,
+            };
+            changes.push(NonceEvent::Changed {
+                address,
+                previous,
+                nonce,
+            });
+        }
+
+        for event in &changes {
+            for observer in &self.observers {
+                observer.on_event(event);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Polls the tracked addresses at the latest block every `interval`, until a poll fails
+    pub async fn run(&self, interval: Duration) -> Result<(), NonceManagerError<M>> {
+        loop {
+            self.poll(None).await?;
+            crate::rt::sleep(interval).await;
+        }
+    }
+
+    fn nonces(&self) -> std::sync::MutexGuard<'_, BTreeMap<Address, Option<Nonce>>> {
+        self.nonces.lock().expect("tracker lock poisoned")
+    }
+}
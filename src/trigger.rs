@@ -0,0 +1,49 @@
+use crate::{LockedNonceManagerMiddleware, NonceManagerError};
+use ethers::providers::{FromErr, Middleware, PendingTransaction};
+use ethers::types::{transaction::eip2718::TypedTransaction, U64};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Condition which has to be met before a transaction passed to
+/// [`LockedNonceManagerMiddleware::send_at`] gets its nonce and is broadcast
+pub enum Trigger {
+    /// Fires once the chain reaches the given block number
+    Block(U64),
+    /// Fires once the manager's [`crate::Clock`] reaches the given time
+    Time(SystemTime),
+}
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Holds `tx` until `trigger` fires and only then assigns it a nonce and broadcasts it.
+    /// No nonce is consumed while waiting, so other transactions keep flowing in the
+    /// meantime.
+    pub async fn send_at<T: Into<TypedTransaction> + Send + Sync>(
+        &self,
+        tx: T,
+        trigger: Trigger,
+    ) -> Result<PendingTransaction<'_, M::Provider>, NonceManagerError<M>> {
+        let tx = tx.into();
+        self.wait_for(trigger).await?;
+        self.send_transaction(tx, None).await
+    }
+
+    async fn wait_for(&self, trigger: Trigger) -> Result<(), NonceManagerError<M>> {
+        match trigger {
+            Trigger::Block(target) => {
+                let interval = self.provider().get_interval();
+                while self.inner.get_block_number().await.map_err(FromErr::from)? < target {
+                    self.sleep(interval).await;
+                }
+            }
+            Trigger::Time(target) => {
+                if let Ok(remaining) = target.duration_since(self.clock.system_time()) {
+                    self.sleep(remaining).await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
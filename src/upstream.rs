@@ -0,0 +1,32 @@
+use crate::{LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::middleware::{
+    nonce_manager::NonceManagerError as UpstreamError, NonceManagerMiddleware,
+};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::Address;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware + Clone,
+{
+    /// Takes over from ethers' own [`NonceManagerMiddleware`] sending from `address`, around
+    /// the same inner middleware and with the counter seeded at the next nonce `upstream`
+    /// would have handed out, so that a live service can swap implementations without
+    /// resyncing with the chain in between. An upstream which was never used is initialized
+    /// from the chain first. Nothing should be sent through `upstream` afterwards, as the
+    /// nonces it hands out are not seen by the new manager.
+    pub async fn from_upstream(
+        upstream: &NonceManagerMiddleware<M>,
+        address: Address,
+    ) -> Result<Self, NonceManagerError<M>> {
+        // returns the current nonce without consuming it once initialized
+        let nonce = match upstream.initialize_nonce(None).await {
+            Ok(nonce) => nonce,
+            Err(UpstreamError::MiddlewareError(err)) => return Err(FromErr::from(err)),
+        };
+        let nonce = Nonce::new(nonce).map_err(NonceManagerError::InvalidNonce)?;
+        let manager = Self::new(upstream.inner().clone(), address);
+        manager.state.seed(nonce).await;
+        Ok(manager)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{JournalEntry, LockedNonceManagerMiddleware, Nonce, NonceManagerError};
+use ethers::providers::{FromErr, Middleware};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, TxHash, H256};
+use ethers::utils::keccak256;
+
+impl<M> LockedNonceManagerMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Journals every managed transaction to the state store before it is broadcast, so that
+    /// after a crash [`Self::restore_from_store`] tells the nonces which may have been
+    /// broadcast from the ones which never were. Transactions are filled and signed through
+    /// the inner middleware to know their hash ahead of the broadcast, and a send fails
+    /// without broadcasting if its entry can't be written. Nonces whose broadcast isn't
+    /// journaled as done are resynced with the chain before the next assignment, while the
+    /// others are free to be assigned again.
+    ///
+    /// Journaled broadcasts are only cleared by snapshots, so this is best combined with
+    /// [`Self::with_checkpoint_interval`].
+    pub fn with_write_ahead_log(mut self) -> Self {
+        self.config_mut().write_ahead = true;
+        self
+    }
+
+    /// Fills and signs `tx` and journals that it is about to be broadcast, if the write-ahead
+    /// log is enabled. Returns the signed transaction to broadcast, which
+    /// [`Self::broadcast`] has to be given for the journaled hash to be the one sent.
+    pub(crate) async fn write_ahead(
+        &self,
+        tx: &mut TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Option<Bytes>, NonceManagerError<M>> {
+        if !self.config().write_ahead || self.state_store.is_none() {
+            return Ok(None);
+        }
+        self.inner
+            .fill_transaction(tx, block)
+            .await
+            .map_err(FromErr::from)?;
+        let signature = self
+            .inner
+            .sign_transaction(tx, self.address)
+            .await
+            .map_err(FromErr::from)?;
+        let raw_tx = tx.rlp_signed(&signature);
+        if let Some(nonce) = tx.nonce() {
+            let nonce = Nonce::new(*nonce).map_err(NonceManagerError::InvalidNonce)?;
+            self.log_broadcasting(nonce, H256::from(keccak256(&raw_tx)))?;
+        }
+        Ok(Some(raw_tx))
+    }
+
+    /// Journals that the transaction `tx_hash` is about to be broadcast at `nonce`, if the
+    /// write-ahead log is enabled
+    pub(crate) fn log_broadcasting(
+        &self,
+        nonce: Nonce,
+        tx_hash: TxHash,
+    ) -> Result<(), NonceManagerError<M>> {
+        let store = match &self.state_store {
+            Some(store) if self.config().write_ahead => store,
+            _ => return Ok(()),
+        };
+        store
+            .append(&JournalEntry::Broadcasting { nonce, tx_hash })
+            .map_err(NonceManagerError::StateStoreError)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{NonceEvent, NonceObserver};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone)]
+/// Where and how [`WebhookNotifier`] delivers events
+pub struct WebhookConfig {
+    /// The URL each event is POSTed to as a JSON object
+    pub url: String,
+    /// How many times a failed delivery is retried before the event is dropped
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled for every further attempt
+    pub retry_delay: Duration,
+    /// Timeout of a single delivery attempt
+    pub timeout: Duration,
+}
+
+impl WebhookConfig {
+    /// Configuration for `url` with 3 retries starting at 1 second, and a 10 second timeout
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// [`NonceObserver`] which POSTs every event as JSON to a webhook, so that tooling outside of
+/// Rust can react to nonce events. The payload is the serialized [`NonceEvent`], whose
+/// `event` field is one of `assigned`, `broadcast`, `confirmed`, `stuck`, `sla_breached`,
+/// `conflict`, `resynced`, `hold_expired`, `private_expired`, `dependency_failed`,
+/// `shadow_diverged` or `changed`.
+///
+/// Deliveries happen in order on a background task and never block the middleware.
+pub struct WebhookNotifier {
+    sender: mpsc::UnboundedSender<NonceEvent>,
+}
+
+impl WebhookNotifier {
+    /// Spawns the delivery task on the current tokio runtime
+    pub fn spawn(config: WebhookConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(deliver_all(config, receiver));
+        Self { sender }
+    }
+}
+
+impl NonceObserver for WebhookNotifier {
+    fn on_event(&self, event: &NonceEvent) {
+        // the delivery task only stops once every notifier handle is dropped
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+async fn deliver_all(config: WebhookConfig, mut receiver: mpsc::UnboundedReceiver<NonceEvent>) {
+    let client = reqwest::Client::new();
+    while let Some(event) = receiver.recv().await {
+        deliver(&client, &config, &event).await;
+    }
+}
+
+async fn deliver(client: &reqwest::Client, config: &WebhookConfig, event: &NonceEvent) {
+    let mut delay = config.retry_delay;
+    for attempt in 0..=config.max_retries {
+        let res = client
+            .post(&config.url)
+            .timeout(config.timeout)
+            .json(event)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        if res.is_ok() || attempt == config.max_retries {
+            return;
+        }
+        crate::rt::sleep(delay).await;
+        delay *= 2;
+    }
+}
@@ -0,0 +1,291 @@
+//! End-to-end checks of the nonce manager against a local anvil node. They need the `anvil`
+//! binary on the `PATH` and are ignored by default, run them with
+//!
+//! ```sh
+//! cargo test --test anvil -- --ignored
+//! ```
+
+use ethers::middleware::transformer::{Transformer, TransformerError, TransformerMiddleware};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::{Anvil, AnvilInstance};
+use ethers_nonce::{LockedNonceManagerMiddleware, Nonce, NonceEvent, NonceObserver};
+use futures_util::future::join_all;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Client = LockedNonceManagerMiddleware<SignerMiddleware<Provider<Http>, LocalWallet>>;
+
+/// Number of transactions fired concurrently by each test
+const SENDS: usize = 200;
+
+/// Collects the nonce of every transaction the manager reports as broadcast
+#[derive(Debug, Clone, Default)]
+struct Broadcasts(Arc<Mutex<Vec<Nonce>>>);
+
+impl NonceObserver for Broadcasts {
+    fn on_event(&self, event: &NonceEvent) {
+        if let NonceEvent::Broadcast { nonce, .. } = event {
+            self.0.lock().unwrap().push(*nonce);
+        }
+    }
+}
+
+impl Broadcasts {
+    /// Asserts that the broadcast nonces are exactly `0..count`, each used once
+    fn assert_contiguous(&self, count: u64) {
+        let mut nonces: Vec<u64> = self.0.lock().unwrap().iter().map(Nonce::as_u64).collect();
+        nonces.sort_unstable();
+        assert_eq!(nonces, (0..count).collect::<Vec<_>>());
+    }
+}
+
+fn client(anvil: &AnvilInstance, broadcasts: &Broadcasts) -> Client {
+    let provider = Provider::<Http>::try_from(anvil.endpoint())
+        .unwrap()
+        .interval(Duration::from_millis(10));
+    let wallet = LocalWallet::from(anvil.keys()[0].clone()).with_chain_id(anvil.chain_id());
+    let address = wallet.address();
+    LockedNonceManagerMiddleware::new(SignerMiddleware::new(provider, wallet), address)
+        .with_observer(broadcasts.clone())
+}
+
+/// Stands in for a DSProxy: sends every transaction to `proxy` instead, remembering the nonce
+/// each one carried when it was transformed
+#[derive(Debug, Clone)]
+struct Redirect {
+    proxy: Address,
+    seen_nonces: Arc<Mutex<Vec<Option<U256>>>>,
+}
+
+impl Redirect {
+    fn new(proxy: Address) -> Self {
+        Self {
+            proxy,
+            seen_nonces: Default::default(),
+        }
+    }
+}
+
+impl Transformer for Redirect {
+    fn transform(&self, tx: &mut TypedTransaction) -> Result<(), TransformerError> {
+        self.seen_nonces.lock().unwrap().push(tx.nonce().copied());
+        tx.set_to(self.proxy);
+        Ok(())
+    }
+}
+
+/// Asserts that the transactions at `hashes` were sent to `proxy` at the nonces `0..count`
+async fn assert_proxied<M: Middleware>(client: &M, hashes: &[TxHash], proxy: Address) {
+    let mut nonces = Vec::new();
+    for hash in hashes {
+        let tx = client.get_transaction(*hash).await.unwrap().unwrap();
+        assert_eq!(tx.to, Some(proxy));
+        nonces.push(tx.nonce.as_u64());
+    }
+    nonces.sort_unstable();
+    assert_eq!(nonces, (0..hashes.len() as u64).collect::<Vec<_>>());
+}
+
+fn transfer(anvil: &AnvilInstance) -> TransactionRequest {
+    TransactionRequest::pay(anvil.addresses()[1], 1u64)
+}
+
+async fn chain_nonce(client: &Client, block: BlockNumber) -> u64 {
+    client
+        .inner()
+        .get_transaction_count(client.state().address(), Some(block.into()))
+        .await
+        .unwrap()
+        .as_u64()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires anvil"]
+async fn concurrent_sends_never_skip_or_reuse_a_nonce() {
+    let anvil = Anvil::new().spawn();
+    let broadcasts = Broadcasts::default();
+    let client = client(&anvil, &broadcasts);
+
+    let sends = (0..SENDS).map(|_| client.send_transaction(transfer(&anvil), None));
+    for pending in join_all(sends).await {
+        pending.unwrap();
+    }
+
+    broadcasts.assert_contiguous(SENDS as u64);
+    assert_eq!(client.next().await.as_u64(), SENDS as u64);
+    assert_eq!(
+        chain_nonce(&client, BlockNumber::Latest).await,
+        SENDS as u64
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires anvil"]
+async fn sends_queue_up_while_mining_is_stalled() {
+    let anvil = Anvil::new().spawn();
+    let broadcasts = Broadcasts::default();
+    let client = client(&anvil, &broadcasts);
+
+    // jam the node: transactions are accepted into the pool but nothing is mined
+    client
+        .provider()
+        .request::<_, ()>("evm_setAutomine", [false])
+        .await
+        .unwrap();
+
+    let sends = (0..SENDS).map(|_| client.send_transaction(transfer(&anvil), None));
+    for pending in join_all(sends).await {
+        pending.unwrap();
+    }
+    assert_eq!(chain_nonce(&client, BlockNumber::Latest).await, 0);
+
+    client
+        .provider()
+        .request::<_, ()>("evm_mine", ())
+        .await
+        .unwrap();
+
+    broadcasts.assert_contiguous(SENDS as u64);
+    assert_eq!(
+        chain_nonce(&client, BlockNumber::Latest).await,
+        SENDS as u64
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires anvil"]
+async fn cancelled_sends_leave_no_gaps() {
+    let anvil = Anvil::new().spawn();
+    let broadcasts = Broadcasts::default();
+    let client = client(&anvil, &broadcasts);
+
+    // drop sends at every stage: before the lock, while signing and after the request
+    // reached the node but before its response arrived
+    let sends = (0..SENDS).map(|i| {
+        let timeout = Duration::from_micros(50 * i as u64);
+        tokio::time::timeout(timeout, client.send_transaction(transfer(&anvil), None))
+    });
+    join_all(sends).await;
+
+    // cancelled broadcasts the manager never heard back from are picked up by the resync
+    // preceding the next assignment
+    client
+        .send_transaction(transfer(&anvil), None)
+        .await
+        .unwrap();
+
+    let mined = chain_nonce(&client, BlockNumber::Latest).await;
+    assert_eq!(client.next().await.as_u64(), mined);
+    assert_eq!(chain_nonce(&client, BlockNumber::Pending).await, mined);
+
+    let mut nonces: Vec<u64> = broadcasts
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(Nonce::as_u64)
+        .collect();
+    nonces.sort_unstable();
+    assert!(
+        nonces.windows(2).all(|pair| pair[0] < pair[1]),
+        "nonce reused"
+    );
+    assert!(nonces.iter().all(|nonce| *nonce < mined));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires anvil"]
+async fn node_killed_mid_run_does_not_advance_the_counter() {
+    let anvil = Anvil::new().spawn();
+    let broadcasts = Broadcasts::default();
+    let client = client(&anvil, &broadcasts);
+    let tx = transfer(&anvil);
+
+    let sends = (0..SENDS).map(|_| client.send_transaction(tx.clone(), None));
+    let kill = async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(anvil);
+    };
+    let (results, ()) = tokio::join!(join_all(sends), kill);
+    let accepted = results.iter().filter(|result| result.is_ok()).count() as u64;
+
+    // only transactions the node acknowledged consumed a nonce
+    broadcasts.assert_contiguous(accepted);
+    assert_eq!(client.next().await.as_u64(), accepted);
+
+    assert!(client.send_transaction(tx, None).await.is_err());
+    assert_eq!(client.next().await.as_u64(), accepted);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires anvil"]
+async fn transformer_outside_the_manager_assigns_the_proxy_transaction_once() {
+    let anvil = Anvil::new().spawn();
+    let broadcasts = Broadcasts::default();
+    let proxy = anvil.addresses()[2];
+    let redirect = Redirect::new(proxy);
+    let client = TransformerMiddleware::new(client(&anvil, &broadcasts), redirect.clone());
+
+    let sends = (0..SENDS).map(|_| client.send_transaction(transfer(&anvil), None));
+    let hashes: Vec<TxHash> = join_all(sends)
+        .await
+        .into_iter()
+        .map(|pending| pending.unwrap().tx_hash())
+        .collect();
+
+    // the transformer ran before the manager assigned a nonce
+    assert!(redirect
+        .seen_nonces
+        .lock()
+        .unwrap()
+        .iter()
+        .all(Option::is_none));
+    broadcasts.assert_contiguous(SENDS as u64);
+    assert_proxied(&client, &hashes, proxy).await;
+    assert_eq!(client.inner().next().await.as_u64(), SENDS as u64);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires anvil"]
+async fn transformer_inside_the_manager_keeps_the_assigned_nonce() {
+    let anvil = Anvil::new().spawn();
+    let broadcasts = Broadcasts::default();
+    let proxy = anvil.addresses()[2];
+    let redirect = Redirect::new(proxy);
+    let provider = Provider::<Http>::try_from(anvil.endpoint())
+        .unwrap()
+        .interval(Duration::from_millis(10));
+    let wallet = LocalWallet::from(anvil.keys()[0].clone()).with_chain_id(anvil.chain_id());
+    let address = wallet.address();
+    let client = LockedNonceManagerMiddleware::new(
+        TransformerMiddleware::new(SignerMiddleware::new(provider, wallet), redirect.clone()),
+        address,
+    )
+    .with_observer(broadcasts.clone());
+
+    let sends = (0..SENDS).map(|_| client.send_transaction(transfer(&anvil), None));
+    let hashes: Vec<TxHash> = join_all(sends)
+        .await
+        .into_iter()
+        .map(|pending| pending.unwrap().tx_hash())
+        .collect();
+
+    // the nonce was assigned to the original call and carried over to the proxy transaction
+    let mut seen: Vec<u64> = redirect
+        .seen_nonces
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|nonce| {
+            nonce
+                .expect("transformed before a nonce was assigned")
+                .as_u64()
+        })
+        .collect();
+    seen.sort_unstable();
+    assert_eq!(seen, (0..SENDS as u64).collect::<Vec<_>>());
+    broadcasts.assert_contiguous(SENDS as u64);
+    assert_proxied(&client, &hashes, proxy).await;
+    assert_eq!(client.next().await.as_u64(), SENDS as u64);
+}
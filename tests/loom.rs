@@ -0,0 +1,90 @@
+//! Model checks of the nonce state under every interleaving loom can produce. The chain is
+//! a mocked provider answering every transaction count lookup with [`CHAIN_NONCE`]. Run them
+//! with
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+//! ```
+
+#![cfg(loom)]
+
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers_nonce::LockedNonceManagerMiddleware;
+use loom::future::block_on;
+use loom::sync::Arc;
+use loom::thread;
+
+type Client = LockedNonceManagerMiddleware<Provider<MockProvider>>;
+
+/// Transaction count the mocked chain reports
+const CHAIN_NONCE: u64 = 5;
+
+/// A manager over a mocked chain able to answer `lookups` transaction count requests
+fn client(lookups: usize) -> Arc<Client> {
+    let (provider, mock) = Provider::mocked();
+    for _ in 0..lookups {
+        mock.push(U256::from(CHAIN_NONCE)).unwrap();
+    }
+    Arc::new(LockedNonceManagerMiddleware::new(provider, Address::zero()))
+}
+
+/// A transaction with everything but the nonce filled in, so filling it takes no requests
+fn transfer() -> TypedTransaction {
+    TransactionRequest::pay(Address::repeat_byte(1), 1u64)
+        .from(Address::zero())
+        .gas(21_000u64)
+        .gas_price(1u64)
+        .into()
+}
+
+/// Fills a transaction through the manager and returns the nonce it was assigned
+async fn assign(client: &Client) -> u64 {
+    let mut tx = transfer();
+    client.fill_transaction(&mut tx, None).await.unwrap();
+    tx.nonce().unwrap().as_u64()
+}
+
+#[test]
+fn concurrent_initialization_agrees_on_the_chain_nonce() {
+    loom::model(|| {
+        let client = client(2);
+        let other = client.clone();
+        let handle = thread::spawn(move || block_on(other.initialize_nonce(None)).unwrap());
+
+        let nonce = block_on(client.initialize_nonce(None)).unwrap();
+        assert_eq!(nonce.as_u64(), CHAIN_NONCE);
+        assert_eq!(handle.join().unwrap().as_u64(), CHAIN_NONCE);
+        assert_eq!(block_on(client.next()).as_u64(), CHAIN_NONCE);
+    });
+}
+
+#[test]
+fn concurrent_assignments_never_reuse_a_nonce() {
+    loom::model(|| {
+        let client = client(2);
+        let other = client.clone();
+        let handle = thread::spawn(move || block_on(assign(&other)));
+
+        let mut nonces = vec![block_on(assign(&client)), handle.join().unwrap()];
+        nonces.sort_unstable();
+        assert_eq!(nonces, [CHAIN_NONCE, CHAIN_NONCE + 1]);
+        assert_eq!(block_on(client.next()).as_u64(), CHAIN_NONCE + 2);
+    });
+}
+
+#[test]
+fn resync_racing_an_assignment_never_moves_the_counter_back() {
+    loom::model(|| {
+        let client = client(2);
+        let other = client.clone();
+        let handle = thread::spawn(move || block_on(other.resync()).unwrap());
+
+        let nonce = block_on(assign(&client));
+        let resynced = handle.join().unwrap().as_u64();
+        assert_eq!(nonce, CHAIN_NONCE);
+        // the resync either ran before the assignment or saw its result
+        assert!(resynced == CHAIN_NONCE || resynced == CHAIN_NONCE + 1);
+        assert_eq!(block_on(client.next()).as_u64(), CHAIN_NONCE + 1);
+    });
+}
@@ -0,0 +1,1034 @@
+//! Nonce desync scenarios reproduced on the in-memory chain of the `testkit` feature. Run them
+//! with
+//!
+//! ```sh
+//! cargo test --features testkit --test simulator
+//! ```
+
+#![cfg(feature = "testkit")]
+
+use async_trait::async_trait;
+use ethers::middleware::policy::{Policy, PolicyMiddleware};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use ethers_nonce::{
+    AdaptiveConcurrency, BackwardNoncePolicy, DriftThrottle, Durability, FileIdempotencyStore,
+    FileStateStore, IdempotencyStore, JournalEntry, LockedNonceManagerMiddleware, MockClock, Nonce,
+    NonceEvent, NonceManagerError, NonceObserver, PendingAheadLimit, PresetNoncePolicy,
+    PrivateRelay, PrivateTransaction, SendEndpoint, SendOptions, SimulatedChain, StateStore,
+    TxTemplate,
+};
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+type Client = LockedNonceManagerMiddleware<Provider<SimulatedChain>>;
+
+type Signer = SignerMiddleware<Provider<SimulatedChain>, LocalWallet>;
+
+/// The key the manager sends from
+const SENDER: Address = H160([0x11; 20]);
+
+fn client(chain: &SimulatedChain) -> Client {
+    LockedNonceManagerMiddleware::new(chain.provider(), SENDER)
+}
+
+/// A client signing locally, for the paths which need signed transactions, and its address
+fn signer(chain: &SimulatedChain) -> (Signer, Address) {
+    let wallet: LocalWallet = "22".repeat(32).parse().unwrap();
+    let address = wallet.address();
+    let signer = SignerMiddleware::new(chain.provider(), wallet.with_chain_id(31337u64));
+    (signer, address)
+}
+
+fn transfer() -> TransactionRequest {
+    TransactionRequest::pay(Address::repeat_byte(1), 1u64).from(SENDER)
+}
+
+async fn send(client: &Client, count: usize) {
+    for _ in 0..count {
+        client.send_transaction(transfer(), None).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn dropped_transactions_are_resent_after_a_reconnect() {
+    let chain = SimulatedChain::new(1);
+    let client = client(&chain);
+    send(&client, 3).await;
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1, 2]);
+
+    // the pool evicts everything after the first transaction, the counter doesn't notice
+    chain.drop_pending(SENDER, 1);
+    assert_eq!(client.next().await.as_u64(), 3);
+
+    client.notify_reconnected().await.unwrap();
+    assert_eq!(client.next().await.as_u64(), 1);
+    send(&client, 2).await;
+    chain.mine();
+    assert_eq!(chain.mined_nonces(SENDER), [0, 1, 2]);
+}
+
+#[tokio::test]
+async fn nonces_taken_by_another_wallet_are_skipped() {
+    let chain = SimulatedChain::new(2);
+    let client = client(&chain);
+    send(&client, 1).await;
+
+    // the same key is used elsewhere and its transaction is mined first
+    chain.send_external(SENDER);
+    chain.mine();
+
+    send(&client, 1).await;
+    chain.mine();
+    assert_eq!(chain.mined_nonces(SENDER), [0, 1, 2]);
+    assert_eq!(client.next().await.as_u64(), 3);
+}
+
+#[tokio::test]
+async fn reorged_transactions_are_mined_again() {
+    let chain = SimulatedChain::new(3);
+    let client = client(&chain);
+    send(&client, 2).await;
+    chain.mine();
+    assert!(client.reconcile().await.unwrap().is_consistent());
+
+    chain.reorg(1);
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1]);
+    chain.mine();
+    assert_eq!(chain.mined_nonces(SENDER), [0, 1]);
+    assert_eq!(client.next().await.as_u64(), 2);
+}
+
+#[tokio::test]
+async fn the_same_seed_replays_the_same_chain() {
+    async fn run(seed: u64) -> (Vec<u64>, Vec<u64>) {
+        let chain = SimulatedChain::new(seed)
+            .with_drop_probability(0.3)
+            .with_reordering(true);
+        let client = client(&chain);
+        for _ in 0..5 {
+            send(&client, 4).await;
+            chain.send_external(SENDER);
+            chain.mine();
+        }
+        (chain.mined_nonces(SENDER), chain.pending_nonces(SENDER))
+    }
+
+    assert_eq!(run(42).await, run(42).await);
+}
+
+#[tokio::test]
+async fn spend_windows_roll_over_with_the_clock() {
+    let chain = SimulatedChain::new(4);
+    let clock = MockClock::new();
+    let client = client(&chain)
+        .with_clock(clock.clone())
+        .with_spend_limit(U256::from(2u64), Duration::from_secs(60));
+    send(&client, 2).await;
+    assert_eq!(client.remaining_spend_limit(), Some(U256::zero()));
+
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(client.remaining_spend_limit(), Some(U256::zero()));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(client.remaining_spend_limit(), Some(U256::from(2u64)));
+}
+
+#[tokio::test]
+async fn idempotent_sends_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("ethers-nonce-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(5);
+    let relayer =
+        || client(&chain).with_idempotency_store(FileIdempotencyStore::open(&path).unwrap());
+
+    let first = relayer();
+    let sent = first
+        .send_idempotent("job-1", transfer(), None)
+        .await
+        .unwrap();
+    let again = first
+        .send_idempotent("job-1", transfer(), None)
+        .await
+        .unwrap();
+    assert_eq!(again, sent);
+    drop(first);
+
+    // a restarted relayer reads what was sent back from the file
+    let restarted = relayer();
+    assert_eq!(restarted.lookup("job-1").unwrap(), Some(sent));
+    let again = restarted
+        .send_idempotent("job-1", transfer(), None)
+        .await
+        .unwrap();
+    assert_eq!(again, sent);
+    assert_eq!(restarted.lookup("job-2").unwrap(), None);
+    assert_eq!(chain.pending_nonces(SENDER), [0]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn idempotent_sends_interrupted_by_a_crash_are_in_doubt() {
+    let path =
+        std::env::temp_dir().join(format!("ethers-nonce-{}-doubt.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(33);
+
+    let store = FileIdempotencyStore::open(&path).unwrap();
+    store.begin("job-1").unwrap();
+    drop(store);
+    // the crash also tore the line being written
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    std::io::Write::write_all(&mut file, b"{\"key\":\"job-2\",\"no").unwrap();
+    drop(file);
+
+    let relayer = client(&chain).with_idempotency_store(FileIdempotencyStore::open(&path).unwrap());
+    assert!(matches!(
+        relayer.lookup("job-1"),
+        Err(NonceManagerError::IdempotencyKeyInDoubt(key)) if key == "job-1"
+    ));
+    assert!(matches!(
+        relayer.send_idempotent("job-1", transfer(), None).await,
+        Err(NonceManagerError::IdempotencyKeyInDoubt(_))
+    ));
+    assert_eq!(relayer.lookup("job-2").unwrap(), None);
+    assert!(chain.pending_nonces(SENDER).is_empty());
+
+    // nothing made it to the chain, so the key is sent again, once for both requests
+    relayer.resolve_idempotent("job-1", None).await.unwrap();
+    let (first, second) = futures_util::join!(
+        relayer.send_idempotent("job-1", transfer(), None),
+        relayer.send_idempotent("job-1", transfer(), None),
+    );
+    assert_eq!(first.unwrap(), second.unwrap());
+    assert_eq!(chain.pending_nonces(SENDER), [0]);
+
+    // the torn line was cut off, so the records written after it are read back
+    drop(relayer);
+    let restarted = FileIdempotencyStore::open(&path).unwrap();
+    assert_eq!(
+        restarted
+            .get("job-1")
+            .unwrap()
+            .map(|sent| sent.nonce.as_u64()),
+        Some(0)
+    );
+    assert!(!restarted.in_doubt("job-1").unwrap());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn sends_at_a_chosen_nonce_move_the_counter_past_it() {
+    let chain = SimulatedChain::new(6);
+    let client = client(&chain);
+    let reserved = client.reserve_nonce(None).await.unwrap();
+    assert_eq!(reserved.as_u64(), 0);
+
+    let err = client
+        .send_transaction_with_nonce(transfer(), reserved, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, NonceManagerError::NonceReserved(_)));
+
+    let chosen = Nonce::try_from(2u64).unwrap();
+    client
+        .send_transaction_with_nonce(transfer(), chosen, None)
+        .await
+        .unwrap();
+    assert_eq!(chain.pending_nonces(SENDER), [2]);
+    assert_eq!(client.next().await.as_u64(), 3);
+    // the nonce skipped over is reserved until it is filled
+    let skipped = Nonce::try_from(1u64).unwrap();
+    assert_eq!(
+        client.reservations().await,
+        [(reserved, false), (skipped, false)]
+    );
+}
+
+#[tokio::test]
+async fn chosen_nonces_are_held_to_the_pending_ahead_limit() {
+    let chain = SimulatedChain::new(36);
+    // even a limit which holds sends back fails a chosen nonce over it
+    let client = client(&chain).with_max_pending_ahead(PendingAheadLimit::wait(3));
+    send(&client, 1).await;
+
+    let far = Nonce::try_from(10_000u64).unwrap();
+    let err = client
+        .send_transaction_with_nonce(transfer(), far, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        NonceManagerError::PendingTooFarAhead { nonce, max: 3, .. } if nonce == far
+    ));
+    assert!(client.reservations().await.is_empty());
+    assert_eq!(client.next().await.as_u64(), 1);
+
+    let near = Nonce::try_from(3u64).unwrap();
+    client
+        .send_transaction_with_nonce(transfer(), near, None)
+        .await
+        .unwrap();
+    assert_eq!(chain.pending_nonces(SENDER), [0, 3]);
+}
+
+#[tokio::test]
+async fn preset_nonces_follow_the_configured_policy() {
+    let chain = SimulatedChain::new(7);
+    let at = |nonce: u64| transfer().nonce(nonce);
+
+    let passthrough = client(&chain);
+    passthrough.send_transaction(at(0), None).await.unwrap();
+    assert_eq!(passthrough.next().await.as_u64(), 0);
+
+    let recording = client(&chain).with_preset_nonce_policy(PresetNoncePolicy::Record);
+    recording.send_transaction(at(1), None).await.unwrap();
+    assert_eq!(recording.next().await.as_u64(), 2);
+    // filling alone doesn't broadcast, so it leaves the counter alone
+    let mut filled = at(3).into();
+    recording.fill_transaction(&mut filled, None).await.unwrap();
+    assert_eq!(recording.next().await.as_u64(), 2);
+
+    let rejecting = client(&chain).with_preset_nonce_policy(PresetNoncePolicy::Reject);
+    let err = rejecting.send_transaction(at(2), None).await.unwrap_err();
+    assert!(matches!(err, NonceManagerError::PresetNonceRejected(_)));
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1]);
+}
+
+/// Collects the nonces reported as assigned
+#[derive(Debug, Clone, Default)]
+struct Assignments(Arc<Mutex<Vec<u64>>>);
+
+impl NonceObserver for Assignments {
+    fn on_event(&self, event: &NonceEvent) {
+        if let NonceEvent::Assigned { nonce, .. } = event {
+            self.0.lock().unwrap().push(nonce.as_u64());
+        }
+    }
+}
+
+#[tokio::test]
+async fn with_nonce_hands_the_nonce_out_again_on_failure() {
+    type Error = NonceManagerError<Provider<SimulatedChain>>;
+    let chain = SimulatedChain::new(8);
+    let assignments = Assignments::default();
+    let client = client(&chain).with_observer(assignments.clone());
+
+    let failed = client
+        .with_nonce(|_| async { Err::<(), Error>(NonceManagerError::NoSender) })
+        .await;
+    assert!(failed.is_err());
+    assert!(assignments.0.lock().unwrap().is_empty());
+
+    let used = client
+        .with_nonce(|nonce| async move { Ok::<_, Error>(nonce) })
+        .await
+        .unwrap();
+    assert_eq!(used.as_u64(), 0);
+    assert_eq!(client.next().await.as_u64(), 1);
+    assert_eq!(*assignments.0.lock().unwrap(), [0]);
+}
+
+#[tokio::test]
+async fn try_next_skips_an_uninitialized_counter() {
+    let chain = SimulatedChain::new(9);
+    let client = client(&chain);
+    assert_eq!(client.try_next(), None);
+
+    send(&client, 1).await;
+    assert_eq!(client.try_next().map(|nonce| nonce.as_u64()), Some(1));
+}
+
+#[tokio::test]
+async fn resync_follows_a_rewound_dev_node() {
+    let chain = SimulatedChain::new(10);
+    let strict = client(&chain);
+    let rewinding = client(&chain).with_dev_node_rewinds();
+    send(&strict, 2).await;
+    send(&rewinding, 1).await;
+    rewinding.reserve_nonce(None).await.unwrap();
+    rewinding
+        .hold_nonce(Duration::from_secs(60), None)
+        .await
+        .unwrap();
+    chain.mine();
+
+    // the block is gone along with its transactions, as after an `evm_revert`
+    chain.reorg_and_drop(1);
+    assert_eq!(strict.resync().await.unwrap().as_u64(), 2);
+    assert_eq!(rewinding.resync().await.unwrap().as_u64(), 0);
+    assert!(rewinding.in_flight().is_empty());
+    // the nonces reserved after the rewound ones are handed out again
+    assert!(rewinding.reservations().await.is_empty());
+    assert_eq!(rewinding.reserve_nonce(None).await.unwrap().as_u64(), 0);
+}
+
+#[tokio::test]
+async fn a_chain_behind_the_counter_follows_the_backward_nonce_policy() {
+    let chain = SimulatedChain::new(11);
+    let tolerant = client(&chain).with_backward_nonce_policy(BackwardNoncePolicy::IgnoreWithin(2));
+    let strict = client(&chain).with_backward_nonce_policy(BackwardNoncePolicy::Error);
+    send(&tolerant, 2).await;
+    send(&strict, 1).await;
+    chain.mine();
+    chain.reorg_and_drop(1);
+
+    assert_eq!(tolerant.resync().await.unwrap().as_u64(), 2);
+    let err = strict.resync().await.unwrap_err();
+    assert!(matches!(err, NonceManagerError::ChainNonceBehind { .. }));
+    assert_eq!(strict.next().await.as_u64(), 3);
+}
+
+#[tokio::test]
+async fn shutdown_drains_and_persists_the_state() {
+    let path = std::env::temp_dir().join(format!("ethers-nonce-state-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(12);
+    let relayer = || client(&chain).with_state_store(FileStateStore::new(&path));
+
+    let first = relayer();
+    send(&first, 2).await;
+    chain.mine();
+    let report = first.shutdown(Duration::from_secs(1)).await.unwrap();
+    assert!(report.is_drained());
+    assert!(report.persisted);
+    let err = first.send_transaction(transfer(), None).await.unwrap_err();
+    assert!(matches!(err, NonceManagerError::ShuttingDown));
+
+    let restarted = relayer();
+    assert!(restarted.restore_from_store().await.unwrap());
+    assert_eq!(restarted.try_next().map(|nonce| nonce.as_u64()), Some(2));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn send_options_apply_to_a_single_send() {
+    let chain = SimulatedChain::new(13);
+    let client = client(&chain);
+    let labeled = SendOptions {
+        label: Some("payout".into()),
+        ..Default::default()
+    };
+    client
+        .send_transaction_with_opts(transfer(), labeled)
+        .await
+        .unwrap();
+    assert_eq!(client.label(Nonce::zero()), Some("payout".into()));
+
+    let bypass = SendOptions {
+        bypass_nonce: true,
+        ..Default::default()
+    };
+    client
+        .send_transaction_with_opts(transfer().nonce(5u64), bypass)
+        .await
+        .unwrap();
+    assert_eq!(chain.pending_nonces(SENDER), [0, 5]);
+    assert_eq!(client.next().await.as_u64(), 1);
+}
+
+#[tokio::test]
+async fn sends_bypassing_the_counter_are_still_checked() {
+    let chain = SimulatedChain::new(29);
+    let client = client(&chain).with_spend_limit(U256::one(), Duration::from_secs(60));
+    let bypass = || SendOptions {
+        bypass_nonce: true,
+        ..Default::default()
+    };
+    client
+        .send_transaction_with_opts(transfer().nonce(5u64), bypass())
+        .await
+        .unwrap();
+    assert_eq!(client.remaining_spend_limit(), Some(U256::zero()));
+
+    let over = client
+        .send_transaction_with_opts(transfer().nonce(6u64), bypass())
+        .await;
+    assert!(matches!(
+        over,
+        Err(NonceManagerError::SpendLimitExceeded { .. })
+    ));
+    assert_eq!(chain.pending_nonces(SENDER), [5]);
+}
+
+#[tokio::test]
+async fn expired_holds_hand_their_nonce_out_again() {
+    let chain = SimulatedChain::new(14);
+    let clock = MockClock::new();
+    let client = client(&chain).with_clock(clock.clone());
+    let held = client
+        .hold_nonce(Duration::from_secs(60), None)
+        .await
+        .unwrap();
+    assert_eq!(held.as_u64(), 0);
+
+    clock.advance(Duration::from_secs(59));
+    assert!(client.expire_holds().await.unwrap().is_empty());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(client.expire_holds().await.unwrap(), [held]);
+    assert!(client.reservations().await.is_empty());
+    assert_eq!(client.next().await.as_u64(), 0);
+}
+
+#[tokio::test]
+async fn expired_holds_which_cant_be_filled_are_released() {
+    let chain = SimulatedChain::new(30);
+    let clock = MockClock::new();
+    // the provider can't sign the filler
+    let client = client(&chain).with_clock(clock.clone());
+    client
+        .hold_nonce(Duration::from_secs(60), None)
+        .await
+        .unwrap();
+    send(&client, 1).await;
+
+    clock.advance(Duration::from_secs(60));
+    assert!(client.expire_holds().await.is_err());
+    assert!(client.reservations().await.is_empty());
+    assert!(client.expire_holds().await.unwrap().is_empty());
+    // the nonce is left for the caller to fill
+    assert_eq!(chain.pending_nonces(SENDER), [1]);
+}
+
+#[tokio::test]
+async fn renewed_holds_outlive_their_ttl() {
+    let chain = SimulatedChain::new(15);
+    let clock = MockClock::new();
+    let client = client(&chain).with_clock(clock.clone());
+    let held = client
+        .hold_nonce(Duration::from_secs(60), None)
+        .await
+        .unwrap();
+
+    clock.advance(Duration::from_secs(50));
+    let left = client
+        .renew_hold(held, Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert_eq!(left, Duration::from_secs(70));
+    clock.advance(Duration::from_secs(60));
+    assert!(client.expire_holds().await.unwrap().is_empty());
+    // expired but not given up yet, so the renewal counts from now
+    clock.advance(Duration::from_secs(20));
+    let left = client
+        .renew_hold(held, Duration::from_secs(30))
+        .await
+        .unwrap();
+    assert_eq!(left, Duration::from_secs(30));
+    clock.advance(Duration::from_secs(29));
+    assert!(client.expire_holds().await.unwrap().is_empty());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(client.expire_holds().await.unwrap(), [held]);
+
+    let err = client
+        .renew_hold(held, Duration::from_secs(60))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, NonceManagerError::NotHeld(_)));
+}
+
+#[tokio::test]
+async fn checkpoints_are_completed_by_the_journal() {
+    let path = std::env::temp_dir().join(format!("ethers-nonce-ckpt-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(16);
+    let relayer = || {
+        client(&chain)
+            .with_state_store(FileStateStore::new(&path))
+            .with_checkpoint_interval(NonZeroUsize::new(2).unwrap())
+    };
+
+    let first = relayer();
+    send(&first, 3).await;
+    drop(first);
+
+    // the snapshot stops at the second send, the journal has the third
+    let store = FileStateStore::new(&path);
+    assert_eq!(
+        store.load().unwrap().unwrap().nonce.map(|n| n.as_u64()),
+        Some(2)
+    );
+    assert_eq!(store.journal().unwrap().len(), 1);
+
+    let restarted = relayer();
+    assert!(restarted.restore_from_store().await.unwrap());
+    assert_eq!(restarted.try_next().map(|nonce| nonce.as_u64()), Some(3));
+    assert_eq!(restarted.in_flight().len(), 3);
+
+    restarted.persist().await.unwrap();
+    assert!(store.journal().unwrap().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn reservations_and_assignments_are_journaled() {
+    let path = std::env::temp_dir().join(format!("ethers-nonce-rsv-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(31);
+    let relayer = || {
+        client(&chain)
+            .with_state_store(FileStateStore::new(&path))
+            .with_checkpoint_interval(NonZeroUsize::new(100).unwrap())
+    };
+
+    let first = relayer();
+    first.reserve_nonce(None).await.unwrap();
+    first
+        .hold_nonce(Duration::from_secs(60), None)
+        .await
+        .unwrap();
+    first.apply_nonce(&mut transfer().into()).await.unwrap();
+    let released = first.reserve_nonce(None).await.unwrap();
+    first.release_reservation(released).await;
+    drop(first);
+
+    let restarted = relayer();
+    assert!(restarted.restore_from_store().await.unwrap());
+    let reservations: Vec<_> = restarted
+        .reservations()
+        .await
+        .into_iter()
+        .map(|(nonce, scheduled)| (nonce.as_u64(), scheduled))
+        .collect();
+    assert_eq!(reservations, [(0, false), (1, false)]);
+    // the released nonce was rolled back
+    assert_eq!(restarted.try_next().map(|nonce| nonce.as_u64()), Some(3));
+
+    restarted.persist().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn sends_whose_entries_were_lost_in_a_crash_are_resynced() {
+    let path = std::env::temp_dir().join(format!("ethers-nonce-lost-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(35);
+    let relayer = || {
+        client(&chain)
+            .with_state_store(FileStateStore::new(&path))
+            .with_checkpoint_interval(NonZeroUsize::new(100).unwrap())
+    };
+
+    let first = relayer();
+    send(&first, 2).await;
+    // the third send was broadcast, then the process crashed before journaling it
+    chain
+        .provider()
+        .send_transaction(transfer().nonce(2), None)
+        .await
+        .unwrap();
+    drop(first);
+
+    let restarted = relayer();
+    assert!(restarted.restore_from_store().await.unwrap());
+    assert_eq!(restarted.try_next().map(|nonce| nonce.as_u64()), Some(2));
+    let sent = restarted
+        .send_managed_transaction(transfer(), None)
+        .await
+        .unwrap();
+    assert_eq!(sent.nonce().as_u64(), 3);
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1, 2, 3]);
+
+    restarted.persist().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn snapshots_are_synced_whatever_the_durability() {
+    let path = std::env::temp_dir().join(format!(
+        "ethers-nonce-{}-durability.json",
+        std::process::id()
+    ));
+    let chain = SimulatedChain::new(34);
+    let client = client(&chain);
+    send(&client, 2).await;
+
+    let store = FileStateStore::new(&path).with_durability(Durability::OsDefault);
+    let entry = JournalEntry::Assigned {
+        nonce: Nonce::zero(),
+    };
+    store.append(&entry).unwrap();
+    assert_eq!(store.journal().unwrap(), [entry]);
+    let snapshot = client.snapshot().await;
+    store.save(&snapshot).unwrap();
+    assert!(store.journal().unwrap().is_empty());
+    assert_eq!(FileStateStore::new(&path).load().unwrap(), Some(snapshot));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn broadcasts_started_before_a_crash_are_resynced() {
+    let path = std::env::temp_dir().join(format!("ethers-nonce-wal-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let chain = SimulatedChain::new(17);
+    let store = FileStateStore::new(&path);
+    let at = |nonce: u64| Nonce::try_from(nonce).unwrap();
+    // the process crashed after starting to broadcast at 1, before the node answered
+    let journal = [
+        JournalEntry::Broadcasting {
+            nonce: at(0),
+            tx_hash: H256::repeat_byte(1),
+        },
+        JournalEntry::Broadcast {
+            nonce: at(0),
+            tx_hash: H256::repeat_byte(1),
+        },
+        JournalEntry::Broadcasting {
+            nonce: at(1),
+            tx_hash: H256::repeat_byte(2),
+        },
+    ];
+    for entry in &journal {
+        store.append(entry).unwrap();
+    }
+
+    let restarted = client(&chain)
+        .with_state_store(FileStateStore::new(&path))
+        .with_write_ahead_log();
+    assert!(restarted.restore_from_store().await.unwrap());
+    assert_eq!(restarted.try_next().map(|nonce| nonce.as_u64()), Some(1));
+    assert!(restarted.snapshot().await.needs_resync);
+
+    restarted.persist().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn failed_sends_are_counted_in_the_latency_of_their_endpoint() {
+    let chain = SimulatedChain::new(18);
+    let client = client(&chain);
+    send(&client, 2).await;
+
+    // the node can't be reached for the third send
+    chain.fail_next(1);
+    assert!(client.send_transaction(transfer(), None).await.is_err());
+    let latency = client.send_latency();
+    assert_eq!(latency.len(), 1);
+    let inner = &latency[&SendEndpoint::Inner];
+    assert_eq!(inner.latency.count, 3);
+    assert_eq!(inner.failures, 1);
+    assert_eq!(client.next().await.as_u64(), 2);
+}
+
+#[tokio::test]
+async fn sends_past_the_concurrency_window_wait_for_a_block() {
+    let chain = SimulatedChain::new(19);
+    let client = client(&chain).with_adaptive_concurrency(AdaptiveConcurrency::new(1, 3));
+    assert_eq!(client.concurrency_window(), Some(1));
+    send(&client, 4).await;
+    assert_eq!(client.concurrency_window(), Some(3));
+
+    // a fifth pending transaction would be one more than the largest window
+    let held_back = tokio::time::timeout(
+        Duration::from_millis(50),
+        client.send_transaction(transfer(), None),
+    )
+    .await;
+    assert!(held_back.is_err());
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1, 2, 3]);
+
+    chain.mine();
+    send(&client, 1).await;
+    assert_eq!(chain.mined_nonces(SENDER), [0, 1, 2, 3]);
+    assert_eq!(chain.pending_nonces(SENDER), [4]);
+}
+
+#[tokio::test]
+async fn throttled_sends_wait_without_holding_the_nonce() {
+    let chain = SimulatedChain::new(26);
+    let throttle = DriftThrottle {
+        free: 0,
+        step_ms: 200,
+        max_delay_ms: 200,
+    };
+    let client = client(&chain).with_drift_throttle(throttle);
+    send(&client, 1).await;
+
+    let started = std::time::Instant::now();
+    let throttled = client.send_transaction(transfer(), None);
+    let probe = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.try_next()
+    };
+    let (throttled, next) = tokio::join!(throttled, probe);
+    throttled.unwrap();
+    // the counter could be read while the second send sat out its delay
+    assert_eq!(next.map(|nonce| nonce.as_u64()), Some(1));
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1]);
+}
+
+/// Collects the nonces the inner middleware picked differently in shadow mode
+#[derive(Debug, Clone, Default)]
+struct Divergences(Arc<Mutex<Vec<(u64, u64)>>>);
+
+impl NonceObserver for Divergences {
+    fn on_event(&self, event: &NonceEvent) {
+        if let NonceEvent::ShadowDiverged {
+            predicted, actual, ..
+        } = event
+        {
+            let divergence = (predicted.as_u64(), actual.as_u64());
+            self.0.lock().unwrap().push(divergence);
+        }
+    }
+}
+
+#[tokio::test]
+async fn shadow_mode_reports_diverging_nonces() {
+    let chain = SimulatedChain::new(20);
+    let divergences = Divergences::default();
+    let client = client(&chain)
+        .with_shadow_mode()
+        .with_observer(divergences.clone());
+    send(&client, 1).await;
+
+    // the node counts a transaction the manager never saw
+    chain.send_external(SENDER);
+    send(&client, 2).await;
+    assert_eq!(*divergences.0.lock().unwrap(), [(1, 2)]);
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1, 2, 3]);
+    assert_eq!(client.next().await.as_u64(), 4);
+}
+
+#[tokio::test]
+async fn taking_over_from_the_upstream_manager_keeps_its_counter() {
+    let chain = SimulatedChain::new(21);
+    let upstream = NonceManagerMiddleware::new(chain.provider(), SENDER);
+    for _ in 0..3 {
+        upstream.send_transaction(transfer(), None).await.unwrap();
+    }
+    // the pool lost the last two, which only the chain knows about
+    chain.drop_pending(SENDER, 1);
+    chain.mine();
+
+    let client = LockedNonceManagerMiddleware::from_upstream(&upstream, SENDER)
+        .await
+        .unwrap();
+    // seeded from the upstream counter without asking the chain
+    assert!(client.state().is_initialized());
+    assert_eq!(client.next().await.as_u64(), 3);
+
+    // an upstream which never sent is initialized from the chain
+    let unused = NonceManagerMiddleware::new(chain.provider(), SENDER);
+    let client = LockedNonceManagerMiddleware::from_upstream(&unused, SENDER)
+        .await
+        .unwrap();
+    assert_eq!(client.next().await.as_u64(), 1);
+}
+
+/// Rejects transfers of more than one wei
+#[derive(Debug)]
+struct SmallTransfers;
+
+#[async_trait]
+impl Policy for SmallTransfers {
+    type Error = String;
+
+    async fn ensure_can_send(&self, tx: TypedTransaction) -> Result<TypedTransaction, String> {
+        match tx.value() {
+            Some(value) if *value > U256::one() => Err(format!("transfer of {value} wei")),
+            _ => Ok(tx),
+        }
+    }
+}
+
+#[tokio::test]
+async fn policy_rejections_leave_the_nonce_unused() {
+    let chain = SimulatedChain::new(22);
+    let large = transfer().value(2u64);
+
+    let outer = client(&chain).behind_policy(SmallTransfers);
+    outer.send_transaction(transfer(), None).await.unwrap();
+    assert!(outer.send_transaction(large.clone(), None).await.is_err());
+    outer.send_transaction(transfer(), None).await.unwrap();
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1]);
+
+    let inner = LockedNonceManagerMiddleware::new(
+        PolicyMiddleware::new(chain.provider(), SmallTransfers),
+        SENDER,
+    );
+    assert!(inner.send_transaction(large, None).await.is_err());
+    inner.send_transaction(transfer(), None).await.unwrap();
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1, 2]);
+}
+
+/// A relay which accepts private transactions but never gets them included
+#[derive(Debug)]
+struct NeverIncluded;
+
+#[async_trait]
+impl PrivateRelay for NeverIncluded {
+    async fn send_private_transaction(
+        &self,
+        raw_tx: Bytes,
+        _max_block_number: U64,
+    ) -> Result<TxHash, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(H256::from(keccak256(&raw_tx)))
+    }
+}
+
+#[tokio::test]
+async fn expired_private_transactions_give_their_nonce_back() {
+    let chain = SimulatedChain::new(23);
+    let (signer, address) = signer(&chain);
+    let client =
+        LockedNonceManagerMiddleware::new(signer, address).with_private_relay(NeverIncluded, 2);
+
+    let (nonce, tx_hash) = client
+        .send_private_transaction(transfer().from(address), None)
+        .await
+        .unwrap();
+    assert!(client.reservations().await.is_empty());
+    assert_eq!(client.in_flight(), [(nonce, tx_hash)]);
+    chain.mine_blocks(2);
+    assert!(client
+        .expire_private_transactions()
+        .await
+        .unwrap()
+        .is_empty());
+
+    // the relay gave up after the last block it was allowed to include the transaction in
+    chain.mine();
+    assert_eq!(client.expire_private_transactions().await.unwrap(), [nonce]);
+    assert!(client.reservations().await.is_empty());
+    assert!(client.in_flight().is_empty());
+    assert_eq!(client.next().await, nonce);
+}
+
+#[tokio::test]
+async fn private_transactions_are_tracked_in_flight() {
+    let chain = SimulatedChain::new(32);
+    let (signer, address) = signer(&chain);
+    let client =
+        LockedNonceManagerMiddleware::new(signer, address).with_private_relay(NeverIncluded, 2);
+    let tx = || transfer().from(address);
+
+    let (private, tx_hash) = client.send_private_transaction(tx(), None).await.unwrap();
+    let scheduled = client.schedule_transaction(tx(), None).await.unwrap();
+    // the private transaction doesn't hold up the flush of the ones above it
+    let flushed = client.broadcast_scheduled().await.unwrap();
+    assert_eq!(flushed.len(), 1);
+    assert_eq!(chain.pending_nonces(address), [scheduled.as_u64()]);
+
+    // replacing it would hand it to the public mempool
+    assert!(matches!(
+        client.speed_up(private).await,
+        Err(NonceManagerError::NotReplaceable(_))
+    ));
+    let snapshot = client.snapshot().await;
+    assert_eq!(snapshot.in_flight[0], (private, tx_hash));
+    assert_eq!(
+        snapshot.private,
+        [PrivateTransaction {
+            nonce: private,
+            tx_hash,
+            max_block_number: chain.provider().get_block_number().await.unwrap() + 2,
+        }]
+    );
+
+    // a restarted manager still reclaims the nonce once the relay dropped the transaction
+    let restored = LockedNonceManagerMiddleware::new(signer(&chain).0, address)
+        .with_private_relay(NeverIncluded, 2);
+    restored.restore(snapshot).await.unwrap();
+    chain.mine_blocks(3);
+    assert_eq!(
+        restored.expire_private_transactions().await.unwrap(),
+        [private]
+    );
+}
+
+#[tokio::test]
+async fn dependents_of_a_released_reservation_are_given_up() {
+    let chain = SimulatedChain::new(24);
+    let (signer, address) = signer(&chain);
+    let client = LockedNonceManagerMiddleware::new(signer, address);
+    let tx = || transfer().from(address);
+
+    let approval = client.schedule_transaction(tx(), None).await.unwrap();
+    let swap = client
+        .schedule_dependent(tx(), approval, None)
+        .await
+        .unwrap();
+    client.schedule_dependent(tx(), swap, None).await.unwrap();
+    assert_eq!(client.reservations().await.len(), 3);
+
+    // the approval won't go out after all, so neither will what depends on it
+    client.release_reservation(approval).await;
+    assert!(client.reservations().await.is_empty());
+    assert_eq!(client.next().await, approval);
+    assert!(client.broadcast_scheduled().await.unwrap().is_empty());
+    assert!(chain.pending_nonces(address).is_empty());
+
+    let unknown = client.schedule_dependent(tx(), approval, None).await;
+    assert!(matches!(unknown, Err(NonceManagerError::NotReserved(_))));
+}
+
+#[tokio::test]
+async fn templates_are_sent_like_any_managed_transaction() {
+    let chain = SimulatedChain::new(25);
+    let template = TxTemplate::new(Address::repeat_byte(1), Bytes::default()).value(1u64.into());
+    let client = client(&chain).with_template("payout", template);
+
+    let first = client.send_template("payout", &[], None).await.unwrap();
+    let second = client.send_template("payout", &[], None).await.unwrap();
+    assert_eq!((first.nonce().as_u64(), second.nonce().as_u64()), (0, 1));
+    assert_eq!(chain.pending_nonces(SENDER), [0, 1]);
+    assert_eq!(
+        client.label(second.nonce()),
+        Some(serde_json::json!({ "template": "payout" }))
+    );
+
+    let unknown = client.send_template("refund", &[], None).await;
+    assert!(matches!(unknown, Err(NonceManagerError::UnknownTemplate(name)) if name == "refund"));
+    assert_eq!(client.next().await.as_u64(), 2);
+}
+
+#[tokio::test]
+async fn connecting_takes_the_signer_address_or_fails() {
+    let chain = SimulatedChain::new(27);
+    let (signer, address) = signer(&chain);
+    let client = LockedNonceManagerMiddleware::connect(signer).await.unwrap();
+    client
+        .send_transaction(transfer().from(address), None)
+        .await
+        .unwrap();
+    assert_eq!(chain.pending_nonces(address), [0]);
+
+    let unsigned = LockedNonceManagerMiddleware::connect(chain.provider()).await;
+    assert!(matches!(unsigned, Err(NonceManagerError::NoSender)));
+}
+
+#[tokio::test]
+async fn applied_nonces_are_tracked_once_submitted_through_the_manager() {
+    let chain = SimulatedChain::new(28);
+    let (signer, address) = signer(&chain);
+    let client = LockedNonceManagerMiddleware::new(signer, address);
+    let mut tx: TypedTransaction = transfer().from(address).into();
+    let nonce = client.apply_nonce(&mut tx).await.unwrap();
+    assert_eq!(nonce.as_u64(), 0);
+    assert_eq!(client.next().await.as_u64(), 1);
+    assert!(client.in_flight().is_empty());
+
+    client
+        .inner()
+        .fill_transaction(&mut tx, None)
+        .await
+        .unwrap();
+    let signature = client.inner().signer().sign_transaction(&tx).await.unwrap();
+    let pending = client
+        .send_raw_transaction(tx.rlp_signed(&signature))
+        .await
+        .unwrap();
+    assert_eq!(client.in_flight(), [(nonce, pending.tx_hash())]);
+    assert_eq!(chain.pending_nonces(address), [0]);
+}